@@ -1,9 +1,17 @@
 fn main() {
     let mut config = prost_build::Config::new();
-    config.bytes(&["."]);
+    config.bytes(["."]);
+    // 默认的 HashMap 没有实现 PartialOrd
+    config.btree_map(["."]);
     config.type_attribute(".", "#[derive(PartialOrd)]");
     config
         .out_dir("src/pb")
         .compile_protos(&["abi.proto"], &["."])
         .unwrap();
+
+    // gRPC 的服务端和客户端代码生成到 OUT_DIR，消息直接使用上面生成的类型
+    tonic_build::configure()
+        .extern_path(".abi", "crate::pb::abi")
+        .compile(&["abi.proto"], &["."])
+        .unwrap();
 }
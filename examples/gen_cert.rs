@@ -23,7 +23,7 @@ async fn main() -> Result<()> {
 // 生成一个 ca 证书
 fn create_ca() -> Result<CertPem> {
     let (cert, key) = generate_ca(
-        &["acme.inc"],
+        ["acme.inc"],
         "CN",
         "Acme Inc.",
         "Acme CA",
@@ -1,5 +1,6 @@
 use anyhow::Result;
 use kv::{CommandRequest, ProstClientStream, TlsClientConnector};
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::TcpStream;
 use tracing::info;
 
@@ -10,11 +11,27 @@ async fn main() -> Result<()> {
     // 以后用配置替换
     let ca_cert = include_str!("../fixtures/ca.cert");
 
-    let addr = "127.0.0.1:9527";
+    // 地址可以从命令行传入，unix:// 开头的地址通过 Unix socket 连接（不走 TLS）
+    let addr = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "127.0.0.1:9527".into());
+
+    #[cfg(unix)]
+    if let Some(path) = kv::parse_unix_addr(&addr) {
+        let stream = tokio::net::UnixStream::connect(path).await?;
+        return run(stream).await;
+    }
+
     let connector = TlsClientConnector::new("kvserver.acme.inc", None, Some(ca_cert))?;
-    let stream = TcpStream::connect(addr).await?;
+    let stream = TcpStream::connect(&addr).await?;
     let stream = connector.connect(stream).await?;
+    run(stream).await
+}
 
+async fn run<S>(stream: S) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
     let mut client = ProstClientStream::new(stream);
     let cmd = CommandRequest::new_hset("table1", "hello", "world".to_string().into());
     let data = client.execute(cmd).await?;
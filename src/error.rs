@@ -8,9 +8,9 @@ pub enum KvError {
 
     #[error("Cannot parse command: `{0}`")]
     InvalidCommand(String),
-    #[error("Cannot convert value {:0} to {1}")]
+    #[error("Cannot convert value {0:?} to {1}")]
     ConvertError(Value, &'static str),
-    #[error("Cannot process command {0} with table: {1}, key: {2}. Error {}")]
+    #[error("Cannot process command {0} with table: {1}, key: {2}. Error {3}")]
     StorageError(&'static str, String, String, String),
 
     #[error("Failed to encode protobuf message")]
@@ -21,8 +21,12 @@ pub enum KvError {
     #[error("Failed to access sled db")]
     SledError(#[from] sled::Error),
 
-    #[error("Frame is larger than max size")]
-    FrameError,
+    #[error("Frame size {0} exceeds the limit {1}")]
+    FrameTooLarge(usize, usize),
+    #[error("Invalid frame: {0}")]
+    InvalidFrame(String),
+    #[error("Timed out reading frame")]
+    FrameTimeout,
 
     #[error("Certificate parse error: error to load {0} {1}")]
     CertificateParseError(&'static str, &'static str),
@@ -33,6 +37,38 @@ pub enum KvError {
     #[error("I/O error")]
     IoError(#[from] std::io::Error),
 
+    #[error("Encryption error: {0}")]
+    CryptoError(String),
+
+    #[error("Invalid JSON: {0}")]
+    JsonError(#[from] serde_json::Error),
+
+    #[error("HTTP error")]
+    HttpError(#[from] hyper::Error),
+
+    #[error("WebSocket error")]
+    WsError(Box<tokio_tungstenite::tungstenite::Error>),
+
+    #[error("Permission denied: {0}")]
+    PermissionDenied(String),
+
+    #[error("RESP protocol error: {0}")]
+    RespError(String),
+
     #[error("Internal error: {0}")]
     Internal(String),
 }
+
+// tungstenite 的错误比较大，放到 Box 里，避免 KvError 整体变大
+impl From<tokio_tungstenite::tungstenite::Error> for KvError {
+    fn from(e: tokio_tungstenite::tungstenite::Error) -> Self {
+        Self::WsError(Box::new(e))
+    }
+}
+
+impl KvError {
+    // key 可能不是 utf8，错误信息里用 lossy 的方式显示
+    pub fn not_found(table: impl Into<String>, key: &[u8]) -> Self {
+        Self::NotFound(table.into(), String::from_utf8_lossy(key).into())
+    }
+}
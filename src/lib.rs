@@ -7,5 +7,7 @@ mod storage;
 pub use error::KvError;
 pub use network::*;
 pub use pb::abi::*;
+pub use pb::grpc::kv_service_client::KvServiceClient;
+pub use pb::{IntoKey, JsonPath};
 pub use service::*;
 pub use storage::*;
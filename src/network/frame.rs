@@ -1,20 +1,80 @@
-use std::io::{Read, Write};
+use std::time::Duration;
 
-use crate::{CommandRequest, CommandResponse, KvError};
+use crate::{CommandRequest, CommandResponse, CompressionAlgorithm, KvError};
 use bytes::{Buf, BufMut, BytesMut};
-use flate2::{read::GzDecoder, write::GzEncoder, Compression};
 use prost::Message;
 use tokio::io::{AsyncRead, AsyncReadExt};
 use tracing::debug;
 
+/// frame 协议的版本，客户端通过 Hello 协商
+pub const PROTOCOL_VERSION: u32 = 1;
 // 长度整个占用 4 个字节
 pub const LEN_LEN: usize = 4;
-// 长度占 31 bit，所以最大的 frame 是 2G
-const MAX_FRAME: usize = 2 * 1024 * 1024 * 1024;
+// 长度占 30 bit，所以协议上最大的 frame 是 1G - 1，再大就会和压缩算法的 bit 重叠
+pub(crate) const MAX_FRAME: usize = LEN_MASK;
+// 握手时 max_frame_size 最小是 4K，太小的话连出错时的回复都发不出去
+pub(crate) const MIN_FRAME: usize = 4 * 1024;
+// 默认每个连接能接收的最大 frame，以及解压后的最大大小
+const DEFAULT_MAX_FRAME_SIZE: usize = 64 * 1024 * 1024;
+// 读取 frame 时每次最多多分配这么多内存，数据真正到了才继续分配
+const READ_CHUNK: usize = 64 * 1024;
 //  如果 payload 超过了 1436 字节，就做压缩
-const COMPRESSION_LIMIT: usize = 1436;
-//  代表压缩的 bit （整个长度 4 字节的最高位）
-const COMPRESSION_BIT: usize = 1 << 31;
+pub(crate) const COMPRESSION_LIMIT: usize = 1436;
+// 长度 4 字节的最高 2 bit 代表压缩算法：00 不压缩，01 zstd，10 gzip，11 lz4
+// 以前只有最高位一个压缩 bit，代表 gzip，所以老的 frame 依然可以正常解析
+const COMPRESSION_SHIFT: usize = 30;
+const LEN_MASK: usize = (1 << COMPRESSION_SHIFT) - 1;
+
+/// 一个连接上发送 frame 时的压缩配置：encode 后的大小超过 threshold 才压缩，algorithm 为 None 时不压缩
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameCompression {
+    pub algorithm: Option<CompressionAlgorithm>,
+    pub threshold: usize,
+}
+
+impl FrameCompression {
+    pub fn new(algorithm: CompressionAlgorithm, threshold: usize) -> Self {
+        Self {
+            algorithm: Some(algorithm),
+            threshold,
+        }
+    }
+
+    pub fn none() -> Self {
+        Self {
+            algorithm: None,
+            threshold: 0,
+        }
+    }
+}
+
+// 没有握手的连接和以前一样，超过 COMPRESSION_LIMIT 就用 gzip 压缩
+impl Default for FrameCompression {
+    fn default() -> Self {
+        Self::new(CompressionAlgorithm::Gzip, COMPRESSION_LIMIT)
+    }
+}
+
+/// 一个连接上接收 frame 时的限制，防止对端用伪造的长度、压缩炸弹或者慢速发送耗尽服务器的资源
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameLimits {
+    /// frame 的最大字节数（不包括 4 字节的长度）
+    pub max_frame_size: usize,
+    /// 压缩的 frame 解压后的最大字节数
+    pub max_decompressed_size: usize,
+    /// 收到 frame 的第一个字节之后，必须在这段时间内读完整个 frame，None 表示不限制
+    pub read_timeout: Option<Duration>,
+}
+
+impl Default for FrameLimits {
+    fn default() -> Self {
+        Self {
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            max_decompressed_size: DEFAULT_MAX_FRAME_SIZE,
+            read_timeout: None,
+        }
+    }
+}
 
 // 处理 Frame 的 encode/decode
 pub trait FrameCoder
@@ -23,94 +83,168 @@ where
 {
     /// 把一个 Message encode 成一个 frame
     fn encode_frame(&self, buf: &mut BytesMut) -> Result<(), KvError> {
+        self.encode_frame_with(buf, FrameCompression::default())
+    }
+
+    /// 和 encode_frame 一样，但使用连接上协商的压缩算法和阈值
+    fn encode_frame_with(
+        &self,
+        buf: &mut BytesMut,
+        compression: FrameCompression,
+    ) -> Result<(), KvError> {
         let size = self.encoded_len();
 
-        if size >= MAX_FRAME {
-            return Err(KvError::FrameError);
+        if size > MAX_FRAME {
+            return Err(KvError::FrameTooLarge(size, MAX_FRAME));
         }
 
-        // 我们先写入长度，如果需要压缩，再重写压缩后的长度
-        buf.put_u32(size as _);
-
-        if size > COMPRESSION_LIMIT {
+        if let Some(algorithm) = compression
+            .algorithm
+            .filter(|_| size > compression.threshold)
+        {
             let mut buf1 = Vec::with_capacity(size);
             self.encode(&mut buf1)?;
-
-            // BytesMut 支持逻辑上的 split （之后还能 unsplit）
-            // 所以我们先把长度这 4 字节拿走，清除（因为我们要重写长度）
-            let payload = buf.split_off(LEN_LEN);
-            buf.clear();
-
-            // 处理 gzip 压缩，具体可以参考 flate2 文档
-            let mut encoder = GzEncoder::new(payload.writer(), Compression::default());
-            encoder.write_all(&buf1[..])?;
-
-            // 压缩完成后，从 gzip encoder 中把 BytesMut 再拿回来
-            let payload = encoder.finish()?.into_inner();
-            debug!("Encode a frame: size {}({})", size, payload.len());
-
-            // 写入压缩后的长度
-            buf.put_u32((payload.len() | COMPRESSION_BIT) as _);
-
-            // 把 BytesMut 再合并回来
-            buf.unsplit(payload);
-
-            Ok(())
-        } else {
-            self.encode(buf)?;
-            Ok(())
+            let payload = algorithm.compress(&buf1)?;
+            debug!(
+                "Encode a frame: size {}({}) {:?}",
+                size,
+                payload.len(),
+                algorithm
+            );
+
+            // 压缩后没有变小，就直接发送没有压缩的数据，省得对方再解压
+            if payload.len() < size {
+                buf.put_u32(encode_header(payload.len(), Some(algorithm)) as _);
+                buf.put_slice(&payload);
+            } else {
+                buf.put_u32(size as _);
+                buf.put_slice(&buf1);
+            }
+            return Ok(());
         }
+
+        buf.put_u32(size as _);
+        self.encode(buf)?;
+        Ok(())
     }
 
     /// 把一个完成的 frame decode 成一个 Message
     fn decode_frame(buf: &mut BytesMut) -> Result<Self, KvError> {
-        // 先取 4 字节，从中拿出长度和 compression bit
+        Self::decode_frame_with(buf, &FrameLimits::default())
+    }
+
+    /// 和 decode_frame 一样，但使用连接上的限制
+    fn decode_frame_with(buf: &mut BytesMut, limits: &FrameLimits) -> Result<Self, KvError> {
+        // 先取 4 字节，从中拿出长度和压缩算法
+        if buf.len() < LEN_LEN {
+            return Err(KvError::InvalidFrame("missing frame header".into()));
+        }
         let header = buf.get_u32() as usize;
-        let (len, compressed) = decode_header(header);
-        debug!("Got a frame: msg: len {}, compressed {}", len, compressed);
-
-        if compressed {
-            // 解压缩
-            let mut decoder = GzDecoder::new(&buf[..len]);
-            let mut buf1 = Vec::with_capacity(len * 2);
-            decoder.read_to_end(&mut buf1)?;
-            // 为啥要有这一句？注释掉后 test 依然通过
-            buf.advance(len);
-
-            // decode 成相应的消息
-            Ok(Self::decode(&buf1[..buf1.len()])?)
-        } else {
-            let msg = Self::decode(&buf[..len])?;
-            // 为啥要有这一句？
-            buf.advance(len);
-            Ok(msg)
+        let (len, algorithm) = decode_header(header);
+        debug!("Got a frame: msg: len {}, compression {:?}", len, algorithm);
+
+        if len > limits.max_frame_size {
+            return Err(KvError::FrameTooLarge(len, limits.max_frame_size));
         }
+        if len > buf.len() {
+            return Err(KvError::InvalidFrame(format!(
+                "expect {} bytes, got {}",
+                len,
+                buf.len()
+            )));
+        }
+
+        let msg = match algorithm {
+            Some(algorithm) => {
+                let data =
+                    algorithm.decompress_bounded(&buf[..len], limits.max_decompressed_size)?;
+                Self::decode(&data[..])?
+            }
+            None => Self::decode(&buf[..len])?,
+        };
+        buf.advance(len);
+        Ok(msg)
     }
 }
 
 impl FrameCoder for CommandRequest {}
 impl FrameCoder for CommandResponse {}
 
-fn decode_header(header: usize) -> (usize, bool) {
-    let len = header & !COMPRESSION_BIT;
-    let compressed = header & COMPRESSION_BIT == COMPRESSION_BIT;
-    (len, compressed)
+fn encode_header(len: usize, algorithm: Option<CompressionAlgorithm>) -> usize {
+    let bits = match algorithm {
+        None => 0,
+        Some(CompressionAlgorithm::Zstd) => 1,
+        Some(CompressionAlgorithm::Gzip) => 2,
+        Some(CompressionAlgorithm::Lz4) => 3,
+    };
+    len | bits << COMPRESSION_SHIFT
 }
 
-pub async fn read_frame<S>(stream: &mut S, buf: &mut BytesMut) -> Result<(), KvError>
+fn decode_header(header: usize) -> (usize, Option<CompressionAlgorithm>) {
+    let algorithm = match header >> COMPRESSION_SHIFT {
+        1 => Some(CompressionAlgorithm::Zstd),
+        2 => Some(CompressionAlgorithm::Gzip),
+        3 => Some(CompressionAlgorithm::Lz4),
+        _ => None,
+    };
+    (header & LEN_MASK, algorithm)
+}
+
+// 根据 buf 开头的长度信息，算出整个 frame 的字节数，数据不够 4 字节时返回 None
+pub(crate) fn frame_len(buf: &[u8]) -> Option<usize> {
+    let header = u32::from_be_bytes(buf.get(..LEN_LEN)?.try_into().ok()?) as usize;
+    let (len, _algorithm) = decode_header(header);
+    Some(LEN_LEN + len)
+}
+
+/// 从 stream 里读取一个完整的 frame（包括 4 字节的长度）追加到 buf 里
+/// 长度超过限制时直接返回错误，不会读取和缓存 frame 的内容
+pub async fn read_frame_with<S>(
+    stream: &mut S,
+    buf: &mut BytesMut,
+    limits: &FrameLimits,
+) -> Result<(), KvError>
+where
+    S: AsyncRead + Unpin + Send,
+{
+    // 连接空闲时一直等待，从第一个字节开始计算超时
+    let first = stream.read_u8().await?;
+    let read = read_frame_rest(stream, buf, first, limits);
+    match limits.read_timeout {
+        Some(timeout) => tokio::time::timeout(timeout, read)
+            .await
+            .map_err(|_| KvError::FrameTimeout)?,
+        None => read.await,
+    }
+}
+
+async fn read_frame_rest<S>(
+    stream: &mut S,
+    buf: &mut BytesMut,
+    first: u8,
+    limits: &FrameLimits,
+) -> Result<(), KvError>
 where
     S: AsyncRead + Unpin + Send,
 {
-    let header = stream.read_u32().await? as usize;
-    let (len, _compressed) = decode_header(header);
-    // 如果没有这么大的内存，就分配至少一个 frame 的内存，保证它可用
-    buf.reserve(LEN_LEN + len);
-    buf.put_u32(header as _);
-    // advance_mut 是 unsafe 的原因是，从当前位置 pos 到 pos + len，这段内存目前没有初始化。
-    // 我们就是为了 reserve 这段内存，然后从stream 里读取，读取完后它就是初始化的了。所以我们这么
-    // 用是安全的
-    unsafe { buf.advance_mut(len) };
-    stream.read_exact(&mut buf[LEN_LEN..]).await?;
+    let mut header = [first, 0, 0, 0];
+    stream.read_exact(&mut header[1..]).await?;
+    let header = u32::from_be_bytes(header);
+    let (len, _algorithm) = decode_header(header as usize);
+    if len > limits.max_frame_size {
+        return Err(KvError::FrameTooLarge(len, limits.max_frame_size));
+    }
+
+    buf.put_u32(header);
+    // 不相信 header 里的长度一次分配所有内存，而是随着数据的到来逐步分配
+    let end = buf.len() + len;
+    let mut body = stream.take(len as u64);
+    while buf.len() < end {
+        buf.reserve((end - buf.len()).min(READ_CHUNK));
+        if body.read_buf(buf).await? == 0 {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+        }
+    }
     Ok(())
 }
 
@@ -119,6 +253,7 @@ mod tests {
     use super::*;
     use crate::Value;
     use bytes::Bytes;
+    use tokio::io::AsyncWriteExt;
 
     struct DummyStream {
         buf: BytesMut,
@@ -130,8 +265,8 @@ mod tests {
             _cx: &mut std::task::Context<'_>,
             buf: &mut tokio::io::ReadBuf<'_>,
         ) -> std::task::Poll<std::io::Result<()>> {
-            // 看看 ReadBuf 需要多大的数据
-            let len = buf.capacity();
+            // 看看 ReadBuf 需要多大的数据，数据不够时有多少给多少
+            let len = buf.remaining().min(self.buf.len());
 
             // split 出这么大的数据
             let data = self.get_mut().buf.split_to(len);
@@ -152,12 +287,128 @@ mod tests {
         let mut stream = DummyStream { buf };
 
         let mut data = BytesMut::new();
-        read_frame(&mut stream, &mut data).await.unwrap();
+        read_frame_with(&mut stream, &mut data, &FrameLimits::default())
+            .await
+            .unwrap();
 
         let cmd1 = CommandRequest::decode_frame(&mut data).unwrap();
         assert_eq!(cmd, cmd1);
     }
 
+    #[tokio::test]
+    async fn read_frame_should_reject_oversized_header() {
+        // 只有一个伪造的 header，声称后面有将近 1G 的数据
+        let mut buf = BytesMut::new();
+        buf.put_u32(LEN_MASK as _);
+        let mut stream = DummyStream { buf };
+        let limits = FrameLimits {
+            max_frame_size: 1024,
+            ..Default::default()
+        };
+
+        let mut data = BytesMut::new();
+        let result = read_frame_with(&mut stream, &mut data, &limits).await;
+        assert!(matches!(
+            result,
+            Err(KvError::FrameTooLarge(LEN_MASK, 1024))
+        ));
+        assert!(data.capacity() < 1024);
+    }
+
+    #[tokio::test]
+    async fn read_frame_should_fail_on_truncated_data() {
+        let mut buf = BytesMut::new();
+        CommandRequest::new_hdel("t1", "k1")
+            .encode_frame(&mut buf)
+            .unwrap();
+        buf.truncate(buf.len() - 1);
+        let mut stream = DummyStream { buf };
+
+        let mut data = BytesMut::new();
+        let result = read_frame_with(&mut stream, &mut data, &FrameLimits::default()).await;
+        assert!(matches!(result, Err(KvError::IoError(_))));
+    }
+
+    #[tokio::test]
+    async fn read_frame_should_time_out_on_slow_sender() {
+        let limits = FrameLimits {
+            read_timeout: Some(Duration::from_millis(50)),
+            ..Default::default()
+        };
+        let mut frame = BytesMut::new();
+        CommandRequest::new_hdel("t1", "k1")
+            .encode_frame(&mut frame)
+            .unwrap();
+
+        // 空闲的连接不会超时：等待一段时间之后再发送完整的 frame
+        let (mut client, mut server) = tokio::io::duplex(64);
+        let data = frame.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            client.write_all(&data).await.unwrap();
+            // 之后只发送 frame 的一部分
+            client.write_all(&data[..3]).await.unwrap();
+            tokio::time::sleep(Duration::from_secs(10)).await;
+        });
+
+        let mut buf = BytesMut::new();
+        read_frame_with(&mut server, &mut buf, &limits)
+            .await
+            .unwrap();
+        assert_eq!(buf, frame);
+
+        let mut buf = BytesMut::new();
+        let result = read_frame_with(&mut server, &mut buf, &limits).await;
+        assert!(matches!(result, Err(KvError::FrameTimeout)));
+    }
+
+    #[test]
+    fn decode_frame_should_reject_hostile_input() {
+        let limits = FrameLimits {
+            max_decompressed_size: 1024 * 1024,
+            ..Default::default()
+        };
+
+        // gzip 炸弹：几 KB 的数据解压出 16MB
+        let cmd = CommandRequest::new_hset("t1", "k1", Bytes::from(vec![0u8; 16 << 20]).into());
+        let mut buf = BytesMut::new();
+        cmd.encode_frame(&mut buf).unwrap();
+        assert!(buf.len() < 64 * 1024);
+        let result = CommandRequest::decode_frame_with(&mut buf, &limits);
+        assert!(matches!(result, Err(KvError::FrameTooLarge(_, 1048576))));
+
+        // header 不完整，或者声称的长度比实际的数据长
+        let mut buf = BytesMut::from(&[0u8, 0][..]);
+        let result = CommandRequest::decode_frame_with(&mut buf, &limits);
+        assert!(matches!(result, Err(KvError::InvalidFrame(_))));
+        let mut buf = BytesMut::new();
+        buf.put_u32(100);
+        buf.put_slice(b"short");
+        let result = CommandRequest::decode_frame_with(&mut buf, &limits);
+        assert!(matches!(result, Err(KvError::InvalidFrame(_))));
+    }
+
+    #[test]
+    fn frame_of_max_length_should_not_overlap_compression_bits() {
+        assert_eq!(
+            decode_header(encode_header(MAX_FRAME, None)),
+            (MAX_FRAME, None)
+        );
+
+        // 刚好 2^30 字节的 frame 会把长度写进压缩算法的 bit，必须在编码时拒绝
+        // vec![0; n] 不会真正占用这么多内存，编码前就会检查大小，所以不会复制数据
+        let request =
+            |len: usize| CommandRequest::new_hset("t1", "k1", Bytes::from(vec![0u8; len]).into());
+        let len = (1 << 30) - 64;
+        let len = len + (1 << 30) - request(len).encoded_len();
+        let cmd = request(len);
+        assert_eq!(cmd.encoded_len(), 1 << 30);
+        let mut buf = BytesMut::new();
+        let result = cmd.encode_frame(&mut buf);
+        assert!(matches!(result, Err(KvError::FrameTooLarge(_, MAX_FRAME))));
+        assert!(buf.is_empty());
+    }
+
     #[test]
     fn command_request_encode_decode_should_work() {
         let mut buf = BytesMut::new();
@@ -199,9 +450,65 @@ mod tests {
         assert_eq!(res, res1);
     }
 
+    #[test]
+    fn frame_compression_algorithms_should_work() {
+        let value: Value = Bytes::from(vec![0u8; 4096]).into();
+        let res: CommandResponse = value.into();
+
+        for algorithm in CompressionAlgorithm::ALL {
+            let mut buf = BytesMut::new();
+            res.encode_frame_with(&mut buf, FrameCompression::new(algorithm, 1024))
+                .unwrap();
+            let header = u32::from_be_bytes(buf[..LEN_LEN].try_into().unwrap()) as usize;
+            assert_eq!(decode_header(header).1, Some(algorithm));
+            assert_eq!(CommandResponse::decode_frame(&mut buf).unwrap(), res);
+        }
+
+        // 不超过阈值，或者不压缩的连接，发送原始数据
+        for compression in [
+            FrameCompression::new(CompressionAlgorithm::Zstd, 8192),
+            FrameCompression::none(),
+        ] {
+            let mut buf = BytesMut::new();
+            res.encode_frame_with(&mut buf, compression).unwrap();
+            assert!(!is_compressed(&buf));
+            assert_eq!(CommandResponse::decode_frame(&mut buf).unwrap(), res);
+        }
+    }
+
+    #[test]
+    fn incompressible_frame_should_not_be_compressed() {
+        // 伪随机的数据压缩后不会变小
+        let mut seed = 42u64;
+        let data: Vec<u8> = (0..4096)
+            .map(|_| {
+                seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+                (seed >> 56) as u8
+            })
+            .collect();
+        let res: CommandResponse = Value::from(Bytes::from(data)).into();
+        let mut buf = BytesMut::new();
+        res.encode_frame(&mut buf).unwrap();
+        assert!(!is_compressed(&buf));
+        assert_eq!(CommandResponse::decode_frame(&mut buf).unwrap(), res);
+    }
+
+    #[test]
+    fn legacy_gzip_frame_should_be_decoded() {
+        // 以前的 frame：最高位表示 gzip 压缩，剩下 31 bit 是长度
+        let cmd = CommandRequest::new_hset("t1", "k1", Bytes::from(vec![1u8; 4096]).into());
+        let payload = CompressionAlgorithm::Gzip
+            .compress(&cmd.encode_to_vec())
+            .unwrap();
+        let mut buf = BytesMut::new();
+        buf.put_u32((payload.len() | 1 << 31) as _);
+        buf.put_slice(&payload);
+        assert_eq!(CommandRequest::decode_frame(&mut buf).unwrap(), cmd);
+    }
+
     fn is_compressed(data: &[u8]) -> bool {
         if let &[v] = &data[..1] {
-            v >> 7 == 1
+            v >> 6 != 0
         } else {
             false
         }
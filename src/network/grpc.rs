@@ -0,0 +1,282 @@
+use std::pin::Pin;
+
+use tokio::sync::mpsc;
+use tokio_stream::{wrappers::ReceiverStream, Stream};
+use tonic::{Request, Response, Status};
+
+use crate::{
+    command_request::RequestData,
+    pb::grpc::kv_service_server::{KvService, KvServiceServer},
+    service::for_each_command,
+    CommandRequest, CommandResponse, Kvpair, Query, Service, StreamEntry, Xread,
+};
+
+type ResponseStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send + Sync>>;
+
+/// gRPC 的 KvService，和 ProstServerStream 共用同一个 Service
+/// unary RPC 的结果和自定义的 frame 协议完全一样，命令出错时也是返回带有 status 的 CommandResponse
+pub struct GrpcService {
+    service: Service,
+}
+
+impl GrpcService {
+    pub fn new(service: Service) -> Self {
+        Self { service }
+    }
+
+    // 包装成 tonic 的 Server 可以直接 add_service 的类型
+    pub fn into_server(self) -> KvServiceServer<Self> {
+        KvServiceServer::new(self)
+    }
+
+    async fn execute(&self, data: RequestData) -> Result<Response<CommandResponse>, Status> {
+        let cmd = CommandRequest {
+            request_data: Some(data),
+        };
+        Ok(Response::new(self.service.execute_async(cmd).await))
+    }
+}
+
+// 每个命令一个 unary RPC，都是把请求包装成 CommandRequest 交给 Service 执行
+macro_rules! kv_service {
+    ($($method:ident => $variant:ident,)* { $($rest:tt)* }) => {
+        #[tonic::async_trait]
+        impl KvService for GrpcService {
+            $(
+                async fn $method(
+                    &self,
+                    request: Request<crate::$variant>,
+                ) -> Result<Response<CommandResponse>, Status> {
+                    self.execute(RequestData::$variant(request.into_inner())).await
+                }
+            )*
+
+            $($rest)*
+        }
+    };
+}
+
+for_each_command!(kv_service {
+    type ScanStream = ResponseStream<Kvpair>;
+    type SubscribeStream = ResponseStream<StreamEntry>;
+
+    async fn execute(
+        &self,
+        request: Request<CommandRequest>,
+    ) -> Result<Response<CommandResponse>, Status> {
+        Ok(Response::new(
+            self.service.execute_async(request.into_inner()).await,
+        ))
+    }
+
+    // 在阻塞线程里遍历 table，边扫描边通过 channel 返回，不需要把所有结果都放在内存里
+    async fn scan(&self, request: Request<Query>) -> Result<Response<Self::ScanStream>, Status> {
+        let query = request.into_inner();
+        if !query.aggregates.is_empty() {
+            return Err(Status::invalid_argument("Scan does not support aggregates"));
+        }
+        let service = self.service.clone();
+        let (tx, rx) = mpsc::channel(16);
+        tokio::task::spawn_blocking(move || {
+            // 客户端断开之后 send 会失败，这时停止遍历
+            let res = service.query_each(&query, &mut |pair| tx.blocking_send(Ok(pair)).is_ok());
+            if let Err(e) = res {
+                let _ = tx.blocking_send(Err(to_status(&e.into())));
+            }
+        });
+        Ok(Response::new(Box::pin(ReceiverStream::new(rx))))
+    }
+
+    async fn subscribe(
+        &self,
+        request: Request<Xread>,
+    ) -> Result<Response<Self::SubscribeStream>, Status> {
+        // 一直阻塞到有新的 entry，每次从上一次返回的最后一个 entry 之后继续读
+        let mut xread = Xread {
+            block: true,
+            timeout_ms: 0,
+            ..request.into_inner()
+        };
+        // $ 要在返回之前确定下来，否则返回之后、开始读取之前写入的 entry 会被漏掉
+        self.service
+            .resolve_last_id(&mut xread)
+            .map_err(|e| to_status(&e.into()))?;
+        let service = self.service.clone();
+        let (tx, rx) = mpsc::channel(16);
+        tokio::spawn(async move {
+            loop {
+                let cmd = CommandRequest {
+                    request_data: Some(RequestData::Xread(xread.clone())),
+                };
+                // 客户端断开之后不再等待
+                let res = tokio::select! {
+                    res = service.execute_async(cmd) => res,
+                    _ = tx.closed() => return,
+                };
+                if res.status != 200 {
+                    let _ = tx.send(Err(to_status(&res))).await;
+                    return;
+                }
+                for entry in res.entries {
+                    xread.after = entry.id.clone();
+                    if tx.send(Ok(entry)).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+        Ok(Response::new(Box::pin(ReceiverStream::new(rx))))
+    }
+});
+
+fn to_status(res: &CommandResponse) -> Status {
+    match res.status {
+        400 => Status::invalid_argument(&res.message),
+        403 => Status::permission_denied(&res.message),
+        404 => Status::not_found(&res.message),
+        _ => Status::internal(&res.message),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use anyhow::Result;
+    use tokio::net::TcpListener;
+    use tokio_stream::{wrappers::TcpListenerStream, StreamExt};
+    use tonic::transport::{Channel, Server};
+
+    use super::*;
+    use crate::{assert_res_ok, KvServiceClient, MemTable, ServiceInner, Value};
+
+    #[tokio::test]
+    async fn grpc_unary_should_work() -> Result<()> {
+        let mut client = connect(start_server().await?).await?;
+
+        let req = crate::Hset {
+            table: "t1".into(),
+            pair: Some(Kvpair::new("k1", "v1".into())),
+        };
+        let res = client.hset(req).await?.into_inner();
+        assert_res_ok(res, &[Value::default()], &[]);
+
+        let res = client.hget(crate::Hget {
+            table: "t1".into(),
+            key: "k1".into(),
+        });
+        assert_res_ok(res.await?.into_inner(), &["v1".into()], &[]);
+
+        // 命令出错时和 frame 协议一样通过 status 返回
+        let res = client
+            .execute(CommandRequest::new_hget("t1", "none"))
+            .await?;
+        assert_eq!(res.into_inner().status, 404);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn grpc_scan_should_work() -> Result<()> {
+        let mut client = connect(start_server().await?).await?;
+        let cmd = CommandRequest::new_hmset("t1", vec![("a:1", 1), ("a:2", 2), ("b:1", 3)]);
+        client.execute(cmd).await?;
+
+        let query = Query {
+            table: "t1".into(),
+            key_pattern: "a:*".into(),
+            ..Default::default()
+        };
+        let stream = client.scan(query.clone()).await?.into_inner();
+        let mut pairs: Vec<_> = stream.collect::<Result<_, _>>().await?;
+        pairs.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(
+            pairs,
+            vec![Kvpair::new("a:1", 1.into()), Kvpair::new("a:2", 2.into())]
+        );
+
+        let limited = Query {
+            limit: 1,
+            ..query.clone()
+        };
+        let stream = client.scan(limited).await?.into_inner();
+        assert_eq!(stream.collect::<Vec<_>>().await.len(), 1);
+
+        // 扫描过程中出现的错误通过 stream 返回
+        let invalid = Query {
+            predicates: vec![crate::Predicate {
+                op: "like".into(),
+                ..Default::default()
+            }],
+            ..query.clone()
+        };
+        let mut stream = client.scan(invalid).await?.into_inner();
+        let status = stream.next().await.unwrap().unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+        // 不能扫描内部使用的 table
+        let internal = Query {
+            table: "__meta__".into(),
+            ..query.clone()
+        };
+        let mut stream = client.scan(internal).await?.into_inner();
+        let status = stream.next().await.unwrap().unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+        let query = Query {
+            aggregates: vec![Default::default()],
+            ..query
+        };
+        let status = client.scan(query).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn grpc_subscribe_should_work() -> Result<()> {
+        let addr = start_server().await?;
+        let mut client = connect(addr).await?;
+
+        let xread = Xread {
+            table: "t1".into(),
+            key: "s".into(),
+            after: "$".into(),
+            ..Default::default()
+        };
+        let mut stream = client.subscribe(xread).await?.into_inner();
+
+        // 订阅之后写入的 entry 会依次推送过来
+        let mut producer = connect(addr).await?;
+        for i in 0..3 {
+            let fields = vec![Kvpair::new("n", i.into())];
+            producer
+                .execute(CommandRequest::new_xadd("t1", "s", None, fields))
+                .await?;
+        }
+        for i in 0..3 {
+            let entry = stream.next().await.unwrap()?;
+            assert_eq!(entry.fields, vec![Kvpair::new("n", i.into())]);
+        }
+
+        Ok(())
+    }
+
+    async fn connect(addr: SocketAddr) -> Result<KvServiceClient<Channel>> {
+        Ok(KvServiceClient::connect(format!("http://{}", addr)).await?)
+    }
+
+    async fn start_server() -> Result<SocketAddr> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+
+        let service: Service = ServiceInner::new(MemTable::new()).into();
+        tokio::spawn(
+            Server::builder()
+                .add_service(GrpcService::new(service).into_server())
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+
+        Ok(addr)
+    }
+}
@@ -0,0 +1,483 @@
+use std::convert::Infallible;
+
+use bytes::{Bytes, BytesMut};
+use http::{header, Method, Request, Response, StatusCode};
+use hyper::{body::HttpBody, server::conn::Http, service::service_fn, Body};
+use serde_json::{json, Map, Number};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tracing::info;
+
+use crate::{value, CommandRequest, CommandResponse, KvError, Kvpair, Service, Value, ValueMap};
+
+// PUT 请求的 body 最大 16MB
+const MAX_BODY: usize = 16 * 1024 * 1024;
+
+/// 处理 HTTP 连接，把 REST 请求翻译成 CommandRequest，返回 JSON 格式的 CommandResponse
+///
+/// - `GET /tables/{t}`：Hgetall
+/// - `GET /tables/{t}/keys/{k}`：Hget
+/// - `PUT /tables/{t}/keys/{k}`：Hset，body 是 JSON 格式的 Value
+/// - `DELETE /tables/{t}/keys/{k}`：Hdel
+pub struct HttpServerStream<S> {
+    inner: S,
+    service: Service,
+}
+
+impl<S> HttpServerStream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    pub fn new(stream: S, service: Service) -> Self {
+        Self {
+            inner: stream,
+            service,
+        }
+    }
+
+    pub async fn process(self) -> Result<(), KvError> {
+        let service = self.service;
+        let handler = service_fn(move |req| handle(req, service.clone()));
+        Http::new()
+            .http1_only(true)
+            .serve_connection(self.inner, handler)
+            .await?;
+        Ok(())
+    }
+}
+
+async fn handle(req: Request<Body>, service: Service) -> Result<Response<Body>, Infallible> {
+    let res = match route(req).await {
+        Ok(cmd) => {
+            info!("Got a new HTTP command: {:?}", cmd);
+            service.execute_async(cmd).await
+        }
+        Err((status, message)) => CommandResponse {
+            status: status.as_u16() as _,
+            message,
+            ..Default::default()
+        },
+    };
+
+    let status = http_status(&res);
+    let body = serde_json::to_vec(&response_to_json(&res)).unwrap_or_default();
+    let res = Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(body.into())
+        .unwrap_or_default();
+    Ok(res)
+}
+
+// CommandResponse 的 status 本来就是 HTTP 状态码
+fn http_status(res: &CommandResponse) -> StatusCode {
+    u16::try_from(res.status)
+        .ok()
+        .and_then(|s| StatusCode::from_u16(s).ok())
+        .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+// 不能翻译成 CommandRequest 的请求，直接以这个状态码和错误信息回复
+type RouteError = (StatusCode, String);
+
+// 把请求翻译成 CommandRequest
+async fn route(req: Request<Body>) -> Result<CommandRequest, RouteError> {
+    let segments = req
+        .uri()
+        .path()
+        .trim_start_matches('/')
+        .split('/')
+        .map(percent_decode)
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| error(StatusCode::BAD_REQUEST, "Invalid percent encoding in path"))?;
+
+    let method = req.method().clone();
+    match segments.as_slice() {
+        [tables, t] if tables == "tables" => match method {
+            Method::GET => Ok(CommandRequest::new_hgetall(table_name(t)?)),
+            _ => Err(method_not_allowed(&method)),
+        },
+        [tables, t, keys, k] if tables == "tables" && keys == "keys" => {
+            let (table, key) = (table_name(t)?, k.clone());
+            match method {
+                Method::GET => Ok(CommandRequest::new_hget(table, key)),
+                Method::DELETE => Ok(CommandRequest::new_hdel(table, key)),
+                Method::PUT => {
+                    let body = read_body(req.into_body()).await?;
+                    let value = serde_json::from_slice(&body)
+                        .map_err(KvError::from)
+                        .and_then(value_from_json)
+                        .map_err(|e| error(StatusCode::BAD_REQUEST, &e.to_string()))?;
+                    Ok(CommandRequest::new_hset(table, key, value))
+                }
+                _ => Err(method_not_allowed(&method)),
+            }
+        }
+        _ => Err(error(StatusCode::NOT_FOUND, "No such route")),
+    }
+}
+
+async fn read_body(mut body: Body) -> Result<Bytes, RouteError> {
+    let mut buf = BytesMut::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|e| error(StatusCode::BAD_REQUEST, &e.to_string()))?;
+        if buf.len() + chunk.len() > MAX_BODY {
+            return Err(error(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "Request body is too large",
+            ));
+        }
+        buf.extend_from_slice(&chunk);
+    }
+    Ok(buf.freeze())
+}
+
+fn table_name(segment: &Bytes) -> Result<String, RouteError> {
+    String::from_utf8(segment.to_vec())
+        .map_err(|_| error(StatusCode::BAD_REQUEST, "Table name must be valid UTF-8"))
+}
+
+fn method_not_allowed(method: &Method) -> RouteError {
+    error(
+        StatusCode::METHOD_NOT_ALLOWED,
+        &format!("Method {} is not allowed", method),
+    )
+}
+
+fn error(status: StatusCode, message: &str) -> RouteError {
+    (status, message.into())
+}
+
+// 解码 path 里的 %XX，key 可以是任意的字节
+fn percent_decode(s: &str) -> Option<Bytes> {
+    let mut result = Vec::with_capacity(s.len());
+    let mut bytes = s.bytes();
+    while let Some(b) = bytes.next() {
+        if b == b'%' {
+            let hex = [bytes.next()?, bytes.next()?];
+            result.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            result.push(b);
+        }
+    }
+    Some(result.into())
+}
+
+// JSON 的结构和 CommandResponse 一致，status、message、values、pairs 总是存在，
+// 其他字段只在不为空时出现。Value 按 protobuf 的 oneof 编码成 {"string": "hello"} 这样的 object，
+// 所以字符串和二进制、整数和浮点数都能区分开
+fn response_to_json(res: &CommandResponse) -> serde_json::Value {
+    let mut obj = Map::new();
+    obj.insert("status".into(), res.status.into());
+    obj.insert("message".into(), res.message.as_str().into());
+    obj.insert(
+        "values".into(),
+        res.values.iter().map(value_to_json).collect(),
+    );
+    obj.insert("pairs".into(), res.pairs.iter().map(pair_to_json).collect());
+
+    let mut optional = |name: &str, items: Vec<serde_json::Value>| {
+        if !items.is_empty() {
+            obj.insert(name.into(), items.into());
+        }
+    };
+    optional(
+        "versions",
+        res.versions
+            .iter()
+            .map(|v| {
+                json!({
+                    "version": v.version,
+                    "timestamp": v.timestamp,
+                    "value": v.value.as_ref().map(value_to_json),
+                    "deleted": v.deleted,
+                })
+            })
+            .collect(),
+    );
+    optional(
+        "scored_values",
+        res.scored_values
+            .iter()
+            .map(|v| {
+                json!({
+                    "member": v.member.as_ref().map(value_to_json),
+                    "score": float_to_json(v.score),
+                })
+            })
+            .collect(),
+    );
+    optional(
+        "entries",
+        res.entries
+            .iter()
+            .map(|e| {
+                let fields: Vec<_> = e.fields.iter().map(pair_to_json).collect();
+                json!({ "id": e.id, "fields": fields })
+            })
+            .collect(),
+    );
+    optional(
+        "pending",
+        res.pending
+            .iter()
+            .map(|p| {
+                json!({
+                    "id": p.id,
+                    "consumer": p.consumer,
+                    "delivered_at": p.delivered_at,
+                    "delivery_count": p.delivery_count,
+                })
+            })
+            .collect(),
+    );
+    optional(
+        "samples",
+        res.samples
+            .iter()
+            .map(|s| json!({ "timestamp": s.timestamp, "value": float_to_json(s.value) }))
+            .collect(),
+    );
+    obj.into()
+}
+
+fn pair_to_json(pair: &Kvpair) -> serde_json::Value {
+    json!({
+        "key": bytes_to_json(&pair.key),
+        "value": pair.value.as_ref().map(value_to_json),
+    })
+}
+
+// key 是 utf8 时编码成字符串，否则和二进制的 Value 一样编码成 {"binary": "<hex>"}
+fn bytes_to_json(data: &[u8]) -> serde_json::Value {
+    match std::str::from_utf8(data) {
+        Ok(s) => s.into(),
+        Err(_) => json!({ "binary": hex::encode(data) }),
+    }
+}
+
+// JSON 里没有 NaN 和无穷大，这时编码成字符串
+fn float_to_json(f: f64) -> serde_json::Value {
+    match Number::from_f64(f) {
+        Some(n) => n.into(),
+        None => f.to_string().into(),
+    }
+}
+
+fn value_to_json(v: &Value) -> serde_json::Value {
+    match &v.value {
+        None => serde_json::Value::Null,
+        Some(value::Value::String(s)) => json!({ "string": s }),
+        Some(value::Value::Binary(b)) => json!({ "binary": hex::encode(b) }),
+        Some(value::Value::Integer(i)) => json!({ "integer": i }),
+        Some(value::Value::Float(f)) => json!({ "float": float_to_json(*f) }),
+        Some(value::Value::Bool(b)) => json!({ "bool": b }),
+        Some(value::Value::List(list)) => {
+            let values: Vec<_> = list.values.iter().map(value_to_json).collect();
+            json!({ "list": values })
+        }
+        Some(value::Value::Map(map)) => {
+            let fields: Map<_, _> = map
+                .fields
+                .iter()
+                .map(|(k, v)| (k.clone(), value_to_json(v)))
+                .collect();
+            json!({ "map": fields })
+        }
+    }
+}
+
+fn value_from_json(json: serde_json::Value) -> Result<Value, KvError> {
+    let invalid = |json: &serde_json::Value| {
+        KvError::InvalidCommand(format!("Cannot convert JSON {} to Value", json))
+    };
+    let obj = match json {
+        serde_json::Value::Null => return Ok(Value::default()),
+        serde_json::Value::Object(obj) if obj.len() == 1 => obj,
+        json => return Err(invalid(&json)),
+    };
+    let (kind, json) = obj.into_iter().next().unwrap();
+
+    let value = match (kind.as_str(), &json) {
+        ("string", serde_json::Value::String(s)) => s.as_str().into(),
+        ("binary", serde_json::Value::String(s)) => {
+            let data = hex::decode(s).map_err(|_| invalid(&json))?;
+            Bytes::from(data).into()
+        }
+        ("integer", serde_json::Value::Number(n)) => {
+            n.as_i64().ok_or_else(|| invalid(&json))?.into()
+        }
+        ("float", serde_json::Value::Number(n)) => n.as_f64().ok_or_else(|| invalid(&json))?.into(),
+        ("float", serde_json::Value::String(s)) => {
+            s.parse::<f64>().map_err(|_| invalid(&json))?.into()
+        }
+        ("bool", serde_json::Value::Bool(b)) => (*b).into(),
+        ("list", serde_json::Value::Array(values)) => values
+            .iter()
+            .cloned()
+            .map(value_from_json)
+            .collect::<Result<Vec<_>, _>>()?
+            .into(),
+        ("map", serde_json::Value::Object(fields)) => ValueMap {
+            fields: fields
+                .iter()
+                .map(|(k, v)| Ok((k.clone(), value_from_json(v.clone())?)))
+                .collect::<Result<_, KvError>>()?,
+        }
+        .into(),
+        _ => return Err(invalid(&json)),
+    };
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use anyhow::Result;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+
+    use super::*;
+    use crate::{MemTable, ServiceInner, ValueList};
+
+    #[test]
+    fn value_json_should_roundtrip() {
+        let values: Vec<Value> = vec![
+            Value::default(),
+            "hello".into(),
+            Bytes::from_static(b"\xff\x00").into(),
+            42.into(),
+            1.5.into(),
+            true.into(),
+            ValueList {
+                values: vec![1.into(), "a".into()],
+            }
+            .into(),
+            ValueMap {
+                fields: [("k".to_string(), 2.into())].into_iter().collect(),
+            }
+            .into(),
+        ];
+        for v in values {
+            assert_eq!(value_from_json(value_to_json(&v)).unwrap(), v);
+        }
+
+        assert_eq!(value_to_json(&"hello".into()), json!({ "string": "hello" }));
+        assert!(value_from_json(json!("hello")).is_err());
+        assert!(value_from_json(json!({ "integer": "1" })).is_err());
+        assert!(value_from_json(json!({ "string": "a", "integer": 1 })).is_err());
+    }
+
+    #[test]
+    fn percent_decode_should_work() {
+        assert_eq!(
+            percent_decode("a%2Fb%ff").unwrap(),
+            Bytes::from_static(b"a/b\xff")
+        );
+        assert!(percent_decode("a%2").is_none());
+        assert!(percent_decode("a%zz").is_none());
+    }
+
+    #[tokio::test]
+    async fn http_gateway_should_work() -> Result<()> {
+        let addr = start_server().await?;
+
+        let (status, body) =
+            request(addr, "PUT", "/tables/t1/keys/k1", r#"{"string":"v1"}"#).await?;
+        assert_eq!(status, 200);
+        assert_eq!(body["values"], json!([null]));
+
+        // key 里的 / 需要编码
+        let body = r#"{"integer":10}"#;
+        let (status, _) = request(addr, "PUT", "/tables/t1/keys/a%2Fb", body).await?;
+        assert_eq!(status, 200);
+
+        let (status, body) = request(addr, "GET", "/tables/t1/keys/k1", "").await?;
+        assert_eq!(status, 200);
+        assert_eq!(body["values"], json!([{ "string": "v1" }]));
+
+        let (status, body) = request(addr, "GET", "/tables/t1", "").await?;
+        assert_eq!(status, 200);
+        let mut pairs = body["pairs"].as_array().unwrap().clone();
+        pairs.sort_by_key(|p| p["key"].to_string());
+        assert_eq!(
+            pairs,
+            vec![
+                json!({ "key": "a/b", "value": { "integer": 10 } }),
+                json!({ "key": "k1", "value": { "string": "v1" } }),
+            ]
+        );
+
+        let (status, _) = request(addr, "DELETE", "/tables/t1/keys/k1", "").await?;
+        assert_eq!(status, 200);
+
+        // status 直接来自 CommandResponse
+        let (status, body) = request(addr, "GET", "/tables/t1/keys/k1", "").await?;
+        assert_eq!(status, 404);
+        assert_eq!(body["status"], 404);
+        assert_eq!(body["message"], "Not found for table: t1, key: k1");
+
+        let (status, _) = request(addr, "PUT", "/tables/t1/keys/k1", "not json").await?;
+        assert_eq!(status, 400);
+        let (status, _) = request(addr, "POST", "/tables/t1", "").await?;
+        assert_eq!(status, 405);
+        let (status, _) = request(addr, "GET", "/nothing", "").await?;
+        assert_eq!(status, 404);
+
+        Ok(())
+    }
+
+    #[test]
+    fn wrong_type_error_should_be_client_error() {
+        // 对普通 value 执行 Pfadd 是客户端的错误，不是服务器的
+        let service: Service = ServiceInner::new(MemTable::new()).into();
+        service.execute(CommandRequest::new_hset("t1", "k1", "v1".into()));
+        let res = service.execute(CommandRequest::new_pfadd("t1", "k1", vec![1.into()]));
+        assert_eq!(http_status(&res), StatusCode::BAD_REQUEST);
+
+        let res = KvError::PermissionDenied("t1".into()).into();
+        assert_eq!(http_status(&res), StatusCode::FORBIDDEN);
+    }
+
+    // 发送一个 HTTP/1.1 请求，返回状态码和 JSON body
+    async fn request(
+        addr: SocketAddr,
+        method: &str,
+        path: &str,
+        body: &str,
+    ) -> Result<(u16, serde_json::Value)> {
+        let mut stream = TcpStream::connect(addr).await?;
+        let req = format!(
+            "{} {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{}",
+            method,
+            path,
+            body.len(),
+            body
+        );
+        stream.write_all(req.as_bytes()).await?;
+        let mut res = String::new();
+        stream.read_to_string(&mut res).await?;
+
+        let status = res[9..12].parse()?;
+        let (_, body) = res.split_once("\r\n\r\n").unwrap();
+        Ok((status, serde_json::from_str(body)?))
+    }
+
+    async fn start_server() -> Result<SocketAddr> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+
+        tokio::spawn(async move {
+            let service: Service = ServiceInner::new(MemTable::new()).into();
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let server = HttpServerStream::new(stream, service.clone());
+                tokio::spawn(server.process());
+            }
+        });
+
+        Ok(addr)
+    }
+}
@@ -1,25 +1,38 @@
 mod frame;
+mod grpc;
+mod http;
+mod resp;
+mod stream;
 mod tls;
+#[cfg(unix)]
+mod unix;
+mod ws;
+pub use grpc::GrpcService;
+pub use http::HttpServerStream;
+pub use resp::{RespFrame, RespParser, RespServerStream};
+pub use stream::ProstStream;
 pub use tls::{TlsClientConnector, TlsServerAcceptor};
+#[cfg(unix)]
+pub use unix::{parse_unix_addr, PeerCred, PeerCredPolicy};
+pub use ws::WsServerStream;
 
-use bytes::BytesMut;
-pub use frame::FrameCoder;
-use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+pub use frame::{read_frame_with, FrameCoder, FrameCompression, FrameLimits, PROTOCOL_VERSION};
+pub(crate) use frame::{COMPRESSION_LIMIT, MAX_FRAME, MIN_FRAME};
+use futures::{SinkExt, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite};
 use tracing::info;
 
-use crate::{CommandRequest, CommandResponse, KvError, Service};
-
-use self::frame::read_frame;
+use crate::{CommandRequest, CommandResponse, HelloReply, KvError, Service};
 
 /// 处理服务器端的某个 accept 下来的 socket 的读写
 pub struct ProstServerStream<S> {
-    inner: S,
+    inner: ProstStream<S, CommandRequest, CommandResponse>,
     service: Service,
 }
 
 /// 处理客户端 socket 的读写
 pub struct ProstClientStream<S> {
-    inner: S,
+    inner: ProstStream<S, CommandResponse, CommandRequest>,
 }
 
 impl<S> ProstServerStream<S>
@@ -28,33 +41,82 @@ where
 {
     pub fn new(stream: S, service: Service) -> Self {
         Self {
-            inner: stream,
+            inner: ProstStream::new(stream),
             service,
         }
     }
 
+    /// 设置接收 frame 时的限制
+    pub fn with_limits(mut self, limits: FrameLimits) -> Self {
+        self.inner = self.inner.with_limits(limits);
+        self
+    }
+
     pub async fn process(mut self) -> Result<(), KvError> {
-        while let Ok(cmd) = self.recv().await {
+        // 执行阻塞的命令时提前读到的下一个请求
+        let mut pending = None;
+        loop {
+            let next = match pending.take() {
+                Some(next) => next,
+                None => self.inner.next().await,
+            };
+            let cmd = match next {
+                Some(Ok(cmd)) => cmd,
+                // frame 太大时没有读取它的内容，无法继续解析后面的数据，回复错误后关闭连接
+                Some(Err(e @ KvError::FrameTooLarge(..))) => {
+                    info!("Reject a frame: {:?}", e);
+                    self.send(e.into()).await?;
+                    return Ok(());
+                }
+                _ => return Ok(()),
+            };
             info!("Got a new command: {:?}", cmd);
-            let res = self.service.execute(cmd);
+            let mut res = match self.execute(cmd, &mut pending).await {
+                Some(res) => res,
+                None => return Ok(()),
+            };
+            // 客户端发过来的 frame 也不能超过这个连接的限制
+            if let Some(hello) = &mut res.hello {
+                let max = self.inner.limits().max_frame_size as u64;
+                hello.max_frame_size = hello.max_frame_size.min(max);
+            }
+            // Hello 的回复还按原来的参数发送，之后的回复才使用协商的结果
+            let hello = res.hello.clone();
             self.send(res).await?;
+            if let Some(hello) = hello {
+                info!("Negotiated with client: {:?}", hello);
+                self.inner.set_compression(hello.frame_compression());
+                self.inner.set_max_send_size(hello.max_frame_size as _);
+            }
         }
-        Ok(())
     }
 
-    async fn send(&mut self, msg: CommandResponse) -> Result<(), KvError> {
-        let mut buf = BytesMut::new();
-        msg.encode_frame(&mut buf)?;
-        let encoded = buf.freeze();
-        self.inner.write_all(&encoded[..]).await?;
-        Ok(())
+    // Blpop 这样的命令可能一直阻塞，等待期间继续读 socket，客户端断开时返回 None，不再等待。
+    // 客户端提前发来的请求最多先存一个到 pending 里，等这个命令执行完再处理
+    async fn execute(
+        &mut self,
+        cmd: CommandRequest,
+        pending: &mut Option<Option<Result<CommandRequest, KvError>>>,
+    ) -> Option<CommandResponse> {
+        let execute = self.service.execute_async(cmd);
+        tokio::pin!(execute);
+        loop {
+            tokio::select! {
+                res = &mut execute => return Some(res),
+                next = self.inner.next(), if pending.is_none() => match next {
+                    Some(Ok(_)) | Some(Err(KvError::FrameTooLarge(..))) => *pending = Some(next),
+                    _ => return None,
+                },
+            }
+        }
     }
 
-    async fn recv(&mut self) -> Result<CommandRequest, KvError> {
-        let mut buf = BytesMut::new();
-        let stream = &mut self.inner;
-        read_frame(stream, &mut buf).await?;
-        CommandRequest::decode_frame(&mut buf)
+    async fn send(&mut self, msg: CommandResponse) -> Result<(), KvError> {
+        match self.inner.send(msg).await {
+            // 超过了客户端能接收的大小，改为回复错误
+            Err(e @ KvError::FrameTooLarge(..)) => self.inner.send(e.into()).await,
+            result => result,
+        }
     }
 }
 
@@ -63,27 +125,45 @@ where
     S: AsyncRead + AsyncWrite + Unpin + Send,
 {
     pub fn new(stream: S) -> Self {
-        Self { inner: stream }
+        Self {
+            inner: ProstStream::new(stream),
+        }
     }
 
-    pub async fn execute(&mut self, cmd: CommandRequest) -> Result<CommandResponse, KvError> {
-        self.send(cmd).await?;
-        self.recv().await
+    /// 设置接收 frame 时的限制
+    pub fn with_limits(mut self, limits: FrameLimits) -> Self {
+        self.inner = self.inner.with_limits(limits);
+        self
     }
 
-    async fn send(&mut self, msg: CommandRequest) -> Result<(), KvError> {
-        let mut buf = BytesMut::new();
-        msg.encode_frame(&mut buf)?;
-        let encoded = buf.freeze();
-        self.inner.write_all(&encoded[..]).await?;
-        Ok(())
+    pub async fn execute(&mut self, cmd: CommandRequest) -> Result<CommandResponse, KvError> {
+        self.inner.send(cmd).await?;
+        match self.inner.next().await {
+            Some(res) => res,
+            None => Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into()),
+        }
     }
 
-    async fn recv(&mut self) -> Result<CommandResponse, KvError> {
-        let mut buf = BytesMut::new();
-        let stream = &mut self.inner;
-        read_frame(stream, &mut buf).await?;
-        CommandResponse::decode_frame(&mut buf)
+    /// 和服务器握手，返回协商的结果和服务器的信息，之后的请求使用协商的压缩算法
+    pub async fn hello(
+        &mut self,
+        compressions: Vec<String>,
+        compression_threshold: u64,
+        max_frame_size: u64,
+    ) -> Result<HelloReply, KvError> {
+        let cmd = CommandRequest::new_hello(compressions, compression_threshold, max_frame_size);
+        let res = self.execute(cmd).await?;
+        match res.hello {
+            Some(hello) if res.status == 200 => {
+                self.inner.set_compression(hello.frame_compression());
+                self.inner.set_max_send_size(hello.max_frame_size as _);
+                Ok(hello)
+            }
+            _ => Err(KvError::Internal(format!(
+                "Handshake failed: {}",
+                res.message
+            ))),
+        }
     }
 }
 
@@ -91,9 +171,9 @@ where
 mod tests {
     use std::net::SocketAddr;
 
-    use crate::{assert_res_ok, MemTable, ServiceInner, Value};
+    use crate::{assert_res_ok, Kvpair, MemTable, ServiceInner, Value};
     use anyhow::Result;
-    use bytes::Bytes;
+    use bytes::{Bytes, BytesMut};
     use tokio::net::{TcpListener, TcpStream};
 
     use super::*;
@@ -135,15 +215,222 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn client_server_blocking_pop_should_work() -> anyhow::Result<()> {
+        let addr = start_server().await?;
+
+        let stream = TcpStream::connect(addr).await?;
+        let mut client = ProstClientStream::new(stream);
+
+        // 列表一直为空，超时后返回 404
+        let cmd = CommandRequest::new_blpop("t1", "q", 50);
+        let res = client.execute(cmd).await?;
+        assert_eq!(res.status, 404);
+
+        // 另一个连接在等待期间写入数据，Blpop 会被唤醒
+        let pusher = tokio::spawn(async move {
+            let stream = TcpStream::connect(addr).await.unwrap();
+            let mut client = ProstClientStream::new(stream);
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            let cmd = CommandRequest::new_rpush("t1", "q", vec!["job".into()]);
+            client.execute(cmd).await.unwrap()
+        });
+        let cmd = CommandRequest::new_blpop("t1", "q", 0);
+        let res = client.execute(cmd).await?;
+        assert_res_ok(res, &["job".into()], &[]);
+        assert_res_ok(pusher.await?, &[1.into()], &[]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn client_server_blocking_xread_should_work() -> anyhow::Result<()> {
+        let addr = start_server().await?;
+
+        let stream = TcpStream::connect(addr).await?;
+        let mut client = ProstClientStream::new(stream);
+
+        // 没有新的 entry，超时后返回空的结果
+        let cmd = CommandRequest::new_xread("t1", "s", "$", 0, Some(50));
+        let res = client.execute(cmd).await?;
+        assert_eq!(res.status, 200);
+        assert!(res.entries.is_empty());
+
+        // 另一个连接在等待期间追加 entry，Xread 会被唤醒
+        let producer = tokio::spawn(async move {
+            let stream = TcpStream::connect(addr).await.unwrap();
+            let mut client = ProstClientStream::new(stream);
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            let fields = vec![Kvpair::new("n", 1.into())];
+            let cmd = CommandRequest::new_xadd("t1", "s", None, fields);
+            client.execute(cmd).await.unwrap()
+        });
+        let cmd = CommandRequest::new_xread("t1", "s", "$", 0, Some(0));
+        let res = client.execute(cmd).await?;
+        assert_eq!(res.entries.len(), 1);
+        assert_eq!(producer.await?.values[0], res.entries[0].id.as_str().into());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn client_server_hello_should_work() -> anyhow::Result<()> {
+        let addr = start_server().await?;
+
+        let stream = TcpStream::connect(addr).await?;
+        let mut client = ProstClientStream::new(stream);
+
+        // 不接受压缩：大的 value 也不压缩
+        let hello = client.hello(vec![], 0, 0).await?;
+        assert_eq!(hello.compression, "");
+        let v: Value = Bytes::from(vec![0u8; 16384]).into();
+        let cmd = CommandRequest::new_hset("t1", "k1", v.clone());
+        client.execute(cmd).await?;
+        client
+            .inner
+            .send(CommandRequest::new_hget("t1", "k1"))
+            .await?;
+        let mut buf = BytesMut::new();
+        read_frame_with(client.inner.get_mut(), &mut buf, &FrameLimits::default()).await?;
+        assert_eq!(buf[0] >> 6, 0);
+        let res = CommandResponse::decode_frame(&mut buf)?;
+        assert_res_ok(res, &[v], &[]);
+
+        // 超过协商的 frame 大小的回复改为返回错误
+        let v: Value = "x".repeat(MIN_FRAME * 2).into();
+        let cmd = CommandRequest::new_hset("t1", "k2", v.clone());
+        client.execute(cmd).await?;
+        let hello = client.hello(vec![], 0, MIN_FRAME as u64).await?;
+        assert_eq!(hello.max_frame_size, MIN_FRAME as u64);
+        let res = client.execute(CommandRequest::new_hget("t1", "k2")).await?;
+        assert_eq!(res.status, 413);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn client_server_negotiated_compression_should_work() -> anyhow::Result<()> {
+        let addr = start_server().await?;
+
+        let stream = TcpStream::connect(addr).await?;
+        let mut client = ProstClientStream::new(stream);
+
+        let compressions = vec!["lz4".into(), "gzip".into()];
+        let hello = client.hello(compressions, 512, 0).await?;
+        assert_eq!(hello.compression, "lz4");
+        assert_eq!(hello.compression_threshold, 512);
+
+        // 请求和回复都用 lz4 压缩
+        let v: Value = Bytes::from(vec![0u8; 1024]).into();
+        let cmd = CommandRequest::new_hset("t1", "k1", v.clone());
+        client.execute(cmd).await?;
+        client
+            .inner
+            .send(CommandRequest::new_hget("t1", "k1"))
+            .await?;
+        let mut buf = BytesMut::new();
+        read_frame_with(client.inner.get_mut(), &mut buf, &FrameLimits::default()).await?;
+        assert_eq!(buf[0] >> 6, 0b11);
+        let res = CommandResponse::decode_frame(&mut buf)?;
+        assert_res_ok(res, &[v], &[]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn client_server_oversized_frame_should_be_rejected() -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move {
+            let service: Service = ServiceInner::new(MemTable::new()).into();
+            let limits = FrameLimits {
+                max_frame_size: 1024,
+                ..Default::default()
+            };
+            let (stream, _) = listener.accept().await.unwrap();
+            let server = ProstServerStream::new(stream, service).with_limits(limits);
+            server.process().await.unwrap();
+        });
+
+        let stream = TcpStream::connect(addr).await?;
+        let mut client = ProstClientStream::new(stream);
+
+        // 握手的结果里 max_frame_size 不超过服务器的限制
+        let hello = client.hello(vec![], 0, 0).await?;
+        assert_eq!(hello.max_frame_size, 1024);
+
+        // 超过限制的 frame 回复 413，然后连接被关闭
+        // 客户端自己也会检查协商的大小，这里去掉这个检查，模拟不遵守协商结果的客户端
+        client.inner.set_max_send_size(MAX_FRAME);
+        let v: Value = "x".repeat(2048).into();
+        let res = client
+            .execute(CommandRequest::new_hset("t1", "k1", v))
+            .await?;
+        assert_eq!(res.status, 413);
+        assert!(client
+            .execute(CommandRequest::new_hget("t1", "k1"))
+            .await
+            .is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn blocked_command_should_stop_when_client_disconnects() -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let server = tokio::spawn(async move {
+            let service: Service = ServiceInner::new(MemTable::new()).into();
+            let (stream, _) = listener.accept().await.unwrap();
+            ProstServerStream::new(stream, service).process().await
+        });
+
+        let stream = TcpStream::connect(addr).await?;
+        let mut client = ProstClientStream::new(stream);
+        client
+            .inner
+            .send(CommandRequest::new_blpop("t1", "q", 0))
+            .await?;
+        drop(client);
+
+        // 没有数据的 Blpop 会一直阻塞，客户端断开之后连接的任务也要结束
+        let res = tokio::time::timeout(std::time::Duration::from_secs(1), server).await;
+        assert!(matches!(res, Ok(Ok(Ok(())))));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pipelined_command_should_wait_for_blocked_command() -> anyhow::Result<()> {
+        let addr = start_server().await?;
+
+        let stream = TcpStream::connect(addr).await?;
+        let mut client = ProstClientStream::new(stream);
+        // 阻塞期间发来的请求在 Blpop 返回之后按顺序处理
+        client
+            .inner
+            .send(CommandRequest::new_blpop("t1", "q", 100))
+            .await?;
+        let cmd = CommandRequest::new_rpush("t1", "q", vec!["job".into()]);
+        client.inner.send(cmd).await?;
+
+        let res = client.inner.next().await.unwrap()?;
+        assert_eq!(res.status, 404);
+        let res = client.inner.next().await.unwrap()?;
+        assert_res_ok(res, &[1.into()], &[]);
+
+        Ok(())
+    }
+
     async fn start_server() -> Result<SocketAddr> {
         let listner = TcpListener::bind("127.0.0.1:0").await.unwrap();
         let addr = listner.local_addr().unwrap();
 
         tokio::spawn(async move {
+            let service: Service = ServiceInner::new(MemTable::new()).into();
             loop {
                 let (stream, _) = listner.accept().await.unwrap();
-                let service: Service = ServiceInner::new(MemTable::new()).into();
-                let server = ProstServerStream::new(stream, service);
+                let server = ProstServerStream::new(stream, service.clone());
                 tokio::spawn(server.process());
             }
         });
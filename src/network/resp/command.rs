@@ -0,0 +1,361 @@
+use bytes::Bytes;
+
+use super::RespFrame;
+use crate::{
+    command_request::RequestData, value, CommandRequest, CommandResponse, Hexist, Hget, Hgetall,
+    Hkeys, Hmdel, Hmget, Hmset, Hrandkey, KvError, Kvpair, Value,
+};
+
+// 一条 Redis 命令翻译之后的结果
+#[derive(Debug, PartialEq)]
+pub(crate) enum RespCommand {
+    // 交给 Service 执行，Reply 决定怎样把 CommandResponse 转换成 RESP 的回复
+    Execute(CommandRequest, Reply),
+    // 不需要访问存储的命令，直接回复
+    Reply(RespFrame),
+    // HELLO，切换协议版本，没有版本号时保持当前的版本
+    Hello(Option<i64>),
+    // 回复 OK 之后关闭连接
+    Quit,
+}
+
+// 同一个 CommandRequest 在不同的 Redis 命令里回复的格式不同，比如 HKEYS 和 HLEN
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Reply {
+    // 第一个 value，不存在时回复 null（HGET、不带 count 的 HRANDFIELD）
+    Single,
+    // 所有 value 组成的数组（HMGET、HKEYS）
+    Values,
+    // 第一个 value 转换成整数（HEXISTS）
+    Integer,
+    // value 的个数（HLEN）
+    Len,
+    // 之前不存在的 field 的个数（HSET）
+    Added,
+    // 之前存在的 field 的个数（HDEL）
+    Removed,
+    // 所有的 field 和 value，RESP3 里是 map（HGETALL）
+    Pairs,
+    // 所有 field 的 value（HVALS）
+    PairValues,
+    // 回复 OK（HMSET）
+    Ok,
+}
+
+/// 把 Redis 的命令翻译成 CommandRequest。Redis 的 hash 对应一个 table，field 对应 table 里的 key
+pub(crate) fn parse_command(frame: RespFrame) -> Result<RespCommand, KvError> {
+    let args = match frame {
+        RespFrame::Array(items) if !items.is_empty() => items
+            .into_iter()
+            .map(|item| match item {
+                RespFrame::Bulk(data) => Ok(data),
+                RespFrame::Simple(s) => Ok(s.into()),
+                _ => Err(KvError::RespError("expected bulk strings".into())),
+            })
+            .collect::<Result<Vec<_>, _>>()?,
+        _ => {
+            return Err(KvError::RespError(
+                "expected an array of bulk strings".into(),
+            ))
+        }
+    };
+
+    let name = String::from_utf8_lossy(&args[0]).to_ascii_lowercase();
+    let args = &args[1..];
+    let arity = |ok: bool| {
+        if ok {
+            Ok(())
+        } else {
+            Err(KvError::InvalidCommand(format!(
+                "wrong number of arguments for '{}' command",
+                name
+            )))
+        }
+    };
+
+    let (data, reply) = match name.as_str() {
+        "ping" => {
+            arity(args.len() <= 1)?;
+            return Ok(RespCommand::Reply(match args.first() {
+                Some(msg) => RespFrame::Bulk(msg.clone()),
+                None => RespFrame::Simple("PONG".into()),
+            }));
+        }
+        "echo" => {
+            arity(args.len() == 1)?;
+            return Ok(RespCommand::Reply(RespFrame::Bulk(args[0].clone())));
+        }
+        // AUTH、SETNAME 等选项目前都忽略
+        "hello" => {
+            let version = args.first().map(parse_int).transpose()?;
+            return Ok(RespCommand::Hello(version));
+        }
+        "select" => {
+            arity(args.len() == 1)?;
+            return Ok(RespCommand::Reply(match parse_int(&args[0])? {
+                0 => RespFrame::ok(),
+                _ => RespFrame::Error("ERR DB index is out of range".into()),
+            }));
+        }
+        // redis-cli 连接时会发送 COMMAND DOCS、CLIENT SETINFO 之类的命令，简单地回复就可以
+        "command" => return Ok(RespCommand::Reply(RespFrame::Array(vec![]))),
+        "client" => return Ok(RespCommand::Reply(RespFrame::ok())),
+        "quit" => return Ok(RespCommand::Quit),
+        "hget" => {
+            arity(args.len() == 2)?;
+            let table = table(&args[0])?;
+            let key = args[1].clone();
+            (RequestData::Hget(Hget { table, key }), Reply::Single)
+        }
+        "hset" | "hmset" => {
+            arity(args.len() >= 3 && args.len() % 2 == 1)?;
+            let pairs = args[1..]
+                .chunks(2)
+                .map(|kv| Kvpair::new(kv[0].clone(), to_value(&kv[1])))
+                .collect();
+            let reply = if name == "hset" {
+                Reply::Added
+            } else {
+                Reply::Ok
+            };
+            let table = table(&args[0])?;
+            (RequestData::Hmset(Hmset { table, pairs }), reply)
+        }
+        "hmget" | "hdel" | "hexists" => {
+            arity(if name == "hexists" {
+                args.len() == 2
+            } else {
+                args.len() >= 2
+            })?;
+            let table = table(&args[0])?;
+            let keys = args[1..].to_vec();
+            match name.as_str() {
+                "hmget" => (RequestData::Hmget(Hmget { table, keys }), Reply::Values),
+                "hdel" => (RequestData::Hmdel(Hmdel { table, keys }), Reply::Removed),
+                _ => {
+                    let key = keys.into_iter().next().unwrap_or_default();
+                    (RequestData::Hexist(Hexist { table, key }), Reply::Integer)
+                }
+            }
+        }
+        "hgetall" | "hvals" => {
+            arity(args.len() == 1)?;
+            let reply = if name == "hgetall" {
+                Reply::Pairs
+            } else {
+                Reply::PairValues
+            };
+            let table = table(&args[0])?;
+            (RequestData::Hgetall(Hgetall { table }), reply)
+        }
+        "hkeys" | "hlen" => {
+            arity(args.len() == 1)?;
+            let reply = if name == "hkeys" {
+                Reply::Values
+            } else {
+                Reply::Len
+            };
+            let table = table(&args[0])?;
+            let pattern = String::new();
+            (RequestData::Hkeys(Hkeys { table, pattern }), reply)
+        }
+        "hrandfield" => {
+            arity(args.len() == 1 || args.len() == 2)?;
+            let (count, reply) = match args.get(1) {
+                Some(count) => (parse_int(count)?, Reply::Values),
+                None => (1, Reply::Single),
+            };
+            let table = table(&args[0])?;
+            (RequestData::Hrandkey(Hrandkey { table, count }), reply)
+        }
+        _ => {
+            return Err(KvError::InvalidCommand(format!(
+                "unknown command '{}'",
+                name
+            )))
+        }
+    };
+
+    let cmd = CommandRequest {
+        request_data: Some(data),
+    };
+    Ok(RespCommand::Execute(cmd, reply))
+}
+
+/// 把 Service 执行的结果转换成 Redis 客户端期望的回复
+pub(crate) fn to_reply(res: CommandResponse, reply: Reply) -> RespFrame {
+    match res.status {
+        200 => {}
+        // key 不存在时 Redis 的命令都不会报错
+        404 => {
+            return match reply {
+                Reply::Single => RespFrame::Null,
+                Reply::Integer | Reply::Len | Reply::Added | Reply::Removed => {
+                    RespFrame::Integer(0)
+                }
+                Reply::Ok => RespFrame::ok(),
+                Reply::Values | Reply::Pairs | Reply::PairValues => RespFrame::Array(vec![]),
+            }
+        }
+        _ => return RespFrame::Error(format!("ERR {}", res.message)),
+    }
+
+    let count = |f: fn(&Value) -> bool| res.values.iter().filter(|v| f(v)).count() as i64;
+    match reply {
+        Reply::Single => res
+            .values
+            .into_iter()
+            .next()
+            .map(to_frame)
+            .unwrap_or(RespFrame::Null),
+        Reply::Values => RespFrame::Array(res.values.into_iter().map(to_frame).collect()),
+        Reply::Integer => RespFrame::Integer(match res.values.first() {
+            Some(Value {
+                value: Some(value::Value::Bool(b)),
+            }) => *b as i64,
+            Some(Value {
+                value: Some(value::Value::Integer(i)),
+            }) => *i,
+            _ => 0,
+        }),
+        Reply::Len => RespFrame::Integer(res.values.len() as i64),
+        Reply::Added => RespFrame::Integer(count(|v| v.value.is_none())),
+        Reply::Removed => RespFrame::Integer(count(|v| v.value.is_some())),
+        Reply::Pairs => RespFrame::Map(
+            res.pairs
+                .into_iter()
+                .map(|p| {
+                    (
+                        RespFrame::Bulk(p.key),
+                        to_frame(p.value.unwrap_or_default()),
+                    )
+                })
+                .collect(),
+        ),
+        Reply::PairValues => RespFrame::Array(
+            res.pairs
+                .into_iter()
+                .map(|p| to_frame(p.value.unwrap_or_default()))
+                .collect(),
+        ),
+        Reply::Ok => RespFrame::ok(),
+    }
+}
+
+// Redis 的 hash 里只有字符串，所以标量都回复成 bulk string，嵌套的列表和 map 回复成数组和 map
+fn to_frame(v: Value) -> RespFrame {
+    match v.value {
+        None => RespFrame::Null,
+        Some(value::Value::String(s)) => RespFrame::Bulk(s.into()),
+        Some(value::Value::Binary(b)) => RespFrame::Bulk(b),
+        Some(value::Value::Integer(i)) => RespFrame::Bulk(i.to_string().into()),
+        Some(value::Value::Float(f)) => RespFrame::Bulk(f.to_string().into()),
+        Some(value::Value::Bool(b)) => RespFrame::Bulk(if b { "1" } else { "0" }.into()),
+        Some(value::Value::List(list)) => {
+            RespFrame::Array(list.values.into_iter().map(to_frame).collect())
+        }
+        Some(value::Value::Map(map)) => RespFrame::Map(
+            map.fields
+                .into_iter()
+                .map(|(k, v)| (RespFrame::Bulk(k.into()), to_frame(v)))
+                .collect(),
+        ),
+    }
+}
+
+// Redis 客户端发来的都是字节串，是 utf8 时存成字符串，否则存成二进制
+fn to_value(data: &Bytes) -> Value {
+    match std::str::from_utf8(data) {
+        Ok(s) => s.into(),
+        Err(_) => data.clone().into(),
+    }
+}
+
+fn table(key: &Bytes) -> Result<String, KvError> {
+    String::from_utf8(key.to_vec())
+        .map_err(|_| KvError::InvalidCommand("hash key must be valid UTF-8".into()))
+}
+
+fn parse_int(data: &Bytes) -> Result<i64, KvError> {
+    std::str::from_utf8(data)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| KvError::InvalidCommand("value is not an integer or out of range".into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(args: &[&str]) -> Result<RespCommand, KvError> {
+        let args = args
+            .iter()
+            .map(|arg| RespFrame::Bulk(Bytes::copy_from_slice(arg.as_bytes())))
+            .collect();
+        parse_command(RespFrame::Array(args))
+    }
+
+    #[test]
+    fn parse_command_should_work() {
+        assert_eq!(
+            command(&["HGET", "t1", "k1"]).unwrap(),
+            RespCommand::Execute(CommandRequest::new_hget("t1", "k1"), Reply::Single)
+        );
+        assert_eq!(
+            command(&["hset", "t1", "k1", "v1", "k2", "v2"]).unwrap(),
+            RespCommand::Execute(
+                CommandRequest::new_hmset("t1", vec![("k1", "v1"), ("k2", "v2")]),
+                Reply::Added
+            )
+        );
+        assert_eq!(
+            command(&["HDEL", "t1", "k1", "k2"]).unwrap(),
+            RespCommand::Execute(
+                CommandRequest::new_hmdel("t1", vec!["k1".into(), "k2".into()]),
+                Reply::Removed
+            )
+        );
+        assert_eq!(
+            command(&["HRANDFIELD", "t1", "-3"]).unwrap(),
+            RespCommand::Execute(CommandRequest::new_hrandkey("t1", -3), Reply::Values)
+        );
+        assert_eq!(
+            command(&["PING"]).unwrap(),
+            RespCommand::Reply(RespFrame::Simple("PONG".into()))
+        );
+        assert_eq!(
+            command(&["HELLO", "3"]).unwrap(),
+            RespCommand::Hello(Some(3))
+        );
+
+        assert!(command(&["HSET", "t1", "k1"]).is_err());
+        assert!(command(&["HEXISTS", "t1", "k1", "k2"]).is_err());
+        assert!(command(&["HRANDFIELD", "t1", "x"]).is_err());
+        assert!(command(&["FLUSHALL"]).is_err());
+        assert!(parse_command(RespFrame::Integer(1)).is_err());
+    }
+
+    #[test]
+    fn to_reply_should_work() {
+        let res: CommandResponse = vec![Value::default(), "v".into()].into();
+        assert_eq!(to_reply(res.clone(), Reply::Added), RespFrame::Integer(1));
+        assert_eq!(to_reply(res.clone(), Reply::Removed), RespFrame::Integer(1));
+        assert_eq!(
+            to_reply(res, Reply::Values),
+            RespFrame::Array(vec![RespFrame::Null, RespFrame::Bulk("v".into())])
+        );
+
+        let res: CommandResponse = Value::from(true).into();
+        assert_eq!(to_reply(res, Reply::Integer), RespFrame::Integer(1));
+        let res: CommandResponse = Value::from(10).into();
+        assert_eq!(to_reply(res, Reply::Single), RespFrame::Bulk("10".into()));
+
+        let res: CommandResponse = KvError::not_found("t1", b"k1").into();
+        assert_eq!(to_reply(res, Reply::Single), RespFrame::Null);
+        let res: CommandResponse = KvError::Internal("oops".into()).into();
+        assert_eq!(
+            to_reply(res, Reply::Single),
+            RespFrame::Error("ERR Internal error: oops".into())
+        );
+    }
+}
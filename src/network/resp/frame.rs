@@ -0,0 +1,390 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::KvError;
+
+// 和 Redis 的 proto-max-bulk-len 一样，单个 bulk string 最大 512MB
+const MAX_BULK_LEN: i64 = 512 * 1024 * 1024;
+// 数组和 map 最多的元素个数，以及最深的嵌套层数
+const MAX_ARRAY_LEN: i64 = 1024 * 1024;
+const MAX_DEPTH: usize = 8;
+// 不是 bulk string 的一行（包括 inline 命令）最长 64KB，和 Redis 一样
+const MAX_LINE_LEN: usize = 64 * 1024;
+
+/// RESP2 / RESP3 的数据。编码时根据协议版本选择格式，RESP2 里没有的类型会转换成相近的类型
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RespFrame {
+    Simple(String),
+    // 错误信息以错误类型开头，比如 "ERR ..."、"NOPROTO ..."
+    Error(String),
+    Integer(i64),
+    Bulk(Bytes),
+    Array(Vec<RespFrame>),
+    // RESP2 里编码成 null bulk string
+    Null,
+    // RESP2 里编码成 key 和 value 交替出现的数组
+    Map(Vec<(RespFrame, RespFrame)>),
+}
+
+impl RespFrame {
+    pub fn ok() -> Self {
+        Self::Simple("OK".into())
+    }
+
+    pub fn encode(&self, buf: &mut BytesMut, resp3: bool) {
+        match self {
+            Self::Simple(s) => put_line(buf, b'+', &sanitize(s)),
+            Self::Error(s) => put_line(buf, b'-', &sanitize(s)),
+            Self::Integer(i) => put_line(buf, b':', &i.to_string()),
+            Self::Bulk(data) => {
+                put_line(buf, b'$', &data.len().to_string());
+                buf.put_slice(data);
+                buf.put_slice(b"\r\n");
+            }
+            Self::Array(items) => {
+                put_line(buf, b'*', &items.len().to_string());
+                for item in items {
+                    item.encode(buf, resp3);
+                }
+            }
+            Self::Null if resp3 => buf.put_slice(b"_\r\n"),
+            Self::Null => buf.put_slice(b"$-1\r\n"),
+            Self::Map(pairs) => {
+                if resp3 {
+                    put_line(buf, b'%', &pairs.len().to_string());
+                } else {
+                    put_line(buf, b'*', &(pairs.len() * 2).to_string());
+                }
+                for (k, v) in pairs {
+                    k.encode(buf, resp3);
+                    v.encode(buf, resp3);
+                }
+            }
+        }
+    }
+}
+
+/// 增量的 RESP 解析器，每个连接一个
+/// 解析过的数据会从 buf 里移走，还没有收齐的数组保存在栈里，数据不完整时下次从断开的地方继续，
+/// 不会从头重新解析。bulk string 直接从 buf 里切出来，不复制数据
+/// 不以类型前缀开头的一行当作 inline 命令（telnet 里直接输入的命令），按空白切分成参数
+/// 出错之后解析器的状态不再可用，调用者应该关闭连接
+#[derive(Debug, Default)]
+pub struct RespParser {
+    stack: Vec<PendingArray>,
+}
+
+// 还没有收齐元素的数组或者 map
+#[derive(Debug)]
+struct PendingArray {
+    map: bool,
+    remaining: usize,
+    items: Vec<RespFrame>,
+}
+
+// 解析一个 frame 的结果
+enum Step {
+    Frame(RespFrame),
+    // 开始了一个数组，接着解析它的元素
+    Nested,
+    Incomplete,
+}
+
+impl RespParser {
+    /// 从 buf 里解析出一个完整的 frame，数据还不完整时返回 None
+    pub fn parse(&mut self, buf: &mut BytesMut) -> Result<Option<RespFrame>, KvError> {
+        loop {
+            let step = match buf.first() {
+                None => Step::Incomplete,
+                Some(b'+' | b'-' | b':' | b'$' | b'*' | b'_' | b'%') => self.step(buf)?,
+                Some(_) if self.stack.is_empty() => parse_inline(buf)?,
+                Some(_) => return Err(protocol_error("unknown frame type")),
+            };
+            match step {
+                Step::Frame(frame) => {
+                    if let Some(frame) = self.complete(frame) {
+                        return Ok(Some(frame));
+                    }
+                }
+                Step::Nested => {}
+                Step::Incomplete => return Ok(None),
+            }
+        }
+    }
+
+    // 解析 buf 开头的一个 frame，完整时把它从 buf 里移走
+    fn step(&mut self, buf: &mut BytesMut) -> Result<Step, KvError> {
+        if self.stack.len() > MAX_DEPTH {
+            return Err(protocol_error("too many nested frames"));
+        }
+        let Some((line, used)) = read_line(buf)? else {
+            return Ok(Step::Incomplete);
+        };
+        let Some((&prefix, line)) = line.split_first() else {
+            return Err(protocol_error("empty line"));
+        };
+
+        let frame = match prefix {
+            b'+' => RespFrame::Simple(String::from_utf8_lossy(line).into()),
+            b'-' => RespFrame::Error(String::from_utf8_lossy(line).into()),
+            b':' => RespFrame::Integer(parse_int(line)?),
+            b'_' => RespFrame::Null,
+            b'$' => match parse_len(line, MAX_BULK_LEN)? {
+                None => RespFrame::Null,
+                Some(len) => {
+                    if buf.len() < used + len + 2 {
+                        return Ok(Step::Incomplete);
+                    }
+                    if &buf[used + len..used + len + 2] != b"\r\n" {
+                        return Err(protocol_error("bulk string is not terminated by CRLF"));
+                    }
+                    buf.advance(used);
+                    let data = buf.split_to(len).freeze();
+                    buf.advance(2);
+                    return Ok(Step::Frame(RespFrame::Bulk(data)));
+                }
+            },
+            b'*' | b'%' => match parse_len(line, MAX_ARRAY_LEN)? {
+                None => RespFrame::Null,
+                Some(len) => {
+                    // map 的每个元素由 key 和 value 两个 frame 组成
+                    let map = prefix == b'%';
+                    let remaining = if map { len * 2 } else { len };
+                    buf.advance(used);
+                    let pending = PendingArray {
+                        map,
+                        remaining,
+                        items: Vec::with_capacity(remaining.min(1024)),
+                    };
+                    if remaining == 0 {
+                        return Ok(Step::Frame(pending.into_frame()));
+                    }
+                    self.stack.push(pending);
+                    return Ok(Step::Nested);
+                }
+            },
+            _ => return Err(protocol_error("unknown frame type")),
+        };
+        buf.advance(used);
+        Ok(Step::Frame(frame))
+    }
+
+    // 把解析出的 frame 放进外层的数组，返回收齐之后最外层的 frame
+    fn complete(&mut self, mut frame: RespFrame) -> Option<RespFrame> {
+        while let Some(top) = self.stack.last_mut() {
+            top.items.push(frame);
+            top.remaining -= 1;
+            if top.remaining > 0 {
+                return None;
+            }
+            frame = self.stack.pop()?.into_frame();
+        }
+        Some(frame)
+    }
+}
+
+impl PendingArray {
+    fn into_frame(self) -> RespFrame {
+        if self.map {
+            let mut items = self.items.into_iter();
+            let pairs = std::iter::from_fn(|| Some((items.next()?, items.next()?)));
+            RespFrame::Map(pairs.collect())
+        } else {
+            RespFrame::Array(self.items)
+        }
+    }
+}
+
+fn parse_inline(buf: &mut BytesMut) -> Result<Step, KvError> {
+    let Some((line, used)) = read_line(buf)? else {
+        return Ok(Step::Incomplete);
+    };
+    let args = line
+        .split(|c| c.is_ascii_whitespace())
+        .filter(|arg| !arg.is_empty())
+        .map(|arg| RespFrame::Bulk(Bytes::copy_from_slice(arg)))
+        .collect();
+    buf.advance(used);
+    Ok(Step::Frame(RespFrame::Array(args)))
+}
+
+fn put_line(buf: &mut BytesMut, prefix: u8, s: &str) {
+    buf.put_u8(prefix);
+    buf.put_slice(s.as_bytes());
+    buf.put_slice(b"\r\n");
+}
+
+// simple string 和 error 里不能出现换行
+fn sanitize(s: &str) -> String {
+    s.replace(['\r', '\n'], " ")
+}
+
+// 读取到 CRLF 为止的一行，返回这一行（不含 CRLF）和包括 CRLF 的长度
+fn read_line(buf: &[u8]) -> Result<Option<(&[u8], usize)>, KvError> {
+    match buf.windows(2).position(|w| w == b"\r\n") {
+        Some(end) if end <= MAX_LINE_LEN => Ok(Some((&buf[..end], end + 2))),
+        None if buf.len() <= MAX_LINE_LEN => Ok(None),
+        _ => Err(protocol_error("line is too long")),
+    }
+}
+
+fn parse_int(line: &[u8]) -> Result<i64, KvError> {
+    std::str::from_utf8(line)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| protocol_error("invalid integer"))
+}
+
+// bulk string 和数组的长度，-1 表示 null
+fn parse_len(line: &[u8], max: i64) -> Result<Option<usize>, KvError> {
+    match parse_int(line)? {
+        -1 => Ok(None),
+        len if (0..=max).contains(&len) => Ok(Some(len as usize)),
+        _ => Err(protocol_error("invalid length")),
+    }
+}
+
+fn protocol_error(msg: &str) -> KvError {
+    KvError::RespError(msg.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(frame: &RespFrame, resp3: bool) -> BytesMut {
+        let mut buf = BytesMut::new();
+        frame.encode(&mut buf, resp3);
+        buf
+    }
+
+    #[test]
+    fn resp_frame_encode_should_work() {
+        let frame = RespFrame::Array(vec![
+            RespFrame::ok(),
+            RespFrame::Integer(-1),
+            RespFrame::Bulk("hello".into()),
+            RespFrame::Null,
+        ]);
+        assert_eq!(
+            &encode(&frame, false)[..],
+            b"*4\r\n+OK\r\n:-1\r\n$5\r\nhello\r\n$-1\r\n"
+        );
+        assert_eq!(
+            &encode(&frame, true)[..],
+            b"*4\r\n+OK\r\n:-1\r\n$5\r\nhello\r\n_\r\n"
+        );
+
+        // RESP2 没有 map，编码成 key 和 value 交替出现的数组
+        let frame = RespFrame::Map(vec![(RespFrame::Bulk("k".into()), RespFrame::Integer(1))]);
+        assert_eq!(&encode(&frame, false)[..], b"*2\r\n$1\r\nk\r\n:1\r\n");
+        assert_eq!(&encode(&frame, true)[..], b"%1\r\n$1\r\nk\r\n:1\r\n");
+
+        let frame = RespFrame::Error("ERR bad\r\nthing".into());
+        assert_eq!(&encode(&frame, false)[..], b"-ERR bad  thing\r\n");
+    }
+
+    #[test]
+    fn resp_frame_parse_should_work() {
+        let frames = [
+            RespFrame::Array(vec![
+                RespFrame::Bulk("HSET".into()),
+                RespFrame::Bulk(Bytes::from_static(b"\r\n\xff")),
+                RespFrame::Null,
+            ]),
+            RespFrame::Map(vec![(
+                RespFrame::Simple("proto".into()),
+                RespFrame::Integer(3),
+            )]),
+            RespFrame::Error("ERR oops".into()),
+        ];
+        let mut buf = BytesMut::new();
+        for frame in &frames {
+            frame.encode(&mut buf, true);
+        }
+        let mut parser = RespParser::default();
+        for frame in frames {
+            assert_eq!(parser.parse(&mut buf).unwrap(), Some(frame));
+        }
+        assert!(buf.is_empty());
+        assert_eq!(parser.parse(&mut buf).unwrap(), None);
+    }
+
+    #[test]
+    fn resp_frame_parse_partial_data_should_wait() {
+        let data = b"*2\r\n$4\r\nHGET\r\n$3\r\nkey\r\n";
+        // 每次只多收到一个字节，直到最后一个字节才得到完整的 frame
+        let mut parser = RespParser::default();
+        let mut buf = BytesMut::new();
+        for b in &data[..data.len() - 1] {
+            buf.put_u8(*b);
+            assert_eq!(parser.parse(&mut buf).unwrap(), None);
+        }
+        buf.put_u8(data[data.len() - 1]);
+        let frame = parser.parse(&mut buf).unwrap();
+        let expected = RespFrame::Array(vec![
+            RespFrame::Bulk("HGET".into()),
+            RespFrame::Bulk("key".into()),
+        ]);
+        assert_eq!(frame, Some(expected));
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn resp_frame_parse_should_not_rescan_pipelined_array() {
+        let items = 10000;
+        let frame = RespFrame::Array(vec![RespFrame::Bulk("value".into()); items]);
+        let mut data = BytesMut::new();
+        frame.encode(&mut data, false);
+
+        // 分成小块收到时，解析过的元素会从 buf 里移走，buf 里只剩下不完整的一小段
+        let mut parser = RespParser::default();
+        let mut buf = BytesMut::new();
+        let mut result = None;
+        for chunk in data.chunks(100) {
+            buf.extend_from_slice(chunk);
+            result = parser.parse(&mut buf).unwrap();
+            assert!(buf.len() < 16);
+        }
+        assert_eq!(result, Some(frame));
+    }
+
+    #[test]
+    fn resp_frame_parse_inline_command_should_work() {
+        let mut buf = BytesMut::from("HGET  t1 k1\r\n\r\n");
+        let mut parser = RespParser::default();
+        assert_eq!(
+            parser.parse(&mut buf).unwrap(),
+            Some(RespFrame::Array(vec![
+                RespFrame::Bulk("HGET".into()),
+                RespFrame::Bulk("t1".into()),
+                RespFrame::Bulk("k1".into()),
+            ]))
+        );
+        // 空行解析成空的数组
+        assert_eq!(
+            parser.parse(&mut buf).unwrap(),
+            Some(RespFrame::Array(vec![]))
+        );
+    }
+
+    #[test]
+    fn resp_frame_parse_invalid_data_should_fail() {
+        let cases: [&[u8]; 7] = [
+            b"*1\r\n!oops\r\n",
+            b"*1\r\n\r\n",
+            b"$3\r\nabcd\r\n",
+            b"*-2\r\n",
+            b":abc\r\n",
+            b"$1073741824\r\n",
+            b"*1\r\n*1\r\n*1\r\n*1\r\n*1\r\n*1\r\n*1\r\n*1\r\n*1\r\n*1\r\n",
+        ];
+        for data in cases {
+            let mut parser = RespParser::default();
+            assert!(parser.parse(&mut BytesMut::from(data)).is_err());
+        }
+
+        // 一直没有换行的数据不能无限制地缓存
+        let mut buf = BytesMut::from(&vec![b'a'; MAX_LINE_LEN + 1][..]);
+        assert!(RespParser::default().parse(&mut buf).is_err());
+    }
+}
@@ -0,0 +1,269 @@
+mod command;
+mod frame;
+
+pub use frame::{RespFrame, RespParser};
+
+use bytes::BytesMut;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tracing::info;
+
+use crate::{KvError, Service};
+
+use self::command::{parse_command, to_reply, RespCommand};
+
+/// 处理说 RESP 协议的 socket，让 redis-cli 等 Redis 客户端可以直接访问 kvs
+pub struct RespServerStream<S> {
+    inner: S,
+    service: Service,
+    // 读缓存，里面可能有多个 pipeline 过来的命令
+    buf: BytesMut,
+    // 记住 buf 里不完整的命令已经解析到了哪里
+    parser: RespParser,
+    // 客户端用 HELLO 3 切换到 RESP3 之后为 true
+    resp3: bool,
+}
+
+impl<S> RespServerStream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    pub fn new(stream: S, service: Service) -> Self {
+        Self {
+            inner: stream,
+            service,
+            buf: BytesMut::new(),
+            parser: RespParser::default(),
+            resp3: false,
+        }
+    }
+
+    pub async fn process(mut self) -> Result<(), KvError> {
+        loop {
+            let frame = match self.parser.parse(&mut self.buf) {
+                Ok(Some(frame)) => frame,
+                Ok(None) => {
+                    if self.inner.read_buf(&mut self.buf).await? == 0 {
+                        return Ok(());
+                    }
+                    continue;
+                }
+                // 协议错误之后找不到下一个命令的开始，回复错误后关闭连接
+                Err(e) => {
+                    self.send(error_frame(&e)).await?;
+                    return Ok(());
+                }
+            };
+            // inline 命令里的空行直接忽略
+            if matches!(&frame, RespFrame::Array(args) if args.is_empty()) {
+                continue;
+            }
+
+            let reply = match parse_command(frame) {
+                Ok(RespCommand::Execute(cmd, reply)) => {
+                    info!("Got a new RESP command: {:?}", cmd);
+                    to_reply(self.service.execute_async(cmd).await, reply)
+                }
+                Ok(RespCommand::Reply(frame)) => frame,
+                Ok(RespCommand::Hello(version)) => self.hello(version),
+                Ok(RespCommand::Quit) => {
+                    self.send(RespFrame::ok()).await?;
+                    return Ok(());
+                }
+                Err(e) => error_frame(&e),
+            };
+            self.send(reply).await?;
+        }
+    }
+
+    // 回复服务器的信息，RESP2 里是数组，RESP3 里是 map
+    fn hello(&mut self, version: Option<i64>) -> RespFrame {
+        match version {
+            None => {}
+            Some(2) => self.resp3 = false,
+            Some(3) => self.resp3 = true,
+            Some(_) => return RespFrame::Error("NOPROTO unsupported protocol version".into()),
+        }
+
+        let field = |k: &str, v: RespFrame| (RespFrame::Bulk(k.to_owned().into()), v);
+        let bulk = |v: &str| RespFrame::Bulk(v.to_owned().into());
+        RespFrame::Map(vec![
+            field("server", bulk("kvs")),
+            field("version", bulk(env!("CARGO_PKG_VERSION"))),
+            field("proto", RespFrame::Integer(if self.resp3 { 3 } else { 2 })),
+            field("mode", bulk("standalone")),
+            field("role", bulk("master")),
+            field("modules", RespFrame::Array(vec![])),
+        ])
+    }
+
+    async fn send(&mut self, frame: RespFrame) -> Result<(), KvError> {
+        let mut buf = BytesMut::new();
+        frame.encode(&mut buf, self.resp3);
+        self.inner.write_all(&buf).await?;
+        Ok(())
+    }
+}
+
+fn error_frame(e: &KvError) -> RespFrame {
+    match e {
+        KvError::InvalidCommand(msg) => RespFrame::Error(format!("ERR {}", msg)),
+        KvError::RespError(msg) => RespFrame::Error(format!("ERR Protocol error: {}", msg)),
+        e => RespFrame::Error(format!("ERR {}", e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use anyhow::Result;
+    use tokio::net::{TcpListener, TcpStream};
+
+    use super::*;
+    use crate::{MemTable, ServiceInner};
+
+    // 像 redis-cli 一样发送原始的 RESP 数据，再解析出 n 个回复
+    async fn roundtrip(stream: &mut TcpStream, data: &[u8], n: usize) -> Result<Vec<RespFrame>> {
+        stream.write_all(data).await?;
+        let mut buf = BytesMut::new();
+        let mut parser = RespParser::default();
+        let mut frames = vec![];
+        while frames.len() < n {
+            match parser.parse(&mut buf)? {
+                Some(frame) => frames.push(frame),
+                None => {
+                    assert!(stream.read_buf(&mut buf).await? > 0);
+                }
+            }
+        }
+        Ok(frames)
+    }
+
+    fn bulk(s: &str) -> RespFrame {
+        RespFrame::Bulk(s.to_owned().into())
+    }
+
+    #[tokio::test]
+    async fn resp_hash_commands_should_work() -> Result<()> {
+        let addr = start_server().await?;
+        let mut stream = TcpStream::connect(addr).await?;
+
+        let data =
+            b"*6\r\n$4\r\nHSET\r\n$2\r\nt1\r\n$2\r\nk1\r\n$2\r\nv1\r\n$2\r\nk2\r\n$2\r\nv2\r\n";
+        let res = roundtrip(&mut stream, data, 1).await?;
+        assert_eq!(res, vec![RespFrame::Integer(2)]);
+
+        // pipeline 的多个命令按顺序回复
+        let data =
+            b"HGET t1 k1\r\nHGET t1 none\r\nHEXISTS t1 k2\r\nHLEN t1\r\nHMGET t1 k1 none\r\n";
+        let res = roundtrip(&mut stream, data, 5).await?;
+        assert_eq!(
+            res,
+            vec![
+                bulk("v1"),
+                RespFrame::Null,
+                RespFrame::Integer(1),
+                RespFrame::Integer(2),
+                RespFrame::Array(vec![bulk("v1"), RespFrame::Null]),
+            ]
+        );
+
+        let res = roundtrip(&mut stream, b"HGETALL t1\r\n", 1).await?;
+        let RespFrame::Array(items) = res.into_iter().next().unwrap() else {
+            panic!("HGETALL should reply an array in RESP2");
+        };
+        let mut items: Vec<_> = items
+            .into_iter()
+            .map(|item| match item {
+                RespFrame::Bulk(data) => data,
+                item => panic!("unexpected frame {:?}", item),
+            })
+            .collect();
+        items.sort();
+        assert_eq!(items, vec!["k1", "k2", "v1", "v2"]);
+
+        let res = roundtrip(&mut stream, b"HDEL t1 k1 none\r\nHGET t1 k1\r\n", 2).await?;
+        assert_eq!(res, vec![RespFrame::Integer(1), RespFrame::Null]);
+
+        let res = roundtrip(&mut stream, b"HGET t1\r\nFLUSHALL\r\n", 2).await?;
+        assert_eq!(
+            res,
+            vec![
+                RespFrame::Error("ERR wrong number of arguments for 'hget' command".into()),
+                RespFrame::Error("ERR unknown command 'flushall'".into()),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn resp3_hello_should_work() -> Result<()> {
+        let addr = start_server().await?;
+        let mut stream = TcpStream::connect(addr).await?;
+
+        let res = roundtrip(&mut stream, b"HELLO 3\r\n", 1).await?;
+        let RespFrame::Map(fields) = &res[0] else {
+            panic!("HELLO 3 should reply a map");
+        };
+        assert!(fields.contains(&(bulk("proto"), RespFrame::Integer(3))));
+
+        // 切换到 RESP3 之后，null 和 map 使用 RESP3 的格式
+        stream
+            .write_all(b"HSET t1 k1 v1\r\nHGET t1 none\r\n")
+            .await?;
+        let res = roundtrip(&mut stream, b"HGETALL t1\r\n", 3).await?;
+        assert_eq!(
+            res,
+            vec![
+                RespFrame::Integer(1),
+                RespFrame::Null,
+                RespFrame::Map(vec![(bulk("k1"), bulk("v1"))]),
+            ]
+        );
+
+        let res = roundtrip(&mut stream, b"HELLO 4\r\n", 1).await?;
+        assert_eq!(
+            res,
+            vec![RespFrame::Error(
+                "NOPROTO unsupported protocol version".into()
+            )]
+        );
+
+        let res = roundtrip(&mut stream, b"*1\r\n:1\r\n", 1).await?;
+        assert_eq!(
+            res,
+            vec![RespFrame::Error(
+                "ERR Protocol error: expected bulk strings".into()
+            )]
+        );
+
+        // 无法解析的数据回复错误之后关闭连接
+        let res = roundtrip(&mut stream, b"*1\r\n$abc\r\n", 1).await?;
+        assert_eq!(
+            res,
+            vec![RespFrame::Error(
+                "ERR Protocol error: invalid integer".into()
+            )]
+        );
+        assert_eq!(stream.read(&mut [0; 1]).await?, 0);
+
+        Ok(())
+    }
+
+    async fn start_server() -> Result<SocketAddr> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+
+        tokio::spawn(async move {
+            let service: Service = ServiceInner::new(MemTable::new()).into();
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let server = RespServerStream::new(stream, service.clone());
+                tokio::spawn(server.process());
+            }
+        });
+
+        Ok(addr)
+    }
+}
@@ -1,20 +1,46 @@
-use std::{marker::PhantomData, pin::Pin, task::Poll};
+use std::{
+    future::Future,
+    marker::PhantomData,
+    pin::Pin,
+    task::{Context, Poll},
+};
 
 use bytes::BytesMut;
-use futures::{ready, FutureExt, Sink, Stream};
-use tokio::io::{AsyncRead, AsyncWrite};
+use futures::{ready, Sink, Stream};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    time::{self, Sleep},
+};
 
-use crate::{network::frame::read_frame, FrameCoder, KvError};
+use crate::{FrameCoder, FrameCompression, FrameLimits, KvError};
+
+use super::frame::{frame_len, LEN_LEN, MAX_FRAME};
+
+// 每次从 stream 读取时最少和最多准备的缓存大小
+const MIN_READ: usize = 1024;
+const MAX_READ: usize = 64 * 1024;
+// 写缓存超过这个大小时，poll_ready 先把数据写出去，对端读得慢时 feed 会等待
+const MAX_WBUF: usize = 64 * 1024;
 
 /// 处理 KV server prost frame 的 stream
+/// 作为 Stream 时每次返回解码后的一个 In，作为 Sink 时把 Out 编码成 frame 写入
 pub struct ProstStream<S, In, Out> {
     // inner stream
     stream: S,
     // 写缓存
     wbuf: BytesMut,
     written: usize,
-    // 读缓存
+    // 读缓存，里面是还没有读完的 frame，poll 之间不会丢失
     rbuf: BytesMut,
+    // 接收 frame 时的限制
+    limits: FrameLimits,
+    // 发送 frame 时的压缩配置，以及对端能接收的最大 frame
+    compression: FrameCompression,
+    max_send_size: usize,
+    // 当前正在读取的 frame 的超时时间，收到 frame 的第一个字节时开始计时
+    deadline: Option<Pin<Box<Sleep>>>,
+    // 收到超过限制的 frame 之后 rbuf 里的数据已经无法分帧，之后不再读取
+    read_failed: bool,
     // 类型占位符
     _in: PhantomData<In>,
     _out: PhantomData<Out>,
@@ -22,67 +48,102 @@ pub struct ProstStream<S, In, Out> {
 
 impl<S, In, Out> Stream for ProstStream<S, In, Out>
 where
-    S: AsyncRead + AsyncWrite + Unpin + Send,
-    In: Unpin + Send + FrameCoder,
-    Out: Unpin + Send,
+    S: AsyncRead + Unpin,
+    In: Unpin + FrameCoder,
+    Out: Unpin,
 {
     /// 当调用 next() 时，得到 Result<In, KvError>
     type Item = Result<In, KvError>;
 
-    fn poll_next(
-        mut self: std::pin::Pin<&mut Self>,
-        cx: &mut std::task::Context<'_>,
-    ) -> Poll<Option<Self::Item>> {
-        // 上一次调用结束后 rbuf 应该为空
-        assert!(self.rbuf.len() == 0);
-
-        // 从 rbuf 中分离出 rest （摆脱对 self 的引用）
-        let mut rest = self.rbuf.split_off(0);
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if this.read_failed {
+            return Poll::Ready(None);
+        }
 
-        // 使用 read_frame 来获取数据
-        let fut = read_frame(&mut self.stream, &mut rest);
-        ready!(Box::pin(fut).poll_unpin(cx))?;
+        loop {
+            // rbuf 里已经有一个完整的 frame，直接解码
+            match this.complete_frame_len() {
+                Ok(Some(len)) => {
+                    this.deadline = None;
+                    let mut frame = this.rbuf.split_to(len);
+                    return Poll::Ready(Some(In::decode_frame_with(&mut frame, &this.limits)));
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    this.read_failed = true;
+                    return Poll::Ready(Some(Err(e)));
+                }
+            }
 
-        // 拿到一个 frame 的数据，把 buffer 合并回去
-        self.rbuf.unsplit(rest);
+            // 连接空闲时不计时，收到 frame 的一部分之后才开始计时
+            if let Some(timeout) = this.limits.read_timeout {
+                if !this.rbuf.is_empty() && this.deadline.is_none() {
+                    this.deadline = Some(Box::pin(time::sleep(timeout)));
+                }
+            }
+            if let Some(deadline) = this.deadline.as_mut() {
+                if deadline.as_mut().poll(cx).is_ready() {
+                    this.deadline = None;
+                    return Poll::Ready(Some(Err(KvError::FrameTimeout)));
+                }
+            }
 
-        // 调用 decode_frame 获取解包后的数据
-        Poll::Ready(Some(In::decode_frame(&mut self.rbuf)))
+            if ready!(this.poll_fill(cx))? == 0 {
+                // 对端关闭了连接：正好在 frame 之间是正常结束，否则数据不完整
+                return Poll::Ready(match this.rbuf.is_empty() {
+                    true => None,
+                    false => Some(Err(
+                        std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into()
+                    )),
+                });
+            }
+        }
     }
 }
 
 impl<S, In, Out> Sink<Out> for ProstStream<S, In, Out>
 where
-    S: AsyncRead + AsyncWrite + Unpin,
-    In: Unpin + Send,
-    Out: Unpin + Send + FrameCoder,
+    S: AsyncWrite + Unpin,
+    In: Unpin,
+    Out: Unpin + FrameCoder,
 {
     /// 如果发送出错，会返回 KvError
     type Error = KvError;
 
-    fn poll_ready(
-        self: std::pin::Pin<&mut Self>,
-        cx: &mut std::task::Context<'_>,
-    ) -> Poll<Result<(), Self::Error>> {
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        if self.wbuf.len() >= MAX_WBUF {
+            ready!(self.poll_flush(cx))?;
+        }
         Poll::Ready(Ok(()))
     }
 
-    fn start_send(self: std::pin::Pin<&mut Self>, item: Out) -> Result<(), Self::Error> {
+    fn start_send(self: Pin<&mut Self>, item: Out) -> Result<(), Self::Error> {
         let this = self.get_mut();
-        item.encode_frame(&mut this.wbuf)?;
+        let start = this.wbuf.len();
+        item.encode_frame_with(&mut this.wbuf, this.compression)?;
+
+        // 超过对端能接收的大小，丢弃这个 frame，调用者可以改为发送错误
+        let size = this.wbuf.len() - start - LEN_LEN;
+        if size > this.max_send_size {
+            this.wbuf.truncate(start);
+            return Err(KvError::FrameTooLarge(size, this.max_send_size));
+        }
 
         Ok(())
     }
 
-    fn poll_flush(
-        self: std::pin::Pin<&mut Self>,
-        cx: &mut std::task::Context<'_>,
-    ) -> Poll<Result<(), Self::Error>> {
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
         let this = self.get_mut();
 
         // 循环写入 stream 中
         while this.written != this.wbuf.len() {
             let n = ready!(Pin::new(&mut this.stream).poll_write(cx, &this.wbuf[this.written..]))?;
+            if n == 0 {
+                return Poll::Ready(Err(
+                    std::io::Error::from(std::io::ErrorKind::WriteZero).into()
+                ));
+            }
             this.written += n;
         }
 
@@ -95,10 +156,7 @@ where
         Poll::Ready(Ok(()))
     }
 
-    fn poll_close(
-        mut self: std::pin::Pin<&mut Self>,
-        cx: &mut std::task::Context<'_>,
-    ) -> Poll<Result<(), Self::Error>> {
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
         // 调用 stream 的 pull_flush 确保写入
         ready!(self.as_mut().poll_flush(cx))?;
 
@@ -114,10 +172,7 @@ where
 // 那么只要内部没有自引用数据，就应该实现 Unpin。
 impl<S, In, Out> Unpin for ProstStream<S, In, Out> where S: Unpin {}
 
-impl<S, In, Out> ProstStream<S, In, Out>
-where
-    S: AsyncRead + AsyncWrite + Send + Unpin,
-{
+impl<S, In, Out> ProstStream<S, In, Out> {
     /// 创建一个 ProstStream
     pub fn new(stream: S) -> Self {
         Self {
@@ -125,31 +180,268 @@ where
             written: 0,
             wbuf: BytesMut::new(),
             rbuf: BytesMut::new(),
-            _in: PhantomData::default(),
-            _out: PhantomData::default(),
+            limits: FrameLimits::default(),
+            compression: FrameCompression::default(),
+            max_send_size: MAX_FRAME,
+            deadline: None,
+            read_failed: false,
+            _in: PhantomData,
+            _out: PhantomData,
+        }
+    }
+
+    /// 设置接收 frame 时的限制
+    pub fn with_limits(mut self, limits: FrameLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn limits(&self) -> &FrameLimits {
+        &self.limits
+    }
+
+    /// 设置发送 frame 时的压缩算法和阈值，一般是 Hello 协商的结果
+    pub fn set_compression(&mut self, compression: FrameCompression) {
+        self.compression = compression;
+    }
+
+    /// 设置对端能接收的最大 frame，超过的 frame 在发送时返回 FrameTooLarge
+    pub fn set_max_send_size(&mut self, size: usize) {
+        self.max_send_size = size;
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.stream
+    }
+
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+
+    // 如果 rbuf 里已经有一个完整的 frame，返回它的长度（包括 4 字节的长度）
+    // 只要读到了 header 就检查长度，不会为超过限制的 frame 缓存数据
+    fn complete_frame_len(&self) -> Result<Option<usize>, KvError> {
+        let Some(len) = frame_len(&self.rbuf) else {
+            return Ok(None);
+        };
+        if len - LEN_LEN > self.limits.max_frame_size {
+            return Err(KvError::FrameTooLarge(
+                len - LEN_LEN,
+                self.limits.max_frame_size,
+            ));
         }
+        Ok((self.rbuf.len() >= len).then_some(len))
+    }
+}
+
+impl<S, In, Out> ProstStream<S, In, Out>
+where
+    S: AsyncRead + Unpin,
+{
+    // 从 stream 读取数据追加到 rbuf，返回读到的字节数，0 表示对端关闭了连接
+    // 按还缺的数据量逐步扩大 rbuf，而不是相信 header 里的长度一次分配
+    fn poll_fill(&mut self, cx: &mut Context<'_>) -> Poll<Result<usize, KvError>> {
+        let missing = frame_len(&self.rbuf)
+            .unwrap_or(LEN_LEN)
+            .saturating_sub(self.rbuf.len());
+        let start = self.rbuf.len();
+        self.rbuf
+            .resize(start + missing.clamp(MIN_READ, MAX_READ), 0);
+
+        let mut buf = ReadBuf::new(&mut self.rbuf[start..]);
+        let result = Pin::new(&mut self.stream).poll_read(cx, &mut buf);
+        let n = buf.filled().len();
+        self.rbuf.truncate(start + n);
+
+        ready!(result)?;
+        Poll::Ready(Ok(n))
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::time::Duration;
+
     use super::*;
-    use crate::{utils::DummyStream, CommandRequest};
+    use crate::{CommandRequest, CommandResponse, CompressionAlgorithm, Value};
     use anyhow::Result;
+    use bytes::{BufMut, Bytes};
     use futures::prelude::*;
+    use tokio::io::{duplex, AsyncWriteExt, DuplexStream};
+
+    type ServerStream = ProstStream<DuplexStream, CommandRequest, CommandResponse>;
+    type ClientStream = ProstStream<DuplexStream, CommandResponse, CommandRequest>;
+
+    fn pair() -> (ClientStream, ServerStream) {
+        let (client, server) = duplex(4096);
+        (ProstStream::new(client), ProstStream::new(server))
+    }
 
     #[tokio::test]
     async fn prost_stream_should_work() -> Result<()> {
-        let buf = BytesMut::new();
-        let stream = DummyStream { buf };
-        let mut stream = ProstStream::<_, CommandRequest, CommandRequest>::new(stream);
+        let (mut client, mut server) = pair();
+
         let cmd = CommandRequest::new_hdel("t1", "k1");
-        stream.send(cmd.clone()).await?;
-        if let Some(Ok(s)) = stream.next().await {
-            assert_eq!(s, cmd);
-        } else {
-            unreachable!();
+        client.send(cmd.clone()).await?;
+        assert_eq!(server.next().await.unwrap()?, cmd);
+
+        // 大的数据会压缩，超过 duplex 的缓存，需要多次读取
+        let v: Value = Bytes::from(vec![1u8; 64 * 1024]).into();
+        let res: CommandResponse = v.into();
+        let sender = tokio::spawn(async move {
+            server.send(res).await.unwrap();
+            server
+        });
+        let res1 = client.next().await.unwrap()?;
+        let mut server = sender.await?;
+        assert_eq!(res1.values.len(), 1);
+
+        // 对端关闭后返回 None
+        drop(client);
+        assert!(server.next().await.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn prost_stream_should_keep_partial_reads() -> Result<()> {
+        let (mut raw, server) = duplex(4096);
+        let mut server = ServerStream::new(server);
+
+        // 两个 frame 一个字节一个字节地到达，中间 poll 多次
+        let cmds = vec![
+            CommandRequest::new_hget("t1", "k1"),
+            CommandRequest::new_hset("t1", "k2", "v2".into()),
+        ];
+        let mut data = BytesMut::new();
+        for cmd in &cmds {
+            cmd.encode_frame(&mut data)?;
         }
+        tokio::spawn(async move {
+            for b in data {
+                raw.write_all(&[b]).await.unwrap();
+                tokio::task::yield_now().await;
+            }
+        });
+
+        let received: Vec<_> = (&mut server).take(2).try_collect().await?;
+        assert_eq!(received, cmds);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn prost_stream_should_work_with_combinators() -> Result<()> {
+        let (mut client, server) = pair();
+
+        // 用标准的 StreamExt / SinkExt 组合出一个 echo 服务
+        let (sink, stream) = server.split();
+        tokio::spawn(
+            stream
+                .map_ok(|cmd: CommandRequest| {
+                    let values = vec![Value::from(format!("{:?}", cmd.request_data.is_some()))];
+                    CommandResponse::from(values)
+                })
+                .forward(sink),
+        );
+
+        let cmds = (0..3).map(|i| Ok(CommandRequest::new_hget("t1", format!("k{}", i))));
+        client.send_all(&mut stream::iter(cmds)).await?;
+        let responses: Vec<_> = (&mut client).take(3).try_collect().await?;
+        assert!(responses
+            .iter()
+            .all(|res| res.values == vec!["true".into()]));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn prost_stream_should_reject_hostile_input() -> Result<()> {
+        let limits = FrameLimits {
+            max_frame_size: 1024,
+            max_decompressed_size: 64 * 1024,
+            read_timeout: Some(Duration::from_millis(50)),
+        };
+
+        // 伪造的长度
+        let (mut raw, server) = duplex(4096);
+        let mut server = ServerStream::new(server).with_limits(limits);
+        raw.write_all(&0x3fff_ffffu32.to_be_bytes()).await?;
+        let result = server.next().await.unwrap();
+        assert!(matches!(result, Err(KvError::FrameTooLarge(_, 1024))));
+        // 之后不会一直重复这个错误
+        assert!(server.next().await.is_none());
+
+        // 只发送一部分之后不再发送
+        let (mut raw, server) = duplex(4096);
+        let mut server = ServerStream::new(server).with_limits(limits);
+        raw.write_all(&[0, 0, 0, 10, 1]).await?;
+        let result = server.next().await.unwrap();
+        assert!(matches!(result, Err(KvError::FrameTimeout)));
+
+        // 发送一部分之后关闭连接
+        let (mut raw, server) = duplex(4096);
+        let mut server = ServerStream::new(server).with_limits(limits);
+        raw.write_all(&[0, 0, 0, 10, 1]).await?;
+        drop(raw);
+        let result = server.next().await.unwrap();
+        assert!(matches!(result, Err(KvError::IoError(_))));
+
+        // 压缩炸弹
+        let (mut raw, server) = duplex(64 * 1024);
+        let mut server = ServerStream::new(server).with_limits(limits);
+        let cmd = CommandRequest::new_hset("t1", "k1", Bytes::from(vec![0u8; 1 << 20]).into());
+        let payload = CompressionAlgorithm::Zstd.compress(&prost::Message::encode_to_vec(&cmd))?;
+        let mut data = BytesMut::new();
+        data.put_u32((payload.len() | 1 << 30) as _);
+        data.put_slice(&payload);
+        raw.write_all(&data).await?;
+        let result = server.next().await.unwrap();
+        assert!(matches!(result, Err(KvError::FrameTooLarge(..))));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn prost_stream_should_respect_send_settings() -> Result<()> {
+        let (mut client, mut server) = pair();
+        server.set_compression(FrameCompression::new(CompressionAlgorithm::Lz4, 16));
+        server.set_max_send_size(128);
+
+        let res: CommandResponse = Value::from("x".repeat(64)).into();
+        server.send(res.clone()).await?;
+        assert_eq!(client.next().await.unwrap()?, res);
+
+        // 超过对端能接收的大小时返回错误，连接还可以继续使用
+        let big: CommandResponse = Value::from(Bytes::from(
+            (0..1024).map(|i| (i * 7 % 251) as u8).collect::<Vec<_>>(),
+        ))
+        .into();
+        let result = server.send(big).await;
+        assert!(matches!(result, Err(KvError::FrameTooLarge(_, 128))));
+        server.send(res.clone()).await?;
+        assert_eq!(client.next().await.unwrap()?, res);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn prost_stream_feed_should_wait_for_slow_reader() -> Result<()> {
+        let (_client, mut server) = pair();
+
+        // 对端不读取时 feed 最终会等待，写缓存不会无限增长
+        let res: CommandResponse = Value::from(Bytes::from(
+            (0..1024).map(|i| (i * 7 % 251) as u8).collect::<Vec<_>>(),
+        ))
+        .into();
+        let feeding = async {
+            loop {
+                server.feed(res.clone()).await.unwrap();
+            }
+        };
+        let result = time::timeout(Duration::from_millis(50), feeding).await;
+        assert!(result.is_err());
+        assert!(server.wbuf.len() < MAX_WBUF + 2048);
         Ok(())
     }
 }
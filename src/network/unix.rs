@@ -0,0 +1,166 @@
+use std::{collections::HashSet, path::Path};
+
+use tokio::net::UnixStream;
+
+use crate::KvError;
+
+/// kvc 等客户端用 unix:// 开头的地址表示 Unix socket
+const UNIX_SCHEME: &str = "unix://";
+
+/// Unix socket 对端进程的身份
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerCred {
+    pub uid: u32,
+    pub gid: u32,
+    // 有的平台拿不到对端的 pid
+    pub pid: Option<i32>,
+}
+
+/// 根据对端进程的 uid / gid 决定是否接受 Unix socket 连接
+/// uid 或者 gid 有一个在允许的列表里就接受，两个列表都为空时拒绝所有连接
+#[derive(Debug, Clone, Default)]
+pub struct PeerCredPolicy {
+    uids: HashSet<u32>,
+    gids: HashSet<u32>,
+}
+
+impl PeerCred {
+    pub fn from_stream(stream: &UnixStream) -> Result<Self, KvError> {
+        let cred = stream.peer_cred()?;
+        Ok(Self {
+            uid: cred.uid(),
+            gid: cred.gid(),
+            pid: cred.pid(),
+        })
+    }
+}
+
+impl PeerCredPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn allow_uid(mut self, uid: u32) -> Self {
+        self.uids.insert(uid);
+        self
+    }
+
+    pub fn allow_gid(mut self, gid: u32) -> Self {
+        self.gids.insert(gid);
+        self
+    }
+
+    pub fn check(&self, cred: &PeerCred) -> Result<(), KvError> {
+        if self.uids.contains(&cred.uid) || self.gids.contains(&cred.gid) {
+            Ok(())
+        } else {
+            Err(KvError::PermissionDenied(format!(
+                "uid {} gid {} is not allowed",
+                cred.uid, cred.gid
+            )))
+        }
+    }
+
+    /// 取出对端的身份并检查，通过后返回对端的身份，方便调用者记录日志或者做进一步的授权
+    pub fn verify(&self, stream: &UnixStream) -> Result<PeerCred, KvError> {
+        let cred = PeerCred::from_stream(stream)?;
+        self.check(&cred)?;
+        Ok(cred)
+    }
+}
+
+/// 如果地址是 unix:// 开头的，返回 socket 文件的路径
+pub fn parse_unix_addr(addr: &str) -> Option<&Path> {
+    addr.strip_prefix(UNIX_SCHEME).map(Path::new)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::MetadataExt;
+
+    use anyhow::Result;
+    use tokio::net::UnixListener;
+
+    use super::*;
+    use crate::{
+        assert_res_ok, CommandRequest, MemTable, ProstClientStream, ProstServerStream, Service,
+        ServiceInner, Value,
+    };
+
+    #[test]
+    fn parse_unix_addr_should_work() {
+        assert_eq!(
+            parse_unix_addr("unix:///tmp/kvs.sock"),
+            Some(Path::new("/tmp/kvs.sock"))
+        );
+        assert_eq!(parse_unix_addr("127.0.0.1:9527"), None);
+    }
+
+    #[test]
+    fn peer_cred_policy_should_work() {
+        let cred = PeerCred {
+            uid: 1000,
+            gid: 100,
+            pid: None,
+        };
+        assert!(PeerCredPolicy::new().check(&cred).is_err());
+        assert!(PeerCredPolicy::new().allow_uid(1000).check(&cred).is_ok());
+        assert!(PeerCredPolicy::new().allow_gid(100).check(&cred).is_ok());
+        let policy = PeerCredPolicy::new().allow_uid(0).allow_gid(0);
+        assert!(matches!(
+            policy.check(&cred),
+            Err(KvError::PermissionDenied(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn unix_socket_should_work() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("kvs.sock");
+        let listener = UnixListener::bind(&path)?;
+        // socket 文件的 owner 就是当前进程的用户，也就是客户端的用户
+        let uid = std::fs::metadata(&path)?.uid();
+        start_server(listener, PeerCredPolicy::new().allow_uid(uid));
+
+        let stream = UnixStream::connect(&path).await?;
+        assert_eq!(PeerCred::from_stream(&stream)?.uid, uid);
+        let mut client = ProstClientStream::new(stream);
+
+        let cmd = CommandRequest::new_hset("t1", "k1", "v1".into());
+        assert_res_ok(client.execute(cmd).await?, &[Value::default()], &[]);
+        let cmd = CommandRequest::new_hget("t1", "k1");
+        assert_res_ok(client.execute(cmd).await?, &["v1".into()], &[]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn unix_socket_should_reject_unknown_peer() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("kvs.sock");
+        let listener = UnixListener::bind(&path)?;
+        start_server(listener, PeerCredPolicy::new());
+
+        // 没有通过检查的连接直接被关闭
+        let stream = UnixStream::connect(&path).await?;
+        let mut client = ProstClientStream::new(stream);
+        let cmd = CommandRequest::new_hget("t1", "k1");
+        assert!(client.execute(cmd).await.is_err());
+
+        Ok(())
+    }
+
+    fn start_server(listener: UnixListener, policy: PeerCredPolicy) {
+        tokio::spawn(async move {
+            let service: Service = ServiceInner::new(MemTable::new()).into();
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                if policy.verify(&stream).is_err() {
+                    continue;
+                }
+                let server = ProstServerStream::new(stream, service.clone());
+                tokio::spawn(server.process());
+            }
+        });
+    }
+}
@@ -0,0 +1,246 @@
+use bytes::BytesMut;
+use futures::{SinkExt, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_tungstenite::tungstenite::{protocol::WebSocketConfig, Message};
+use tracing::info;
+
+use crate::{CommandRequest, FrameCoder, KvError, Service};
+
+use super::frame::frame_len;
+
+// 一个 message 就是一个 frame，限制在 64MB 以内
+const MAX_MESSAGE_SIZE: usize = 64 * 1024 * 1024;
+
+/// 处理 WebSocket 连接，让浏览器可以直接访问 kvs
+/// 每个 binary message 里是一个用 FrameCoder 编码的 CommandRequest / CommandResponse
+/// 需要 TLS 的话，先用 TlsServerAcceptor 把 socket 转换成 TLS stream 再传进来
+pub struct WsServerStream<S> {
+    inner: S,
+    service: Service,
+}
+
+impl<S> WsServerStream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    pub fn new(stream: S, service: Service) -> Self {
+        Self {
+            inner: stream,
+            service,
+        }
+    }
+
+    pub async fn process(self) -> Result<(), KvError> {
+        let config = WebSocketConfig {
+            max_message_size: Some(MAX_MESSAGE_SIZE),
+            max_frame_size: Some(MAX_MESSAGE_SIZE),
+            ..Default::default()
+        };
+        let mut ws = tokio_tungstenite::accept_async_with_config(self.inner, Some(config)).await?;
+
+        // ping 和 close 由 tungstenite 自动回复，这里只需要处理数据
+        // 执行阻塞的命令时提前读到的下一个 message
+        let mut pending = None;
+        loop {
+            let msg = match pending.take() {
+                Some(msg) => msg,
+                None => ws.next().await,
+            };
+            let msg = match msg {
+                Some(msg) => msg?,
+                None => break,
+            };
+            let res = match msg {
+                Message::Binary(data) => match decode_request(&data) {
+                    Ok(cmd) => {
+                        info!("Got a new WebSocket command: {:?}", cmd);
+                        let execute = self.service.execute_async(cmd);
+                        tokio::pin!(execute);
+                        // 阻塞的命令等待期间继续读取，这样才能回复 ping，客户端断开时也不再等待。
+                        // 提前发来的数据 message 最多先存一个，等这个命令执行完再处理
+                        loop {
+                            tokio::select! {
+                                res = &mut execute => break res,
+                                msg = ws.next(), if pending.is_none() => match msg {
+                                    Some(Ok(Message::Ping(_) | Message::Pong(_))) => {}
+                                    Some(Ok(Message::Binary(_) | Message::Text(_))) => {
+                                        pending = Some(msg)
+                                    }
+                                    Some(Err(e)) => return Err(e.into()),
+                                    _ => return Ok(()),
+                                },
+                            }
+                        }
+                    }
+                    Err(e) => e.into(),
+                },
+                Message::Text(_) => {
+                    KvError::InvalidCommand("expect a binary message".into()).into()
+                }
+                _ => continue,
+            };
+
+            let mut buf = BytesMut::new();
+            res.encode_frame(&mut buf)?;
+            ws.send(Message::Binary(buf.to_vec())).await?;
+        }
+        Ok(())
+    }
+}
+
+// message 里必须正好是一个完整的 frame，不能多也不能少
+fn decode_request(data: &[u8]) -> Result<CommandRequest, KvError> {
+    if frame_len(data) != Some(data.len()) {
+        return Err(KvError::InvalidCommand(
+            "message is not a complete frame".into(),
+        ));
+    }
+    CommandRequest::decode_frame(&mut BytesMut::from(data))
+        .map_err(|e| KvError::InvalidCommand(format!("invalid frame: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use anyhow::Result;
+    use bytes::Bytes;
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_tungstenite::{client_async, WebSocketStream};
+
+    use super::*;
+    use crate::{
+        assert_res_ok, CommandResponse, Kvpair, MemTable, ServiceInner, TlsClientConnector,
+        TlsServerAcceptor, Value,
+    };
+
+    const CA_CERT: &str = include_str!("../../fixtures/ca.cert");
+    const SERVER_CERT: &str = include_str!("../../fixtures/server.cert");
+    const SERVER_KEY: &str = include_str!("../../fixtures/server.key");
+
+    async fn execute<S>(ws: &mut WebSocketStream<S>, cmd: CommandRequest) -> Result<CommandResponse>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let mut buf = BytesMut::new();
+        cmd.encode_frame(&mut buf)?;
+        ws.send(Message::Binary(buf.to_vec())).await?;
+        recv(ws).await
+    }
+
+    fn decode_response(data: &[u8]) -> Result<CommandResponse> {
+        assert_eq!(frame_len(data), Some(data.len()));
+        Ok(CommandResponse::decode_frame(&mut BytesMut::from(data))?)
+    }
+
+    async fn recv<S>(ws: &mut WebSocketStream<S>) -> Result<CommandResponse>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        match ws.next().await {
+            Some(Ok(Message::Binary(data))) => Ok(decode_response(&data)?),
+            msg => panic!("unexpected message {:?}", msg),
+        }
+    }
+
+    #[tokio::test]
+    async fn ws_commands_should_work() -> Result<()> {
+        let addr = start_server(None).await?;
+        let stream = TcpStream::connect(addr).await?;
+        let (mut ws, _) = client_async(format!("ws://{}/", addr), stream).await?;
+
+        let cmd = CommandRequest::new_hset("t1", "k1", "v1".into());
+        assert_res_ok(execute(&mut ws, cmd).await?, &[Value::default()], &[]);
+
+        // 大的数据会被压缩，同样可以正常读写
+        let v: Value = Bytes::from(vec![0u8; 16384]).into();
+        let cmd = CommandRequest::new_hset("t1", "k2", v.clone());
+        assert_res_ok(execute(&mut ws, cmd).await?, &[Value::default()], &[]);
+
+        let cmd = CommandRequest::new_hmget("t1", vec!["k1".into(), "k2".into()]);
+        assert_res_ok(execute(&mut ws, cmd).await?, &["v1".into(), v], &[]);
+
+        // 不是一个完整 frame 的 message 和 text message 回复 400，连接继续可用
+        ws.send(Message::Binary(vec![0, 0, 0, 8, 1])).await?;
+        assert_eq!(recv(&mut ws).await?.status, 400);
+        ws.send(Message::Text("HGET t1 k1".into())).await?;
+        assert_eq!(recv(&mut ws).await?.status, 400);
+
+        let cmd = CommandRequest::new_hget("t1", "k1");
+        assert_res_ok(execute(&mut ws, cmd).await?, &["v1".into()], &[]);
+
+        ws.close(None).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn wss_watch_should_work() -> Result<()> {
+        let acceptor = TlsServerAcceptor::new(SERVER_CERT, SERVER_KEY, None)?;
+        let addr = start_server(Some(acceptor)).await?;
+        let connector = TlsClientConnector::new("kvserver.acme.inc", None, Some(CA_CERT))?;
+        let stream = connector.connect(TcpStream::connect(addr).await?).await?;
+        let (mut ws, _) = client_async("wss://kvserver.acme.inc/", stream).await?;
+
+        // 浏览器里用阻塞的 Xread 来等待新的 entry
+        let producer = tokio::spawn(async move {
+            let stream = TcpStream::connect(addr).await.unwrap();
+            let stream = connector.connect(stream).await.unwrap();
+            let (mut ws, _) = client_async("wss://kvserver.acme.inc/", stream)
+                .await
+                .unwrap();
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            let fields = vec![Kvpair::new("n", 1.into())];
+            let cmd = CommandRequest::new_xadd("t1", "s", None, fields);
+            execute(&mut ws, cmd).await.unwrap()
+        });
+        let cmd = CommandRequest::new_xread("t1", "s", "$", 0, Some(0));
+        let res = execute(&mut ws, cmd).await?;
+        assert_eq!(res.entries.len(), 1);
+        assert_eq!(producer.await?.values[0], res.entries[0].id.as_str().into());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn ws_should_answer_ping_while_blocked() -> Result<()> {
+        let addr = start_server(None).await?;
+        let stream = TcpStream::connect(addr).await?;
+        let (mut ws, _) = client_async(format!("ws://{}/", addr), stream).await?;
+
+        let mut buf = BytesMut::new();
+        CommandRequest::new_blpop("t1", "q", 200).encode_frame(&mut buf)?;
+        ws.send(Message::Binary(buf.to_vec())).await?;
+        // Blpop 还在等待时 ping 也会被回复
+        ws.send(Message::Ping(b"hi".to_vec())).await?;
+        match ws.next().await {
+            Some(Ok(Message::Pong(data))) => assert_eq!(data, b"hi"),
+            msg => panic!("unexpected message {:?}", msg),
+        }
+        assert_eq!(recv(&mut ws).await?.status, 404);
+
+        ws.close(None).await?;
+        Ok(())
+    }
+
+    async fn start_server(acceptor: Option<TlsServerAcceptor>) -> Result<SocketAddr> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+
+        tokio::spawn(async move {
+            let service: Service = ServiceInner::new(MemTable::new()).into();
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let service = service.clone();
+                match acceptor.clone() {
+                    None => tokio::spawn(WsServerStream::new(stream, service).process()),
+                    Some(acceptor) => tokio::spawn(async move {
+                        let stream = acceptor.accept(stream).await?;
+                        WsServerStream::new(stream, service).process().await
+                    }),
+                };
+            }
+        });
+
+        Ok(addr)
+    }
+}
@@ -2,7 +2,7 @@
 #[derive(PartialOrd)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct CommandRequest {
-    #[prost(oneof="command_request::RequestData", tags="1, 2, 3, 4, 5, 6, 7, 8, 9")]
+    #[prost(oneof="command_request::RequestData", tags="1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31, 32, 33, 34, 35, 36, 37, 38, 39, 40, 41, 42, 43, 44, 45, 46, 47, 48, 49, 50, 51, 52, 53, 54, 55, 56, 57, 58, 59, 60, 61, 62, 63, 64, 65, 66, 67, 68, 69, 70, 71, 72, 73")]
     pub request_data: ::core::option::Option<command_request::RequestData>,
 }
 /// Nested message and enum types in `CommandRequest`.
@@ -28,6 +28,134 @@ pub mod command_request {
         Hexist(super::Hexist),
         #[prost(message, tag="9")]
         Hmexist(super::Hmexist),
+        #[prost(message, tag="10")]
+        Hversioning(super::Hversioning),
+        #[prost(message, tag="11")]
+        HgetVersion(super::HgetVersion),
+        #[prost(message, tag="12")]
+        Hhistory(super::Hhistory),
+        #[prost(message, tag="13")]
+        HgetallAsOf(super::HgetallAsOf),
+        #[prost(message, tag="14")]
+        Lpush(super::Lpush),
+        #[prost(message, tag="15")]
+        Rpush(super::Rpush),
+        #[prost(message, tag="16")]
+        Lpop(super::Lpop),
+        #[prost(message, tag="17")]
+        Rpop(super::Rpop),
+        #[prost(message, tag="18")]
+        Lrange(super::Lrange),
+        #[prost(message, tag="19")]
+        Llen(super::Llen),
+        #[prost(message, tag="20")]
+        Ltrim(super::Ltrim),
+        #[prost(message, tag="21")]
+        Blpop(super::Blpop),
+        #[prost(message, tag="22")]
+        Brpop(super::Brpop),
+        #[prost(message, tag="23")]
+        Sadd(super::Sadd),
+        #[prost(message, tag="24")]
+        Srem(super::Srem),
+        #[prost(message, tag="25")]
+        Smembers(super::Smembers),
+        #[prost(message, tag="26")]
+        Sismember(super::Sismember),
+        #[prost(message, tag="27")]
+        Sinter(super::Sinter),
+        #[prost(message, tag="28")]
+        Sunion(super::Sunion),
+        #[prost(message, tag="29")]
+        Zadd(super::Zadd),
+        #[prost(message, tag="30")]
+        Zrange(super::Zrange),
+        #[prost(message, tag="31")]
+        Zrangebyscore(super::Zrangebyscore),
+        #[prost(message, tag="32")]
+        Zrank(super::Zrank),
+        #[prost(message, tag="33")]
+        Zincrby(super::Zincrby),
+        #[prost(message, tag="34")]
+        HgetPath(super::HgetPath),
+        #[prost(message, tag="35")]
+        HsetPath(super::HsetPath),
+        #[prost(message, tag="36")]
+        HdelPath(super::HdelPath),
+        #[prost(message, tag="37")]
+        JsonGet(super::JsonGet),
+        #[prost(message, tag="38")]
+        JsonSet(super::JsonSet),
+        #[prost(message, tag="39")]
+        JsonDel(super::JsonDel),
+        #[prost(message, tag="40")]
+        JsonArrAppend(super::JsonArrAppend),
+        #[prost(message, tag="41")]
+        JsonNumIncrBy(super::JsonNumIncrBy),
+        #[prost(message, tag="42")]
+        Xadd(super::Xadd),
+        #[prost(message, tag="43")]
+        Xrange(super::Xrange),
+        #[prost(message, tag="44")]
+        Xread(super::Xread),
+        #[prost(message, tag="45")]
+        Xlen(super::Xlen),
+        #[prost(message, tag="46")]
+        Xtrim(super::Xtrim),
+        #[prost(message, tag="47")]
+        XgroupCreate(super::XgroupCreate),
+        #[prost(message, tag="48")]
+        Xreadgroup(super::Xreadgroup),
+        #[prost(message, tag="49")]
+        Xack(super::Xack),
+        #[prost(message, tag="50")]
+        Xpending(super::Xpending),
+        #[prost(message, tag="51")]
+        TsAdd(super::TsAdd),
+        #[prost(message, tag="52")]
+        TsRange(super::TsRange),
+        #[prost(message, tag="53")]
+        TsSetRetention(super::TsSetRetention),
+        #[prost(message, tag="54")]
+        TsCreateRule(super::TsCreateRule),
+        #[prost(message, tag="55")]
+        TsDeleteRule(super::TsDeleteRule),
+        #[prost(message, tag="56")]
+        Pfadd(super::Pfadd),
+        #[prost(message, tag="57")]
+        Pfcount(super::Pfcount),
+        #[prost(message, tag="58")]
+        Pfmerge(super::Pfmerge),
+        #[prost(message, tag="59")]
+        Bfadd(super::Bfadd),
+        #[prost(message, tag="60")]
+        Bfexists(super::Bfexists),
+        #[prost(message, tag="61")]
+        Setbit(super::Setbit),
+        #[prost(message, tag="62")]
+        Getbit(super::Getbit),
+        #[prost(message, tag="63")]
+        Bitcount(super::Bitcount),
+        #[prost(message, tag="64")]
+        Bitop(super::Bitop),
+        #[prost(message, tag="65")]
+        Bitpos(super::Bitpos),
+        #[prost(message, tag="66")]
+        IndexCreate(super::IndexCreate),
+        #[prost(message, tag="67")]
+        IndexDrop(super::IndexDrop),
+        #[prost(message, tag="68")]
+        IndexLookup(super::IndexLookup),
+        #[prost(message, tag="69")]
+        IndexRange(super::IndexRange),
+        #[prost(message, tag="70")]
+        Query(super::Query),
+        #[prost(message, tag="71")]
+        Hkeys(super::Hkeys),
+        #[prost(message, tag="72")]
+        Hrandkey(super::Hrandkey),
+        #[prost(message, tag="73")]
+        Hello(super::Hello),
     }
 }
 /// 服务器的响应
@@ -46,6 +174,24 @@ pub struct CommandResponse {
     /// 成功返回的 kv pairs
     #[prost(message, repeated, tag="4")]
     pub pairs: ::prost::alloc::vec::Vec<Kvpair>,
+    /// 成功返回的历史版本
+    #[prost(message, repeated, tag="5")]
+    pub versions: ::prost::alloc::vec::Vec<VersionedValue>,
+    /// 成功返回的有序集合成员
+    #[prost(message, repeated, tag="6")]
+    pub scored_values: ::prost::alloc::vec::Vec<ScoredValue>,
+    /// 成功返回的流里的消息
+    #[prost(message, repeated, tag="7")]
+    pub entries: ::prost::alloc::vec::Vec<StreamEntry>,
+    /// 成功返回的消费组里待确认的消息
+    #[prost(message, repeated, tag="8")]
+    pub pending: ::prost::alloc::vec::Vec<PendingEntry>,
+    /// 成功返回的时间序列采样点
+    #[prost(message, repeated, tag="9")]
+    pub samples: ::prost::alloc::vec::Vec<Sample>,
+    /// Hello 协商的结果
+    #[prost(message, optional, tag="10")]
+    pub hello: ::core::option::Option<HelloReply>,
 }
 /// 从 table 中获取一个 key，返回 value
 #[derive(PartialOrd)]
@@ -53,8 +199,8 @@ pub struct CommandResponse {
 pub struct Hget {
     #[prost(string, tag="1")]
     pub table: ::prost::alloc::string::String,
-    #[prost(string, tag="2")]
-    pub key: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", tag="2")]
+    pub key: ::prost::bytes::Bytes,
 }
 /// 从 table 中获取所有的 Kvpair
 #[derive(PartialOrd)]
@@ -63,20 +209,38 @@ pub struct Hgetall {
     #[prost(string, tag="1")]
     pub table: ::prost::alloc::string::String,
 }
+/// 返回 table 中匹配 pattern（glob，为空表示所有 key）的 key，不返回 value
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Hkeys {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag="2")]
+    pub pattern: ::prost::alloc::string::String,
+}
+/// 随机返回 table 中的 key。count 为正数时 key 不重复，为负数时可以重复，为 0 时返回一个
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Hrandkey {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(int64, tag="2")]
+    pub count: i64,
+}
 /// 从 table 中获取一组 key，返回它们的 value
 #[derive(PartialOrd)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Hmget {
     #[prost(string, tag="1")]
     pub table: ::prost::alloc::string::String,
-    #[prost(string, repeated, tag="2")]
-    pub keys: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    #[prost(bytes="bytes", repeated, tag="2")]
+    pub keys: ::prost::alloc::vec::Vec<::prost::bytes::Bytes>,
 }
 /// 返回的值
 #[derive(PartialOrd)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Value {
-    #[prost(oneof="value::Value", tags="1, 2, 3, 4, 5")]
+    #[prost(oneof="value::Value", tags="1, 2, 3, 4, 5, 6, 7")]
     pub value: ::core::option::Option<value::Value>,
 }
 /// Nested message and enum types in `Value`.
@@ -94,14 +258,28 @@ pub mod value {
         Float(f64),
         #[prost(bool, tag="5")]
         Bool(bool),
+        /// 嵌套的列表和 map
+        #[prost(message, tag="6")]
+        List(super::ValueList),
+        #[prost(message, tag="7")]
+        Map(super::ValueMap),
     }
 }
+/// 嵌套的 map，key 是字符串
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ValueMap {
+    #[prost(btree_map="string, message", tag="1")]
+    pub fields: ::prost::alloc::collections::BTreeMap<::prost::alloc::string::String, Value>,
+}
 /// 返回的 kvpair
+/// key 可以是任意的字节。bytes 和 string 在 protobuf 里的编码方式相同，
+/// 所以之前用 string 做 key 的客户端仍然兼容
 #[derive(PartialOrd)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Kvpair {
-    #[prost(string, tag="1")]
-    pub key: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", tag="1")]
+    pub key: ::prost::bytes::Bytes,
     #[prost(message, optional, tag="2")]
     pub value: ::core::option::Option<Value>,
 }
@@ -131,8 +309,8 @@ pub struct Hmset {
 pub struct Hdel {
     #[prost(string, tag="1")]
     pub table: ::prost::alloc::string::String,
-    #[prost(string, tag="2")]
-    pub key: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", tag="2")]
+    pub key: ::prost::bytes::Bytes,
 }
 /// 从 table 中删除一组 key，返回它们之前的值
 #[derive(PartialOrd)]
@@ -140,8 +318,8 @@ pub struct Hdel {
 pub struct Hmdel {
     #[prost(string, tag="1")]
     pub table: ::prost::alloc::string::String,
-    #[prost(string, repeated, tag="2")]
-    pub keys: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    #[prost(bytes="bytes", repeated, tag="2")]
+    pub keys: ::prost::alloc::vec::Vec<::prost::bytes::Bytes>,
 }
 /// 查看 key 是否存在
 #[derive(PartialOrd)]
@@ -149,8 +327,8 @@ pub struct Hmdel {
 pub struct Hexist {
     #[prost(string, tag="1")]
     pub table: ::prost::alloc::string::String,
-    #[prost(string, tag="2")]
-    pub key: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", tag="2")]
+    pub key: ::prost::bytes::Bytes,
 }
 /// 查看一组 key 是否存在
 #[derive(PartialOrd)]
@@ -158,6 +336,986 @@ pub struct Hexist {
 pub struct Hmexist {
     #[prost(string, tag="1")]
     pub table: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", repeated, tag="2")]
+    pub keys: ::prost::alloc::vec::Vec<::prost::bytes::Bytes>,
+}
+/// 多版本的保留策略，0 表示不限制
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct VersionPolicy {
+    /// 每个 key 最多保留多少个版本
+    #[prost(uint32, tag="1")]
+    pub max_versions: u32,
+    /// 版本最多保留多少秒
+    #[prost(uint64, tag="2")]
+    pub max_age_secs: u64,
+}
+/// 开启或关闭 table 的多版本，policy 为空表示关闭
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Hversioning {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(message, optional, tag="2")]
+    pub policy: ::core::option::Option<VersionPolicy>,
+}
+/// 某个 key 的一个历史版本
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct VersionedValue {
+    /// 单调递增的版本号
+    #[prost(uint64, tag="1")]
+    pub version: u64,
+    /// 写入时间，unix 毫秒
+    #[prost(uint64, tag="2")]
+    pub timestamp: u64,
+    #[prost(message, optional, tag="3")]
+    pub value: ::core::option::Option<Value>,
+    /// 这个版本是一次删除
+    #[prost(bool, tag="4")]
+    pub deleted: bool,
+}
+/// 获取 key 在版本 version 时的值
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct HgetVersion {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", tag="2")]
+    pub key: ::prost::bytes::Bytes,
+    #[prost(uint64, tag="3")]
+    pub version: u64,
+}
+/// 获取 key 的所有历史版本
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Hhistory {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", tag="2")]
+    pub key: ::prost::bytes::Bytes,
+}
+/// 获取 table 在版本 version 时的所有 Kvpair
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct HgetallAsOf {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(uint64, tag="2")]
+    pub version: u64,
+}
+/// 列表，按顺序存放一组 value。既可以作为嵌套的 Value，也用来存放列表和集合
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ValueList {
+    #[prost(message, repeated, tag="1")]
+    pub values: ::prost::alloc::vec::Vec<Value>,
+}
+/// 把 values 依次插入到列表的头部，返回插入后列表的长度
+/// 和 Redis 一样，Lpush [a, b, c] 之后列表是 [c, b, a]
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Lpush {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", tag="2")]
+    pub key: ::prost::bytes::Bytes,
+    #[prost(message, repeated, tag="3")]
+    pub values: ::prost::alloc::vec::Vec<Value>,
+}
+/// 把 values 依次追加到列表的尾部，返回插入后列表的长度
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Rpush {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", tag="2")]
+    pub key: ::prost::bytes::Bytes,
+    #[prost(message, repeated, tag="3")]
+    pub values: ::prost::alloc::vec::Vec<Value>,
+}
+/// 从列表的头部弹出 count 个 value，count 为 0 时弹出一个
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Lpop {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", tag="2")]
+    pub key: ::prost::bytes::Bytes,
+    #[prost(uint32, tag="3")]
+    pub count: u32,
+}
+/// 从列表的尾部弹出 count 个 value，count 为 0 时弹出一个
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Rpop {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", tag="2")]
+    pub key: ::prost::bytes::Bytes,
+    #[prost(uint32, tag="3")]
+    pub count: u32,
+}
+/// 获取列表 [start, stop] 之间的 value，负数表示从尾部开始数，-1 是最后一个
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Lrange {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", tag="2")]
+    pub key: ::prost::bytes::Bytes,
+    #[prost(int64, tag="3")]
+    pub start: i64,
+    #[prost(int64, tag="4")]
+    pub stop: i64,
+}
+/// 获取列表的长度
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Llen {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", tag="2")]
+    pub key: ::prost::bytes::Bytes,
+}
+/// 只保留列表 [start, stop] 之间的 value，下标的含义和 Lrange 相同
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Ltrim {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", tag="2")]
+    pub key: ::prost::bytes::Bytes,
+    #[prost(int64, tag="3")]
+    pub start: i64,
+    #[prost(int64, tag="4")]
+    pub stop: i64,
+}
+/// 从列表的头部弹出一个 value，列表为空时最多等待 timeout_ms 毫秒，0 表示一直等待
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Blpop {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", tag="2")]
+    pub key: ::prost::bytes::Bytes,
+    #[prost(uint64, tag="3")]
+    pub timeout_ms: u64,
+}
+/// 从列表的尾部弹出一个 value，列表为空时最多等待 timeout_ms 毫秒，0 表示一直等待
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Brpop {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", tag="2")]
+    pub key: ::prost::bytes::Bytes,
+    #[prost(uint64, tag="3")]
+    pub timeout_ms: u64,
+}
+/// 列表的元数据。列表里的 value 分别存放，下标在 [head, tail) 之间
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ListMeta {
+    #[prost(sint64, tag="1")]
+    pub head: i64,
+    #[prost(sint64, tag="2")]
+    pub tail: i64,
+}
+/// 往集合里加入一组成员，返回新加入的成员个数
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Sadd {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", tag="2")]
+    pub key: ::prost::bytes::Bytes,
+    #[prost(message, repeated, tag="3")]
+    pub members: ::prost::alloc::vec::Vec<Value>,
+}
+/// 从集合里删除一组成员，返回实际删除的成员个数
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Srem {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", tag="2")]
+    pub key: ::prost::bytes::Bytes,
+    #[prost(message, repeated, tag="3")]
+    pub members: ::prost::alloc::vec::Vec<Value>,
+}
+/// 获取集合的所有成员
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Smembers {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", tag="2")]
+    pub key: ::prost::bytes::Bytes,
+}
+/// 查看 member 是否在集合里
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Sismember {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", tag="2")]
+    pub key: ::prost::bytes::Bytes,
+    #[prost(message, optional, tag="3")]
+    pub member: ::core::option::Option<Value>,
+}
+/// 获取一组集合的交集
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Sinter {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", repeated, tag="2")]
+    pub keys: ::prost::alloc::vec::Vec<::prost::bytes::Bytes>,
+}
+/// 获取一组集合的并集
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Sunion {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", repeated, tag="2")]
+    pub keys: ::prost::alloc::vec::Vec<::prost::bytes::Bytes>,
+}
+/// 有序集合的成员和它的分数
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ScoredValue {
+    #[prost(message, optional, tag="1")]
+    pub member: ::core::option::Option<Value>,
+    #[prost(double, tag="2")]
+    pub score: f64,
+}
+/// 往有序集合里加入一组成员，已经存在的成员会更新分数，返回新加入的成员个数
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Zadd {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", tag="2")]
+    pub key: ::prost::bytes::Bytes,
+    #[prost(message, repeated, tag="3")]
+    pub members: ::prost::alloc::vec::Vec<ScoredValue>,
+}
+/// 按排名获取有序集合 [start, stop] 之间的成员，下标的含义和 Lrange 相同
+/// rev 为 true 时按分数从大到小排名
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Zrange {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", tag="2")]
+    pub key: ::prost::bytes::Bytes,
+    #[prost(int64, tag="3")]
+    pub start: i64,
+    #[prost(int64, tag="4")]
+    pub stop: i64,
+    #[prost(bool, tag="5")]
+    pub rev: bool,
+}
+/// 获取分数在 [min, max] 之间的成员
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Zrangebyscore {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", tag="2")]
+    pub key: ::prost::bytes::Bytes,
+    #[prost(double, tag="3")]
+    pub min: f64,
+    #[prost(double, tag="4")]
+    pub max: f64,
+}
+/// 获取成员的排名，从 0 开始。rev 为 true 时按分数从大到小排名
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Zrank {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", tag="2")]
+    pub key: ::prost::bytes::Bytes,
+    #[prost(message, optional, tag="3")]
+    pub member: ::core::option::Option<Value>,
+    #[prost(bool, tag="4")]
+    pub rev: bool,
+}
+/// 给成员的分数加上 increment，成员不存在时先加入，返回新的分数
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Zincrby {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", tag="2")]
+    pub key: ::prost::bytes::Bytes,
+    #[prost(message, optional, tag="3")]
+    pub member: ::core::option::Option<Value>,
+    #[prost(double, tag="4")]
+    pub increment: f64,
+}
+/// path 是嵌套 value 里逐层的 map key 或者列表下标，列表下标可以是负数，-1 是最后一个
+/// 获取 key 的 value 在 path 处的值
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct HgetPath {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", tag="2")]
+    pub key: ::prost::bytes::Bytes,
+    #[prost(string, repeated, tag="3")]
+    pub path: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+/// 只修改 key 的 value 在 path 处的值，返回之前的值
+/// path 中间不存在的 map key 会自动创建，key 不存在时从一个空的 map 开始
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct HsetPath {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", tag="2")]
+    pub key: ::prost::bytes::Bytes,
+    #[prost(string, repeated, tag="3")]
+    pub path: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    #[prost(message, optional, tag="4")]
+    pub value: ::core::option::Option<Value>,
+}
+/// 删除 key 的 value 在 path 处的 map key 或者列表元素，返回被删除的值
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct HdelPath {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", tag="2")]
+    pub key: ::prost::bytes::Bytes,
+    #[prost(string, repeated, tag="3")]
+    pub path: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+/// JSON 文档以嵌套的 Value 存放在普通的 table 里，path 是 JSONPath，空字符串等同于 $
+/// 获取文档里所有匹配 path 的值，每个值以 JSON 字符串返回
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct JsonGet {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", tag="2")]
+    pub key: ::prost::bytes::Bytes,
+    #[prost(string, tag="3")]
+    pub path: ::prost::alloc::string::String,
+}
+/// 把所有匹配 path 的值设置成 json，返回设置的个数。写入前会校验 json
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct JsonSet {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", tag="2")]
+    pub key: ::prost::bytes::Bytes,
+    #[prost(string, tag="3")]
+    pub path: ::prost::alloc::string::String,
+    #[prost(string, tag="4")]
+    pub json: ::prost::alloc::string::String,
+}
+/// 删除所有匹配 path 的值，返回删除的个数。path 是 $ 时删除整个文档
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct JsonDel {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", tag="2")]
+    pub key: ::prost::bytes::Bytes,
+    #[prost(string, tag="3")]
+    pub path: ::prost::alloc::string::String,
+}
+/// 往所有匹配 path 的 array 里追加一组 JSON 值，返回追加后 array 的长度
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct JsonArrAppend {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", tag="2")]
+    pub key: ::prost::bytes::Bytes,
+    #[prost(string, tag="3")]
+    pub path: ::prost::alloc::string::String,
+    #[prost(string, repeated, tag="4")]
+    pub values: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+/// 原子地给所有匹配 path 的数字加上 increment，返回新的值
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct JsonNumIncrBy {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", tag="2")]
+    pub key: ::prost::bytes::Bytes,
+    #[prost(string, tag="3")]
+    pub path: ::prost::alloc::string::String,
+    #[prost(double, tag="4")]
+    pub increment: f64,
+}
+/// 流是只能追加的消息序列。消息 id 的格式是 "毫秒时间戳-序号"，按时间有序
+/// 流里的一条消息
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct StreamEntry {
+    #[prost(string, tag="1")]
+    pub id: ::prost::alloc::string::String,
+    #[prost(message, repeated, tag="2")]
+    pub fields: ::prost::alloc::vec::Vec<Kvpair>,
+}
+/// 流的元数据：最后一条消息的 id 和当前的长度
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct StreamMeta {
+    #[prost(uint64, tag="1")]
+    pub last_ms: u64,
+    #[prost(uint64, tag="2")]
+    pub last_seq: u64,
+    #[prost(uint64, tag="3")]
+    pub length: u64,
+}
+/// 已经投递给消费者、还没有确认的消息
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct PendingEntry {
+    #[prost(string, tag="1")]
+    pub id: ::prost::alloc::string::String,
+    #[prost(string, tag="2")]
+    pub consumer: ::prost::alloc::string::String,
+    /// 最后一次投递的时间，unix 毫秒
+    #[prost(uint64, tag="3")]
+    pub delivered_at: u64,
+    #[prost(uint32, tag="4")]
+    pub delivery_count: u32,
+}
+/// 消费组的状态：最后投递的消息 id 和待确认的消息
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct StreamGroup {
+    #[prost(string, tag="1")]
+    pub last_delivered: ::prost::alloc::string::String,
+    #[prost(message, repeated, tag="2")]
+    pub pending: ::prost::alloc::vec::Vec<PendingEntry>,
+}
+/// 往流里追加一条消息，返回消息的 id
+/// id 为空或者 "*" 时自动生成，否则必须比流里最后一条消息的 id 大
+/// max_len / max_age_ms 不为 0 时，追加之后按长度 / 时间裁剪流
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Xadd {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", tag="2")]
+    pub key: ::prost::bytes::Bytes,
+    #[prost(string, tag="3")]
+    pub id: ::prost::alloc::string::String,
+    #[prost(message, repeated, tag="4")]
+    pub fields: ::prost::alloc::vec::Vec<Kvpair>,
+    #[prost(uint64, tag="5")]
+    pub max_len: u64,
+    #[prost(uint64, tag="6")]
+    pub max_age_ms: u64,
+}
+/// 获取 id 在 [start, end] 之间的消息，"-" 表示最小，"+" 表示最大，count 为 0 表示不限制
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Xrange {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", tag="2")]
+    pub key: ::prost::bytes::Bytes,
+    #[prost(string, tag="3")]
+    pub start: ::prost::alloc::string::String,
+    #[prost(string, tag="4")]
+    pub end: ::prost::alloc::string::String,
+    #[prost(uint32, tag="5")]
+    pub count: u32,
+}
+/// 获取 id 大于 after 的消息，after 为 "$" 表示只读取之后新追加的消息
+/// block 为 true 时，没有消息就等待 timeout_ms 毫秒，0 表示一直等待
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Xread {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", tag="2")]
+    pub key: ::prost::bytes::Bytes,
+    #[prost(string, tag="3")]
+    pub after: ::prost::alloc::string::String,
+    #[prost(uint32, tag="4")]
+    pub count: u32,
+    #[prost(bool, tag="5")]
+    pub block: bool,
+    #[prost(uint64, tag="6")]
+    pub timeout_ms: u64,
+}
+/// 获取流的长度
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Xlen {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", tag="2")]
+    pub key: ::prost::bytes::Bytes,
+}
+/// 按长度或者时间裁剪流，返回删除的消息个数
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Xtrim {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", tag="2")]
+    pub key: ::prost::bytes::Bytes,
+    #[prost(uint64, tag="3")]
+    pub max_len: u64,
+    #[prost(uint64, tag="4")]
+    pub max_age_ms: u64,
+}
+/// 创建消费组，从 id 大于 start 的消息开始投递，start 为 "$" 表示只投递之后新追加的消息
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct XgroupCreate {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", tag="2")]
+    pub key: ::prost::bytes::Bytes,
+    #[prost(string, tag="3")]
+    pub group: ::prost::alloc::string::String,
+    #[prost(string, tag="4")]
+    pub start: ::prost::alloc::string::String,
+}
+/// 以 consumer 的身份从消费组读取还没有投递过的消息，读到的消息会记为待确认
+/// block 和 timeout_ms 的含义和 Xread 相同
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Xreadgroup {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", tag="2")]
+    pub key: ::prost::bytes::Bytes,
+    #[prost(string, tag="3")]
+    pub group: ::prost::alloc::string::String,
+    #[prost(string, tag="4")]
+    pub consumer: ::prost::alloc::string::String,
+    #[prost(uint32, tag="5")]
+    pub count: u32,
+    #[prost(bool, tag="6")]
+    pub block: bool,
+    #[prost(uint64, tag="7")]
+    pub timeout_ms: u64,
+}
+/// 确认一组消息已经处理完，返回确认的个数
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Xack {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", tag="2")]
+    pub key: ::prost::bytes::Bytes,
+    #[prost(string, tag="3")]
+    pub group: ::prost::alloc::string::String,
+    #[prost(string, repeated, tag="4")]
+    pub ids: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+/// 获取消费组里所有待确认的消息
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Xpending {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", tag="2")]
+    pub key: ::prost::bytes::Bytes,
+    #[prost(string, tag="3")]
+    pub group: ::prost::alloc::string::String,
+}
+/// 时间序列是按时间戳排序的数值采样点。聚合方式 aggregation 可以是 avg / min / max / sum / count
+/// 时间序列的一个采样点，timestamp 是 unix 毫秒
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Sample {
+    #[prost(uint64, tag="1")]
+    pub timestamp: u64,
+    #[prost(double, tag="2")]
+    pub value: f64,
+}
+/// 降采样规则：源序列每写入一个采样点，就把它所在的 bucket_ms 毫秒的时间桶重新聚合，
+/// 结果以时间桶的起点为时间戳写入 dest_table 里的 dest_key
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct DownsampleRule {
+    #[prost(string, tag="1")]
+    pub dest_table: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", tag="2")]
+    pub dest_key: ::prost::bytes::Bytes,
+    #[prost(string, tag="3")]
+    pub aggregation: ::prost::alloc::string::String,
+    #[prost(uint64, tag="4")]
+    pub bucket_ms: u64,
+}
+/// 时间序列的元数据：保留时长和降采样规则
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct TimeSeriesMeta {
+    /// 每次写入时删除早于序列里最大的时间戳 retention_ms 毫秒的数据，0 表示一直保留
+    #[prost(uint64, tag="1")]
+    pub retention_ms: u64,
+    #[prost(message, repeated, tag="2")]
+    pub rules: ::prost::alloc::vec::Vec<DownsampleRule>,
+    /// 序列里最大的时间戳
+    #[prost(uint64, tag="3")]
+    pub max_timestamp: u64,
+}
+/// 写入一个采样点，timestamp 为 0 时使用当前时间，返回采样点的时间戳
+/// 同一个时间戳的采样点会被覆盖
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct TsAdd {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", tag="2")]
+    pub key: ::prost::bytes::Bytes,
+    #[prost(uint64, tag="3")]
+    pub timestamp: u64,
+    #[prost(double, tag="4")]
+    pub value: f64,
+}
+/// 获取时间戳在 [from, to] 之间的采样点，to 为 0 表示不限制
+/// aggregation 不为空时按 bucket_ms 毫秒的时间桶聚合，返回的时间戳是时间桶的起点
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct TsRange {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", tag="2")]
+    pub key: ::prost::bytes::Bytes,
+    #[prost(uint64, tag="3")]
+    pub from: u64,
+    #[prost(uint64, tag="4")]
+    pub to: u64,
+    #[prost(string, tag="5")]
+    pub aggregation: ::prost::alloc::string::String,
+    #[prost(uint64, tag="6")]
+    pub bucket_ms: u64,
+}
+/// 设置时间序列的保留时长，0 表示一直保留
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct TsSetRetention {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", tag="2")]
+    pub key: ::prost::bytes::Bytes,
+    #[prost(uint64, tag="3")]
+    pub retention_ms: u64,
+}
+/// 给时间序列添加降采样规则，目标序列相同的规则会被替换
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct TsCreateRule {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", tag="2")]
+    pub key: ::prost::bytes::Bytes,
+    #[prost(message, optional, tag="3")]
+    pub rule: ::core::option::Option<DownsampleRule>,
+}
+/// 删除写入 dest_table 里 dest_key 的降采样规则，返回删除的个数
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct TsDeleteRule {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", tag="2")]
+    pub key: ::prost::bytes::Bytes,
+    #[prost(string, tag="3")]
+    pub dest_table: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", tag="4")]
+    pub dest_key: ::prost::bytes::Bytes,
+}
+/// HyperLogLog 和布隆过滤器以 Value::Binary 直接存放在 table 里
+/// 往 key 的 HyperLogLog 里加入一组元素，返回估计值是否可能发生了变化
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Pfadd {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", tag="2")]
+    pub key: ::prost::bytes::Bytes,
+    #[prost(message, repeated, tag="3")]
+    pub items: ::prost::alloc::vec::Vec<Value>,
+}
+/// 估计一组 key 的并集的基数
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Pfcount {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", repeated, tag="2")]
+    pub keys: ::prost::alloc::vec::Vec<::prost::bytes::Bytes>,
+}
+/// 把一组 key 的 HyperLogLog 合并到 dest 里
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Pfmerge {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", tag="2")]
+    pub dest: ::prost::bytes::Bytes,
+    #[prost(bytes="bytes", repeated, tag="3")]
+    pub sources: ::prost::alloc::vec::Vec<::prost::bytes::Bytes>,
+}
+/// 往 key 的布隆过滤器里加入一组元素，返回每个元素之前是否不存在
+/// key 不存在时按 capacity 和 error_rate 创建过滤器，为 0 时使用默认值
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Bfadd {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", tag="2")]
+    pub key: ::prost::bytes::Bytes,
+    #[prost(message, repeated, tag="3")]
+    pub items: ::prost::alloc::vec::Vec<Value>,
+    #[prost(uint64, tag="4")]
+    pub capacity: u64,
+    #[prost(double, tag="5")]
+    pub error_rate: f64,
+}
+/// 返回每个元素是否可能存在于 key 的布隆过滤器里
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Bfexists {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", tag="2")]
+    pub key: ::prost::bytes::Bytes,
+    #[prost(message, repeated, tag="3")]
+    pub items: ::prost::alloc::vec::Vec<Value>,
+}
+/// 位图是 table 里的 Value::Binary，offset 0 是第一个字节的最高位
+/// 设置 offset 处的位，返回之前的值
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Setbit {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", tag="2")]
+    pub key: ::prost::bytes::Bytes,
+    #[prost(uint64, tag="3")]
+    pub offset: u64,
+    #[prost(bool, tag="4")]
+    pub value: bool,
+}
+/// 获取 offset 处的位
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Getbit {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", tag="2")]
+    pub key: ::prost::bytes::Bytes,
+    #[prost(uint64, tag="3")]
+    pub offset: u64,
+}
+/// 统计字节区间 [start, end] 里为 1 的位的个数，负数表示从尾部开始数
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Bitcount {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", tag="2")]
+    pub key: ::prost::bytes::Bytes,
+    #[prost(int64, tag="3")]
+    pub start: i64,
+    #[prost(int64, tag="4")]
+    pub end: i64,
+}
+/// 对一组位图做位运算（AND / OR / XOR / NOT），结果写入 dest，返回结果的字节数
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Bitop {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag="2")]
+    pub op: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", tag="3")]
+    pub dest: ::prost::bytes::Bytes,
+    #[prost(bytes="bytes", repeated, tag="4")]
+    pub keys: ::prost::alloc::vec::Vec<::prost::bytes::Bytes>,
+}
+/// 在字节区间 [start, end] 里找第一个值为 bit 的位，返回它的 offset，找不到时返回 -1
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Bitpos {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(bytes="bytes", tag="2")]
+    pub key: ::prost::bytes::Bytes,
+    #[prost(bool, tag="3")]
+    pub bit: bool,
+    #[prost(int64, tag="4")]
+    pub start: i64,
+    #[prost(int64, tag="5")]
+    pub end: i64,
+}
+/// 二级索引建在 value 本身（path 为空）或者嵌套 value 的某个字段上，只索引标量
+/// Hset / Hmset / Hdel / Hmdel 会维护索引，查询时会用主数据校验索引项
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct IndexDef {
+    #[prost(string, tag="1")]
+    pub name: ::prost::alloc::string::String,
+    #[prost(string, repeated, tag="2")]
+    pub path: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+/// 一个 table 上所有的索引
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct TableIndexes {
+    #[prost(message, repeated, tag="1")]
+    pub indexes: ::prost::alloc::vec::Vec<IndexDef>,
+}
+/// 创建索引并为已有的数据建立索引项，返回建立的索引项个数。索引名只能包含字母、数字、_ 和 -
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct IndexCreate {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag="2")]
+    pub name: ::prost::alloc::string::String,
+    #[prost(string, repeated, tag="3")]
+    pub path: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+/// 删除索引，返回索引是否存在
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct IndexDrop {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag="2")]
+    pub name: ::prost::alloc::string::String,
+}
+/// 获取被索引的值等于 value 的所有 kv pair
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct IndexLookup {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag="2")]
+    pub name: ::prost::alloc::string::String,
+    #[prost(message, optional, tag="3")]
+    pub value: ::core::option::Option<Value>,
+}
+/// 获取被索引的值在 [start, end] 之间的 kv pair，按被索引的值排序
+/// start / end 为空表示不限制，limit 为 0 表示不限制个数
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct IndexRange {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag="2")]
+    pub name: ::prost::alloc::string::String,
+    #[prost(message, optional, tag="3")]
+    pub start: ::core::option::Option<Value>,
+    #[prost(message, optional, tag="4")]
+    pub end: ::core::option::Option<Value>,
+    #[prost(uint32, tag="5")]
+    pub limit: u32,
+}
+/// 嵌套 value 里的一个字段，path 为空表示 value 本身
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct FieldPath {
+    #[prost(string, repeated, tag="1")]
+    pub path: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+/// 过滤条件：path 处的值和 value 比较。op 可以是 eq / ne / lt / le / gt / ge，
+/// type（value 是类型名：null / string / binary / integer / float / bool / list / map）或者 exists
+/// 整数和浮点数之间按数值比较，path 不存在时条件不满足
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Predicate {
+    #[prost(string, repeated, tag="1")]
+    pub path: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    #[prost(string, tag="2")]
+    pub op: ::prost::alloc::string::String,
+    #[prost(message, optional, tag="3")]
+    pub value: ::core::option::Option<Value>,
+}
+/// 聚合：func 可以是 count / sum / min / max / avg，只有整数和浮点数参与 sum / min / max / avg
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Aggregate {
+    #[prost(string, tag="1")]
+    pub func: ::prost::alloc::string::String,
+    #[prost(string, repeated, tag="2")]
+    pub path: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+/// 在服务器端遍历 table，返回 key 匹配 key_pattern（glob，为空表示所有 key）并且满足所有 predicates 的数据
+/// aggregates 为空时返回 kv pair，按 projection 投影，最多 limit 个（0 表示不限制）；
+/// 否则每个聚合返回一个 value
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Query {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag="2")]
+    pub key_pattern: ::prost::alloc::string::String,
+    #[prost(message, repeated, tag="3")]
+    pub predicates: ::prost::alloc::vec::Vec<Predicate>,
+    #[prost(message, repeated, tag="4")]
+    pub projection: ::prost::alloc::vec::Vec<FieldPath>,
+    #[prost(uint32, tag="5")]
+    pub limit: u32,
+    #[prost(message, repeated, tag="6")]
+    pub aggregates: ::prost::alloc::vec::Vec<Aggregate>,
+}
+/// 握手：连接建立后客户端可以先发送 Hello，协商协议版本、压缩算法和限制
+/// 不发送 Hello 的老客户端按协议版本 1、超过 1436 字节使用 gzip 压缩处理
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Hello {
+    /// 客户端支持的最高协议版本
+    #[prost(uint32, tag="1")]
+    pub version: u32,
+    /// 客户端支持的压缩算法（gzip / zstd / lz4），按优先级排列，为空表示不压缩
     #[prost(string, repeated, tag="2")]
-    pub keys: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    pub compressions: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    /// 客户端能接收的最大 frame（字节），0 表示使用服务器的限制
+    #[prost(uint64, tag="3")]
+    pub max_frame_size: u64,
+    /// 客户端的名字，只用于日志
+    #[prost(string, tag="4")]
+    pub client_name: ::prost::alloc::string::String,
+    /// 压缩的阈值（字节），frame 超过这个大小才压缩，0 表示使用服务器默认的阈值
+    #[prost(uint64, tag="5")]
+    pub compression_threshold: u64,
+}
+/// Hello 的结果，以及服务器的信息
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct HelloReply {
+    /// 双方都支持的最高协议版本
+    #[prost(uint32, tag="1")]
+    pub version: u32,
+    /// 双方发送 frame 时使用的压缩算法，为空表示不压缩
+    #[prost(string, tag="2")]
+    pub compression: ::prost::alloc::string::String,
+    /// 双方都能接受的最大 frame（字节）
+    #[prost(uint64, tag="3")]
+    pub max_frame_size: u64,
+    #[prost(string, tag="4")]
+    pub server_name: ::prost::alloc::string::String,
+    #[prost(string, tag="5")]
+    pub server_version: ::prost::alloc::string::String,
+    /// 服务器支持的所有压缩算法和命令
+    #[prost(string, repeated, tag="6")]
+    pub compressions: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    #[prost(string, repeated, tag="7")]
+    pub commands: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    /// 协商后的压缩阈值，双方发送的 frame 超过这个大小才压缩
+    #[prost(uint64, tag="8")]
+    pub compression_threshold: u64,
 }
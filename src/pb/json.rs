@@ -0,0 +1,346 @@
+use serde_json::{Map, Number};
+
+use super::abi::{value, Value, ValueMap};
+use crate::KvError;
+
+// JSON 文档以嵌套的 Value 存放：object 对应 ValueMap，array 对应 ValueList，
+// null 对应空的 Value。这样写入时就完成了校验，存储的是紧凑的 protobuf 编码
+impl Value {
+    pub fn from_json(json: &str) -> Result<Self, KvError> {
+        let v: serde_json::Value = serde_json::from_str(json)?;
+        Ok(v.into())
+    }
+
+    pub fn to_json(&self) -> Result<String, KvError> {
+        Ok(serde_json::to_string(&serde_json::Value::try_from(self)?)?)
+    }
+}
+
+impl From<serde_json::Value> for Value {
+    fn from(v: serde_json::Value) -> Self {
+        match v {
+            serde_json::Value::Null => Value::default(),
+            serde_json::Value::Bool(b) => b.into(),
+            serde_json::Value::Number(n) => match n.as_i64() {
+                Some(i) => i.into(),
+                None => n.as_f64().unwrap_or_default().into(),
+            },
+            serde_json::Value::String(s) => s.into(),
+            serde_json::Value::Array(values) => values
+                .into_iter()
+                .map(Value::from)
+                .collect::<Vec<_>>()
+                .into(),
+            serde_json::Value::Object(fields) => ValueMap {
+                fields: fields.into_iter().map(|(k, v)| (k, v.into())).collect(),
+            }
+            .into(),
+        }
+    }
+}
+
+impl TryFrom<&Value> for serde_json::Value {
+    type Error = KvError;
+
+    fn try_from(v: &Value) -> Result<Self, Self::Error> {
+        Ok(match &v.value {
+            None => serde_json::Value::Null,
+            Some(value::Value::Bool(b)) => (*b).into(),
+            Some(value::Value::Integer(i)) => (*i).into(),
+            Some(value::Value::Float(f)) => Number::from_f64(*f)
+                .map(serde_json::Value::Number)
+                .ok_or_else(|| KvError::ConvertError(v.clone(), "JSON"))?,
+            Some(value::Value::String(s)) => s.as_str().into(),
+            Some(value::Value::List(list)) => serde_json::Value::Array(
+                list.values
+                    .iter()
+                    .map(serde_json::Value::try_from)
+                    .collect::<Result<_, _>>()?,
+            ),
+            Some(value::Value::Map(map)) => serde_json::Value::Object(
+                map.fields
+                    .iter()
+                    .map(|(k, v)| Ok((k.clone(), serde_json::Value::try_from(v)?)))
+                    .collect::<Result<Map<_, _>, KvError>>()?,
+            ),
+            Some(value::Value::Binary(_)) => return Err(KvError::ConvertError(v.clone(), "JSON")),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Key(String),
+    Index(i64),
+    Wildcard,
+}
+
+// JSONPath 的一个子集：$ 表示根，.name 和 ['name'] 表示 object 的字段，
+// [n] 表示 array 的下标（负数从尾部开始数），.* 和 [*] 匹配所有的子节点
+#[derive(Debug, Clone, PartialEq)]
+pub struct JsonPath {
+    segments: Vec<Segment>,
+}
+
+impl JsonPath {
+    pub fn parse(path: &str) -> Result<Self, KvError> {
+        let invalid = || KvError::InvalidCommand(format!("Invalid JSONPath: {}", path));
+        let rest = path.strip_prefix('$').unwrap_or(path);
+        let chars: Vec<char> = rest.chars().collect();
+        let mut segments = vec![];
+        let mut i = 0;
+        while i < chars.len() {
+            match chars[i] {
+                '.' => {
+                    let end = chars[i + 1..]
+                        .iter()
+                        .position(|c| *c == '.' || *c == '[')
+                        .map_or(chars.len(), |p| i + 1 + p);
+                    let name: String = chars[i + 1..end].iter().collect();
+                    segments.push(match name.as_str() {
+                        "" => return Err(invalid()),
+                        "*" => Segment::Wildcard,
+                        _ => Segment::Key(name),
+                    });
+                    i = end;
+                }
+                '[' => {
+                    let end = i + chars[i..]
+                        .iter()
+                        .position(|c| *c == ']')
+                        .ok_or_else(invalid)?;
+                    let inner: String = chars[i + 1..end].iter().collect();
+                    let quoted = inner.len() >= 2
+                        && (inner.starts_with('\'') && inner.ends_with('\'')
+                            || inner.starts_with('"') && inner.ends_with('"'));
+                    segments.push(if inner == "*" {
+                        Segment::Wildcard
+                    } else if quoted {
+                        Segment::Key(inner[1..inner.len() - 1].to_string())
+                    } else {
+                        Segment::Index(inner.trim().parse().map_err(|_| invalid())?)
+                    });
+                    i = end + 1;
+                }
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(Self { segments })
+    }
+
+    // 是否指向整个文档
+    pub fn is_root(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    // 找到所有匹配的节点
+    pub fn select<'a>(&self, doc: &'a Value) -> Vec<&'a Value> {
+        let mut result = vec![doc];
+        for seg in &self.segments {
+            result = result.into_iter().flat_map(|v| children(v, seg)).collect();
+        }
+        result
+    }
+
+    // 对所有匹配的节点调用 f，返回匹配的个数
+    pub fn for_each_mut(
+        &self,
+        doc: &mut Value,
+        f: &mut dyn FnMut(&mut Value) -> Result<(), KvError>,
+    ) -> Result<usize, KvError> {
+        visit_mut(doc, &self.segments, f)
+    }
+
+    // 把所有匹配的节点设置成 value，返回设置的个数
+    // 最后一段是 object 里不存在的字段时会新建这个字段
+    pub fn set(&self, doc: &mut Value, value: Value) -> Result<usize, KvError> {
+        let (last, parents) = match self.segments.split_last() {
+            Some(v) => v,
+            None => {
+                *doc = value;
+                return Ok(1);
+            }
+        };
+
+        let mut count = 0;
+        visit_mut(doc, parents, &mut |parent| {
+            match (&mut parent.value, last) {
+                (Some(value::Value::Map(map)), Segment::Key(k)) => {
+                    map.fields.insert(k.clone(), value.clone());
+                    count += 1;
+                }
+                (Some(value::Value::Map(map)), Segment::Wildcard) => {
+                    for v in map.fields.values_mut() {
+                        *v = value.clone();
+                        count += 1;
+                    }
+                }
+                (Some(value::Value::List(list)), Segment::Index(i)) => {
+                    if let Some(i) = list_index(*i, list.values.len()) {
+                        list.values[i] = value.clone();
+                        count += 1;
+                    }
+                }
+                (Some(value::Value::List(list)), Segment::Wildcard) => {
+                    for v in list.values.iter_mut() {
+                        *v = value.clone();
+                        count += 1;
+                    }
+                }
+                _ => {}
+            }
+            Ok(())
+        })?;
+        Ok(count)
+    }
+
+    // 删除所有匹配的节点，返回删除的个数。不能删除根节点
+    pub fn delete(&self, doc: &mut Value) -> Result<usize, KvError> {
+        let (last, parents) = self.segments.split_last().ok_or_else(|| {
+            KvError::InvalidCommand("Cannot delete the root of a document".into())
+        })?;
+
+        let mut count = 0;
+        visit_mut(doc, parents, &mut |parent| {
+            match (&mut parent.value, last) {
+                (Some(value::Value::Map(map)), Segment::Key(k)) => {
+                    count += map.fields.remove(k).map_or(0, |_| 1);
+                }
+                (Some(value::Value::Map(map)), Segment::Wildcard) => {
+                    count += map.fields.len();
+                    map.fields.clear();
+                }
+                (Some(value::Value::List(list)), Segment::Index(i)) => {
+                    if let Some(i) = list_index(*i, list.values.len()) {
+                        list.values.remove(i);
+                        count += 1;
+                    }
+                }
+                (Some(value::Value::List(list)), Segment::Wildcard) => {
+                    count += list.values.len();
+                    list.values.clear();
+                }
+                _ => {}
+            }
+            Ok(())
+        })?;
+        Ok(count)
+    }
+}
+
+fn children<'a>(v: &'a Value, seg: &Segment) -> Vec<&'a Value> {
+    match (&v.value, seg) {
+        (Some(value::Value::Map(map)), Segment::Key(k)) => map.fields.get(k).into_iter().collect(),
+        (Some(value::Value::Map(map)), Segment::Wildcard) => map.fields.values().collect(),
+        (Some(value::Value::List(list)), Segment::Index(i)) => list_index(*i, list.values.len())
+            .map(|i| &list.values[i])
+            .into_iter()
+            .collect(),
+        (Some(value::Value::List(list)), Segment::Wildcard) => list.values.iter().collect(),
+        _ => vec![],
+    }
+}
+
+fn visit_mut(
+    v: &mut Value,
+    segments: &[Segment],
+    f: &mut dyn FnMut(&mut Value) -> Result<(), KvError>,
+) -> Result<usize, KvError> {
+    let (seg, rest) = match segments.split_first() {
+        Some(v) => v,
+        None => {
+            f(v)?;
+            return Ok(1);
+        }
+    };
+
+    let mut count = 0;
+    match (&mut v.value, seg) {
+        (Some(value::Value::Map(map)), Segment::Key(k)) => {
+            if let Some(child) = map.fields.get_mut(k) {
+                count += visit_mut(child, rest, f)?;
+            }
+        }
+        (Some(value::Value::Map(map)), Segment::Wildcard) => {
+            for child in map.fields.values_mut() {
+                count += visit_mut(child, rest, f)?;
+            }
+        }
+        (Some(value::Value::List(list)), Segment::Index(i)) => {
+            if let Some(i) = list_index(*i, list.values.len()) {
+                count += visit_mut(&mut list.values[i], rest, f)?;
+            }
+        }
+        (Some(value::Value::List(list)), Segment::Wildcard) => {
+            for child in list.values.iter_mut() {
+                count += visit_mut(child, rest, f)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(count)
+}
+
+fn list_index(i: i64, len: usize) -> Option<usize> {
+    let i = if i < 0 { len as i64 + i } else { i };
+    (0..len as i64).contains(&i).then_some(i as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_conversion_should_work() {
+        let json = r#"{"a":1,"b":[true,null,"x"],"c":{"d":1.5}}"#;
+        let v = Value::from_json(json).unwrap();
+        assert_eq!(v.to_json().unwrap(), json);
+        assert!(Value::from_json("{bad json").is_err());
+    }
+
+    #[test]
+    fn jsonpath_parse_should_work() {
+        use Segment::*;
+        let path = JsonPath::parse("$.a['b c'][0].*[-1][*]").unwrap();
+        assert_eq!(
+            path.segments,
+            vec![
+                Key("a".into()),
+                Key("b c".into()),
+                Index(0),
+                Wildcard,
+                Index(-1),
+                Wildcard
+            ]
+        );
+        assert!(JsonPath::parse("$").unwrap().is_root());
+        assert!(JsonPath::parse("").unwrap().is_root());
+        assert!(JsonPath::parse("$..a").is_err());
+        assert!(JsonPath::parse("$[abc]").is_err());
+        assert!(JsonPath::parse("$a").is_err());
+    }
+
+    #[test]
+    fn jsonpath_select_set_delete_should_work() {
+        let mut doc = Value::from_json(r#"{"items":[{"n":1},{"n":2}],"name":"x"}"#).unwrap();
+        let path = JsonPath::parse("$.items[*].n").unwrap();
+        assert_eq!(path.select(&doc), vec![&Value::from(1), &Value::from(2)]);
+        assert_eq!(path.set(&mut doc, 0.into()).unwrap(), 2);
+        assert_eq!(
+            doc.to_json().unwrap(),
+            r#"{"items":[{"n":0},{"n":0}],"name":"x"}"#
+        );
+
+        // 不存在的字段会新建
+        let path = JsonPath::parse("$.tags").unwrap();
+        assert_eq!(path.set(&mut doc, vec![].into()).unwrap(), 1);
+
+        let path = JsonPath::parse("$.items[-1]").unwrap();
+        assert_eq!(path.delete(&mut doc).unwrap(), 1);
+        assert_eq!(
+            doc.to_json().unwrap(),
+            r#"{"items":[{"n":0}],"name":"x","tags":[]}"#
+        );
+        assert!(JsonPath::parse("$").unwrap().delete(&mut doc).is_err());
+    }
+}
@@ -1,103 +1,1074 @@
 pub mod abi;
+// tonic 生成的 gRPC 服务端和客户端，消息类型都来自 abi
+pub mod grpc {
+    tonic::include_proto!("abi");
+}
+mod json;
+mod path;
+
+pub use json::JsonPath;
+
+use abi::{command_request::RequestData, *};
+use bytes::Bytes;
+use http::StatusCode;
+use prost::Message;
+use std::{
+    collections::{BTreeMap, HashMap},
+    convert::TryFrom,
+};
+
+use crate::{CompressionAlgorithm, FrameCompression, KvError, PROTOCOL_VERSION};
+
+// 可以作为 key 的类型。key 是任意的字节，为了兼容之前的用法，字符串也可以直接当 key 用
+pub trait IntoKey {
+    fn into_key(self) -> Bytes;
+}
+
+impl IntoKey for Bytes {
+    fn into_key(self) -> Bytes {
+        self
+    }
+}
+
+impl IntoKey for Vec<u8> {
+    fn into_key(self) -> Bytes {
+        self.into()
+    }
+}
+
+impl IntoKey for &[u8] {
+    fn into_key(self) -> Bytes {
+        Bytes::copy_from_slice(self)
+    }
+}
+
+impl<const N: usize> IntoKey for &[u8; N] {
+    fn into_key(self) -> Bytes {
+        Bytes::copy_from_slice(self)
+    }
+}
+
+impl IntoKey for String {
+    fn into_key(self) -> Bytes {
+        self.into()
+    }
+}
+
+impl IntoKey for &String {
+    fn into_key(self) -> Bytes {
+        Bytes::copy_from_slice(self.as_bytes())
+    }
+}
+
+impl IntoKey for &str {
+    fn into_key(self) -> Bytes {
+        Bytes::copy_from_slice(self.as_bytes())
+    }
+}
+
+impl CommandRequest {
+    // 创建 HSET 命令
+    pub fn new_hset(table: impl Into<String>, key: impl IntoKey, value: Value) -> Self {
+        Self {
+            request_data: Some(RequestData::Hset(Hset {
+                table: table.into(),
+                pair: Some(Kvpair::new(key, value)),
+            })),
+        }
+    }
+
+    // 创建 HGET 命令
+    pub fn new_hget(table: impl Into<String>, key: impl IntoKey) -> Self {
+        Self {
+            request_data: Some(RequestData::Hget(Hget {
+                table: table.into(),
+                key: key.into_key(),
+            })),
+        }
+    }
+
+    pub fn new_hmget(table: impl Into<String>, keys: Vec<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Hmget(Hmget {
+                table: table.into(),
+                keys: keys.into_iter().map(IntoKey::into_key).collect(),
+            })),
+        }
+    }
+
+    pub fn new_hmset(table: impl Into<String>, pairs: Vec<(&str, impl Into<Value>)>) -> Self {
+        Self {
+            request_data: Some(RequestData::Hmset(Hmset {
+                table: table.into(),
+                pairs: pairs.into_iter().map(|v| v.into()).collect::<Vec<_>>(),
+            })),
+        }
+    }
+
+    pub fn new_hexist(table: impl Into<String>, key: impl IntoKey) -> Self {
+        Self {
+            request_data: Some(RequestData::Hexist(Hexist {
+                table: table.into(),
+                key: key.into_key(),
+            })),
+        }
+    }
+
+    pub fn new_hmexist(table: impl Into<String>, keys: Vec<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Hmexist(Hmexist {
+                table: table.into(),
+                keys: keys.into_iter().map(IntoKey::into_key).collect(),
+            })),
+        }
+    }
+
+    // 创建 HGETALL 命令
+    pub fn new_hgetall(table: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Hgetall(Hgetall {
+                table: table.into(),
+            })),
+        }
+    }
+
+    pub fn new_hdel(table: impl Into<String>, key: impl IntoKey) -> Self {
+        Self {
+            request_data: Some(RequestData::Hdel(Hdel {
+                table: table.into(),
+                key: key.into_key(),
+            })),
+        }
+    }
+
+    pub fn new_hmdel(table: impl Into<String>, keys: Vec<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Hmdel(Hmdel {
+                table: table.into(),
+                keys: keys.into_iter().map(IntoKey::into_key).collect(),
+            })),
+        }
+    }
+}
+
+impl CommandRequest {
+    // 开启 table 的多版本，policy 为 None 时关闭
+    pub fn new_hversioning(table: impl Into<String>, policy: Option<VersionPolicy>) -> Self {
+        Self {
+            request_data: Some(RequestData::Hversioning(Hversioning {
+                table: table.into(),
+                policy,
+            })),
+        }
+    }
+
+    pub fn new_hget_version(table: impl Into<String>, key: impl IntoKey, version: u64) -> Self {
+        Self {
+            request_data: Some(RequestData::HgetVersion(HgetVersion {
+                table: table.into(),
+                key: key.into_key(),
+                version,
+            })),
+        }
+    }
+
+    pub fn new_hhistory(table: impl Into<String>, key: impl IntoKey) -> Self {
+        Self {
+            request_data: Some(RequestData::Hhistory(Hhistory {
+                table: table.into(),
+                key: key.into_key(),
+            })),
+        }
+    }
+
+    pub fn new_hgetall_as_of(table: impl Into<String>, version: u64) -> Self {
+        Self {
+            request_data: Some(RequestData::HgetallAsOf(HgetallAsOf {
+                table: table.into(),
+                version,
+            })),
+        }
+    }
+}
+
+impl CommandRequest {
+    pub fn new_lpush(table: impl Into<String>, key: impl IntoKey, values: Vec<Value>) -> Self {
+        Self {
+            request_data: Some(RequestData::Lpush(Lpush {
+                table: table.into(),
+                key: key.into_key(),
+                values,
+            })),
+        }
+    }
+
+    pub fn new_rpush(table: impl Into<String>, key: impl IntoKey, values: Vec<Value>) -> Self {
+        Self {
+            request_data: Some(RequestData::Rpush(Rpush {
+                table: table.into(),
+                key: key.into_key(),
+                values,
+            })),
+        }
+    }
+
+    pub fn new_lpop(table: impl Into<String>, key: impl IntoKey, count: u32) -> Self {
+        Self {
+            request_data: Some(RequestData::Lpop(Lpop {
+                table: table.into(),
+                key: key.into_key(),
+                count,
+            })),
+        }
+    }
+
+    pub fn new_rpop(table: impl Into<String>, key: impl IntoKey, count: u32) -> Self {
+        Self {
+            request_data: Some(RequestData::Rpop(Rpop {
+                table: table.into(),
+                key: key.into_key(),
+                count,
+            })),
+        }
+    }
+
+    pub fn new_lrange(table: impl Into<String>, key: impl IntoKey, start: i64, stop: i64) -> Self {
+        Self {
+            request_data: Some(RequestData::Lrange(Lrange {
+                table: table.into(),
+                key: key.into_key(),
+                start,
+                stop,
+            })),
+        }
+    }
+
+    pub fn new_llen(table: impl Into<String>, key: impl IntoKey) -> Self {
+        Self {
+            request_data: Some(RequestData::Llen(Llen {
+                table: table.into(),
+                key: key.into_key(),
+            })),
+        }
+    }
+
+    pub fn new_ltrim(table: impl Into<String>, key: impl IntoKey, start: i64, stop: i64) -> Self {
+        Self {
+            request_data: Some(RequestData::Ltrim(Ltrim {
+                table: table.into(),
+                key: key.into_key(),
+                start,
+                stop,
+            })),
+        }
+    }
+
+    // timeout_ms 为 0 表示一直等待
+    pub fn new_blpop(table: impl Into<String>, key: impl IntoKey, timeout_ms: u64) -> Self {
+        Self {
+            request_data: Some(RequestData::Blpop(Blpop {
+                table: table.into(),
+                key: key.into_key(),
+                timeout_ms,
+            })),
+        }
+    }
+
+    pub fn new_brpop(table: impl Into<String>, key: impl IntoKey, timeout_ms: u64) -> Self {
+        Self {
+            request_data: Some(RequestData::Brpop(Brpop {
+                table: table.into(),
+                key: key.into_key(),
+                timeout_ms,
+            })),
+        }
+    }
+}
+
+impl CommandRequest {
+    pub fn new_sadd(table: impl Into<String>, key: impl IntoKey, members: Vec<Value>) -> Self {
+        Self {
+            request_data: Some(RequestData::Sadd(Sadd {
+                table: table.into(),
+                key: key.into_key(),
+                members,
+            })),
+        }
+    }
+
+    pub fn new_srem(table: impl Into<String>, key: impl IntoKey, members: Vec<Value>) -> Self {
+        Self {
+            request_data: Some(RequestData::Srem(Srem {
+                table: table.into(),
+                key: key.into_key(),
+                members,
+            })),
+        }
+    }
+
+    pub fn new_smembers(table: impl Into<String>, key: impl IntoKey) -> Self {
+        Self {
+            request_data: Some(RequestData::Smembers(Smembers {
+                table: table.into(),
+                key: key.into_key(),
+            })),
+        }
+    }
+
+    pub fn new_sismember(table: impl Into<String>, key: impl IntoKey, member: Value) -> Self {
+        Self {
+            request_data: Some(RequestData::Sismember(Sismember {
+                table: table.into(),
+                key: key.into_key(),
+                member: Some(member),
+            })),
+        }
+    }
+
+    pub fn new_sinter(table: impl Into<String>, keys: Vec<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Sinter(Sinter {
+                table: table.into(),
+                keys: keys.into_iter().map(IntoKey::into_key).collect(),
+            })),
+        }
+    }
+
+    pub fn new_sunion(table: impl Into<String>, keys: Vec<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Sunion(Sunion {
+                table: table.into(),
+                keys: keys.into_iter().map(IntoKey::into_key).collect(),
+            })),
+        }
+    }
+
+    pub fn new_zadd(
+        table: impl Into<String>,
+        key: impl IntoKey,
+        members: Vec<ScoredValue>,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Zadd(Zadd {
+                table: table.into(),
+                key: key.into_key(),
+                members,
+            })),
+        }
+    }
+
+    pub fn new_zrange(
+        table: impl Into<String>,
+        key: impl IntoKey,
+        start: i64,
+        stop: i64,
+        rev: bool,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Zrange(Zrange {
+                table: table.into(),
+                key: key.into_key(),
+                start,
+                stop,
+                rev,
+            })),
+        }
+    }
+
+    pub fn new_zrangebyscore(
+        table: impl Into<String>,
+        key: impl IntoKey,
+        min: f64,
+        max: f64,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Zrangebyscore(Zrangebyscore {
+                table: table.into(),
+                key: key.into_key(),
+                min,
+                max,
+            })),
+        }
+    }
+
+    pub fn new_zrank(
+        table: impl Into<String>,
+        key: impl IntoKey,
+        member: Value,
+        rev: bool,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Zrank(Zrank {
+                table: table.into(),
+                key: key.into_key(),
+                member: Some(member),
+                rev,
+            })),
+        }
+    }
+
+    pub fn new_zincrby(
+        table: impl Into<String>,
+        key: impl IntoKey,
+        member: Value,
+        increment: f64,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Zincrby(Zincrby {
+                table: table.into(),
+                key: key.into_key(),
+                member: Some(member),
+                increment,
+            })),
+        }
+    }
+}
+
+impl CommandRequest {
+    pub fn new_hget_path(table: impl Into<String>, key: impl IntoKey, path: &[&str]) -> Self {
+        Self {
+            request_data: Some(RequestData::HgetPath(HgetPath {
+                table: table.into(),
+                key: key.into_key(),
+                path: to_path(path),
+            })),
+        }
+    }
+
+    pub fn new_hset_path(
+        table: impl Into<String>,
+        key: impl IntoKey,
+        path: &[&str],
+        value: Value,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::HsetPath(HsetPath {
+                table: table.into(),
+                key: key.into_key(),
+                path: to_path(path),
+                value: Some(value),
+            })),
+        }
+    }
+
+    pub fn new_hdel_path(table: impl Into<String>, key: impl IntoKey, path: &[&str]) -> Self {
+        Self {
+            request_data: Some(RequestData::HdelPath(HdelPath {
+                table: table.into(),
+                key: key.into_key(),
+                path: to_path(path),
+            })),
+        }
+    }
+}
+
+fn to_path(path: &[&str]) -> Vec<String> {
+    path.iter().map(|s| s.to_string()).collect()
+}
+
+impl CommandRequest {
+    pub fn new_json_get(
+        table: impl Into<String>,
+        key: impl IntoKey,
+        path: impl Into<String>,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::JsonGet(JsonGet {
+                table: table.into(),
+                key: key.into_key(),
+                path: path.into(),
+            })),
+        }
+    }
+
+    pub fn new_json_set(
+        table: impl Into<String>,
+        key: impl IntoKey,
+        path: impl Into<String>,
+        json: impl Into<String>,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::JsonSet(JsonSet {
+                table: table.into(),
+                key: key.into_key(),
+                path: path.into(),
+                json: json.into(),
+            })),
+        }
+    }
+
+    pub fn new_json_del(
+        table: impl Into<String>,
+        key: impl IntoKey,
+        path: impl Into<String>,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::JsonDel(JsonDel {
+                table: table.into(),
+                key: key.into_key(),
+                path: path.into(),
+            })),
+        }
+    }
+
+    pub fn new_json_arr_append(
+        table: impl Into<String>,
+        key: impl IntoKey,
+        path: impl Into<String>,
+        values: Vec<String>,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::JsonArrAppend(JsonArrAppend {
+                table: table.into(),
+                key: key.into_key(),
+                path: path.into(),
+                values,
+            })),
+        }
+    }
+
+    pub fn new_json_num_incr_by(
+        table: impl Into<String>,
+        key: impl IntoKey,
+        path: impl Into<String>,
+        increment: f64,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::JsonNumIncrBy(JsonNumIncrBy {
+                table: table.into(),
+                key: key.into_key(),
+                path: path.into(),
+                increment,
+            })),
+        }
+    }
+}
+
+impl CommandRequest {
+    // id 为 None 时自动生成
+    pub fn new_xadd(
+        table: impl Into<String>,
+        key: impl IntoKey,
+        id: Option<String>,
+        fields: Vec<Kvpair>,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Xadd(Xadd {
+                table: table.into(),
+                key: key.into_key(),
+                id: id.unwrap_or_default(),
+                fields,
+                ..Default::default()
+            })),
+        }
+    }
+
+    pub fn new_xrange(
+        table: impl Into<String>,
+        key: impl IntoKey,
+        start: impl Into<String>,
+        end: impl Into<String>,
+        count: u32,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Xrange(Xrange {
+                table: table.into(),
+                key: key.into_key(),
+                start: start.into(),
+                end: end.into(),
+                count,
+            })),
+        }
+    }
+
+    // timeout_ms 为 None 时不等待，Some(0) 表示一直等待
+    pub fn new_xread(
+        table: impl Into<String>,
+        key: impl IntoKey,
+        after: impl Into<String>,
+        count: u32,
+        timeout_ms: Option<u64>,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Xread(Xread {
+                table: table.into(),
+                key: key.into_key(),
+                after: after.into(),
+                count,
+                block: timeout_ms.is_some(),
+                timeout_ms: timeout_ms.unwrap_or_default(),
+            })),
+        }
+    }
+
+    pub fn new_xlen(table: impl Into<String>, key: impl IntoKey) -> Self {
+        Self {
+            request_data: Some(RequestData::Xlen(Xlen {
+                table: table.into(),
+                key: key.into_key(),
+            })),
+        }
+    }
+
+    pub fn new_xtrim(
+        table: impl Into<String>,
+        key: impl IntoKey,
+        max_len: u64,
+        max_age_ms: u64,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Xtrim(Xtrim {
+                table: table.into(),
+                key: key.into_key(),
+                max_len,
+                max_age_ms,
+            })),
+        }
+    }
+
+    pub fn new_xgroup_create(
+        table: impl Into<String>,
+        key: impl IntoKey,
+        group: impl Into<String>,
+        start: impl Into<String>,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::XgroupCreate(XgroupCreate {
+                table: table.into(),
+                key: key.into_key(),
+                group: group.into(),
+                start: start.into(),
+            })),
+        }
+    }
+
+    // timeout_ms 的含义和 new_xread 相同
+    pub fn new_xreadgroup(
+        table: impl Into<String>,
+        key: impl IntoKey,
+        group: impl Into<String>,
+        consumer: impl Into<String>,
+        count: u32,
+        timeout_ms: Option<u64>,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Xreadgroup(Xreadgroup {
+                table: table.into(),
+                key: key.into_key(),
+                group: group.into(),
+                consumer: consumer.into(),
+                count,
+                block: timeout_ms.is_some(),
+                timeout_ms: timeout_ms.unwrap_or_default(),
+            })),
+        }
+    }
+
+    pub fn new_xack(
+        table: impl Into<String>,
+        key: impl IntoKey,
+        group: impl Into<String>,
+        ids: Vec<String>,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Xack(Xack {
+                table: table.into(),
+                key: key.into_key(),
+                group: group.into(),
+                ids,
+            })),
+        }
+    }
 
-use abi::{command_request::RequestData, *};
-use bytes::Bytes;
-use http::StatusCode;
-use prost::Message;
-use std::convert::TryFrom;
+    pub fn new_xpending(
+        table: impl Into<String>,
+        key: impl IntoKey,
+        group: impl Into<String>,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Xpending(Xpending {
+                table: table.into(),
+                key: key.into_key(),
+                group: group.into(),
+            })),
+        }
+    }
+}
+
+impl CommandRequest {
+    // timestamp 为 0 时使用当前时间
+    pub fn new_ts_add(
+        table: impl Into<String>,
+        key: impl IntoKey,
+        timestamp: u64,
+        value: f64,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::TsAdd(TsAdd {
+                table: table.into(),
+                key: key.into_key(),
+                timestamp,
+                value,
+            })),
+        }
+    }
+
+    // aggregation 为 None 时返回原始的采样点，否则是 (聚合方式, 时间桶的毫秒数)
+    pub fn new_ts_range(
+        table: impl Into<String>,
+        key: impl IntoKey,
+        from: u64,
+        to: u64,
+        aggregation: Option<(&str, u64)>,
+    ) -> Self {
+        let (aggregation, bucket_ms) = aggregation.unwrap_or_default();
+        Self {
+            request_data: Some(RequestData::TsRange(TsRange {
+                table: table.into(),
+                key: key.into_key(),
+                from,
+                to,
+                aggregation: aggregation.into(),
+                bucket_ms,
+            })),
+        }
+    }
+
+    pub fn new_ts_set_retention(
+        table: impl Into<String>,
+        key: impl IntoKey,
+        retention_ms: u64,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::TsSetRetention(TsSetRetention {
+                table: table.into(),
+                key: key.into_key(),
+                retention_ms,
+            })),
+        }
+    }
+
+    pub fn new_ts_create_rule(
+        table: impl Into<String>,
+        key: impl IntoKey,
+        dest_table: impl Into<String>,
+        dest_key: impl IntoKey,
+        aggregation: impl Into<String>,
+        bucket_ms: u64,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::TsCreateRule(TsCreateRule {
+                table: table.into(),
+                key: key.into_key(),
+                rule: Some(DownsampleRule {
+                    dest_table: dest_table.into(),
+                    dest_key: dest_key.into_key(),
+                    aggregation: aggregation.into(),
+                    bucket_ms,
+                }),
+            })),
+        }
+    }
 
-use crate::KvError;
+    pub fn new_ts_delete_rule(
+        table: impl Into<String>,
+        key: impl IntoKey,
+        dest_table: impl Into<String>,
+        dest_key: impl IntoKey,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::TsDeleteRule(TsDeleteRule {
+                table: table.into(),
+                key: key.into_key(),
+                dest_table: dest_table.into(),
+                dest_key: dest_key.into_key(),
+            })),
+        }
+    }
+}
 
 impl CommandRequest {
-    // 创建 HSET 命令
-    pub fn new_hset(table: impl Into<String>, key: impl Into<String>, value: Value) -> Self {
+    pub fn new_pfadd(table: impl Into<String>, key: impl IntoKey, items: Vec<Value>) -> Self {
         Self {
-            request_data: Some(RequestData::Hset(Hset {
+            request_data: Some(RequestData::Pfadd(Pfadd {
                 table: table.into(),
-                pair: Some(Kvpair::new(key, value)),
+                key: key.into_key(),
+                items,
             })),
         }
     }
 
-    // 创建 HGET 命令
-    pub fn new_hget(table: impl Into<String>, key: impl Into<String>) -> Self {
+    pub fn new_pfcount(table: impl Into<String>, keys: Vec<String>) -> Self {
         Self {
-            request_data: Some(RequestData::Hget(Hget {
+            request_data: Some(RequestData::Pfcount(Pfcount {
                 table: table.into(),
-                key: key.into(),
+                keys: keys.into_iter().map(IntoKey::into_key).collect(),
             })),
         }
     }
 
-    pub fn new_hmget(table: impl Into<String>, keys: Vec<String>) -> Self {
+    pub fn new_pfmerge(table: impl Into<String>, dest: impl IntoKey, sources: Vec<String>) -> Self {
         Self {
-            request_data: Some(RequestData::Hmget(Hmget {
+            request_data: Some(RequestData::Pfmerge(Pfmerge {
                 table: table.into(),
-                keys,
+                dest: dest.into_key(),
+                sources: sources.into_iter().map(IntoKey::into_key).collect(),
             })),
         }
     }
 
-    pub fn new_hmset(table: impl Into<String>, pairs: Vec<(&str, impl Into<Value>)>) -> Self {
+    // 使用默认的容量和误判率
+    pub fn new_bfadd(table: impl Into<String>, key: impl IntoKey, items: Vec<Value>) -> Self {
+        Self::new_bfadd_with(table, key, items, 0, 0.0)
+    }
+
+    pub fn new_bfadd_with(
+        table: impl Into<String>,
+        key: impl IntoKey,
+        items: Vec<Value>,
+        capacity: u64,
+        error_rate: f64,
+    ) -> Self {
         Self {
-            request_data: Some(RequestData::Hmset(Hmset {
+            request_data: Some(RequestData::Bfadd(Bfadd {
                 table: table.into(),
-                pairs: pairs.into_iter().map(|v| v.into()).collect::<Vec<_>>(),
+                key: key.into_key(),
+                items,
+                capacity,
+                error_rate,
             })),
         }
     }
 
-    pub fn new_hexist(table: impl Into<String>, key: impl Into<String>) -> Self {
+    pub fn new_bfexists(table: impl Into<String>, key: impl IntoKey, items: Vec<Value>) -> Self {
         Self {
-            request_data: Some(RequestData::Hexist(Hexist {
+            request_data: Some(RequestData::Bfexists(Bfexists {
                 table: table.into(),
-                key: key.into(),
+                key: key.into_key(),
+                items,
             })),
         }
     }
+}
 
-    pub fn new_hmexist(table: impl Into<String>, keys: Vec<String>) -> Self {
+impl CommandRequest {
+    pub fn new_setbit(
+        table: impl Into<String>,
+        key: impl IntoKey,
+        offset: u64,
+        value: bool,
+    ) -> Self {
         Self {
-            request_data: Some(RequestData::Hmexist(Hmexist {
+            request_data: Some(RequestData::Setbit(Setbit {
                 table: table.into(),
-                keys,
+                key: key.into_key(),
+                offset,
+                value,
             })),
         }
     }
 
-    // 创建 HGETALL 命令
-    pub fn new_hgetall(table: impl Into<String>) -> Self {
+    pub fn new_getbit(table: impl Into<String>, key: impl IntoKey, offset: u64) -> Self {
         Self {
-            request_data: Some(RequestData::Hgetall(Hgetall {
+            request_data: Some(RequestData::Getbit(Getbit {
                 table: table.into(),
+                key: key.into_key(),
+                offset,
             })),
         }
     }
 
-    pub fn new_hdel(table: impl Into<String>, key: impl Into<String>) -> Self {
+    pub fn new_bitcount(table: impl Into<String>, key: impl IntoKey, start: i64, end: i64) -> Self {
         Self {
-            request_data: Some(RequestData::Hdel(Hdel {
+            request_data: Some(RequestData::Bitcount(Bitcount {
                 table: table.into(),
-                key: key.into(),
+                key: key.into_key(),
+                start,
+                end,
             })),
         }
     }
 
-    pub fn new_hmdel(table: impl Into<String>, keys: Vec<String>) -> Self {
+    pub fn new_bitop(
+        table: impl Into<String>,
+        op: impl Into<String>,
+        dest: impl IntoKey,
+        keys: Vec<String>,
+    ) -> Self {
         Self {
-            request_data: Some(RequestData::Hmdel(Hmdel {
+            request_data: Some(RequestData::Bitop(Bitop {
+                table: table.into(),
+                op: op.into(),
+                dest: dest.into_key(),
+                keys: keys.into_iter().map(IntoKey::into_key).collect(),
+            })),
+        }
+    }
+
+    pub fn new_bitpos(
+        table: impl Into<String>,
+        key: impl IntoKey,
+        bit: bool,
+        start: i64,
+        end: i64,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Bitpos(Bitpos {
+                table: table.into(),
+                key: key.into_key(),
+                bit,
+                start,
+                end,
+            })),
+        }
+    }
+}
+
+impl CommandRequest {
+    pub fn new_index_create(
+        table: impl Into<String>,
+        name: impl Into<String>,
+        path: Vec<String>,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::IndexCreate(IndexCreate {
+                table: table.into(),
+                name: name.into(),
+                path,
+            })),
+        }
+    }
+
+    pub fn new_index_drop(table: impl Into<String>, name: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::IndexDrop(IndexDrop {
+                table: table.into(),
+                name: name.into(),
+            })),
+        }
+    }
+
+    pub fn new_index_lookup(
+        table: impl Into<String>,
+        name: impl Into<String>,
+        value: Value,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::IndexLookup(IndexLookup {
+                table: table.into(),
+                name: name.into(),
+                value: Some(value),
+            })),
+        }
+    }
+
+    // start / end 为 None 表示不限制
+    pub fn new_index_range(
+        table: impl Into<String>,
+        name: impl Into<String>,
+        start: Option<Value>,
+        end: Option<Value>,
+        limit: u32,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::IndexRange(IndexRange {
+                table: table.into(),
+                name: name.into(),
+                start,
+                end,
+                limit,
+            })),
+        }
+    }
+}
+
+impl CommandRequest {
+    pub fn new_query(query: Query) -> Self {
+        Self {
+            request_data: Some(RequestData::Query(query)),
+        }
+    }
+}
+
+impl CommandRequest {
+    pub fn new_hkeys(table: impl Into<String>, pattern: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Hkeys(Hkeys {
+                table: table.into(),
+                pattern: pattern.into(),
+            })),
+        }
+    }
+
+    pub fn new_hrandkey(table: impl Into<String>, count: i64) -> Self {
+        Self {
+            request_data: Some(RequestData::Hrandkey(Hrandkey {
                 table: table.into(),
-                keys,
+                count,
+            })),
+        }
+    }
+}
+
+impl CommandRequest {
+    /// 按当前的协议版本握手，compressions 按优先级排列
+    pub fn new_hello(
+        compressions: Vec<String>,
+        compression_threshold: u64,
+        max_frame_size: u64,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Hello(Hello {
+                version: PROTOCOL_VERSION,
+                compressions,
+                max_frame_size,
+                client_name: concat!("kv/", env!("CARGO_PKG_VERSION")).into(),
+                compression_threshold,
             })),
         }
     }
 }
 
+impl HelloReply {
+    /// 协商后的 frame 压缩配置，没有选中压缩算法时不压缩
+    pub fn frame_compression(&self) -> FrameCompression {
+        match CompressionAlgorithm::from_name(&self.compression) {
+            Some(algorithm) => FrameCompression::new(algorithm, self.compression_threshold as _),
+            None => FrameCompression::none(),
+        }
+    }
+}
+
+impl ScoredValue {
+    pub fn new(member: Value, score: f64) -> Self {
+        Self {
+            member: Some(member),
+            score,
+        }
+    }
+}
+
+impl Sample {
+    pub fn new(timestamp: u64, value: f64) -> Self {
+        Self { timestamp, value }
+    }
+}
+
+impl VersionPolicy {
+    // 最多保留 max_versions 个版本，且不超过 max_age_secs 秒，0 表示不限制
+    pub fn new(max_versions: u32, max_age_secs: u64) -> Self {
+        Self {
+            max_versions,
+            max_age_secs,
+        }
+    }
+}
+
 impl Kvpair {
     // 创建一个新的 kv pair
-    pub fn new(key: impl Into<String>, value: Value) -> Self {
+    pub fn new(key: impl IntoKey, value: Value) -> Self {
         Self {
-            key: key.into(),
+            key: key.into_key(),
             value: Some(value),
         }
     }
@@ -170,6 +1141,14 @@ impl From<i64> for Value {
     }
 }
 
+impl From<f64> for Value {
+    fn from(f: f64) -> Self {
+        Self {
+            value: Some(value::Value::Float(f)),
+        }
+    }
+}
+
 impl From<bool> for Value {
     fn from(b: bool) -> Self {
         Self {
@@ -178,6 +1157,106 @@ impl From<bool> for Value {
     }
 }
 
+impl From<Vec<Value>> for Value {
+    fn from(values: Vec<Value>) -> Self {
+        ValueList { values }.into()
+    }
+}
+
+impl From<ValueList> for Value {
+    fn from(list: ValueList) -> Self {
+        Self {
+            value: Some(value::Value::List(list)),
+        }
+    }
+}
+
+impl From<BTreeMap<String, Value>> for Value {
+    fn from(fields: BTreeMap<String, Value>) -> Self {
+        ValueMap { fields }.into()
+    }
+}
+
+impl From<HashMap<String, Value>> for Value {
+    fn from(fields: HashMap<String, Value>) -> Self {
+        fields.into_iter().collect::<BTreeMap<_, _>>().into()
+    }
+}
+
+impl From<ValueMap> for Value {
+    fn from(map: ValueMap) -> Self {
+        Self {
+            value: Some(value::Value::Map(map)),
+        }
+    }
+}
+
+impl TryFrom<Value> for Vec<Value> {
+    type Error = KvError;
+
+    fn try_from(v: Value) -> Result<Self, Self::Error> {
+        match v.value {
+            Some(value::Value::List(list)) => Ok(list.values),
+            _ => Err(KvError::ConvertError(v, "List")),
+        }
+    }
+}
+
+impl TryFrom<Value> for BTreeMap<String, Value> {
+    type Error = KvError;
+
+    fn try_from(v: Value) -> Result<Self, Self::Error> {
+        match v.value {
+            Some(value::Value::Map(map)) => Ok(map.fields),
+            _ => Err(KvError::ConvertError(v, "Map")),
+        }
+    }
+}
+
+impl TryFrom<Value> for String {
+    type Error = KvError;
+
+    fn try_from(v: Value) -> Result<Self, Self::Error> {
+        match v.value {
+            Some(value::Value::String(s)) => Ok(s),
+            _ => Err(KvError::ConvertError(v, "String")),
+        }
+    }
+}
+
+impl TryFrom<Value> for i64 {
+    type Error = KvError;
+
+    fn try_from(v: Value) -> Result<Self, Self::Error> {
+        match v.value {
+            Some(value::Value::Integer(i)) => Ok(i),
+            _ => Err(KvError::ConvertError(v, "Integer")),
+        }
+    }
+}
+
+impl TryFrom<Value> for f64 {
+    type Error = KvError;
+
+    fn try_from(v: Value) -> Result<Self, Self::Error> {
+        match v.value {
+            Some(value::Value::Float(f)) => Ok(f),
+            _ => Err(KvError::ConvertError(v, "Float")),
+        }
+    }
+}
+
+impl TryFrom<Value> for bool {
+    type Error = KvError;
+
+    fn try_from(v: Value) -> Result<Self, Self::Error> {
+        match v.value {
+            Some(value::Value::Bool(b)) => Ok(b),
+            _ => Err(KvError::ConvertError(v, "Bool")),
+        }
+    }
+}
+
 // 从 Value 转换成 CommandResponse
 // 这里定义了 Value 如何转换成 CommandResponse
 // 所以，在service/command_service.rs中可以直接使用 v.into()
@@ -191,10 +1270,19 @@ impl From<Value> for CommandResponse {
     }
 }
 
+impl From<(Bytes, Value)> for Kvpair {
+    fn from(v: (Bytes, Value)) -> Self {
+        Self {
+            key: v.0,
+            value: Some(v.1),
+        }
+    }
+}
+
 impl From<(String, Value)> for Kvpair {
     fn from(v: (String, Value)) -> Self {
         Self {
-            key: v.0,
+            key: v.0.into(),
             value: Some(v.1),
         }
     }
@@ -221,6 +1309,66 @@ impl From<Vec<Value>> for CommandResponse {
     }
 }
 
+impl From<Vec<VersionedValue>> for CommandResponse {
+    fn from(v: Vec<VersionedValue>) -> Self {
+        Self {
+            status: StatusCode::OK.as_u16() as _,
+            versions: v,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<Vec<ScoredValue>> for CommandResponse {
+    fn from(v: Vec<ScoredValue>) -> Self {
+        Self {
+            status: StatusCode::OK.as_u16() as _,
+            scored_values: v,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<Vec<StreamEntry>> for CommandResponse {
+    fn from(v: Vec<StreamEntry>) -> Self {
+        Self {
+            status: StatusCode::OK.as_u16() as _,
+            entries: v,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<Vec<PendingEntry>> for CommandResponse {
+    fn from(v: Vec<PendingEntry>) -> Self {
+        Self {
+            status: StatusCode::OK.as_u16() as _,
+            pending: v,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<Vec<Sample>> for CommandResponse {
+    fn from(v: Vec<Sample>) -> Self {
+        Self {
+            status: StatusCode::OK.as_u16() as _,
+            samples: v,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<HelloReply> for CommandResponse {
+    fn from(v: HelloReply) -> Self {
+        Self {
+            status: StatusCode::OK.as_u16() as _,
+            hello: Some(v),
+            ..Default::default()
+        }
+    }
+}
+
 // 从 KvError 转换成 CommandResponse
 impl From<KvError> for CommandResponse {
     fn from(e: KvError) -> Self {
@@ -228,13 +1376,20 @@ impl From<KvError> for CommandResponse {
             // as _ 的意思是让编译器自己推断要转成啥类型
             status: StatusCode::INTERNAL_SERVER_ERROR.as_u16() as _,
             message: e.to_string(),
-            values: vec![],
-            pairs: vec![],
+            ..Default::default()
         };
 
         match e {
             KvError::NotFound(_, _) => result.status = StatusCode::NOT_FOUND.as_u16() as _,
-            KvError::InvalidCommand(_) => result.status = StatusCode::BAD_REQUEST.as_u16() as _,
+            // 对错误类型的 key 执行命令（比如对普通 value 执行 Pfadd）是客户端的错误
+            KvError::InvalidCommand(_)
+            | KvError::ConvertError(..)
+            | KvError::JsonError(_)
+            | KvError::InvalidFrame(_) => result.status = StatusCode::BAD_REQUEST.as_u16() as _,
+            KvError::PermissionDenied(_) => result.status = StatusCode::FORBIDDEN.as_u16() as _,
+            KvError::FrameTooLarge(..) => {
+                result.status = StatusCode::PAYLOAD_TOO_LARGE.as_u16() as _
+            }
             _ => {}
         }
 
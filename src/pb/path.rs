@@ -0,0 +1,142 @@
+use std::mem;
+
+use super::abi::{value, Value, ValueMap};
+use crate::KvError;
+
+// 嵌套 value 的路径操作。path 的每一段是 map 的 key 或者列表的下标，
+// 列表下标可以是负数，-1 表示最后一个元素
+impl Value {
+    // 获取 path 处的值，路径不存在时返回 None
+    pub fn get_path(&self, path: &[String]) -> Option<&Value> {
+        path.iter().try_fold(self, |v, seg| child(v, seg))
+    }
+
+    // 设置 path 处的值，返回之前的值。空的 path 表示替换整个 value
+    // 中间不存在的 map key 会创建成空的 map，列表下标必须已经存在
+    pub fn set_path(&mut self, path: &[String], value: Value) -> Result<Option<Value>, KvError> {
+        let (last, parents) = match path.split_last() {
+            Some(v) => v,
+            None => return Ok(Some(mem::replace(self, value))),
+        };
+
+        let mut current = self;
+        for seg in parents {
+            if let Some(value::Value::Map(map)) = &mut current.value {
+                map.fields
+                    .entry(seg.clone())
+                    .or_insert_with(|| ValueMap::default().into());
+            }
+            current = child_mut(current, seg).ok_or_else(|| invalid_path(path))?;
+        }
+
+        match &mut current.value {
+            Some(value::Value::Map(map)) => Ok(map.fields.insert(last.clone(), value)),
+            Some(value::Value::List(list)) => {
+                let i = list_index(last, list.values.len()).ok_or_else(|| invalid_path(path))?;
+                Ok(Some(mem::replace(&mut list.values[i], value)))
+            }
+            _ => Err(invalid_path(path)),
+        }
+    }
+
+    // 删除 path 处的 map key 或者列表元素，返回被删除的值，路径不存在时返回 None
+    pub fn del_path(&mut self, path: &[String]) -> Result<Option<Value>, KvError> {
+        let (last, parents) = path.split_last().ok_or_else(|| invalid_path(path))?;
+        let parent = parents.iter().try_fold(self, |v, seg| child_mut(v, seg));
+
+        match parent.and_then(|v| v.value.as_mut()) {
+            Some(value::Value::Map(map)) => Ok(map.fields.remove(last)),
+            Some(value::Value::List(list)) => {
+                Ok(list_index(last, list.values.len()).map(|i| list.values.remove(i)))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+fn child<'a>(v: &'a Value, seg: &str) -> Option<&'a Value> {
+    match &v.value {
+        Some(value::Value::Map(map)) => map.fields.get(seg),
+        Some(value::Value::List(list)) => {
+            list_index(seg, list.values.len()).map(|i| &list.values[i])
+        }
+        _ => None,
+    }
+}
+
+fn child_mut<'a>(v: &'a mut Value, seg: &str) -> Option<&'a mut Value> {
+    match &mut v.value {
+        Some(value::Value::Map(map)) => map.fields.get_mut(seg),
+        Some(value::Value::List(list)) => {
+            let i = list_index(seg, list.values.len())?;
+            list.values.get_mut(i)
+        }
+        _ => None,
+    }
+}
+
+// 把列表下标转换成 usize，负数从尾部开始数，越界时返回 None
+fn list_index(seg: &str, len: usize) -> Option<usize> {
+    let i: i64 = seg.parse().ok()?;
+    let i = if i < 0 { len as i64 + i } else { i };
+    (0..len as i64).contains(&i).then_some(i as usize)
+}
+
+fn invalid_path(path: &[String]) -> KvError {
+    KvError::InvalidCommand(format!("Invalid path: {}", path.join(".")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn path(p: &str) -> Vec<String> {
+        p.split('.').map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn get_and_set_path_should_work() {
+        let mut user: Value = BTreeMap::from([
+            ("name".to_string(), Value::from("alice")),
+            ("tags".to_string(), vec!["a".into(), "b".into()].into()),
+        ])
+        .into();
+
+        assert_eq!(user.get_path(&path("name")), Some(&"alice".into()));
+        assert_eq!(user.get_path(&path("tags.-1")), Some(&"b".into()));
+        assert_eq!(user.get_path(&path("tags.2")), None);
+        assert_eq!(user.get_path(&path("name.first")), None);
+
+        let old = user.set_path(&path("tags.0"), "c".into()).unwrap();
+        assert_eq!(old, Some("a".into()));
+        // 中间不存在的 map 会自动创建
+        let old = user
+            .set_path(&path("address.city"), "paris".into())
+            .unwrap();
+        assert_eq!(old, None);
+        assert_eq!(user.get_path(&path("address.city")), Some(&"paris".into()));
+
+        // 不能越界，也不能穿过标量
+        assert!(user.set_path(&path("tags.5"), 1.into()).is_err());
+        assert!(user.set_path(&path("name.first"), 1.into()).is_err());
+    }
+
+    #[test]
+    fn del_path_should_work() {
+        let mut v: Value = BTreeMap::from([(
+            "tags".to_string(),
+            Value::from(vec!["a".into(), "b".into()]),
+        )])
+        .into();
+
+        assert_eq!(v.del_path(&path("tags.0")).unwrap(), Some("a".into()));
+        assert_eq!(v.get_path(&path("tags")), Some(&vec!["b".into()].into()));
+        assert_eq!(v.del_path(&path("none.x")).unwrap(), None);
+        assert_eq!(
+            v.del_path(&path("tags")).unwrap(),
+            Some(vec!["b".into()].into())
+        );
+        assert!(v.del_path(&[]).is_err());
+    }
+}
@@ -1,13 +1,26 @@
-use kv::{Service, ServiceInner, MemTable, ProstServerStream, TlsServerAcceptor};
+use kv::{Service, ServiceInner, MemTable, FrameLimits, GrpcService, HttpServerStream, ProstServerStream, RespServerStream, TlsServerAcceptor, WsServerStream};
 use tokio::net::TcpListener;
-use tracing::info;
+use tokio_stream::wrappers::TcpListenerStream;
+use tracing::{info, warn};
 use anyhow::Result;
+use std::time::Duration;
 
 
 #[tokio::main]
 async fn main() -> Result<()> {
     tracing_subscriber::fmt::init();
     let addr = "127.0.0.1:9527";
+    // Redis 客户端（redis-cli 等）使用的 RESP 端口，不走 TLS
+    let resp_addr = "127.0.0.1:6379";
+    // HTTP/JSON 网关，给前端和脚本使用
+    let http_addr = "127.0.0.1:8080";
+    // gRPC 端口，给 tonic 等 gRPC 客户端使用
+    let grpc_addr = "127.0.0.1:50051";
+    // WebSocket 端口，给浏览器里的 dashboard 使用，和 9527 一样走 TLS（wss://）
+    let ws_addr = "127.0.0.1:9528";
+    // 给同一台机器上的 sidecar 使用的 Unix socket，不走 TLS，用对端进程的 uid 做授权
+    #[cfg(unix)]
+    let unix_path = "/tmp/kvs.sock";
 
     // 以后从配置文件取
     let server_cert = include_str!("../fixtures/server.cert");
@@ -15,6 +28,119 @@ async fn main() -> Result<()> {
 
     let acceptor = TlsServerAcceptor::new(server_cert, server_key, None)?;
     let service: Service = ServiceInner::new(MemTable::new()).into();
+    // 每个 frame 开始之后必须在 30 秒内发送完，防止慢速发送占住连接
+    let limits = FrameLimits {
+        read_timeout: Some(Duration::from_secs(30)),
+        ..Default::default()
+    };
+
+    if let Some(resp_listener) = bind_optional(resp_addr, "RESP").await {
+        let resp_service = service.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, addr) = match resp_listener.accept().await {
+                    Ok(v) => v,
+                    Err(e) => {
+                        warn!("Failed to accept RESP connection: {:?}", e);
+                        continue;
+                    }
+                };
+                info!("RESP client {:?} connected", addr);
+                let stream = RespServerStream::new(stream, resp_service.clone());
+                tokio::spawn(async move { stream.process().await });
+            }
+        });
+    }
+
+    if let Some(http_listener) = bind_optional(http_addr, "HTTP").await {
+        let http_service = service.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, addr) = match http_listener.accept().await {
+                    Ok(v) => v,
+                    Err(e) => {
+                        warn!("Failed to accept HTTP connection: {:?}", e);
+                        continue;
+                    }
+                };
+                info!("HTTP client {:?} connected", addr);
+                let stream = HttpServerStream::new(stream, http_service.clone());
+                tokio::spawn(async move { stream.process().await });
+            }
+        });
+    }
+
+    if let Some(grpc_listener) = bind_optional(grpc_addr, "gRPC").await {
+        let grpc_service = GrpcService::new(service.clone()).into_server();
+        tokio::spawn(async move {
+            if let Err(e) = tonic::transport::Server::builder()
+                .add_service(grpc_service)
+                .serve_with_incoming(TcpListenerStream::new(grpc_listener))
+                .await
+            {
+                warn!("gRPC server stopped: {:?}", e);
+            }
+        });
+    }
+
+    if let Some(ws_listener) = bind_optional(ws_addr, "WebSocket").await {
+        let ws_service = service.clone();
+        let ws_acceptor = acceptor.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, addr) = match ws_listener.accept().await {
+                    Ok(v) => v,
+                    Err(e) => {
+                        warn!("Failed to accept WebSocket connection: {:?}", e);
+                        continue;
+                    }
+                };
+                info!("WebSocket client {:?} connected", addr);
+                let tls = ws_acceptor.clone();
+                let service = ws_service.clone();
+                tokio::spawn(async move {
+                    let stream = tls.accept(stream).await?;
+                    WsServerStream::new(stream, service).process().await
+                });
+            }
+        });
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        use kv::PeerCredPolicy;
+        use tokio::net::UnixListener;
+
+        // 上次退出时留下的 socket 文件会导致 bind 失败
+        let _ = std::fs::remove_file(unix_path);
+        let unix_listener = UnixListener::bind(unix_path)?;
+        // 只允许和 kvs 同一个用户的进程连接
+        let policy = PeerCredPolicy::new().allow_uid(std::fs::metadata(unix_path)?.uid());
+        info!("Start listening Unix socket on {}", unix_path);
+        let unix_service = service.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = match unix_listener.accept().await {
+                    Ok(v) => v,
+                    Err(e) => {
+                        warn!("Failed to accept Unix socket connection: {:?}", e);
+                        continue;
+                    }
+                };
+                match policy.verify(&stream) {
+                    Ok(cred) => info!("Unix socket client {:?} connected", cred),
+                    Err(e) => {
+                        warn!("Reject Unix socket connection: {:?}", e);
+                        continue;
+                    }
+                }
+                let stream = ProstServerStream::new(stream, unix_service.clone()).with_limits(limits);
+                tokio::spawn(async move { stream.process().await });
+            }
+        });
+    }
+
     let listner = TcpListener::bind(addr).await?;
     info!("Start listening on {}", addr);
     loop {
@@ -22,7 +148,22 @@ async fn main() -> Result<()> {
         let (stream, addr) = listner.accept().await?;
         info!("Client {:?} connected", addr);
         let stream = tls.accept(stream).await?;
-        let stream = ProstServerStream::new(stream, service.clone());
+        let stream = ProstServerStream::new(stream, service.clone()).with_limits(limits);
         tokio::spawn(async move { stream.process().await });
     }
 }
+
+// 附加的端口（RESP、HTTP、gRPC、WebSocket）是可选的，比如 6379 已经被 Redis 占用时
+// 只打印警告，不影响其它端口
+async fn bind_optional(addr: &str, name: &str) -> Option<TcpListener> {
+    match TcpListener::bind(addr).await {
+        Ok(listener) => {
+            info!("Start listening {} on {}", name, addr);
+            Some(listener)
+        }
+        Err(e) => {
+            warn!("Failed to listen {} on {}: {:?}", name, addr, e);
+            None
+        }
+    }
+}
@@ -1,10 +1,11 @@
 use crate::*;
+use std::ops::Bound;
 
 impl CommandService for Hget {
     fn execute(self, store: &impl Storage) -> CommandResponse {
         match store.get(&self.table, &self.key) {
             Ok(Some(v)) => v.into(),
-            Ok(None) => KvError::NotFound(self.table, self.key).into(),
+            Ok(None) => KvError::not_found(self.table, &self.key).into(),
             Err(e) => e.into(),
         }
     }
@@ -12,8 +13,8 @@ impl CommandService for Hget {
 
 impl CommandService for Hgetall {
     fn execute(self, store: &impl Storage) -> CommandResponse {
-        match store.get_all(&self.table) {
-            Ok(v) => v.into(),
+        match store.snapshot(&self.table) {
+            Ok(v) => v.into_iter().collect::<Vec<_>>().into(),
             Err(e) => e.into(),
         }
     }
@@ -22,7 +23,7 @@ impl CommandService for Hgetall {
 impl CommandService for Hset {
     fn execute(self, store: &impl Storage) -> CommandResponse {
         match self.pair {
-            Some(v) => match store.set(&self.table, v.key, v.value.unwrap_or_default()) {
+            Some(v) => match store.set_indexed(&self.table, v.key, v.value.unwrap_or_default()) {
                 Ok(Some(v)) => v.into(),
                 Ok(None) => Value::default().into(),
                 Err(e) => e.into(),
@@ -41,9 +42,9 @@ impl CommandService for Hset {
 
 impl CommandService for Hdel {
     fn execute(self, store: &impl Storage) -> CommandResponse {
-        match store.del(&self.table, &self.key) {
+        match store.del_indexed(&self.table, &self.key) {
             Ok(Some(v)) => v.into(),
-            Ok(None) => KvError::NotFound(self.table, self.key).into(),
+            Ok(None) => KvError::not_found(self.table, &self.key).into(),
             Err(e) => e.into(),
         }
     }
@@ -60,15 +61,16 @@ impl CommandService for Hexist {
 
 impl CommandService for Hmget {
     fn execute(self, store: &impl Storage) -> CommandResponse {
-        self.keys
-            .iter()
-            .map(|key| match store.get(&self.table, key) {
-                Ok(Some(v)) => Ok(v),
-                Ok(None) => Ok(Value::default()),
-                Err(e) => Err(e),
-            })
-            .collect::<Result<Vec<Value>, KvError>>()
-            .into()
+        // 用快照保证读到的多个 key 是同一时刻的
+        match store.snapshot_keys(&self.table, &self.keys) {
+            Ok(snapshot) => self
+                .keys
+                .iter()
+                .map(|key| snapshot.get(key).cloned().unwrap_or_default())
+                .collect::<Vec<Value>>()
+                .into(),
+            Err(e) => e.into(),
+        }
     }
 }
 
@@ -84,7 +86,7 @@ impl CommandService for Hmset {
         pairs
             .into_iter()
             .map(
-                |kv| match store.set(&table, kv.key, kv.value.unwrap_or_default()) {
+                |kv| match store.set_indexed(&table, kv.key, kv.value.unwrap_or_default()) {
                     Ok(Some(v)) => Ok(v),
                     Ok(None) => Ok(Value::default()),
                     Err(e) => Err(e),
@@ -97,15 +99,15 @@ impl CommandService for Hmset {
 
 impl CommandService for Hmexist {
     fn execute(self, store: &impl Storage) -> CommandResponse {
-        self.keys
-            .iter()
-            .map(|key| match store.contains(&self.table, key) {
-                // TODO: 有没有更优雅的写法？
-                Ok(v) => Ok(v.into()),
-                Err(e) => Err(e),
-            })
-            .collect::<Result<Vec<Value>, KvError>>()
-            .into()
+        match store.snapshot_keys(&self.table, &self.keys) {
+            Ok(snapshot) => self
+                .keys
+                .iter()
+                .map(|key| snapshot.contains(key).into())
+                .collect::<Vec<Value>>()
+                .into(),
+            Err(e) => e.into(),
+        }
     }
 }
 
@@ -113,7 +115,7 @@ impl CommandService for Hmdel {
     fn execute(self, store: &impl Storage) -> CommandResponse {
         self.keys
             .iter()
-            .map(|key| match store.del(&self.table, key) {
+            .map(|key| match store.del_indexed(&self.table, key) {
                 Ok(Some(v)) => Ok(v),
                 Ok(None) => Ok(Value::default()),
                 Err(e) => Err(e),
@@ -123,9 +125,735 @@ impl CommandService for Hmdel {
     }
 }
 
+impl CommandService for Hversioning {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.set_versioning(&self.table, self.policy) {
+            Ok(()) => Value::default().into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for HgetVersion {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.get_version(&self.table, &self.key, self.version) {
+            Ok(Some(v)) => v.into(),
+            Ok(None) => KvError::not_found(self.table, &self.key).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Hhistory {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        store.history(&self.table, &self.key).into()
+    }
+}
+
+impl CommandService for HgetallAsOf {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        store.get_all_as_of(&self.table, self.version).into()
+    }
+}
+
+impl CommandService for Lpush {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.push_front(&self.table, &self.key, self.values) {
+            Ok(len) => Value::from(len as i64).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Rpush {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.push_back(&self.table, &self.key, self.values) {
+            Ok(len) => Value::from(len as i64).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Lpop {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let count = self.count.max(1) as usize;
+        popped(
+            store.pop_front(&self.table, &self.key, count),
+            self.table,
+            &self.key,
+        )
+    }
+}
+
+impl CommandService for Rpop {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let count = self.count.max(1) as usize;
+        popped(
+            store.pop_back(&self.table, &self.key, count),
+            self.table,
+            &self.key,
+        )
+    }
+}
+
+impl CommandService for Lrange {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        store
+            .list_range(&self.table, &self.key, self.start, self.stop)
+            .into()
+    }
+}
+
+impl CommandService for Llen {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.list_len(&self.table, &self.key) {
+            Ok(len) => Value::from(len as i64).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Ltrim {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.list_trim(&self.table, &self.key, self.start, self.stop) {
+            Ok(()) => Value::default().into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+// 这里只尝试弹出一次，列表为空时的等待由 Service::execute_async 处理
+impl CommandService for Blpop {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        popped(
+            store.pop_front(&self.table, &self.key, 1),
+            self.table,
+            &self.key,
+        )
+    }
+}
+
+impl CommandService for Brpop {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        popped(
+            store.pop_back(&self.table, &self.key, 1),
+            self.table,
+            &self.key,
+        )
+    }
+}
+
+impl CommandService for Sadd {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.set_add(&self.table, &self.key, self.members) {
+            Ok(n) => Value::from(n as i64).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Srem {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.set_remove(&self.table, &self.key, &self.members) {
+            Ok(n) => Value::from(n as i64).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Smembers {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        store.set_members(&self.table, &self.key).into()
+    }
+}
+
+impl CommandService for Sismember {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let member = self.member.unwrap_or_default();
+        match store.set_is_member(&self.table, &self.key, &member) {
+            Ok(v) => Value::from(v).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Sinter {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        store.set_inter(&self.table, &self.keys).into()
+    }
+}
+
+impl CommandService for Sunion {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        store.set_union(&self.table, &self.keys).into()
+    }
+}
+
+impl CommandService for Zadd {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.zset_add(&self.table, &self.key, self.members) {
+            Ok(n) => Value::from(n as i64).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Zrange {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        store
+            .zset_range(&self.table, &self.key, self.start, self.stop, self.rev)
+            .into()
+    }
+}
+
+impl CommandService for Zrangebyscore {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        store
+            .zset_range_by_score(&self.table, &self.key, self.min, self.max)
+            .into()
+    }
+}
+
+impl CommandService for Zrank {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let member = self.member.unwrap_or_default();
+        match store.zset_rank(&self.table, &self.key, &member, self.rev) {
+            Ok(Some(rank)) => Value::from(rank as i64).into(),
+            Ok(None) => KvError::not_found(self.table, &self.key).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Zincrby {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let member = self.member.unwrap_or_default();
+        match store.zset_incr_by(&self.table, &self.key, member, self.increment) {
+            Ok(score) => Value::from(score).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for HgetPath {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.get_at(&self.table, &self.key, &self.path) {
+            Ok(Some(v)) => v.into(),
+            Ok(None) => KvError::not_found(self.table, &self.key).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for HsetPath {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let value = self.value.unwrap_or_default();
+        match store.set_at(&self.table, &self.key, &self.path, value) {
+            Ok(Some(v)) => v.into(),
+            Ok(None) => Value::default().into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for HdelPath {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.del_at(&self.table, &self.key, &self.path) {
+            Ok(Some(v)) => v.into(),
+            Ok(None) => KvError::not_found(self.table, &self.key).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for JsonGet {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let result = JsonPath::parse(&self.path)
+            .and_then(|path| store.json_get(&self.table, &self.key, &path));
+        match result {
+            Ok(Some(values)) => values
+                .iter()
+                .map(|v| v.to_json().map(Value::from))
+                .collect::<Result<Vec<_>, _>>()
+                .into(),
+            Ok(None) => KvError::not_found(self.table, &self.key).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for JsonSet {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let result = JsonPath::parse(&self.path).and_then(|path| {
+            let value = Value::from_json(&self.json)?;
+            store.json_set(&self.table, &self.key, &path, value)
+        });
+        match result {
+            Ok(n) => Value::from(n as i64).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for JsonDel {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let result = JsonPath::parse(&self.path)
+            .and_then(|path| store.json_del(&self.table, &self.key, &path));
+        match result {
+            Ok(n) => Value::from(n as i64).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for JsonArrAppend {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let result = JsonPath::parse(&self.path).and_then(|path| {
+            let values = self
+                .values
+                .iter()
+                .map(|v| Value::from_json(v))
+                .collect::<Result<Vec<_>, _>>()?;
+            store.json_arr_append(&self.table, &self.key, &path, values)
+        });
+        result.into()
+    }
+}
+
+impl CommandService for JsonNumIncrBy {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        JsonPath::parse(&self.path)
+            .and_then(|path| store.json_incr_by(&self.table, &self.key, &path, self.increment))
+            .into()
+    }
+}
+
+impl CommandService for Xadd {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let id = match self.id.as_str() {
+            "" | "*" => None,
+            id => match id.parse::<StreamId>() {
+                Ok(id) => Some(id),
+                Err(e) => return e.into(),
+            },
+        };
+        let result = store
+            .stream_add(&self.table, &self.key, id, self.fields)
+            .and_then(|id| {
+                if self.max_len > 0 || self.max_age_ms > 0 {
+                    store.stream_trim(&self.table, &self.key, self.max_len, self.max_age_ms)?;
+                }
+                Ok(id)
+            });
+        match result {
+            Ok(id) => Value::from(id.to_string()).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Xrange {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let range = StreamId::parse_start(&self.start)
+            .and_then(|start| Ok((start, StreamId::parse_end(&self.end)?)));
+        match range {
+            Ok((start, end)) => store
+                .stream_range(
+                    &self.table,
+                    &self.key,
+                    Bound::Included(start),
+                    Bound::Included(end),
+                    self.count as usize,
+                )
+                .into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+// 这里只读取一次，block 时的等待由 Service::execute_async 处理
+impl CommandService for Xread {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let after = match self.after.as_str() {
+            "$" => store.stream_last_id(&self.table, &self.key),
+            after => after.parse(),
+        };
+        match after {
+            Ok(after) => store
+                .stream_range(
+                    &self.table,
+                    &self.key,
+                    Bound::Excluded(after),
+                    Bound::Unbounded,
+                    self.count as usize,
+                )
+                .into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Xlen {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.stream_len(&self.table, &self.key) {
+            Ok(len) => Value::from(len as i64).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Xtrim {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.stream_trim(&self.table, &self.key, self.max_len, self.max_age_ms) {
+            Ok(n) => Value::from(n as i64).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for XgroupCreate {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let start = match self.start.as_str() {
+            "$" => Ok(None),
+            start => start.parse().map(Some),
+        };
+        let result =
+            start.and_then(|start| store.group_create(&self.table, &self.key, &self.group, start));
+        match result {
+            Ok(()) => Value::default().into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+// 这里只读取一次，block 时的等待由 Service::execute_async 处理
+impl CommandService for Xreadgroup {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        store
+            .group_read(
+                &self.table,
+                &self.key,
+                &self.group,
+                &self.consumer,
+                self.count as usize,
+            )
+            .into()
+    }
+}
+
+impl CommandService for Xack {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let ids = self
+            .ids
+            .iter()
+            .map(|id| id.parse())
+            .collect::<Result<Vec<StreamId>, _>>();
+        let result = ids.and_then(|ids| store.group_ack(&self.table, &self.key, &self.group, &ids));
+        match result {
+            Ok(n) => Value::from(n as i64).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Xpending {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        store
+            .group_pending(&self.table, &self.key, &self.group)
+            .into()
+    }
+}
+
+impl CommandService for TsAdd {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.ts_add(&self.table, &self.key, self.timestamp, self.value) {
+            Ok(timestamp) => Value::from(timestamp as i64).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for TsRange {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let aggregation = match self.aggregation.as_str() {
+            "" => Ok(None),
+            aggregation => aggregation
+                .parse::<Aggregation>()
+                .map(|a| Some((a, self.bucket_ms))),
+        };
+        let to = if self.to == 0 { u64::MAX } else { self.to };
+        aggregation
+            .and_then(|aggregation| {
+                store.ts_range(&self.table, &self.key, self.from, to, aggregation)
+            })
+            .into()
+    }
+}
+
+impl CommandService for TsSetRetention {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.ts_set_retention(&self.table, &self.key, self.retention_ms) {
+            Ok(()) => Value::default().into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for TsCreateRule {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let rule = self.rule.unwrap_or_default();
+        if let Err(e) = check_table(&rule.dest_table) {
+            return e.into();
+        }
+        match store.ts_create_rule(&self.table, &self.key, rule) {
+            Ok(()) => Value::default().into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for TsDeleteRule {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.ts_delete_rule(&self.table, &self.key, &self.dest_table, &self.dest_key) {
+            Ok(n) => Value::from(n as i64).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Pfadd {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.pf_add(&self.table, &self.key, &self.items) {
+            Ok(changed) => Value::from(changed).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Pfcount {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.pf_count(&self.table, &self.keys) {
+            Ok(n) => Value::from(n as i64).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Pfmerge {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.pf_merge(&self.table, &self.dest, &self.sources) {
+            Ok(()) => Value::default().into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Bfadd {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let result = store.bf_add(
+            &self.table,
+            &self.key,
+            &self.items,
+            self.capacity,
+            self.error_rate,
+        );
+        match result {
+            Ok(added) => added
+                .into_iter()
+                .map(Value::from)
+                .collect::<Vec<_>>()
+                .into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Bfexists {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.bf_exists(&self.table, &self.key, &self.items) {
+            Ok(exists) => exists
+                .into_iter()
+                .map(Value::from)
+                .collect::<Vec<_>>()
+                .into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Setbit {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.set_bit(&self.table, &self.key, self.offset, self.value) {
+            Ok(old) => Value::from(old).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Getbit {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.get_bit(&self.table, &self.key, self.offset) {
+            Ok(bit) => Value::from(bit).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Bitcount {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.bit_count(&self.table, &self.key, self.start, self.end) {
+            Ok(n) => Value::from(n as i64).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Bitop {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let result = self
+            .op
+            .parse::<BitOp>()
+            .and_then(|op| store.bit_op(&self.table, op, &self.dest, &self.keys));
+        match result {
+            Ok(len) => Value::from(len as i64).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Bitpos {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.bit_pos(&self.table, &self.key, self.bit, self.start, self.end) {
+            Ok(pos) => Value::from(pos.map_or(-1, |p| p as i64)).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for IndexCreate {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.create_index(&self.table, &self.name, self.path) {
+            Ok(n) => Value::from(n as i64).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for IndexDrop {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.drop_index(&self.table, &self.name) {
+            Ok(existed) => Value::from(existed).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for IndexLookup {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let value = self.value.unwrap_or_default();
+        store.index_lookup(&self.table, &self.name, &value).into()
+    }
+}
+
+impl CommandService for IndexRange {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        store
+            .index_range(
+                &self.table,
+                &self.name,
+                self.start.as_ref(),
+                self.end.as_ref(),
+                self.limit as usize,
+            )
+            .into()
+    }
+}
+
+impl CommandService for Query {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        if self.aggregates.is_empty() {
+            store.query(&self).into()
+        } else {
+            store.aggregate(&self).into()
+        }
+    }
+}
+
+impl CommandService for Hkeys {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.get_keys(&self.table) {
+            Ok(keys) => keys
+                .filter(|k| self.pattern.is_empty() || glob_match(self.pattern.as_bytes(), k))
+                .map(Value::from)
+                .collect::<Vec<_>>()
+                .into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Hrandkey {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let count = if self.count == 0 { 1 } else { self.count };
+        match store.random_keys(&self.table, count) {
+            Ok(keys) => keys.into_iter().map(Value::from).collect::<Vec<_>>().into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+// 握手和 storage 无关，只根据客户端的能力和服务器的能力算出双方都支持的参数
+impl CommandService for Hello {
+    fn execute(self, _store: &impl Storage) -> CommandResponse {
+        if self.version == 0 {
+            return KvError::InvalidCommand("protocol version must be at least 1".into()).into();
+        }
+        // 选出客户端最希望使用、服务器也支持的压缩算法
+        let compression = self
+            .compressions
+            .into_iter()
+            .find(|c| CompressionAlgorithm::from_name(c).is_some())
+            .unwrap_or_default();
+        let compression_threshold = match self.compression_threshold {
+            0 => COMPRESSION_LIMIT as u64,
+            threshold => threshold,
+        };
+        let max_frame_size = match self.max_frame_size {
+            0 => MAX_FRAME as u64,
+            size => size.clamp(MIN_FRAME as u64, MAX_FRAME as u64),
+        };
+
+        HelloReply {
+            version: self.version.min(PROTOCOL_VERSION),
+            compression,
+            max_frame_size,
+            server_name: "kvs".into(),
+            server_version: env!("CARGO_PKG_VERSION").into(),
+            compressions: CompressionAlgorithm::ALL
+                .iter()
+                .map(|c| c.name().to_string())
+                .collect(),
+            commands: COMMANDS.iter().map(|c| c.to_string()).collect(),
+            compression_threshold,
+        }
+        .into()
+    }
+}
+
+// 列表为空时返回 404
+fn popped(result: Result<Vec<Value>, KvError>, table: String, key: &[u8]) -> CommandResponse {
+    match result {
+        Ok(values) if values.is_empty() => KvError::not_found(table, key).into(),
+        Ok(values) => values.into(),
+        Err(e) => e.into(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use bytes::Bytes;
+    use std::collections::BTreeMap;
 
     // 下面这行的作用是，标识下面的函数是一个单元测试
     // 所以测试里的辅助函数不应该标注这个
@@ -223,6 +951,22 @@ mod tests {
         assert_res_ok(res, &[20.into(), 30.into()], &[]);
     }
 
+    #[test]
+    fn binary_key_should_work() {
+        let store = MemTable::new();
+        let key = Bytes::from_static(&[0xde, 0xad, 0xbe, 0xef]);
+        let cmd = CommandRequest::new_hset("t1", key.clone(), 10.into());
+        dispatch(cmd, &store);
+        let cmd = CommandRequest::new_hget("t1", &key[..]);
+        assert_res_ok(dispatch(cmd, &store), &[10.into()], &[]);
+        let cmd = CommandRequest::new_hgetall("t1");
+        assert_res_ok(dispatch(cmd, &store), &[], &[Kvpair::new(key, 10.into())]);
+
+        // 不存在的二进制 key，错误信息里用 lossy 的方式显示
+        let cmd = CommandRequest::new_hget("t1", b"\xff");
+        assert_res_error(dispatch(cmd, &store), 404, "Not found");
+    }
+
     #[test]
     fn hget_with_non_exist_key_should_return_404() {
         let store = MemTable::new();
@@ -254,6 +998,604 @@ mod tests {
         assert_res_ok(res, &[], pairs);
     }
 
+    #[test]
+    fn versioning_commands_should_work() {
+        let store = MemTable::new();
+        // 开启多版本之前写入的数据
+        dispatch(CommandRequest::new_hset("score", "u1", 10.into()), &store);
+        let cmd = CommandRequest::new_hversioning("score", Some(VersionPolicy::new(0, 0)));
+        assert_res_ok(dispatch(cmd, &store), &[Value::default()], &[]);
+
+        dispatch(CommandRequest::new_hset("score", "u1", 20.into()), &store);
+        dispatch(CommandRequest::new_hset("score", "u2", 5.into()), &store);
+        dispatch(CommandRequest::new_hdel("score", "u1"), &store);
+
+        let res = dispatch(CommandRequest::new_hhistory("score", "u1"), &store);
+        let versions: Vec<_> = res.versions.iter().map(|v| v.version).collect();
+        assert_eq!(versions, vec![0, 1, 3]);
+        assert!(res.versions[2].deleted);
+
+        let cmd = CommandRequest::new_hget_version("score", "u1", 0);
+        assert_res_ok(dispatch(cmd, &store), &[10.into()], &[]);
+        let cmd = CommandRequest::new_hget_version("score", "u1", 2);
+        assert_res_ok(dispatch(cmd, &store), &[20.into()], &[]);
+        let cmd = CommandRequest::new_hget_version("score", "u1", 3);
+        assert_res_error(dispatch(cmd, &store), 404, "Not found");
+
+        let cmd = CommandRequest::new_hgetall_as_of("score", 2);
+        let pairs = &[Kvpair::new("u1", 20.into()), Kvpair::new("u2", 5.into())];
+        assert_res_ok(dispatch(cmd, &store), &[], pairs);
+        let cmd = CommandRequest::new_hgetall_as_of("score", 3);
+        assert_res_ok(dispatch(cmd, &store), &[], &[Kvpair::new("u2", 5.into())]);
+    }
+
+    #[test]
+    fn unversioned_table_should_not_keep_history() {
+        let store = MemTable::new();
+        dispatch(CommandRequest::new_hset("score", "u1", 10.into()), &store);
+        dispatch(CommandRequest::new_hset("score", "u1", 20.into()), &store);
+        let res = dispatch(CommandRequest::new_hhistory("score", "u1"), &store);
+        assert!(res.versions.is_empty());
+
+        // 没有历史的 key 读任何版本都得到当前值
+        let cmd = CommandRequest::new_hget_version("score", "u1", 0);
+        assert_res_ok(dispatch(cmd, &store), &[20.into()], &[]);
+    }
+
+    #[test]
+    fn list_commands_should_work() {
+        let store = MemTable::new();
+        let cmd = CommandRequest::new_rpush("t1", "q", vec![1.into(), 2.into()]);
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[2.into()], &[]);
+
+        let cmd = CommandRequest::new_lpush("t1", "q", vec![3.into(), 4.into()]);
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[4.into()], &[]);
+
+        let cmd = CommandRequest::new_lrange("t1", "q", 0, -1);
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[4.into(), 3.into(), 1.into(), 2.into()], &[]);
+
+        let cmd = CommandRequest::new_lpop("t1", "q", 0);
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[4.into()], &[]);
+
+        let cmd = CommandRequest::new_rpop("t1", "q", 2);
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[2.into(), 1.into()], &[]);
+
+        let cmd = CommandRequest::new_ltrim("t1", "q", 1, -1);
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[Value::default()], &[]);
+
+        let cmd = CommandRequest::new_llen("t1", "q");
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[0.into()], &[]);
+
+        let cmd = CommandRequest::new_lpop("t1", "q", 1);
+        let res = dispatch(cmd, &store);
+        assert_res_error(res, 404, "Not found");
+
+        let cmd = CommandRequest::new_brpop("t1", "q", 10);
+        let res = dispatch(cmd, &store);
+        assert_res_error(res, 404, "Not found");
+    }
+
+    #[test]
+    fn set_commands_should_work() {
+        let store = MemTable::new();
+        let cmd = CommandRequest::new_sadd("t1", "s1", vec!["a".into(), "b".into()]);
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[2.into()], &[]);
+
+        let cmd = CommandRequest::new_sadd("t1", "s2", vec!["b".into(), "c".into()]);
+        dispatch(cmd, &store);
+
+        let cmd = CommandRequest::new_sismember("t1", "s1", "a".into());
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[true.into()], &[]);
+
+        let cmd = CommandRequest::new_sinter("t1", vec!["s1".into(), "s2".into()]);
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &["b".into()], &[]);
+
+        let cmd = CommandRequest::new_sunion("t1", vec!["s1".into(), "s2".into()]);
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &["a".into(), "b".into(), "c".into()], &[]);
+
+        let cmd = CommandRequest::new_srem("t1", "s1", vec!["a".into()]);
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[1.into()], &[]);
+
+        let cmd = CommandRequest::new_smembers("t1", "s1");
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &["b".into()], &[]);
+    }
+
+    #[test]
+    fn sorted_set_commands_should_work() {
+        let store = MemTable::new();
+        let members = vec![
+            ScoredValue::new("alice".into(), 10.0),
+            ScoredValue::new("bob".into(), 20.0),
+        ];
+        let cmd = CommandRequest::new_zadd("t1", "board", members.clone());
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[2.into()], &[]);
+
+        let cmd = CommandRequest::new_zincrby("t1", "board", "alice".into(), 15.0);
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[25.0.into()], &[]);
+
+        let cmd = CommandRequest::new_zrange("t1", "board", 0, 0, true);
+        let res = dispatch(cmd, &store);
+        assert_eq!(res.status, 200);
+        assert_eq!(
+            res.scored_values,
+            vec![ScoredValue::new("alice".into(), 25.0)]
+        );
+
+        let cmd = CommandRequest::new_zrangebyscore("t1", "board", 0.0, 20.0);
+        let res = dispatch(cmd, &store);
+        assert_eq!(res.scored_values, vec![members[1].clone()]);
+
+        let cmd = CommandRequest::new_zrank("t1", "board", "bob".into(), false);
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[0.into()], &[]);
+
+        let cmd = CommandRequest::new_zrank("t1", "board", "carol".into(), false);
+        let res = dispatch(cmd, &store);
+        assert_res_error(res, 404, "Not found");
+    }
+
+    #[test]
+    fn path_commands_should_work() {
+        let store = MemTable::new();
+        let cmd = CommandRequest::new_hset_path("t1", "u1", &["name"], "alice".into());
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[Value::default()], &[]);
+
+        let tags: Value = vec!["a".into(), "b".into()].into();
+        let cmd = CommandRequest::new_hset_path("t1", "u1", &["tags"], tags);
+        dispatch(cmd, &store);
+
+        let cmd = CommandRequest::new_hget_path("t1", "u1", &["tags", "1"]);
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &["b".into()], &[]);
+
+        let cmd = CommandRequest::new_hdel_path("t1", "u1", &["tags", "0"]);
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &["a".into()], &[]);
+
+        let cmd = CommandRequest::new_hget("t1", "u1");
+        let res = dispatch(cmd, &store);
+        let expected: Value = BTreeMap::from([
+            ("name".to_string(), Value::from("alice")),
+            ("tags".to_string(), vec!["b".into()].into()),
+        ])
+        .into();
+        assert_res_ok(res, &[expected], &[]);
+
+        let cmd = CommandRequest::new_hget_path("t1", "u1", &["tags", "1"]);
+        let res = dispatch(cmd, &store);
+        assert_res_error(res, 404, "Not found");
+
+        let cmd = CommandRequest::new_hset_path("t1", "u1", &["name", "first"], "a".into());
+        let res = dispatch(cmd, &store);
+        assert_res_error(res, 400, "Invalid path");
+    }
+
+    #[test]
+    fn json_commands_should_work() {
+        let store = MemTable::new();
+        let cmd = CommandRequest::new_json_set("t1", "cfg", "$", r#"{"port":80,"hosts":[]}"#);
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[1.into()], &[]);
+
+        let cmd =
+            CommandRequest::new_json_arr_append("t1", "cfg", "$.hosts", vec![r#""a""#.into()]);
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[1.into()], &[]);
+
+        let cmd = CommandRequest::new_json_num_incr_by("t1", "cfg", "$.port", 1.0);
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[81.into()], &[]);
+
+        let cmd = CommandRequest::new_json_get("t1", "cfg", "");
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[r#"{"hosts":["a"],"port":81}"#.into()], &[]);
+
+        let cmd = CommandRequest::new_json_del("t1", "cfg", "$.hosts");
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[1.into()], &[]);
+
+        // 写入前会校验 JSON 和 JSONPath
+        let cmd = CommandRequest::new_json_set("t1", "cfg", "$.a", "{oops");
+        let res = dispatch(cmd, &store);
+        assert_res_error(res, 400, "Invalid JSON");
+
+        let cmd = CommandRequest::new_json_get("t1", "cfg", "$..a");
+        let res = dispatch(cmd, &store);
+        assert_res_error(res, 400, "Invalid JSONPath");
+
+        let cmd = CommandRequest::new_json_get("t1", "none", "$");
+        let res = dispatch(cmd, &store);
+        assert_res_error(res, 404, "Not found");
+    }
+
+    #[test]
+    fn stream_commands_should_work() {
+        let store = MemTable::new();
+        let fields = vec![Kvpair::new("event", "created".into())];
+        let cmd = CommandRequest::new_xadd("t1", "s1", Some("1-0".into()), fields.clone());
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &["1-0".into()], &[]);
+
+        let cmd = CommandRequest::new_xadd("t1", "s1", Some("1-0".into()), fields.clone());
+        let res = dispatch(cmd, &store);
+        assert_res_error(res, 400, "not greater than");
+
+        let cmd = CommandRequest::new_xadd("t1", "s1", None, fields.clone());
+        let res = dispatch(cmd, &store);
+        assert_eq!(res.status, 200);
+
+        let cmd = CommandRequest::new_xrange("t1", "s1", "-", "+", 0);
+        let res = dispatch(cmd, &store);
+        assert_eq!(res.entries.len(), 2);
+        assert_eq!(res.entries[0].id, "1-0");
+        assert_eq!(res.entries[0].fields, fields);
+
+        let cmd = CommandRequest::new_xread("t1", "s1", "1-0", 0, None);
+        let res = dispatch(cmd, &store);
+        assert_eq!(res.entries.len(), 1);
+
+        let cmd = CommandRequest::new_xread("t1", "s1", "$", 0, None);
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[], &[]);
+
+        let cmd = CommandRequest::new_xgroup_create("t1", "s1", "g1", "0");
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[Value::default()], &[]);
+
+        let cmd = CommandRequest::new_xreadgroup("t1", "s1", "g1", "c1", 1, None);
+        let res = dispatch(cmd, &store);
+        assert_eq!(res.entries.len(), 1);
+
+        let cmd = CommandRequest::new_xpending("t1", "s1", "g1");
+        let res = dispatch(cmd, &store);
+        assert_eq!(res.pending.len(), 1);
+        assert_eq!(res.pending[0].consumer, "c1");
+
+        let cmd = CommandRequest::new_xack("t1", "s1", "g1", vec!["1-0".into()]);
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[1.into()], &[]);
+
+        let cmd = CommandRequest::new_xtrim("t1", "s1", 1, 0);
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[1.into()], &[]);
+
+        let cmd = CommandRequest::new_xlen("t1", "s1");
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[1.into()], &[]);
+    }
+
+    #[test]
+    fn timeseries_commands_should_work() {
+        let store = MemTable::new();
+        let cmd = CommandRequest::new_ts_create_rule("t1", "cpu", "t1_1s", "cpu", "avg", 1000);
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[Value::default()], &[]);
+
+        let cmd = CommandRequest::new_ts_create_rule("t1", "cpu", "t1_1s", "cpu", "median", 1000);
+        let res = dispatch(cmd, &store);
+        assert_res_error(res, 400, "Invalid aggregation");
+
+        for (ts, v) in [(1000, 1.0), (1500, 3.0), (2000, 5.0)] {
+            let res = dispatch(CommandRequest::new_ts_add("t1", "cpu", ts, v), &store);
+            assert_res_ok(res, &[(ts as i64).into()], &[]);
+        }
+
+        let cmd = CommandRequest::new_ts_range("t1", "cpu", 1200, 0, None);
+        let res = dispatch(cmd, &store);
+        assert_eq!(
+            res.samples,
+            vec![Sample::new(1500, 3.0), Sample::new(2000, 5.0)]
+        );
+
+        let cmd = CommandRequest::new_ts_range("t1", "cpu", 0, 0, Some(("sum", 1000)));
+        let res = dispatch(cmd, &store);
+        assert_eq!(
+            res.samples,
+            vec![Sample::new(1000, 4.0), Sample::new(2000, 5.0)]
+        );
+
+        let cmd = CommandRequest::new_ts_range("t1_1s", "cpu", 0, 0, None);
+        let res = dispatch(cmd, &store);
+        assert_eq!(
+            res.samples,
+            vec![Sample::new(1000, 2.0), Sample::new(2000, 5.0)]
+        );
+
+        let cmd = CommandRequest::new_ts_set_retention("t1", "cpu", 100);
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[Value::default()], &[]);
+        dispatch(CommandRequest::new_ts_add("t1", "cpu", 2050, 1.0), &store);
+        let cmd = CommandRequest::new_ts_range("t1", "cpu", 0, 0, None);
+        let res = dispatch(cmd, &store);
+        assert_eq!(
+            res.samples,
+            vec![Sample::new(2000, 5.0), Sample::new(2050, 1.0)]
+        );
+
+        let cmd = CommandRequest::new_ts_delete_rule("t1", "cpu", "t1_1s", "cpu");
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[1.into()], &[]);
+    }
+
+    #[test]
+    fn probabilistic_commands_should_work() {
+        let store = MemTable::new();
+        let items: Vec<Value> = (0..10).map(Value::from).collect();
+        let cmd = CommandRequest::new_pfadd("t1", "h1", items.clone());
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[true.into()], &[]);
+
+        let cmd = CommandRequest::new_pfadd("t1", "h2", vec![10.into()]);
+        dispatch(cmd, &store);
+        let cmd = CommandRequest::new_pfmerge("t1", "all", vec!["h1".into(), "h2".into()]);
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[Value::default()], &[]);
+        let cmd = CommandRequest::new_pfcount("t1", vec!["all".into()]);
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[11.into()], &[]);
+
+        let cmd = CommandRequest::new_bfadd("t1", "bf", vec!["a".into(), "a".into()]);
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[true.into(), false.into()], &[]);
+        let cmd = CommandRequest::new_bfexists("t1", "bf", vec!["a".into(), "b".into()]);
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[true.into(), false.into()], &[]);
+
+        let cmd = CommandRequest::new_bfadd_with("t1", "bf2", items, 100, 1.5);
+        let res = dispatch(cmd, &store);
+        assert_res_error(res, 400, "Invalid bloom filter");
+
+        // 已经存在的普通 value 不能当作 sketch 使用
+        let cmd = CommandRequest::new_pfadd("t1", "bf", vec![1.into()]);
+        let res = dispatch(cmd, &store);
+        assert_res_error(res, 400, "HyperLogLog");
+    }
+
+    #[test]
+    fn bitmap_commands_should_work() {
+        let store = MemTable::new();
+        for offset in [1, 9] {
+            let res = dispatch(CommandRequest::new_setbit("t1", "d1", offset, true), &store);
+            assert_res_ok(res, &[false.into()], &[]);
+        }
+        let res = dispatch(CommandRequest::new_setbit("t1", "d2", 9, true), &store);
+        assert_res_ok(res, &[false.into()], &[]);
+
+        let res = dispatch(CommandRequest::new_getbit("t1", "d1", 9), &store);
+        assert_res_ok(res, &[true.into()], &[]);
+        let res = dispatch(CommandRequest::new_bitcount("t1", "d1", 0, -1), &store);
+        assert_res_ok(res, &[2.into()], &[]);
+
+        let cmd = CommandRequest::new_bitop("t1", "and", "both", vec!["d1".into(), "d2".into()]);
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[2.into()], &[]);
+        let res = dispatch(
+            CommandRequest::new_bitpos("t1", "both", true, 0, -1),
+            &store,
+        );
+        assert_res_ok(res, &[9.into()], &[]);
+        let res = dispatch(CommandRequest::new_bitpos("t1", "both", true, 0, 0), &store);
+        assert_res_ok(res, &[(-1).into()], &[]);
+
+        let cmd = CommandRequest::new_bitop("t1", "nand", "both", vec!["d1".into()]);
+        let res = dispatch(cmd, &store);
+        assert_res_error(res, 400, "Invalid bit operation");
+    }
+
+    #[test]
+    fn index_commands_should_work() {
+        let store = MemTable::new();
+        let user = |name: &str, age: i64| -> Value {
+            BTreeMap::from([
+                ("name".to_string(), Value::from(name)),
+                ("age".to_string(), Value::from(age)),
+            ])
+            .into()
+        };
+        dispatch(
+            CommandRequest::new_hset("users", "u1", user("alice", 30)),
+            &store,
+        );
+        let cmd = CommandRequest::new_index_create("users", "by_age", vec!["age".into()]);
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[1.into()], &[]);
+
+        // Hset / Hdel 会维护索引
+        dispatch(
+            CommandRequest::new_hset("users", "u2", user("bob", 25)),
+            &store,
+        );
+        dispatch(
+            CommandRequest::new_hset("users", "u3", user("carol", 30)),
+            &store,
+        );
+        dispatch(CommandRequest::new_hdel("users", "u1"), &store);
+
+        let cmd = CommandRequest::new_index_lookup("users", "by_age", 30.into());
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[], &[Kvpair::new("u3", user("carol", 30))]);
+
+        let cmd = CommandRequest::new_index_range("users", "by_age", None, Some(30.into()), 0);
+        let res = dispatch(cmd, &store);
+        let pairs = [
+            Kvpair::new("u2", user("bob", 25)),
+            Kvpair::new("u3", user("carol", 30)),
+        ];
+        assert_res_ok(res, &[], &pairs);
+
+        // HsetPath 修改被索引的字段也会维护索引
+        dispatch(
+            CommandRequest::new_hset_path("users", "u2", &["age"], 30.into()),
+            &store,
+        );
+        let cmd = CommandRequest::new_index_lookup("users", "by_age", 25.into());
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[], &[]);
+        let cmd = CommandRequest::new_index_lookup("users", "by_age", 30.into());
+        let res = dispatch(cmd, &store);
+        let pairs = [
+            Kvpair::new("u2", user("bob", 30)),
+            Kvpair::new("u3", user("carol", 30)),
+        ];
+        assert_res_ok(res, &[], &pairs);
+
+        let res = dispatch(CommandRequest::new_index_drop("users", "by_age"), &store);
+        assert_res_ok(res, &[true.into()], &[]);
+        let cmd = CommandRequest::new_index_lookup("users", "by_age", 30.into());
+        let res = dispatch(cmd, &store);
+        assert_res_error(res, 400, "does not exist");
+    }
+
+    #[test]
+    fn query_command_should_work() {
+        let store = MemTable::new();
+        let pairs = [("m:cpu", 10), ("m:mem", 20), ("x:cpu", 30)];
+        for (key, v) in pairs {
+            dispatch(CommandRequest::new_hset("t1", key, v.into()), &store);
+        }
+
+        let query = Query {
+            table: "t1".into(),
+            key_pattern: "m:*".into(),
+            predicates: vec![Predicate {
+                path: vec![],
+                op: "gt".into(),
+                value: Some(10.into()),
+            }],
+            ..Default::default()
+        };
+        let res = dispatch(CommandRequest::new_query(query.clone()), &store);
+        assert_res_ok(res, &[], &[Kvpair::new("m:mem", 20.into())]);
+
+        let query = Query {
+            predicates: vec![],
+            aggregates: vec![Aggregate {
+                func: "sum".into(),
+                path: vec![],
+            }],
+            ..query
+        };
+        let res = dispatch(CommandRequest::new_query(query), &store);
+        assert_res_ok(res, &[30.into()], &[]);
+    }
+
+    #[test]
+    fn hkeys_and_hrandkey_should_work() {
+        let store = MemTable::new();
+        for key in ["user:1:profile", "user:2:profile", "user:1:cart"] {
+            dispatch(CommandRequest::new_hset("t1", key, 1.into()), &store);
+        }
+
+        let res = dispatch(CommandRequest::new_hkeys("t1", "user:*:profile"), &store);
+        assert_eq!(res.status, 200);
+        let mut keys = res.values;
+        keys.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let expected: Vec<Value> = vec![
+            Bytes::from("user:1:profile").into(),
+            Bytes::from("user:2:profile").into(),
+        ];
+        assert_eq!(keys, expected);
+
+        let res = dispatch(CommandRequest::new_hkeys("t1", ""), &store);
+        assert_eq!(res.values.len(), 3);
+
+        // count 为 0 时返回一个 key
+        let res = dispatch(CommandRequest::new_hrandkey("t1", 0), &store);
+        assert_eq!(res.values.len(), 1);
+        let res = dispatch(CommandRequest::new_hrandkey("t1", -5), &store);
+        assert_eq!(res.values.len(), 5);
+        let res = dispatch(CommandRequest::new_hrandkey("t1", i64::MIN), &store);
+        assert_eq!(res.status, 400);
+    }
+
+    #[test]
+    fn internal_tables_should_be_rejected() {
+        let store = MemTable::new();
+        dispatch(CommandRequest::new_hset("t1", "k1", 1.into()), &store);
+        dispatch(
+            CommandRequest::new_index_create("t1", "by_x", vec![]),
+            &store,
+        );
+
+        let cmds = vec![
+            CommandRequest::new_hset("__meta__", "k1", 1.into()),
+            CommandRequest::new_hgetall("__index__.t1.by_x"),
+            CommandRequest::new_hdel("__index__.t1.by_x", "k1"),
+            CommandRequest::new_index_create("__meta__", "by_x", vec![]),
+            CommandRequest::new_query(Query {
+                table: "__history__.t1".into(),
+                ..Default::default()
+            }),
+            CommandRequest::new_ts_create_rule("t1", "ts", "__meta__", "k1", "avg", 1000),
+        ];
+        for cmd in cmds {
+            let res = dispatch(cmd, &store);
+            assert_res_error(res, 400, "reserved");
+        }
+
+        // 内部的 table 没有被修改
+        assert!(store.get("__meta__", b"k1").unwrap().is_none());
+        assert_eq!(store.get_all("__index__.t1.by_x").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn hello_should_work() {
+        let store = MemTable::new();
+        let compressions = vec!["br".into(), "zstd".into(), "gzip".into()];
+        let res = dispatch(CommandRequest::new_hello(compressions, 4096, 8192), &store);
+        assert_eq!(res.status, 200);
+        let hello = res.hello.unwrap();
+        assert_eq!(hello.version, PROTOCOL_VERSION);
+        assert_eq!(hello.compression, "zstd");
+        assert_eq!(hello.compression_threshold, 4096);
+        assert_eq!(hello.max_frame_size, 8192);
+        assert_eq!(hello.server_name, "kvs");
+        assert!(hello.commands.iter().any(|c| c == "Hget"));
+        assert!(hello.commands.iter().any(|c| c == "Hello"));
+
+        // 没有双方都支持的压缩算法时不压缩，阈值和 max_frame_size 为 0 时使用服务器的默认值
+        let res = dispatch(CommandRequest::new_hello(vec!["br".into()], 0, 0), &store);
+        let hello = res.hello.unwrap();
+        assert_eq!(hello.compression, "");
+        assert_eq!(hello.compression_threshold, COMPRESSION_LIMIT as u64);
+        assert_eq!(hello.max_frame_size, MAX_FRAME as u64);
+
+        // max_frame_size 不能超过 30 bit 能表示的长度
+        let res = dispatch(CommandRequest::new_hello(vec![], 0, 1 << 30), &store);
+        assert_eq!(res.hello.unwrap().max_frame_size, (1 << 30) - 1);
+
+        // 也不能小到连错误的回复都放不下
+        let res = dispatch(CommandRequest::new_hello(vec![], 0, 1), &store);
+        assert_eq!(res.hello.unwrap().max_frame_size, MIN_FRAME as u64);
+
+        // 更新的客户端使用服务器的版本，版本 0 是无效的
+        let hello = Hello {
+            version: PROTOCOL_VERSION + 1,
+            ..Default::default()
+        };
+        assert_eq!(
+            hello.execute(&store).hello.unwrap().version,
+            PROTOCOL_VERSION
+        );
+        assert_eq!(Hello::default().execute(&store).status, 400);
+    }
+
     fn set_key_pairs<T: Into<Value>>(table: &str, pairs: Vec<(&str, T)>, store: &impl Storage) {
         pairs
             .into_iter()
@@ -1,7 +1,10 @@
 use crate::{
-    command_request::RequestData, CommandRequest, CommandResponse, KvError, MemTable, Storage,
+    command_request::RequestData, CommandRequest, CommandResponse, KvError, Kvpair, MemTable,
+    Query, QueryStorage, Storage, StreamStorage, Xread,
 };
-use std::sync::Arc;
+use http::StatusCode;
+use std::{sync::Arc, time::Duration};
+use tokio::time::{self, Instant};
 use tracing::debug;
 
 mod command_service;
@@ -62,8 +65,10 @@ impl<Store> Clone for Service<Store> {
 // on_executed: 当服务器处理完 CommandRequest 得到 CommandResponse 时触发
 // on_before_send: 在服务器发送 CommandReponse 之前触发， fn 接收的是 mut CommandResponse，意味着可以修改 response
 // on_after_send: 在服务器发送完 Commandresponse 后触发
+// data_pushed: 有数据写入列表或者 stream 时通知等待中的 Blpop / Brpop / Xread / Xreadgroup
 pub struct ServiceInner<Store> {
     store: Store,
+    data_pushed: tokio::sync::Notify,
     on_received: Vec<fn(&CommandRequest)>,
     on_executed: Vec<fn(&CommandResponse)>,
     on_before_send: Vec<fn(&mut CommandResponse)>,
@@ -74,6 +79,7 @@ impl<Store: Storage> ServiceInner<Store> {
     pub fn new(store: Store) -> Self {
         Self {
             store,
+            data_pushed: tokio::sync::Notify::new(),
             on_received: vec![],
             on_executed: vec![],
             on_before_send: vec![],
@@ -103,10 +109,91 @@ impl<Store: Storage> ServiceInner<Store> {
 }
 
 impl<Store: Storage> Service<Store> {
+    // 执行命令。Blpop / Brpop / Xread / Xreadgroup 在这里不会等待，没有数据时直接返回
     pub fn execute(&self, cmd: CommandRequest) -> CommandResponse {
         debug!("Got request: {:?}", cmd);
         self.inner.on_received.notify(&cmd);
-        let mut res = dispatch(cmd, &self.inner.store);
+        let res = self.dispatch(cmd);
+        self.executed(res)
+    }
+
+    // 和 execute 一样，但 Blpop / Brpop，以及设置了 block 的 Xread / Xreadgroup
+    // 会一直等到有数据或者超时
+    pub async fn execute_async(&self, mut cmd: CommandRequest) -> CommandResponse {
+        let timeout_ms = match &mut cmd.request_data {
+            Some(RequestData::Blpop(v)) => v.timeout_ms,
+            Some(RequestData::Brpop(v)) => v.timeout_ms,
+            Some(RequestData::Xread(v)) if v.block => {
+                // $ 表示只等待新的 entry，需要在开始等待之前确定下来
+                if let Err(e) = self.resolve_last_id(v) {
+                    return self.execute_failed(cmd, e);
+                }
+                v.timeout_ms
+            }
+            Some(RequestData::Xreadgroup(v)) if v.block => v.timeout_ms,
+            _ => return self.execute(cmd),
+        };
+
+        debug!("Got request: {:?}", cmd);
+        self.inner.on_received.notify(&cmd);
+        let deadline = (timeout_ms > 0).then(|| Instant::now() + Duration::from_millis(timeout_ms));
+        let res = loop {
+            // 先注册通知再尝试读取，这样读取失败之后到开始等待之间写入的数据也不会错过
+            let pushed = self.inner.data_pushed.notified();
+            let res = self.dispatch(cmd.clone());
+            if !is_empty_result(&res) {
+                break res;
+            }
+            match deadline {
+                Some(deadline) => {
+                    if time::timeout_at(deadline, pushed).await.is_err() {
+                        break res;
+                    }
+                }
+                None => pushed.await,
+            }
+        };
+        self.executed(res)
+    }
+
+    // 把 Xread 里表示"只读新 entry"的 $ 换成流当前最后一个 entry 的 id
+    pub(crate) fn resolve_last_id(&self, xread: &mut Xread) -> Result<(), KvError> {
+        if xread.after == "$" {
+            let id = self.inner.store.stream_last_id(&xread.table, &xread.key)?;
+            xread.after = id.to_string();
+        }
+        Ok(())
+    }
+
+    // 边遍历边把 Query 的结果交给 f，f 返回 false 时停止。遍历是同步的，需要在阻塞线程里调用
+    pub(crate) fn query_each(
+        &self,
+        query: &Query,
+        f: &mut dyn FnMut(Kvpair) -> bool,
+    ) -> Result<(), KvError> {
+        check_table(&query.table)?;
+        self.inner.store.query_each(query, f)
+    }
+
+    fn execute_failed(&self, cmd: CommandRequest, e: KvError) -> CommandResponse {
+        debug!("Got request: {:?}", cmd);
+        self.inner.on_received.notify(&cmd);
+        self.executed(e.into())
+    }
+
+    fn dispatch(&self, cmd: CommandRequest) -> CommandResponse {
+        let pushed = matches!(
+            cmd.request_data,
+            Some(RequestData::Lpush(_)) | Some(RequestData::Rpush(_)) | Some(RequestData::Xadd(_))
+        );
+        let res = dispatch(cmd, &self.inner.store);
+        if pushed {
+            self.inner.data_pushed.notify_waiters();
+        }
+        res
+    }
+
+    fn executed(&self, mut res: CommandResponse) -> CommandResponse {
         debug!("Executed response: {:?}", res);
         self.inner.on_executed.notify(&res);
         self.inner.on_before_send.notify(&mut res);
@@ -126,20 +213,169 @@ impl<Store: Storage> From<ServiceInner<Store>> for Service<Store> {
     }
 }
 
-// 从 Request 中得到 Response, 目前处理 HGET / HSET /HGETALL
-pub fn dispatch(cmd: CommandRequest, store: &impl Storage) -> CommandResponse {
-    match cmd.request_data {
-        Some(RequestData::Hget(v)) => v.execute(store),
-        Some(RequestData::Hgetall(v)) => v.execute(store),
-        Some(RequestData::Hset(v)) => v.execute(store),
-        Some(RequestData::Hdel(v)) => v.execute(store),
-        Some(RequestData::Hexist(v)) => v.execute(store),
-        Some(RequestData::Hmget(v)) => v.execute(store),
-        Some(RequestData::Hmset(v)) => v.execute(store),
-        Some(RequestData::Hmexist(v)) => v.execute(store),
-        Some(RequestData::Hmdel(v)) => v.execute(store),
-        None => KvError::InvalidCommand("Request has no data".into()).into(),
+// 所有命令的 RPC 方法名和 RequestData 里的名字，gRPC 的 KvService 和 Hello 返回的命令列表都由它生成
+// 用法：for_each_command!(callback) 或 for_each_command!(callback { ... })，
+// 展开成 callback! { hget => Hget, ..., { ... } }
+macro_rules! for_each_command {
+    ($callback:ident $({ $($rest:tt)* })?) => {
+        $callback! {
+            hget => Hget,
+            hgetall => Hgetall,
+            hmget => Hmget,
+            hset => Hset,
+            hmset => Hmset,
+            hdel => Hdel,
+            hmdel => Hmdel,
+            hexist => Hexist,
+            hmexist => Hmexist,
+            hversioning => Hversioning,
+            hget_version => HgetVersion,
+            hhistory => Hhistory,
+            hgetall_as_of => HgetallAsOf,
+            lpush => Lpush,
+            rpush => Rpush,
+            lpop => Lpop,
+            rpop => Rpop,
+            lrange => Lrange,
+            llen => Llen,
+            ltrim => Ltrim,
+            blpop => Blpop,
+            brpop => Brpop,
+            sadd => Sadd,
+            srem => Srem,
+            smembers => Smembers,
+            sismember => Sismember,
+            sinter => Sinter,
+            sunion => Sunion,
+            zadd => Zadd,
+            zrange => Zrange,
+            zrangebyscore => Zrangebyscore,
+            zrank => Zrank,
+            zincrby => Zincrby,
+            hget_path => HgetPath,
+            hset_path => HsetPath,
+            hdel_path => HdelPath,
+            json_get => JsonGet,
+            json_set => JsonSet,
+            json_del => JsonDel,
+            json_arr_append => JsonArrAppend,
+            json_num_incr_by => JsonNumIncrBy,
+            xadd => Xadd,
+            xrange => Xrange,
+            xread => Xread,
+            xlen => Xlen,
+            xtrim => Xtrim,
+            xgroup_create => XgroupCreate,
+            xreadgroup => Xreadgroup,
+            xack => Xack,
+            xpending => Xpending,
+            ts_add => TsAdd,
+            ts_range => TsRange,
+            ts_set_retention => TsSetRetention,
+            ts_create_rule => TsCreateRule,
+            ts_delete_rule => TsDeleteRule,
+            pfadd => Pfadd,
+            pfcount => Pfcount,
+            pfmerge => Pfmerge,
+            bfadd => Bfadd,
+            bfexists => Bfexists,
+            setbit => Setbit,
+            getbit => Getbit,
+            bitcount => Bitcount,
+            bitop => Bitop,
+            bitpos => Bitpos,
+            index_create => IndexCreate,
+            index_drop => IndexDrop,
+            index_lookup => IndexLookup,
+            index_range => IndexRange,
+            query => Query,
+            hkeys => Hkeys,
+            hrandkey => Hrandkey,
+            hello => Hello,
+            $({ $($rest)* })?
+        }
+    };
+}
+pub(crate) use for_each_command;
+
+macro_rules! command_names {
+    ($($method:ident => $variant:ident,)*) => {
+        pub(crate) const COMMANDS: &[&str] = &[$(stringify!($variant)),*];
+    };
+}
+
+for_each_command!(command_names);
+
+// 命令操作的 table，Hello 和 table 无关
+macro_rules! command_table {
+    (Hello, $v:ident) => {
+        ""
+    };
+    ($variant:ident, $v:ident) => {
+        &$v.table
+    };
+}
+
+macro_rules! dispatch_commands {
+    ($($method:ident => $variant:ident,)*) => {
+        // 从 Request 中得到 Response
+        pub fn dispatch(cmd: CommandRequest, store: &impl Storage) -> CommandResponse {
+            match cmd.request_data {
+                $(
+                    Some(RequestData::$variant(v)) => {
+                        if let Err(e) = check_table(command_table!($variant, v)) {
+                            return e.into();
+                        }
+                        v.execute(store)
+                    }
+                )*
+                None => KvError::InvalidCommand("Request has no data".into()).into(),
+            }
+        }
+    };
+}
+
+for_each_command!(dispatch_commands);
+
+// __ 开头的 table 是 storage 内部使用的（索引、多版本的历史等），命令不能直接读写
+pub(crate) fn check_table(table: &str) -> Result<(), KvError> {
+    if table.starts_with("__") {
+        return Err(KvError::InvalidCommand(format!(
+            "Table {} is reserved for internal use",
+            table
+        )));
     }
+    Ok(())
+}
+
+// 列表为空时 Blpop / Brpop 返回 404，stream 没有新数据时 Xread / Xreadgroup 返回空的结果
+fn is_empty_result(res: &CommandResponse) -> bool {
+    res.status == StatusCode::NOT_FOUND.as_u16() as u32
+        || (res.status == StatusCode::OK.as_u16() as u32
+            && res.values.is_empty()
+            && res.entries.is_empty())
+}
+
+#[cfg(test)]
+use crate::Value;
+
+// 测试成功返回的结果
+#[cfg(test)]
+pub fn assert_res_ok(mut res: CommandResponse, values: &[Value], pairs: &[Kvpair]) {
+    res.pairs.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    assert_eq!(res.status, 200);
+    assert_eq!(res.message, "");
+    assert_eq!(res.values, values);
+    assert_eq!(res.pairs, pairs);
+}
+
+// 测试失败返回的结果
+#[cfg(test)]
+pub fn assert_res_error(res: CommandResponse, code: u32, msg: &str) {
+    assert_eq!(res.status, code);
+    assert!(res.message.contains(msg));
+    assert_eq!(res.values, &[]);
+    assert_eq!(res.pairs, &[]);
 }
 
 #[cfg(test)]
@@ -199,30 +435,8 @@ mod tests {
             .into();
 
         let res = service.execute(CommandRequest::new_hset("t1", "k1", "v1".into()));
-        assert_eq!(res.status, StatusCode::CREATED.as_u16() as _);
+        assert_eq!(res.status, StatusCode::CREATED.as_u16() as u32);
         assert_eq!(res.message, "");
         assert_eq!(res.values, vec![Value::default()]);
     }
 }
-
-#[cfg(test)]
-use crate::{Kvpair, Value};
-
-// 测试成功返回的结果
-#[cfg(test)]
-pub fn assert_res_ok(mut res: CommandResponse, values: &[Value], pairs: &[Kvpair]) {
-    res.pairs.sort_by(|a, b| a.partial_cmp(b).unwrap());
-    assert_eq!(res.status, 200);
-    assert_eq!(res.message, "");
-    assert_eq!(res.values, values);
-    assert_eq!(res.pairs, pairs);
-}
-
-// 测试失败返回的结果
-#[cfg(test)]
-pub fn assert_res_error(res: CommandResponse, code: u32, msg: &str) {
-    assert_eq!(res.status, code);
-    assert!(res.message.contains(msg));
-    assert_eq!(res.values, &[]);
-    assert_eq!(res.pairs, &[]);
-}
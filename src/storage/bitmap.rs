@@ -0,0 +1,445 @@
+use bytes::Bytes;
+use std::{ops::Bound, ops::Range, str::FromStr};
+
+use super::{index::prefix_end, list::index_range, lock_row};
+use crate::{value, KvError, Storage, Value};
+
+// 和 Redis 一样，位图最大 2^32 位（512MB）
+const MAX_OFFSET: u64 = 1 << 32;
+// 每个块的字节数
+const CHUNK: usize = 4096;
+
+// 多个位图之间的位运算
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BitOp {
+    And,
+    Or,
+    Xor,
+    Not,
+}
+
+impl FromStr for BitOp {
+    type Err = KvError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "AND" => Ok(BitOp::And),
+            "OR" => Ok(BitOp::Or),
+            "XOR" => Ok(BitOp::Xor),
+            "NOT" => Ok(BitOp::Not),
+            _ => Err(KvError::InvalidCommand(format!(
+                "Invalid bit operation: {}",
+                s
+            ))),
+        }
+    }
+}
+
+// 在 Storage 的基础上提供位图，所有实现了 Storage 的结构都自动获得这些方法
+// offset 0 是第一个字节的最高位。不存在的 key 当作空的位图，超出长度的位都是 0
+// 位图按 CHUNK 字节分块存放，Setbit 只需要读写一个块，不用复制整个位图
+pub trait BitmapStorage: Storage {
+    // 设置 offset 处的位，返回之前的值。位图不够长时用 0 补齐
+    fn set_bit(&self, table: &str, key: &[u8], offset: u64, bit: bool) -> Result<bool, KvError> {
+        if offset >= MAX_OFFSET {
+            return Err(KvError::InvalidCommand(format!(
+                "Bit offset {} is out of range",
+                offset
+            )));
+        }
+
+        let byte = (offset / 8) as usize;
+        let (index, pos, mask) = (byte / CHUNK, byte % CHUNK, 0x80u8 >> (offset % 8));
+        let bitmaps = bitmap_table(table);
+        let _guard = lock_row(&bitmaps, key);
+        let mut old = false;
+        self.update(&bitmaps, &chunk_key(key, index), &mut |v| {
+            let mut buf = to_chunk(v)?;
+            if buf.len() <= pos {
+                buf.resize(pos + 1, 0);
+            }
+            old = buf[pos] & mask != 0;
+            if bit {
+                buf[pos] |= mask;
+            } else {
+                buf[pos] &= !mask;
+            }
+            Ok(Some(Bytes::from(buf).into()))
+        })?;
+        // 先写块再更新长度，没有加锁的读取最多暂时看不到这一位
+        if bitmap_len(self, table, key)? <= byte {
+            set_len(self, table, key, byte + 1)?;
+        }
+        Ok(old)
+    }
+
+    fn get_bit(&self, table: &str, key: &[u8], offset: u64) -> Result<bool, KvError> {
+        let byte = (offset / 8) as usize;
+        if offset >= MAX_OFFSET || byte >= bitmap_len(self, table, key)? {
+            return Ok(false);
+        }
+        let chunk = to_chunk(self.get(&bitmap_table(table), &chunk_key(key, byte / CHUNK))?)?;
+        let pos = byte % CHUNK;
+        Ok(pos < chunk.len() && chunk[pos] & (0x80 >> (offset % 8)) != 0)
+    }
+
+    // 统计字节区间 [start, end] 里为 1 的位的个数，负数表示从尾部开始数
+    fn bit_count(&self, table: &str, key: &[u8], start: i64, end: i64) -> Result<u64, KvError> {
+        let range = index_range(start, end, bitmap_len(self, table, key)?);
+        let mut count = 0;
+        visit_chunks(self, table, key, range, &mut |_, bytes| {
+            count += bytes.iter().map(|b| b.count_ones() as u64).sum::<u64>();
+            true
+        })?;
+        Ok(count)
+    }
+
+    // 对一组位图做位运算，结果写入 dest，返回结果的字节数。长度不同的位图用 0 补齐
+    // NOT 只能有一个源位图。结果为空时删除 dest
+    // 逐块计算，每次只读取每个源位图的一个块。dest 也可以是源位图之一
+    fn bit_op(
+        &self,
+        table: &str,
+        op: BitOp,
+        dest: &[u8],
+        keys: &[Bytes],
+    ) -> Result<usize, KvError> {
+        if keys.is_empty() || (op == BitOp::Not && keys.len() != 1) {
+            return Err(KvError::InvalidCommand(format!(
+                "Invalid number of keys for {:?}",
+                op
+            )));
+        }
+
+        let bitmaps = bitmap_table(table);
+        let _guard = lock_row(&bitmaps, dest);
+        let len = keys
+            .iter()
+            .map(|k| bitmap_len(self, table, k))
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .max()
+            .unwrap_or_default();
+        let chunks = len.div_ceil(CHUNK);
+        for index in 0..chunks {
+            let sources = keys
+                .iter()
+                .map(|k| to_chunk(self.get(&bitmaps, &chunk_key(k, index))?))
+                .collect::<Result<Vec<_>, _>>()?;
+            let size = CHUNK.min(len - index * CHUNK);
+            let byte = |b: &Vec<u8>, i: usize| b.get(i).copied().unwrap_or_default();
+            let result: Vec<u8> = (0..size)
+                .map(|i| {
+                    let mut bytes = sources.iter().map(|b| byte(b, i));
+                    let first = bytes.next().unwrap_or_default();
+                    match op {
+                        BitOp::And => bytes.fold(first, |acc, b| acc & b),
+                        BitOp::Or => bytes.fold(first, |acc, b| acc | b),
+                        BitOp::Xor => bytes.fold(first, |acc, b| acc ^ b),
+                        BitOp::Not => !first,
+                    }
+                })
+                .collect();
+
+            // 全是 0 的块不用存
+            if result.iter().all(|b| *b == 0) {
+                self.del(&bitmaps, &chunk_key(dest, index))?;
+            } else {
+                self.set(&bitmaps, chunk_key(dest, index), Bytes::from(result).into())?;
+            }
+        }
+
+        // 删除 dest 原来超出结果长度的块
+        let prefix = bitmap_prefix(dest);
+        let end = prefix_end(&prefix);
+        let stale = self.get_range(
+            &bitmaps,
+            Bound::Included(&chunk_key(dest, chunks)),
+            end.as_deref().map_or(Bound::Unbounded, Bound::Excluded),
+        )?;
+        for pair in stale {
+            self.del(&bitmaps, &pair.key)?;
+        }
+        set_len(self, table, dest, len)?;
+        Ok(len)
+    }
+
+    // 在字节区间 [start, end] 里找第一个值为 bit 的位，返回它在整个位图里的 offset，找不到时返回 None
+    fn bit_pos(
+        &self,
+        table: &str,
+        key: &[u8],
+        bit: bool,
+        start: i64,
+        end: i64,
+    ) -> Result<Option<u64>, KvError> {
+        let range = index_range(start, end, bitmap_len(self, table, key)?);
+        let last = range.end;
+        // 下一个要检查的字节，不存在的块和块末尾缺少的字节都是 0
+        let mut next = range.start;
+        let mut pos = None;
+        visit_chunks(self, table, key, range, &mut |offset, bytes| {
+            if !bit && offset > next {
+                pos = Some(next as u64 * 8);
+                return false;
+            }
+            pos = bytes.iter().enumerate().find_map(|(i, b)| {
+                // 找 0 的时候取反，统一成找第一个 1
+                let b = if bit { *b } else { !*b };
+                (b != 0).then(|| ((offset + i) * 8) as u64 + b.leading_zeros() as u64)
+            });
+            next = offset + bytes.len();
+            pos.is_none()
+        })?;
+        if pos.is_none() && !bit && next < last {
+            pos = Some(next as u64 * 8);
+        }
+        Ok(pos)
+    }
+}
+
+impl<T: Storage + ?Sized> BitmapStorage for T {}
+
+// 位图存放在另外一个 table 里：key 的长度（u32 大端）| key 这一行记录位图的字节数，
+// key 的长度 | key | 块的序号（u32 大端）这一行存放一个块，块不存在或者比 CHUNK 短时缺少的字节都是 0
+fn bitmap_table(table: &str) -> String {
+    format!("__bitmap__.{}", table)
+}
+
+fn bitmap_prefix(key: &[u8]) -> Vec<u8> {
+    let mut buf = (key.len() as u32).to_be_bytes().to_vec();
+    buf.extend_from_slice(key);
+    buf
+}
+
+fn chunk_key(key: &[u8], index: usize) -> Bytes {
+    let mut buf = bitmap_prefix(key);
+    buf.extend_from_slice(&(index as u32).to_be_bytes());
+    buf.into()
+}
+
+fn bitmap_len<S: Storage + ?Sized>(store: &S, table: &str, key: &[u8]) -> Result<usize, KvError> {
+    let len: Option<i64> = store
+        .get(&bitmap_table(table), &bitmap_prefix(key))?
+        .map(|v| v.try_into())
+        .transpose()?;
+    Ok(len.unwrap_or_default().max(0) as usize)
+}
+
+fn set_len<S: Storage + ?Sized>(
+    store: &S,
+    table: &str,
+    key: &[u8],
+    len: usize,
+) -> Result<(), KvError> {
+    let bitmaps = bitmap_table(table);
+    if len == 0 {
+        store.del(&bitmaps, &bitmap_prefix(key))?;
+    } else {
+        store.set(&bitmaps, bitmap_prefix(key).into(), (len as i64).into())?;
+    }
+    Ok(())
+}
+
+fn to_chunk(v: Option<Value>) -> Result<Vec<u8>, KvError> {
+    match v {
+        None => Ok(vec![]),
+        Some(Value {
+            value: Some(value::Value::Binary(buf)),
+        }) => Ok(buf.to_vec()),
+        Some(v) => Err(KvError::ConvertError(v, "Bitmap")),
+    }
+}
+
+// 按顺序遍历位图在字节区间 range 里存在的数据，f 收到数据在位图里的字节偏移和数据本身
+// 不存在的块和块末尾缺少的字节不会传给 f。f 返回 false 时停止
+fn visit_chunks<S: Storage + ?Sized>(
+    store: &S,
+    table: &str,
+    key: &[u8],
+    range: Range<usize>,
+    f: &mut dyn FnMut(usize, &[u8]) -> bool,
+) -> Result<(), KvError> {
+    if range.is_empty() {
+        return Ok(());
+    }
+    let chunks = store.get_range(
+        &bitmap_table(table),
+        Bound::Included(&chunk_key(key, range.start / CHUNK)),
+        Bound::Included(&chunk_key(key, (range.end - 1) / CHUNK)),
+    )?;
+    for pair in chunks {
+        let index = match pair.key.len().checked_sub(4) {
+            Some(i) => u32::from_be_bytes(pair.key[i..].try_into().unwrap()) as usize,
+            None => continue,
+        };
+        let buf = to_chunk(pair.value)?;
+        let base = index * CHUNK;
+        let (lo, hi) = (range.start.max(base), range.end.min(base + buf.len()));
+        if lo < hi && !f(lo, &buf[lo - base..hi - base]) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MemTable, SledDb};
+    use tempfile::tempdir;
+
+    #[test]
+    fn memtable_bitmap_should_work() {
+        test_bitmap(MemTable::new());
+    }
+
+    #[test]
+    fn sleddb_bitmap_should_work() {
+        let dir = tempdir().unwrap();
+        test_bitmap(SledDb::new(dir));
+    }
+
+    #[test]
+    fn memtable_bitop_should_work() {
+        test_bitop(MemTable::new());
+    }
+
+    #[test]
+    fn sleddb_bitop_should_work() {
+        let dir = tempdir().unwrap();
+        test_bitop(SledDb::new(dir));
+    }
+
+    fn test_bitmap(store: impl Storage) {
+        assert!(!store.set_bit("t1", b"dau", 7, true).unwrap());
+        assert!(store.set_bit("t1", b"dau", 7, true).unwrap());
+        assert!(!store.set_bit("t1", b"dau", 20, true).unwrap());
+        // offset 0 是第一个字节的最高位
+        assert_eq!(bitmap(&store, b"dau"), vec![0x01, 0x00, 0x08]);
+
+        assert!(store.get_bit("t1", b"dau", 7).unwrap());
+        assert!(!store.get_bit("t1", b"dau", 8).unwrap());
+        assert!(!store.get_bit("t1", b"dau", 1000).unwrap());
+        assert!(!store.get_bit("t1", b"none", 0).unwrap());
+
+        assert_eq!(store.bit_count("t1", b"dau", 0, -1).unwrap(), 2);
+        assert_eq!(store.bit_count("t1", b"dau", 1, -1).unwrap(), 1);
+        assert_eq!(store.bit_count("t1", b"none", 0, -1).unwrap(), 0);
+
+        assert_eq!(store.bit_pos("t1", b"dau", true, 0, -1).unwrap(), Some(7));
+        assert_eq!(store.bit_pos("t1", b"dau", true, 1, -1).unwrap(), Some(20));
+        assert_eq!(store.bit_pos("t1", b"dau", false, 0, -1).unwrap(), Some(0));
+        assert_eq!(store.bit_pos("t1", b"dau", true, 1, 1).unwrap(), None);
+
+        assert!(store.set_bit("t1", b"dau", 7, false).unwrap());
+        assert_eq!(store.bit_count("t1", b"dau", 0, -1).unwrap(), 1);
+
+        assert!(store.set_bit("t1", b"dau", MAX_OFFSET, true).is_err());
+        // 位图不存放在 table 里，不会影响同名的普通 value
+        store.set("t1", "s".into(), "hello".into()).unwrap();
+        assert!(!store.set_bit("t1", b"s", 0, true).unwrap());
+        assert_eq!(store.get("t1", b"s").unwrap(), Some("hello".into()));
+    }
+
+    #[test]
+    fn set_bit_should_only_touch_one_chunk() {
+        let store = MemTable::new();
+        let last = MAX_OFFSET - 1;
+        assert!(!store.set_bit("t1", b"big", last, true).unwrap());
+        assert!(store.get_bit("t1", b"big", last).unwrap());
+        // 长度一行，最后一个块一行，块只有用到的那么长
+        let rows: Vec<_> = store.get_iter("__bitmap__.t1").unwrap().collect();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(store.bit_count("t1", b"big", 0, -1).unwrap(), 1);
+        assert_eq!(
+            store.bit_pos("t1", b"big", true, 0, -1).unwrap(),
+            Some(last)
+        );
+        assert_eq!(store.bit_pos("t1", b"big", false, 0, -1).unwrap(), Some(0));
+        assert_eq!(
+            store.bit_pos("t1", b"big", false, -1, -1).unwrap(),
+            Some(last - 7)
+        );
+
+        // 跨块的位运算，dest 原来多出来的块会被删除
+        let offset = ((2 * CHUNK + 10) * 8) as u64;
+        store.set_bit("t1", b"mid", offset, true).unwrap();
+        let len = store
+            .bit_op("t1", BitOp::Not, b"dest", &["mid".into()])
+            .unwrap();
+        assert_eq!(len, 2 * CHUNK + 11);
+        assert_eq!(
+            store.bit_count("t1", b"dest", 0, -1).unwrap(),
+            len as u64 * 8 - 1
+        );
+        assert!(!store.get_bit("t1", b"dest", offset).unwrap());
+        set_bytes(&store, b"small", &[0xff; CHUNK + 1]);
+        store
+            .bit_op("t1", BitOp::And, b"dest", &["small".into()])
+            .unwrap();
+        assert_eq!(bitmap(&store, b"dest"), vec![0xff; CHUNK + 1]);
+        assert_eq!(store.bit_pos("t1", b"dest", false, 0, -1).unwrap(), None);
+        let rows = store.get_iter("__bitmap__.t1").unwrap();
+        assert_eq!(
+            rows.filter(|p| p.key.starts_with(&bitmap_prefix(b"dest")))
+                .count(),
+            3
+        );
+    }
+
+    // 读出整个位图
+    fn bitmap(store: &impl Storage, key: &[u8]) -> Vec<u8> {
+        let mut buf = vec![0; bitmap_len(store, "t1", key).unwrap()];
+        visit_chunks(store, "t1", key, 0..buf.len(), &mut |offset, bytes| {
+            buf[offset..offset + bytes.len()].copy_from_slice(bytes);
+            true
+        })
+        .unwrap();
+        buf
+    }
+
+    // 按位写入一个位图
+    fn set_bytes(store: &impl Storage, key: &[u8], bytes: &[u8]) {
+        for (i, b) in bytes.iter().enumerate() {
+            for j in 0..8 {
+                let bit = b & (0x80 >> j) != 0;
+                store.set_bit("t1", key, (i * 8 + j) as u64, bit).unwrap();
+            }
+        }
+    }
+
+    fn test_bitop(store: impl Storage) {
+        set_bytes(&store, b"a", &[0b1100, 0xff]);
+        set_bytes(&store, b"b", &[0b1010]);
+        let keys: Vec<Bytes> = vec!["a".into(), "b".into()];
+
+        let expected = [
+            (BitOp::And, vec![0b1000, 0x00]),
+            (BitOp::Or, vec![0b1110, 0xff]),
+            (BitOp::Xor, vec![0b0110, 0xff]),
+        ];
+        for (op, result) in expected {
+            assert_eq!(store.bit_op("t1", op, b"dest", &keys).unwrap(), 2);
+            assert_eq!(bitmap(&store, b"dest"), result);
+        }
+
+        assert_eq!(
+            store.bit_op("t1", BitOp::Not, b"dest", &keys[1..]).unwrap(),
+            1
+        );
+        assert_eq!(bitmap(&store, b"dest"), vec![0b1111_0101]);
+        assert!(store.bit_op("t1", BitOp::Not, b"dest", &keys).is_err());
+
+        // 源位图都不存在时删除 dest
+        assert_eq!(
+            store
+                .bit_op("t1", BitOp::Or, b"dest", &["none".into()])
+                .unwrap(),
+            0
+        );
+        assert!(bitmap(&store, b"dest").is_empty());
+        assert!(!store.get_bit("t1", b"dest", 0).unwrap());
+        assert!("nand".parse::<BitOp>().is_err());
+    }
+}
@@ -0,0 +1,245 @@
+use bytes::{BufMut, BytesMut};
+
+use super::{hash64, set::member_key};
+use crate::{value, IndexedStorage, KvError, Storage, Value};
+
+const MAGIC: &[u8] = b"BLOM";
+// Bfadd 自动创建过滤器时使用的默认参数
+const DEFAULT_CAPACITY: u64 = 100;
+const DEFAULT_ERROR_RATE: f64 = 0.01;
+// 位数组最多 2^32 位（512MB），和 bitmap 的 MAX_OFFSET 一致
+const MAX_BITS: f64 = (1u64 << 32) as f64;
+// 哈希函数的个数。误判率 1e-19 时也只需要 63 个，更多只会让每次操作变慢
+const MAX_HASHES: u32 = 64;
+
+// 布隆过滤器。以 Value::Binary 存放："BLOM" | 哈希函数个数 k（u32 大端）| 位数组
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BloomFilter {
+    hashes: u32,
+    bits: Vec<u8>,
+}
+
+impl BloomFilter {
+    // 按预计的元素个数和误判率计算位数组的大小和哈希函数的个数
+    pub fn new(capacity: u64, error_rate: f64) -> Result<Self, KvError> {
+        if capacity == 0 || !(error_rate > 0.0 && error_rate < 1.0) {
+            return Err(KvError::InvalidCommand(format!(
+                "Invalid bloom filter capacity {} or error rate {}",
+                capacity, error_rate
+            )));
+        }
+        let ln2 = std::f64::consts::LN_2;
+        let bits = (-(capacity as f64) * error_rate.ln() / (ln2 * ln2)).ceil();
+        // 参数来自客户端，先检查大小再分配，避免过大的分配让整个服务退出
+        if bits > MAX_BITS {
+            return Err(KvError::InvalidCommand(format!(
+                "Bloom filter with capacity {} and error rate {} exceeds {} bits",
+                capacity, error_rate, MAX_BITS
+            )));
+        }
+        let bits = bits as usize;
+        let bytes = bits.max(8).div_ceil(8);
+        let hashes = ((bytes * 8) as f64 / capacity as f64 * ln2)
+            .round()
+            .clamp(1.0, MAX_HASHES as f64) as u32;
+        Ok(Self {
+            hashes,
+            bits: vec![0; bytes],
+        })
+    }
+
+    // 加入一个元素，返回这个元素之前是否不存在
+    pub fn add(&mut self, item: &[u8]) -> bool {
+        let mut added = false;
+        for i in self.positions(item) {
+            let (byte, mask) = (i / 8, 1 << (i % 8));
+            added |= self.bits[byte] & mask == 0;
+            self.bits[byte] |= mask;
+        }
+        added
+    }
+
+    // 元素是否可能存在。返回 false 时一定不存在
+    pub fn contains(&self, item: &[u8]) -> bool {
+        self.positions(item)
+            .all(|i| self.bits[i / 8] & (1 << (i % 8)) != 0)
+    }
+
+    // 用两个独立的哈希模拟 k 个哈希函数：h1 + i * h2
+    fn positions(&self, item: &[u8]) -> impl Iterator<Item = usize> {
+        let len = (self.bits.len() * 8) as u64;
+        let h1 = hash64(item, 0);
+        let h2 = hash64(item, 1) | 1;
+        (0..self.hashes as u64).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % len) as usize)
+    }
+}
+
+impl From<&BloomFilter> for Value {
+    fn from(bf: &BloomFilter) -> Self {
+        let mut buf = BytesMut::with_capacity(MAGIC.len() + 4 + bf.bits.len());
+        buf.put_slice(MAGIC);
+        buf.put_u32(bf.hashes);
+        buf.put_slice(&bf.bits);
+        buf.freeze().into()
+    }
+}
+
+impl TryFrom<&Value> for BloomFilter {
+    type Error = KvError;
+
+    fn try_from(v: &Value) -> Result<Self, Self::Error> {
+        let header = MAGIC.len() + 4;
+        match &v.value {
+            // 位数组不能为空，哈希函数的个数要在 [1, MAX_HASHES] 之间，否则数据是损坏的
+            Some(value::Value::Binary(buf)) if buf.len() > header && buf.starts_with(MAGIC) => {
+                let hashes = u32::from_be_bytes(buf[MAGIC.len()..header].try_into().unwrap());
+                if !(1..=MAX_HASHES).contains(&hashes) {
+                    return Err(KvError::ConvertError(v.clone(), "BloomFilter"));
+                }
+                Ok(Self {
+                    hashes,
+                    bits: buf[header..].to_vec(),
+                })
+            }
+            _ => Err(KvError::ConvertError(v.clone(), "BloomFilter")),
+        }
+    }
+}
+
+// 在 Storage 的基础上提供布隆过滤器，所有实现了 Storage 的结构都自动获得这些方法
+// 过滤器直接存放在 table 里，key 已经存在但不是布隆过滤器时返回错误
+pub trait BloomStorage: Storage {
+    // 往 key 的过滤器里加入一组元素，返回每个元素之前是否不存在
+    // key 不存在时按 capacity 和 error_rate 创建过滤器，为 0 时使用默认值（100 个元素，1% 的误判率）
+    fn bf_add(
+        &self,
+        table: &str,
+        key: &[u8],
+        items: &[Value],
+        capacity: u64,
+        error_rate: f64,
+    ) -> Result<Vec<bool>, KvError> {
+        let mut added = vec![];
+        self.update_indexed(table, key, &mut |v| {
+            let mut bf = match v {
+                Some(v) => BloomFilter::try_from(&v)?,
+                None => BloomFilter::new(
+                    if capacity == 0 {
+                        DEFAULT_CAPACITY
+                    } else {
+                        capacity
+                    },
+                    if error_rate == 0.0 {
+                        DEFAULT_ERROR_RATE
+                    } else {
+                        error_rate
+                    },
+                )?,
+            };
+            added = items.iter().map(|item| bf.add(&member_key(item))).collect();
+            Ok(Some((&bf).into()))
+        })?;
+        Ok(added)
+    }
+
+    // 每个元素是否可能存在，key 不存在时都是 false
+    fn bf_exists(&self, table: &str, key: &[u8], items: &[Value]) -> Result<Vec<bool>, KvError> {
+        let bf = match self.get(table, key)? {
+            Some(v) => BloomFilter::try_from(&v)?,
+            None => return Ok(vec![false; items.len()]),
+        };
+        Ok(items
+            .iter()
+            .map(|item| bf.contains(&member_key(item)))
+            .collect())
+    }
+}
+
+impl<T: Storage + ?Sized> BloomStorage for T {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MemTable, SledDb};
+    use tempfile::tempdir;
+
+    #[test]
+    fn memtable_bloom_filter_should_work() {
+        test_bloom_filter(MemTable::new());
+    }
+
+    #[test]
+    fn sleddb_bloom_filter_should_work() {
+        let dir = tempdir().unwrap();
+        test_bloom_filter(SledDb::new(dir));
+    }
+
+    #[test]
+    fn bloom_filter_false_positive_rate_should_be_bounded() {
+        let mut bf = BloomFilter::new(1000, 0.01).unwrap();
+        for i in 0..1000 {
+            bf.add(format!("in-{}", i).as_bytes());
+        }
+        assert!((0..1000).all(|i| bf.contains(format!("in-{}", i).as_bytes())));
+        let false_positives = (0..10000)
+            .filter(|i| bf.contains(format!("out-{}", i).as_bytes()))
+            .count();
+        assert!(false_positives < 300, "{}", false_positives);
+
+        assert!(BloomFilter::new(0, 0.01).is_err());
+        assert!(BloomFilter::new(100, 1.0).is_err());
+    }
+
+    #[test]
+    fn oversized_bloom_filter_should_be_rejected() {
+        assert!(matches!(
+            BloomFilter::new(u64::MAX, 1e-300),
+            Err(KvError::InvalidCommand(_))
+        ));
+        let store = MemTable::new();
+        let res = store.bf_add("t1", b"bf", &["a".into()], u64::MAX, 1e-300);
+        assert!(matches!(res, Err(KvError::InvalidCommand(_))));
+        assert!(store.get("t1", b"bf").unwrap().is_none());
+    }
+
+    #[test]
+    fn corrupted_bloom_filter_should_be_rejected() {
+        let filter = |hashes: u32, bits: &[u8]| {
+            let mut buf = BytesMut::new();
+            buf.put_slice(MAGIC);
+            buf.put_u32(hashes);
+            buf.put_slice(bits);
+            Value::from(buf.freeze())
+        };
+        assert!(BloomFilter::try_from(&filter(3, &[0; 8])).is_ok());
+        assert!(BloomFilter::try_from(&filter(0, &[0; 8])).is_err());
+        assert!(BloomFilter::try_from(&filter(MAX_HASHES + 1, &[0; 8])).is_err());
+        assert!(BloomFilter::try_from(&filter(u32::MAX, &[0; 8])).is_err());
+        assert!(BloomFilter::try_from(&filter(3, &[])).is_err());
+
+        // 误判率极小时哈希函数的个数也不超过上限
+        let bf = BloomFilter::new(10, 1e-100).unwrap();
+        assert_eq!(bf.hashes, MAX_HASHES);
+    }
+
+    fn test_bloom_filter(store: impl Storage) {
+        let items: Vec<Value> = vec!["a".into(), "b".into()];
+        let res = store.bf_add("t1", b"bf", &items, 1000, 0.001).unwrap();
+        assert_eq!(res, vec![true, true]);
+        let res = store
+            .bf_add("t1", b"bf", &["a".into(), "c".into()], 0, 0.0)
+            .unwrap();
+        assert_eq!(res, vec![false, true]);
+
+        let res = store
+            .bf_exists("t1", b"bf", &["a".into(), "c".into(), "d".into()])
+            .unwrap();
+        assert_eq!(res, vec![true, true, false]);
+        let res = store.bf_exists("t1", b"none", &items).unwrap();
+        assert_eq!(res, vec![false, false]);
+
+        assert!(store.bf_add("t1", b"bad", &items, 100, 2.0).is_err());
+        store.set("t1", "s".into(), "hello".into()).unwrap();
+        assert!(store.bf_exists("t1", b"s", &items).is_err());
+    }
+}
@@ -0,0 +1,202 @@
+use aes_gcm_siv::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    Aes256GcmSiv, Nonce,
+};
+use std::{collections::BTreeMap, fmt, fs, path::Path};
+
+use crate::KvError;
+
+// 密钥长度 256 bit
+const KEY_LEN: usize = 32;
+// AES-GCM-SIV 的 nonce 长度
+const NONCE_LEN: usize = 12;
+// key id 占 4 个字节
+const KEY_ID_LEN: usize = 4;
+
+// 存放一组 AEAD 密钥，支持密钥轮换:
+// - id 最大的密钥是当前密钥（active），所有新的写入都用它加密
+// - 旧的密钥保留下来，用于解密之前写入的数据
+// - id 最小的密钥用于确定性地加密 key（同样的 key 总是得到同样的密文，这样才能查找），
+//   所以它在轮换时不能删除
+pub struct Keyring {
+    keys: BTreeMap<u32, Aes256GcmSiv>,
+}
+
+impl Keyring {
+    // 从本地的密钥文件加载，每行一个密钥，格式为 `<id>:<64 位 hex>`，
+    // 空行和 # 开头的行会被忽略
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, KvError> {
+        let content = fs::read_to_string(path)?;
+        Self::parse(&content)
+    }
+
+    pub fn parse(content: &str) -> Result<Self, KvError> {
+        let mut keys = BTreeMap::new();
+        for line in content.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (id, key) = line
+                .split_once(':')
+                .ok_or_else(|| KvError::CryptoError(format!("Invalid key line: {}", line)))?;
+            let id: u32 = id
+                .trim()
+                .parse()
+                .map_err(|_| KvError::CryptoError(format!("Invalid key id: {}", id)))?;
+            let key = hex::decode(key.trim())
+                .map_err(|_| KvError::CryptoError(format!("Invalid hex for key {}", id)))?;
+            keys.insert(id, Self::new_cipher(id, &key)?);
+        }
+
+        if keys.is_empty() {
+            return Err(KvError::CryptoError("Key file has no keys".into()));
+        }
+        Ok(Self { keys })
+    }
+
+    // 当前用来加密 value 的密钥 id
+    pub fn active_id(&self) -> u32 {
+        // keys 不会为空，在 parse 里保证了
+        *self.keys.keys().next_back().unwrap()
+    }
+
+    // 加密 value。密文格式为: key id (4 bytes) | nonce (12 bytes) | ciphertext
+    // aad 把密文和它所在的位置（比如 table 和 key）绑定在一起，防止密文被挪用
+    pub fn encrypt(&self, plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>, KvError> {
+        let id = self.active_id();
+        let nonce = Aes256GcmSiv::generate_nonce(&mut OsRng);
+        let ciphertext = self.keys[&id]
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: plaintext,
+                    aad,
+                },
+            )
+            .map_err(|_| KvError::CryptoError("Failed to encrypt value".into()))?;
+
+        let mut buf = Vec::with_capacity(KEY_ID_LEN + NONCE_LEN + ciphertext.len());
+        buf.extend_from_slice(&id.to_be_bytes());
+        buf.extend_from_slice(&nonce);
+        buf.extend_from_slice(&ciphertext);
+        Ok(buf)
+    }
+
+    // 解密 encrypt 生成的密文，根据密文里的 key id 选择密钥
+    pub fn decrypt(&self, data: &[u8], aad: &[u8]) -> Result<Vec<u8>, KvError> {
+        if data.len() < KEY_ID_LEN + NONCE_LEN {
+            return Err(KvError::CryptoError("Ciphertext is too short".into()));
+        }
+
+        let (id, rest) = data.split_at(KEY_ID_LEN);
+        let id = u32::from_be_bytes(id.try_into().unwrap());
+        let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+        let cipher = self
+            .keys
+            .get(&id)
+            .ok_or_else(|| KvError::CryptoError(format!("Key {} not found in keyring", id)))?;
+
+        cipher
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad,
+                },
+            )
+            .map_err(|_| KvError::CryptoError("Failed to decrypt value".into()))
+    }
+
+    // 确定性加密，用于加密 key。AES-GCM-SIV 在 nonce 固定时只会泄露“两个明文是否相等”
+    pub fn encrypt_deterministic(&self, plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>, KvError> {
+        let (_, cipher) = self.keys.iter().next().unwrap();
+        cipher
+            .encrypt(
+                &Nonce::default(),
+                Payload {
+                    msg: plaintext,
+                    aad,
+                },
+            )
+            .map_err(|_| KvError::CryptoError("Failed to encrypt key".into()))
+    }
+
+    pub fn decrypt_deterministic(&self, data: &[u8], aad: &[u8]) -> Result<Vec<u8>, KvError> {
+        let (_, cipher) = self.keys.iter().next().unwrap();
+        cipher
+            .decrypt(&Nonce::default(), Payload { msg: data, aad })
+            .map_err(|_| KvError::CryptoError("Failed to decrypt key".into()))
+    }
+
+    fn new_cipher(id: u32, key: &[u8]) -> Result<Aes256GcmSiv, KvError> {
+        if key.len() != KEY_LEN {
+            return Err(KvError::CryptoError(format!(
+                "Key {} must be {} bytes",
+                id, KEY_LEN
+            )));
+        }
+        Ok(Aes256GcmSiv::new_from_slice(key).unwrap())
+    }
+}
+
+// 不要把密钥打印到日志里
+impl fmt::Debug for Keyring {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Keyring")
+            .field("ids", &self.keys.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY1: &str = "1:0000000000000000000000000000000000000000000000000000000000000001";
+    const KEY2: &str = "2:0000000000000000000000000000000000000000000000000000000000000002";
+
+    #[test]
+    fn keyring_parse_should_work() {
+        let keyring = Keyring::parse(&format!("# keys\n{}\n\n{}\n", KEY2, KEY1)).unwrap();
+        assert_eq!(keyring.active_id(), 2);
+
+        assert!(Keyring::parse("").is_err());
+        assert!(Keyring::parse("1:abcd").is_err());
+        assert!(Keyring::parse("abcd").is_err());
+    }
+
+    #[test]
+    fn encrypt_decrypt_should_work() {
+        let keyring = Keyring::parse(KEY1).unwrap();
+        let data = keyring.encrypt(b"hello", b"t1:k1").unwrap();
+        assert_eq!(keyring.decrypt(&data, b"t1:k1").unwrap(), b"hello");
+
+        // aad 不匹配时无法解密
+        assert!(keyring.decrypt(&data, b"t1:k2").is_err());
+    }
+
+    #[test]
+    fn rotated_keyring_should_decrypt_old_data() {
+        let old = Keyring::parse(KEY1).unwrap();
+        let data = old.encrypt(b"hello", b"").unwrap();
+
+        let new = Keyring::parse(&format!("{}\n{}", KEY1, KEY2)).unwrap();
+        assert_eq!(new.decrypt(&data, b"").unwrap(), b"hello");
+        let data = new.encrypt(b"hello", b"").unwrap();
+        assert_eq!(&data[..KEY_ID_LEN], &2u32.to_be_bytes());
+
+        // 旧的 keyring 没有新密钥，解不开
+        assert!(old.decrypt(&data, b"").is_err());
+    }
+
+    #[test]
+    fn deterministic_encryption_should_be_stable() {
+        let keyring = Keyring::parse(&format!("{}\n{}", KEY1, KEY2)).unwrap();
+        let a = keyring.encrypt_deterministic(b"key", b"t1").unwrap();
+        let b = keyring.encrypt_deterministic(b"key", b"t1").unwrap();
+        assert_eq!(a, b);
+        assert_ne!(a, keyring.encrypt_deterministic(b"key", b"t2").unwrap());
+        assert_eq!(keyring.decrypt_deterministic(&a, b"t1").unwrap(), b"key");
+    }
+}
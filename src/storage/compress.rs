@@ -0,0 +1,195 @@
+use std::{
+    convert::TryFrom,
+    io::{Read, Write},
+};
+
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+
+use crate::KvError;
+
+// 存储层和 frame 共用的压缩算法，数值会写进 value 的 envelope 里，所以不能改
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressionAlgorithm {
+    Zstd = 1,
+    Lz4 = 2,
+    Gzip = 3,
+}
+
+// 某个 table 的 value 压缩配置：value 编码后的大小达到 threshold 才压缩
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ValueCompression {
+    pub algorithm: CompressionAlgorithm,
+    pub threshold: usize,
+}
+
+impl ValueCompression {
+    pub fn zstd(threshold: usize) -> Self {
+        Self {
+            algorithm: CompressionAlgorithm::Zstd,
+            threshold,
+        }
+    }
+
+    pub fn lz4(threshold: usize) -> Self {
+        Self {
+            algorithm: CompressionAlgorithm::Lz4,
+            threshold,
+        }
+    }
+
+    // 压缩数据。如果数据太小，或者压缩后没有变小，返回 None
+    pub fn compress(&self, data: &[u8]) -> Result<Option<Vec<u8>>, KvError> {
+        if data.len() < self.threshold {
+            return Ok(None);
+        }
+
+        let compressed = self.algorithm.compress(data)?;
+        if compressed.len() < data.len() {
+            Ok(Some(compressed))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+impl CompressionAlgorithm {
+    pub const ALL: [Self; 3] = [Self::Zstd, Self::Lz4, Self::Gzip];
+
+    // Hello 协商时使用的名字
+    pub fn name(&self) -> &'static str {
+        match self {
+            CompressionAlgorithm::Zstd => "zstd",
+            CompressionAlgorithm::Lz4 => "lz4",
+            CompressionAlgorithm::Gzip => "gzip",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|algo| algo.name() == name)
+    }
+
+    pub fn compress(&self, data: &[u8]) -> Result<Vec<u8>, KvError> {
+        match self {
+            CompressionAlgorithm::Zstd => Ok(zstd::encode_all(data, 0)?),
+            CompressionAlgorithm::Lz4 => Ok(lz4_flex::compress_prepend_size(data)),
+            CompressionAlgorithm::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(data)?;
+                Ok(encoder.finish()?)
+            }
+        }
+    }
+
+    pub fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, KvError> {
+        self.decompress_bounded(data, usize::MAX)
+    }
+
+    // 解压后超过 max 字节就停止并返回错误，防止很小的数据解压出巨大的内容（压缩炸弹）
+    pub fn decompress_bounded(&self, data: &[u8], max: usize) -> Result<Vec<u8>, KvError> {
+        let limit = (max as u64).saturating_add(1);
+        let mut buf = Vec::with_capacity(data.len().saturating_mul(2).min(max));
+        match self {
+            CompressionAlgorithm::Zstd => {
+                zstd::stream::read::Decoder::new(data)?
+                    .take(limit)
+                    .read_to_end(&mut buf)?;
+            }
+            CompressionAlgorithm::Lz4 => {
+                // lz4 的数据以 4 字节（little endian）的原始长度开头，解压时会按这个长度分配内存
+                let size = data
+                    .get(..4)
+                    .map(|v| u32::from_le_bytes([v[0], v[1], v[2], v[3]]) as usize)
+                    .unwrap_or_default();
+                if size > max {
+                    return Err(KvError::FrameTooLarge(size, max));
+                }
+                buf = lz4_flex::decompress_size_prepended(data)
+                    .map_err(|e| KvError::Internal(format!("Failed to decompress lz4: {}", e)))?;
+            }
+            CompressionAlgorithm::Gzip => {
+                GzDecoder::new(data).take(limit).read_to_end(&mut buf)?;
+            }
+        }
+
+        if buf.len() > max {
+            return Err(KvError::FrameTooLarge(buf.len(), max));
+        }
+        Ok(buf)
+    }
+}
+
+impl TryFrom<u8> for CompressionAlgorithm {
+    type Error = KvError;
+
+    fn try_from(v: u8) -> Result<Self, Self::Error> {
+        match v {
+            1 => Ok(CompressionAlgorithm::Zstd),
+            2 => Ok(CompressionAlgorithm::Lz4),
+            3 => Ok(CompressionAlgorithm::Gzip),
+            _ => Err(KvError::Internal(format!(
+                "Unknown compression algorithm {}",
+                v
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compress_decompress_should_work() {
+        let data = vec![42u8; 4096];
+        for algorithm in CompressionAlgorithm::ALL {
+            let config = ValueCompression {
+                algorithm,
+                threshold: 1024,
+            };
+            let compressed = config.compress(&data).unwrap().unwrap();
+            assert!(compressed.len() < data.len());
+            assert_eq!(config.algorithm.decompress(&compressed).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn decompress_bounded_should_stop_at_limit() {
+        let data = vec![0u8; 1024 * 1024];
+        for algorithm in CompressionAlgorithm::ALL {
+            let compressed = algorithm.compress(&data).unwrap();
+            assert!(compressed.len() < 16 * 1024);
+            assert_eq!(
+                algorithm
+                    .decompress_bounded(&compressed, data.len())
+                    .unwrap(),
+                data
+            );
+            assert!(matches!(
+                algorithm.decompress_bounded(&compressed, 4096),
+                Err(KvError::FrameTooLarge(_, 4096))
+            ));
+        }
+
+        // lz4 开头的长度是伪造的，也不会按它分配内存
+        let mut forged = u32::MAX.to_le_bytes().to_vec();
+        forged.extend_from_slice(&[0; 16]);
+        assert!(CompressionAlgorithm::Lz4
+            .decompress_bounded(&forged, 4096)
+            .is_err());
+    }
+
+    #[test]
+    fn small_data_should_not_be_compressed() {
+        let config = ValueCompression::zstd(1024);
+        assert_eq!(config.compress(&[42u8; 100]).unwrap(), None);
+    }
+
+    #[test]
+    fn algorithm_should_round_trip_through_u8() {
+        for algo in CompressionAlgorithm::ALL {
+            assert_eq!(CompressionAlgorithm::try_from(algo as u8).unwrap(), algo);
+            assert_eq!(CompressionAlgorithm::from_name(algo.name()), Some(algo));
+        }
+        assert!(CompressionAlgorithm::try_from(0).is_err());
+    }
+}
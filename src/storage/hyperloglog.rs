@@ -0,0 +1,212 @@
+use bytes::{BufMut, Bytes, BytesMut};
+
+use super::{hash64, set::member_key};
+use crate::{value, IndexedStorage, KvError, Storage, Value};
+
+const MAGIC: &[u8] = b"HYLL";
+// 用哈希值的高 14 位选择寄存器，标准误差约为 1.04 / sqrt(16384) = 0.81%
+const PRECISION: u32 = 14;
+const REGISTERS: usize = 1 << PRECISION;
+
+// HyperLogLog 基数估计。以 Value::Binary 存放："HYLL" | 16384 个寄存器，每个寄存器一个字节
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HyperLogLog {
+    registers: Vec<u8>,
+}
+
+impl Default for HyperLogLog {
+    fn default() -> Self {
+        Self {
+            registers: vec![0; REGISTERS],
+        }
+    }
+}
+
+impl HyperLogLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // 加入一个元素，返回是否有寄存器发生了变化
+    pub fn add(&mut self, item: &[u8]) -> bool {
+        let h = hash64(item, 0);
+        let index = (h >> (64 - PRECISION)) as usize;
+        // 剩下的位里第一个 1 的位置，最低位补一个 1 保证结果不超过 64 - PRECISION + 1
+        let rest = (h << PRECISION) | (1 << (PRECISION - 1));
+        let rank = rest.leading_zeros() as u8 + 1;
+        if rank > self.registers[index] {
+            self.registers[index] = rank;
+            true
+        } else {
+            false
+        }
+    }
+
+    // 合并另一个 HyperLogLog，结果估计的是两者的并集
+    pub fn merge(&mut self, other: &HyperLogLog) {
+        for (r, o) in self.registers.iter_mut().zip(&other.registers) {
+            *r = (*r).max(*o);
+        }
+    }
+
+    // 估计加入过的不同元素的个数
+    pub fn count(&self) -> u64 {
+        let m = REGISTERS as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum: f64 = self.registers.iter().map(|r| 2f64.powi(-(*r as i32))).sum();
+        let estimate = alpha * m * m / sum;
+
+        // 基数较小时用线性计数修正
+        let zeros = self.registers.iter().filter(|r| **r == 0).count();
+        if estimate <= 2.5 * m && zeros > 0 {
+            (m * (m / zeros as f64).ln()).round() as u64
+        } else {
+            estimate.round() as u64
+        }
+    }
+}
+
+impl From<&HyperLogLog> for Value {
+    fn from(hll: &HyperLogLog) -> Self {
+        let mut buf = BytesMut::with_capacity(MAGIC.len() + REGISTERS);
+        buf.put_slice(MAGIC);
+        buf.put_slice(&hll.registers);
+        buf.freeze().into()
+    }
+}
+
+impl TryFrom<&Value> for HyperLogLog {
+    type Error = KvError;
+
+    fn try_from(v: &Value) -> Result<Self, Self::Error> {
+        match &v.value {
+            Some(value::Value::Binary(buf))
+                if buf.len() == MAGIC.len() + REGISTERS && buf.starts_with(MAGIC) =>
+            {
+                Ok(Self {
+                    registers: buf[MAGIC.len()..].to_vec(),
+                })
+            }
+            _ => Err(KvError::ConvertError(v.clone(), "HyperLogLog")),
+        }
+    }
+}
+
+// 在 Storage 的基础上提供 HyperLogLog，所有实现了 Storage 的结构都自动获得这些方法
+// sketch 直接存放在 table 里，key 已经存在但不是 HyperLogLog 时返回错误
+pub trait HyperLogLogStorage: Storage {
+    // 往 key 的 HyperLogLog 里加入一组元素，返回估计值是否可能发生了变化
+    fn pf_add(&self, table: &str, key: &[u8], items: &[Value]) -> Result<bool, KvError> {
+        let mut changed = false;
+        self.update_indexed(table, key, &mut |v| {
+            // key 不存在时即使没有元素也会创建
+            changed = v.is_none();
+            let mut hll = match v {
+                Some(v) => HyperLogLog::try_from(&v)?,
+                None => HyperLogLog::new(),
+            };
+            for item in items {
+                changed |= hll.add(&member_key(item));
+            }
+            Ok(Some((&hll).into()))
+        })?;
+        Ok(changed)
+    }
+
+    // 估计一组 key 的并集的基数，不存在的 key 当作空集
+    fn pf_count(&self, table: &str, keys: &[Bytes]) -> Result<u64, KvError> {
+        Ok(merged(self, table, keys)?.count())
+    }
+
+    // 把一组 key 的 HyperLogLog 合并到 dest 里，dest 原有的数据会保留
+    fn pf_merge(&self, table: &str, dest: &[u8], sources: &[Bytes]) -> Result<(), KvError> {
+        let merged = merged(self, table, sources)?;
+        self.update_indexed(table, dest, &mut |v| {
+            let mut hll = match v {
+                Some(v) => HyperLogLog::try_from(&v)?,
+                None => HyperLogLog::new(),
+            };
+            hll.merge(&merged);
+            Ok(Some((&hll).into()))
+        })?;
+        Ok(())
+    }
+}
+
+impl<T: Storage + ?Sized> HyperLogLogStorage for T {}
+
+fn merged<S: Storage + ?Sized>(
+    store: &S,
+    table: &str,
+    keys: &[Bytes],
+) -> Result<HyperLogLog, KvError> {
+    let mut result = HyperLogLog::new();
+    for key in keys {
+        if let Some(v) = store.get(table, key)? {
+            result.merge(&HyperLogLog::try_from(&v)?);
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MemTable, SledDb};
+    use tempfile::tempdir;
+
+    #[test]
+    fn memtable_hyperloglog_should_work() {
+        test_hyperloglog(MemTable::new());
+    }
+
+    #[test]
+    fn sleddb_hyperloglog_should_work() {
+        let dir = tempdir().unwrap();
+        test_hyperloglog(SledDb::new(dir));
+    }
+
+    #[test]
+    fn hyperloglog_estimate_should_be_accurate() {
+        let mut hll = HyperLogLog::new();
+        for i in 0..100_000 {
+            hll.add(format!("user-{}", i).as_bytes());
+        }
+        assert_near(hll.count(), 100_000);
+
+        let mut small = HyperLogLog::new();
+        for i in 0..100 {
+            small.add(&[i]);
+        }
+        assert_near(small.count(), 100);
+    }
+
+    // 估计值的误差不超过 3%
+    fn assert_near(count: u64, expected: u64) {
+        let error = (count as f64 - expected as f64).abs() / expected as f64;
+        assert!(error < 0.03, "estimated {}, expected {}", count, expected);
+    }
+
+    fn test_hyperloglog(store: impl Storage) {
+        let items = |r: std::ops::Range<i64>| r.map(Value::from).collect::<Vec<_>>();
+        assert!(store.pf_add("t1", b"h1", &items(0..100)).unwrap());
+        // 重复的元素不会改变估计值
+        assert!(!store.pf_add("t1", b"h1", &items(0..100)).unwrap());
+        assert!(store.pf_add("t1", b"h2", &items(50..150)).unwrap());
+
+        assert_near(store.pf_count("t1", &["h1".into()]).unwrap(), 100);
+        let keys = ["h1".into(), "h2".into(), "none".into()];
+        let union = store.pf_count("t1", &keys).unwrap();
+        assert_near(union, 150);
+
+        store
+            .pf_merge("t1", b"all", &["h1".into(), "h2".into()])
+            .unwrap();
+        assert_eq!(store.pf_count("t1", &["all".into()]).unwrap(), union);
+
+        // 不是 HyperLogLog 的 value 不能当作 sketch 使用
+        store.set("t1", "s".into(), "hello".into()).unwrap();
+        assert!(store.pf_add("t1", b"s", &items(0..1)).is_err());
+        assert!(store.pf_count("t1", &["s".into()]).is_err());
+    }
+}
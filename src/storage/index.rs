@@ -0,0 +1,533 @@
+use bytes::Bytes;
+use std::ops::Bound;
+
+use super::{
+    get_message, lock_row, update_message,
+    version::{write_versioned, META_TABLE},
+};
+use crate::{value, IndexDef, KvError, Kvpair, Storage, TableIndexes, Value};
+
+// 索引存放在 __index__.{table}.{name} 里，key 是 "按顺序编码的被索引的值 | 主键"，value 是主键
+// 被索引的值的编码是自定界的，并且保持值的顺序，所以等值查询和范围查询都是有序后端里的一段区间
+fn index_table(table: &str, name: &str) -> String {
+    format!("__index__.{}.{}", table, name)
+}
+
+// table 上所有索引的定义存放在 __meta__ 里
+fn indexes_key(table: &str) -> Vec<u8> {
+    format!("indexes.{}", table).into_bytes()
+}
+
+fn entry_key(encoded: &[u8], key: &[u8]) -> Bytes {
+    [encoded, key].concat().into()
+}
+
+// 把标量编码成保持顺序的字节：类型标签 | 值。不同类型之间按标签排序
+// 整数翻转符号位，浮点数按 IEEE 754 的全序规则变换，字符串和二进制把 0x00 转义成 0x00 0xff，
+// 并以 0x00 0x01 结尾，这样任何编码都不会是另一个编码的前缀
+fn encode_ordered(v: &Value) -> Option<Vec<u8>> {
+    let escaped = |tag: u8, data: &[u8]| {
+        let mut buf = vec![tag];
+        for b in data {
+            buf.push(*b);
+            if *b == 0 {
+                buf.push(0xff);
+            }
+        }
+        buf.extend_from_slice(&[0x00, 0x01]);
+        buf
+    };
+
+    match &v.value {
+        Some(value::Value::Bool(b)) => Some(vec![0x01, *b as u8]),
+        Some(value::Value::Integer(i)) => {
+            let mut buf = vec![0x02];
+            buf.extend_from_slice(&((*i as u64) ^ (1 << 63)).to_be_bytes());
+            Some(buf)
+        }
+        Some(value::Value::Float(f)) => {
+            let mut buf = vec![0x03];
+            buf.extend_from_slice(&ordered_f64(*f));
+            Some(buf)
+        }
+        Some(value::Value::String(s)) => Some(escaped(0x04, s.as_bytes())),
+        Some(value::Value::Binary(b)) => Some(escaped(0x05, b)),
+        // 空值、列表和 map 不会被索引
+        _ => None,
+    }
+}
+
+// 按 IEEE 754 的全序规则把浮点数变换成按字节比较时保持顺序的 8 个字节：
+// 负数所有的位取反，正数翻转符号位
+pub(super) fn ordered_f64(f: f64) -> [u8; 8] {
+    let bits = f.to_bits();
+    let bits = if bits >> 63 == 1 {
+        !bits
+    } else {
+        bits ^ (1 << 63)
+    };
+    bits.to_be_bytes()
+}
+
+// 比所有以 prefix 开头的 key 都大的最小的 key，prefix 全是 0xff 时返回 None
+pub(super) fn prefix_end(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < 0xff {
+            end.push(last + 1);
+            return Some(end);
+        }
+    }
+    None
+}
+
+// 获取 value 里被 def 索引的值的编码
+fn indexed(def: &IndexDef, value: &Value) -> Option<Vec<u8>> {
+    value.get_path(&def.path).and_then(encode_ordered)
+}
+
+// 在 Storage 的基础上提供二级索引，所有实现了 Storage 的结构都自动获得这些方法
+// 索引建在 value 本身（path 为空）或者嵌套 value 的某个字段上，只索引标量
+// update_indexed / set_indexed / del_indexed 在写入的同时维护索引。写入时先加新的索引项，再写主数据，最后删旧的索引项，
+// 查询时会用主数据校验每个索引项，所以并发写入时也不会返回过期的结果
+pub trait IndexedStorage: Storage {
+    // 创建索引并为已有的数据建立索引项，返回建立的索引项个数
+    fn create_index(&self, table: &str, name: &str, path: Vec<String>) -> Result<usize, KvError> {
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            return Err(KvError::InvalidCommand(format!(
+                "Invalid index name: {}",
+                name
+            )));
+        }
+
+        let def = IndexDef {
+            name: name.into(),
+            path,
+        };
+        update_message(
+            self,
+            META_TABLE,
+            &indexes_key(table),
+            |indexes: &mut TableIndexes| {
+                if indexes.indexes.iter().any(|d| d.name == def.name) {
+                    return Err(KvError::InvalidCommand(format!(
+                        "Index {} already exists",
+                        def.name
+                    )));
+                }
+                indexes.indexes.push(def.clone());
+                Ok(())
+            },
+        )??;
+
+        // 先保存定义再回填，之后的写入都会维护这个索引
+        // 遍历到的 value 可能已经被并发的写入修改，所以持有行锁重新读取当前的值再建立索引项
+        let mut count = 0;
+        for key in self.get_keys(table)? {
+            let _guard = lock_row(table, &key);
+            let Some(value) = self.get(table, &key)? else {
+                continue;
+            };
+            if let Some(encoded) = indexed(&def, &value) {
+                self.set(
+                    &index_table(table, name),
+                    entry_key(&encoded, &key),
+                    key.into(),
+                )?;
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    // 删除索引和它所有的索引项，返回索引是否存在
+    fn drop_index(&self, table: &str, name: &str) -> Result<bool, KvError> {
+        let existed = update_message(
+            self,
+            META_TABLE,
+            &indexes_key(table),
+            |indexes: &mut TableIndexes| {
+                let count = indexes.indexes.len();
+                indexes.indexes.retain(|d| d.name != name);
+                count != indexes.indexes.len()
+            },
+        )?;
+
+        let index = index_table(table, name);
+        for pair in self.get_iter(&index)? {
+            self.del(&index, &pair.key)?;
+        }
+        Ok(existed)
+    }
+
+    fn list_indexes(&self, table: &str) -> Result<Vec<IndexDef>, KvError> {
+        let indexes: TableIndexes = get_message(self, META_TABLE, &indexes_key(table))?;
+        Ok(indexes.indexes)
+    }
+
+    // 和 update_versioned 一样，但会同时维护 table 上的所有索引。所有写主数据的命令都应该经过这里
+    // 持有行锁读出旧值并算出新值，先加新的索引项，再写主数据（同时记录版本），最后删旧的索引项
+    fn update_indexed(
+        &self,
+        table: &str,
+        key: &[u8],
+        f: &mut dyn FnMut(Option<Value>) -> Result<Option<Value>, KvError>,
+    ) -> Result<Option<Value>, KvError> {
+        let _guard = lock_row(table, key);
+        let defs = self.list_indexes(table)?;
+        let value = f(self.get(table, key)?)?;
+        let new: Vec<_> = defs
+            .iter()
+            .map(|def| value.as_ref().and_then(|v| indexed(def, v)))
+            .collect();
+        for (def, encoded) in defs.iter().zip(&new) {
+            if let Some(encoded) = encoded {
+                self.set(
+                    &index_table(table, &def.name),
+                    entry_key(encoded, key),
+                    Bytes::copy_from_slice(key).into(),
+                )?;
+            }
+        }
+
+        let (old, value) = write_versioned(self, table, key, &mut |_| Ok(value.clone()))?;
+        if let Some(old) = &old {
+            remove_stale(self, table, key, &defs, old, &new)?;
+        }
+        Ok(value)
+    }
+
+    // 和 set_versioned 一样，但会同时维护 table 上的所有索引
+    fn set_indexed(&self, table: &str, key: Bytes, value: Value) -> Result<Option<Value>, KvError> {
+        let mut old = None;
+        self.update_indexed(table, &key, &mut |v| {
+            old = v;
+            Ok(Some(value.clone()))
+        })?;
+        Ok(old)
+    }
+
+    // 和 del_versioned 一样，但会同时删除这个 key 的所有索引项
+    fn del_indexed(&self, table: &str, key: &[u8]) -> Result<Option<Value>, KvError> {
+        let mut old = None;
+        self.update_indexed(table, key, &mut |v| {
+            old = v;
+            Ok(None)
+        })?;
+        Ok(old)
+    }
+
+    // 获取被索引的值等于 value 的所有 kv pair，按主键排序
+    fn index_lookup(&self, table: &str, name: &str, value: &Value) -> Result<Vec<Kvpair>, KvError> {
+        let def = find_index(self, table, name)?;
+        let start = encode_ordered(value).ok_or_else(|| not_indexable(value))?;
+        let end = prefix_end(&start);
+        scan(
+            self,
+            table,
+            &def,
+            Bound::Included(start),
+            end.map_or(Bound::Unbounded, Bound::Excluded),
+            0,
+        )
+    }
+
+    // 获取被索引的值在 [start, end] 之间的 kv pair，按被索引的值排序，None 表示不限制，limit 为 0 表示不限制个数
+    fn index_range(
+        &self,
+        table: &str,
+        name: &str,
+        start: Option<&Value>,
+        end: Option<&Value>,
+        limit: usize,
+    ) -> Result<Vec<Kvpair>, KvError> {
+        let def = find_index(self, table, name)?;
+        let start = match start {
+            Some(v) => Bound::Included(encode_ordered(v).ok_or_else(|| not_indexable(v))?),
+            None => Bound::Unbounded,
+        };
+        let end = match end {
+            Some(v) => {
+                let encoded = encode_ordered(v).ok_or_else(|| not_indexable(v))?;
+                prefix_end(&encoded).map_or(Bound::Unbounded, Bound::Excluded)
+            }
+            None => Bound::Unbounded,
+        };
+        scan(self, table, &def, start, end, limit)
+    }
+}
+
+impl<T: Storage + ?Sized> IndexedStorage for T {}
+
+fn find_index<S: Storage + ?Sized>(
+    store: &S,
+    table: &str,
+    name: &str,
+) -> Result<IndexDef, KvError> {
+    store
+        .list_indexes(table)?
+        .into_iter()
+        .find(|d| d.name == name)
+        .ok_or_else(|| KvError::InvalidCommand(format!("Index {} does not exist", name)))
+}
+
+fn not_indexable(v: &Value) -> KvError {
+    KvError::ConvertError(v.clone(), "indexable scalar")
+}
+
+// 删除 old 对应的、和新的索引项不同的索引项
+fn remove_stale<S: Storage + ?Sized>(
+    store: &S,
+    table: &str,
+    key: &[u8],
+    defs: &[IndexDef],
+    old: &Value,
+    new: &[Option<Vec<u8>>],
+) -> Result<(), KvError> {
+    for (def, new) in defs.iter().zip(new) {
+        if let Some(old) = indexed(def, old) {
+            if Some(&old) != new.as_ref() {
+                store.del(&index_table(table, &def.name), &entry_key(&old, key))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// 按顺序读取一段索引项，用主数据校验之后返回对应的 kv pair
+fn scan<S: Storage + ?Sized>(
+    store: &S,
+    table: &str,
+    def: &IndexDef,
+    start: Bound<Vec<u8>>,
+    end: Bound<Vec<u8>>,
+    limit: usize,
+) -> Result<Vec<Kvpair>, KvError> {
+    let limit = if limit == 0 { usize::MAX } else { limit };
+    let entries = store.get_range(
+        &index_table(table, &def.name),
+        start.as_ref().map(|k| k.as_slice()),
+        end.as_ref().map(|k| k.as_slice()),
+    )?;
+
+    let mut result = vec![];
+    for entry in entries {
+        if result.len() >= limit {
+            break;
+        }
+        let key = match entry.value.and_then(|v| v.value) {
+            Some(value::Value::Binary(key)) => key,
+            _ => continue,
+        };
+        let encoded = &entry.key[..entry.key.len().saturating_sub(key.len())];
+        if let Some(value) = store.get(table, &key)? {
+            if indexed(def, &value).as_deref() == Some(encoded) {
+                result.push(Kvpair::new(key, value));
+            }
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        JsonPath, JsonStorage, MemTable, PathStorage, SledDb, VersionPolicy, VersionedStorage,
+    };
+    use std::{collections::BTreeMap, sync::Arc, thread};
+    use tempfile::tempdir;
+
+    #[test]
+    fn memtable_secondary_index_should_work() {
+        test_secondary_index(MemTable::new());
+    }
+
+    #[test]
+    fn sleddb_secondary_index_should_work() {
+        let dir = tempdir().unwrap();
+        test_secondary_index(SledDb::new(dir));
+    }
+
+    #[test]
+    fn memtable_path_writes_should_maintain_indexes() {
+        test_path_writes(MemTable::new());
+    }
+
+    #[test]
+    fn sleddb_path_writes_should_maintain_indexes() {
+        let dir = tempdir().unwrap();
+        test_path_writes(SledDb::new(dir));
+    }
+
+    #[test]
+    fn create_index_should_not_leave_stale_entries_under_concurrent_writes() {
+        let store = Arc::new(MemTable::new());
+        for i in 0..100 {
+            store.set("t1", format!("k{}", i).into(), 0.into()).unwrap();
+        }
+
+        let writer = {
+            let store = store.clone();
+            thread::spawn(move || {
+                for round in 1..=5i64 {
+                    for i in 0..100 {
+                        store
+                            .set_indexed("t1", format!("k{}", i).into(), round.into())
+                            .unwrap();
+                    }
+                }
+            })
+        };
+        store.create_index("t1", "by_value", vec![]).unwrap();
+        writer.join().unwrap();
+
+        // 每个 key 只有当前的值对应的一个索引项
+        let entries = store.get_iter(&index_table("t1", "by_value")).unwrap();
+        assert_eq!(entries.count(), 100);
+        assert_eq!(
+            store
+                .index_lookup("t1", "by_value", &5.into())
+                .unwrap()
+                .len(),
+            100
+        );
+    }
+
+    #[test]
+    fn encode_ordered_should_preserve_order() {
+        let values: Vec<Value> = vec![
+            false.into(),
+            true.into(),
+            i64::MIN.into(),
+            (-1).into(),
+            0.into(),
+            i64::MAX.into(),
+            f64::NEG_INFINITY.into(),
+            (-1.5).into(),
+            0.0.into(),
+            2.5.into(),
+            "".into(),
+            "a".into(),
+            "a\0".into(),
+            "ab".into(),
+            "b".into(),
+        ];
+        let encoded: Vec<_> = values.iter().map(|v| encode_ordered(v).unwrap()).collect();
+        assert!(encoded.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(encode_ordered(&Value::default()), None);
+        assert_eq!(prefix_end(&[1, 0xff]), Some(vec![2]));
+        assert_eq!(prefix_end(&[0xff]), None);
+    }
+
+    fn user(name: &str, age: i64) -> Value {
+        BTreeMap::from([
+            ("name".to_string(), Value::from(name)),
+            ("age".to_string(), Value::from(age)),
+        ])
+        .into()
+    }
+
+    fn keys(pairs: Vec<Kvpair>) -> Vec<Bytes> {
+        pairs.into_iter().map(|p| p.key).collect()
+    }
+
+    fn test_secondary_index(store: impl Storage) {
+        store.set("users", "u1".into(), user("alice", 30)).unwrap();
+        store.set("users", "u2".into(), user("bob", 25)).unwrap();
+        // 已有的数据会被回填
+        let n = store
+            .create_index("users", "age", vec!["age".into()])
+            .unwrap();
+        assert_eq!(n, 2);
+        assert!(store.create_index("users", "age", vec![]).is_err());
+        assert!(store.create_index("users", "a.b", vec![]).is_err());
+
+        store
+            .set_indexed("users", "u3".into(), user("carol", 30))
+            .unwrap();
+        let res = store.index_lookup("users", "age", &30.into()).unwrap();
+        assert_eq!(keys(res), vec![Bytes::from("u1"), Bytes::from("u3")]);
+
+        // 修改和删除之后旧的索引项不再返回
+        store
+            .set_indexed("users", "u1".into(), user("alice", 31))
+            .unwrap();
+        store.del_indexed("users", b"u3").unwrap();
+        let res = store.index_lookup("users", "age", &30.into()).unwrap();
+        assert!(res.is_empty());
+
+        let res = store
+            .index_range("users", "age", Some(&25.into()), Some(&31.into()), 0)
+            .unwrap();
+        assert_eq!(keys(res), vec![Bytes::from("u2"), Bytes::from("u1")]);
+        let res = store
+            .index_range("users", "age", Some(&26.into()), None, 0)
+            .unwrap();
+        assert_eq!(keys(res), vec![Bytes::from("u1")]);
+        let res = store.index_range("users", "age", None, None, 1).unwrap();
+        assert_eq!(keys(res), vec![Bytes::from("u2")]);
+
+        // 绕过索引直接写入的数据会在查询时被校验掉
+        store.set("users", "u2".into(), user("bob", 40)).unwrap();
+        let res = store.index_lookup("users", "age", &25.into()).unwrap();
+        assert!(res.is_empty());
+
+        assert!(store.index_lookup("users", "none", &1.into()).is_err());
+        assert!(store.drop_index("users", "age").unwrap());
+        assert!(!store.drop_index("users", "age").unwrap());
+        assert!(store.list_indexes("users").unwrap().is_empty());
+        assert!(store.index_lookup("users", "age", &31.into()).is_err());
+    }
+
+    fn test_path_writes(store: impl Storage) {
+        store
+            .set_versioning(
+                "users",
+                Some(VersionPolicy {
+                    max_versions: 0,
+                    max_age_secs: 0,
+                }),
+            )
+            .unwrap();
+        store
+            .create_index("users", "age", vec!["age".into()])
+            .unwrap();
+        store
+            .set_indexed("users", "u1".into(), user("alice", 30))
+            .unwrap();
+
+        // HsetPath / JsonSet 等命令的写入也会维护索引和历史版本
+        store
+            .set_at("users", b"u1", &["age".into()], 31.into())
+            .unwrap();
+        assert!(store
+            .index_lookup("users", "age", &30.into())
+            .unwrap()
+            .is_empty());
+        let res = store.index_lookup("users", "age", &31.into()).unwrap();
+        assert_eq!(keys(res), vec![Bytes::from("u1")]);
+        assert_eq!(store.history("users", b"u1").unwrap().len(), 2);
+        assert_eq!(store.get_iter("__index__.users.age").unwrap().count(), 1);
+
+        let path = JsonPath::parse("$.age").unwrap();
+        store.json_incr_by("users", b"u1", &path, 1.0).unwrap();
+        let res = store.index_lookup("users", "age", &32.into()).unwrap();
+        assert_eq!(keys(res), vec![Bytes::from("u1")]);
+
+        store.del_at("users", b"u1", &["age".into()]).unwrap();
+        assert!(store
+            .get_iter("__index__.users.age")
+            .unwrap()
+            .next()
+            .is_none());
+        store
+            .json_del("users", b"u1", &JsonPath::parse("$").unwrap())
+            .unwrap();
+        assert_eq!(store.history("users", b"u1").unwrap().len(), 5);
+    }
+}
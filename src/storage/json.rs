@@ -0,0 +1,234 @@
+use std::fmt::Display;
+
+use crate::{value, IndexedStorage, JsonPath, KvError, Storage, Value};
+
+// 2^63，i64 能表示的范围的上界
+const I64_BOUND: f64 = 9_223_372_036_854_775_808.0;
+
+// 在 Storage 的基础上提供 JSON 文档的操作，所有实现了 Storage 的结构都自动获得这些方法
+// 文档就是普通 table 里嵌套的 Value，修改通过 update_indexed 完成，所以是原子的，并且会维护索引和历史版本
+pub trait JsonStorage: Storage {
+    // 获取文档里所有匹配 path 的值，文档不存在时返回 None
+    fn json_get(
+        &self,
+        table: &str,
+        key: &[u8],
+        path: &JsonPath,
+    ) -> Result<Option<Vec<Value>>, KvError> {
+        Ok(self
+            .get(table, key)?
+            .map(|doc| path.select(&doc).into_iter().cloned().collect()))
+    }
+
+    // 把所有匹配 path 的值设置成 value，返回设置的个数。新的文档只能在根节点创建
+    fn json_set(
+        &self,
+        table: &str,
+        key: &[u8],
+        path: &JsonPath,
+        value: Value,
+    ) -> Result<usize, KvError> {
+        let mut count = 0;
+        self.update_indexed(table, key, &mut |doc| {
+            let mut doc = match doc {
+                Some(doc) => doc,
+                None if path.is_root() => Value::default(),
+                None => {
+                    return Err(KvError::InvalidCommand(
+                        "New document must be created at the root".into(),
+                    ))
+                }
+            };
+            count = path.set(&mut doc, value.clone())?;
+            Ok(Some(doc))
+        })?;
+        Ok(count)
+    }
+
+    // 删除所有匹配 path 的值，返回删除的个数。path 是根节点时删除整个文档
+    fn json_del(&self, table: &str, key: &[u8], path: &JsonPath) -> Result<usize, KvError> {
+        if path.is_root() {
+            return Ok(self.del_indexed(table, key)?.map_or(0, |_| 1));
+        }
+
+        let mut count = 0;
+        self.update_indexed(table, key, &mut |doc| match doc {
+            Some(mut doc) => {
+                count = path.delete(&mut doc)?;
+                Ok(Some(doc))
+            }
+            None => {
+                count = 0;
+                Ok(None)
+            }
+        })?;
+        Ok(count)
+    }
+
+    // 往所有匹配 path 的 array 里追加 values，返回每个匹配的 array 追加之后的长度，
+    // 匹配到的不是 array 时对应的结果为 null
+    fn json_arr_append(
+        &self,
+        table: &str,
+        key: &[u8],
+        path: &JsonPath,
+        values: Vec<Value>,
+    ) -> Result<Vec<Value>, KvError> {
+        modify_matches(self, table, key, path, &mut |v| match &mut v.value {
+            Some(value::Value::List(list)) => {
+                list.values.extend(values.iter().cloned());
+                Ok((list.values.len() as i64).into())
+            }
+            _ => Ok(Value::default()),
+        })
+    }
+
+    // 给所有匹配 path 的数字加上 increment，返回新的值
+    // 整数加上整数仍然是整数，否则结果是浮点数
+    fn json_incr_by(
+        &self,
+        table: &str,
+        key: &[u8],
+        path: &JsonPath,
+        increment: f64,
+    ) -> Result<Vec<Value>, KvError> {
+        // JSON 里没有 NaN 和无穷大
+        if !increment.is_finite() {
+            return Err(KvError::InvalidCommand(format!(
+                "Increment {} is not a finite number",
+                increment
+            )));
+        }
+        // i64 的范围是 [-2^63, 2^63)，超出这个范围的 increment 转换成整数时会被截断
+        let int_increment = (increment.fract() == 0.0
+            && (-I64_BOUND..I64_BOUND).contains(&increment))
+        .then_some(increment as i64);
+
+        let overflow =
+            |v: &dyn Display| KvError::InvalidCommand(format!("{} + {} overflows", v, increment));
+        let finite = |f: f64| Some(f).filter(|f| f.is_finite());
+        modify_matches(self, table, key, path, &mut |v| {
+            let result: Value = match v.value {
+                Some(value::Value::Integer(i)) if increment.fract() == 0.0 => int_increment
+                    .and_then(|n| i.checked_add(n))
+                    .ok_or_else(|| overflow(&i))?
+                    .into(),
+                Some(value::Value::Integer(i)) => finite(i as f64 + increment)
+                    .ok_or_else(|| overflow(&i))?
+                    .into(),
+                Some(value::Value::Float(f)) => {
+                    finite(f + increment).ok_or_else(|| overflow(&f))?.into()
+                }
+                _ => return Err(KvError::ConvertError(v.clone(), "Number")),
+            };
+            *v = result.clone();
+            Ok(result)
+        })
+    }
+}
+
+impl<T: Storage + ?Sized> JsonStorage for T {}
+
+// 原子地修改文档里所有匹配 path 的值，收集 f 的返回值
+fn modify_matches<S: Storage + ?Sized>(
+    store: &S,
+    table: &str,
+    key: &[u8],
+    path: &JsonPath,
+    f: &mut dyn FnMut(&mut Value) -> Result<Value, KvError>,
+) -> Result<Vec<Value>, KvError> {
+    let mut result = vec![];
+    let doc = store.update_indexed(table, key, &mut |doc| {
+        result.clear();
+        match doc {
+            Some(mut doc) => {
+                path.for_each_mut(&mut doc, &mut |v| {
+                    result.push(f(v)?);
+                    Ok(())
+                })?;
+                Ok(Some(doc))
+            }
+            None => Ok(None),
+        }
+    })?;
+
+    match doc {
+        Some(_) => Ok(result),
+        None => Err(KvError::not_found(table, key)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MemTable, SledDb};
+    use tempfile::tempdir;
+
+    #[test]
+    fn memtable_json_should_work() {
+        test_json(MemTable::new());
+    }
+
+    #[test]
+    fn sleddb_json_should_work() {
+        let dir = tempdir().unwrap();
+        test_json(SledDb::new(dir));
+    }
+
+    fn test_json(store: impl Storage) {
+        let path = |p: &str| JsonPath::parse(p).unwrap();
+        let doc = Value::from_json(r#"{"name":"svc","port":80,"hosts":["a"]}"#).unwrap();
+
+        // 新文档只能在根节点创建
+        assert!(store
+            .json_set("t1", b"cfg", &path("$.name"), "x".into())
+            .is_err());
+        assert_eq!(store.json_set("t1", b"cfg", &path("$"), doc).unwrap(), 1);
+
+        let res = store.json_set("t1", b"cfg", &path("$.debug"), true.into());
+        assert_eq!(res.unwrap(), 1);
+        let res = store.json_arr_append("t1", b"cfg", &path("$.hosts"), vec!["b".into()]);
+        assert_eq!(res.unwrap(), vec![2.into()]);
+        let res = store.json_incr_by("t1", b"cfg", &path("$.port"), 1.0);
+        assert_eq!(res.unwrap(), vec![81.into()]);
+        let res = store.json_incr_by("t1", b"cfg", &path("$.port"), 0.5);
+        assert_eq!(res.unwrap(), vec![81.5.into()]);
+        assert!(store
+            .json_incr_by("t1", b"cfg", &path("$.name"), 1.0)
+            .is_err());
+        // 整数溢出时返回错误，不会修改文档
+        store
+            .json_set("t1", b"cfg", &path("$.big"), i64::MAX.into())
+            .unwrap();
+        assert!(store
+            .json_incr_by("t1", b"cfg", &path("$.big"), 1.0)
+            .is_err());
+        assert!(store
+            .json_incr_by("t1", b"cfg", &path("$.big"), -1e19)
+            .is_err());
+        assert!(store
+            .json_incr_by("t1", b"cfg", &path("$.big"), f64::NAN)
+            .is_err());
+        let res = store.json_incr_by("t1", b"cfg", &path("$.big"), -1.0);
+        assert_eq!(res.unwrap(), vec![(i64::MAX - 1).into()]);
+        assert_eq!(store.json_del("t1", b"cfg", &path("$.big")).unwrap(), 1);
+
+        assert_eq!(
+            store.json_del("t1", b"cfg", &path("$.hosts[0]")).unwrap(),
+            1
+        );
+        let doc = store.get("t1", b"cfg").unwrap().unwrap();
+        assert_eq!(
+            doc.to_json().unwrap(),
+            r#"{"debug":true,"hosts":["b"],"name":"svc","port":81.5}"#
+        );
+        let res = store.json_get("t1", b"cfg", &path("$.hosts[*]")).unwrap();
+        assert_eq!(res, Some(vec!["b".into()]));
+
+        assert_eq!(store.json_del("t1", b"cfg", &path("$")).unwrap(), 1);
+        assert_eq!(store.json_get("t1", b"cfg", &path("$")).unwrap(), None);
+        assert!(store
+            .json_incr_by("t1", b"cfg", &path("$.port"), 1.0)
+            .is_err());
+    }
+}
@@ -0,0 +1,246 @@
+use bytes::Bytes;
+use std::ops::Range;
+
+use super::{get_message, lock_row, message_to_value};
+use crate::{KvError, ListMeta, Storage, Value};
+
+// 列表的元数据存放在另外一个 table 里，不会和普通的 key 冲突
+fn list_table(table: &str) -> String {
+    format!("__list__.{}", table)
+}
+
+// 列表里的每个 value 单独存放，这样 push / pop 只需要读写被修改的 value，不用每次都重写整个列表
+fn item_table(table: &str) -> String {
+    format!("__list_item__.{}", table)
+}
+
+// value 的 key 是列表的 key 加上 8 字节的下标，下标的长度固定，所以不同列表的 key 不会相同
+fn item_key(key: &[u8], index: i64) -> Bytes {
+    [key, &index.to_be_bytes()].concat().into()
+}
+
+fn list_len(meta: &ListMeta) -> usize {
+    (meta.tail - meta.head) as usize
+}
+
+// 列表为空时删除元数据，下次 push 时下标从 0 重新开始
+fn save_meta<S: Storage + ?Sized>(
+    store: &S,
+    table: &str,
+    key: &[u8],
+    meta: &ListMeta,
+) -> Result<(), KvError> {
+    if meta.head == meta.tail {
+        store.del(&list_table(table), key)?;
+    } else {
+        store.set(
+            &list_table(table),
+            Bytes::copy_from_slice(key),
+            message_to_value(meta),
+        )?;
+    }
+    Ok(())
+}
+
+// 读取下标为 index 的 value
+fn get_item<S: Storage + ?Sized>(
+    store: &S,
+    table: &str,
+    key: &[u8],
+    index: i64,
+) -> Result<Value, KvError> {
+    store
+        .get(&item_table(table), &item_key(key, index))?
+        .ok_or_else(|| KvError::Internal(format!("List item {} is missing", index)))
+}
+
+// 在 Storage 的基础上提供列表的能力，所有实现了 Storage 的结构都自动获得这些方法
+// 元数据和 value 分开存放，同一个列表的操作用 row lock 串行起来，所以是原子的。列表为空时会删除对应的 key。
+// push 先写 value 再更新元数据，pop 先更新元数据再删除 value，中途失败时最多留下一些读不到的 value
+pub trait ListStorage: Storage {
+    // 把 values 依次插入到列表头部，返回插入后列表的长度
+    fn push_front(&self, table: &str, key: &[u8], values: Vec<Value>) -> Result<usize, KvError> {
+        let _guard = lock_row(&list_table(table), key);
+        let mut meta: ListMeta = get_message(self, &list_table(table), key)?;
+        for v in values {
+            meta.head -= 1;
+            self.set(&item_table(table), item_key(key, meta.head), v)?;
+        }
+        save_meta(self, table, key, &meta)?;
+        Ok(list_len(&meta))
+    }
+
+    // 把 values 依次追加到列表尾部，返回插入后列表的长度
+    fn push_back(&self, table: &str, key: &[u8], values: Vec<Value>) -> Result<usize, KvError> {
+        let _guard = lock_row(&list_table(table), key);
+        let mut meta: ListMeta = get_message(self, &list_table(table), key)?;
+        for v in values {
+            self.set(&item_table(table), item_key(key, meta.tail), v)?;
+            meta.tail += 1;
+        }
+        save_meta(self, table, key, &meta)?;
+        Ok(list_len(&meta))
+    }
+
+    // 从列表头部弹出最多 count 个 value
+    fn pop_front(&self, table: &str, key: &[u8], count: usize) -> Result<Vec<Value>, KvError> {
+        let _guard = lock_row(&list_table(table), key);
+        let mut meta: ListMeta = get_message(self, &list_table(table), key)?;
+        let indexes = meta.head..meta.head + count.min(list_len(&meta)) as i64;
+        let values = indexes
+            .clone()
+            .map(|i| get_item(self, table, key, i))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        meta.head = indexes.end;
+        save_meta(self, table, key, &meta)?;
+        for i in indexes {
+            self.del(&item_table(table), &item_key(key, i))?;
+        }
+        Ok(values)
+    }
+
+    // 从列表尾部弹出最多 count 个 value，最后一个 value 在最前面
+    fn pop_back(&self, table: &str, key: &[u8], count: usize) -> Result<Vec<Value>, KvError> {
+        let _guard = lock_row(&list_table(table), key);
+        let mut meta: ListMeta = get_message(self, &list_table(table), key)?;
+        let indexes = meta.tail - count.min(list_len(&meta)) as i64..meta.tail;
+        let values = indexes
+            .clone()
+            .rev()
+            .map(|i| get_item(self, table, key, i))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        meta.tail = indexes.start;
+        save_meta(self, table, key, &meta)?;
+        for i in indexes {
+            self.del(&item_table(table), &item_key(key, i))?;
+        }
+        Ok(values)
+    }
+
+    // 获取列表 [start, stop] 之间的 value，负数表示从尾部开始数
+    fn list_range(
+        &self,
+        table: &str,
+        key: &[u8],
+        start: i64,
+        stop: i64,
+    ) -> Result<Vec<Value>, KvError> {
+        let _guard = lock_row(&list_table(table), key);
+        let meta: ListMeta = get_message(self, &list_table(table), key)?;
+        index_range(start, stop, list_len(&meta))
+            .map(|i| get_item(self, table, key, meta.head + i as i64))
+            .collect()
+    }
+
+    fn list_len(&self, table: &str, key: &[u8]) -> Result<usize, KvError> {
+        let meta: ListMeta = get_message(self, &list_table(table), key)?;
+        Ok(list_len(&meta))
+    }
+
+    // 只保留列表 [start, stop] 之间的 value
+    fn list_trim(&self, table: &str, key: &[u8], start: i64, stop: i64) -> Result<(), KvError> {
+        let _guard = lock_row(&list_table(table), key);
+        let mut meta: ListMeta = get_message(self, &list_table(table), key)?;
+        let range = index_range(start, stop, list_len(&meta));
+        let old = meta.clone();
+        meta.head = old.head + range.start as i64;
+        meta.tail = old.head + range.end as i64;
+        save_meta(self, table, key, &meta)?;
+        for i in (old.head..meta.head).chain(meta.tail..old.tail) {
+            self.del(&item_table(table), &item_key(key, i))?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: Storage + ?Sized> ListStorage for T {}
+
+// 把 Redis 风格的闭区间下标 [start, stop] 转换成 Range，负数表示从尾部开始数
+pub(super) fn index_range(start: i64, stop: i64, len: usize) -> Range<usize> {
+    let len = len as i64;
+    let start = if start < 0 {
+        (len + start).max(0)
+    } else {
+        start
+    };
+    let stop = if stop < 0 {
+        len + stop
+    } else {
+        stop.min(len - 1)
+    };
+    if start > stop {
+        0..0
+    } else {
+        start as usize..stop as usize + 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MemTable, SledDb};
+    use tempfile::tempdir;
+
+    #[test]
+    fn memtable_list_should_work() {
+        test_list(MemTable::new());
+    }
+
+    #[test]
+    fn sleddb_list_should_work() {
+        let dir = tempdir().unwrap();
+        test_list(SledDb::new(dir));
+    }
+
+    fn test_list(store: impl Storage) {
+        let values = |v: &[i64]| v.iter().map(|&i| Value::from(i)).collect::<Vec<_>>();
+
+        assert_eq!(store.push_back("t1", b"q", values(&[1, 2])).unwrap(), 2);
+        assert_eq!(store.push_front("t1", b"q", values(&[3, 4])).unwrap(), 4);
+        assert_eq!(
+            store.list_range("t1", b"q", 0, -1).unwrap(),
+            values(&[4, 3, 1, 2])
+        );
+        assert_eq!(store.list_len("t1", b"q").unwrap(), 4);
+        // 列表和普通的 key 互不影响
+        assert_eq!(store.get("t1", b"q").unwrap(), None);
+
+        assert_eq!(store.pop_front("t1", b"q", 1).unwrap(), values(&[4]));
+        assert_eq!(store.pop_back("t1", b"q", 2).unwrap(), values(&[2, 1]));
+        assert_eq!(store.list_range("t1", b"q", 0, -1).unwrap(), values(&[3]));
+
+        // 弹出最后一个 value 之后列表就不存在了
+        assert_eq!(store.pop_back("t1", b"q", 10).unwrap(), values(&[3]));
+        assert_eq!(store.pop_front("t1", b"q", 1).unwrap(), vec![]);
+        assert_eq!(store.list_len("t1", b"q").unwrap(), 0);
+        assert!(store.get_all("__list__.t1").unwrap().is_empty());
+        assert!(store.get_all("__list_item__.t1").unwrap().is_empty());
+
+        store
+            .push_back("t1", b"q", values(&[1, 2, 3, 4, 5]))
+            .unwrap();
+        store.list_trim("t1", b"q", 1, -2).unwrap();
+        assert_eq!(
+            store.list_range("t1", b"q", 0, -1).unwrap(),
+            values(&[2, 3, 4])
+        );
+        // 每个 value 单独存放，trim 掉的 value 也会被删除
+        assert_eq!(store.get_all("__list_item__.t1").unwrap().len(), 3);
+        store.list_trim("t1", b"q", 5, 10).unwrap();
+        assert_eq!(store.list_len("t1", b"q").unwrap(), 0);
+        assert!(store.get_all("__list_item__.t1").unwrap().is_empty());
+    }
+
+    #[test]
+    fn index_range_should_work() {
+        assert_eq!(index_range(0, -1, 5), 0..5);
+        assert_eq!(index_range(1, 2, 5), 1..3);
+        assert_eq!(index_range(-2, -1, 5), 3..5);
+        assert_eq!(index_range(-10, 100, 5), 0..5);
+        assert_eq!(index_range(3, 1, 5), 0..0);
+        assert_eq!(index_range(5, 10, 5), 0..0);
+        assert_eq!(index_range(0, -1, 0), 0..0);
+    }
+}
@@ -1,10 +1,29 @@
-use crate::{KvError, Kvpair, Storage, Value};
-use dashmap::{mapref::one::Ref, DashMap};
+use crate::{KvError, Kvpair, Snapshot, Storage, Value};
+use bytes::Bytes;
+use dashmap::{mapref::entry::Entry, mapref::one::Ref, DashMap};
+use std::{collections::BTreeSet, ops::Bound, sync::RwLock};
 
 // 使用 DashMap 构建的 MemTable 实现了 Storage trait
 #[derive(Clone, Debug, Default)]
 pub struct MemTable {
-    tables: DashMap<String, DashMap<String, Value>>,
+    tables: DashMap<String, Table>,
+}
+
+// DashMap 里的 key 是无序的，另外用一个 BTreeSet 按顺序记录所有的 key，用于按范围读取
+// 增删 key 时先锁住 key 所在的 shard 再修改 keys，所以 keys 和 data 里的 key 总是一致的
+#[derive(Debug, Default)]
+struct Table {
+    data: DashMap<Bytes, Value>,
+    keys: RwLock<BTreeSet<Bytes>>,
+}
+
+impl Clone for Table {
+    fn clone(&self) -> Self {
+        Self {
+            data: self.data.clone(),
+            keys: RwLock::new(self.keys.read().unwrap().clone()),
+        }
+    }
 }
 
 impl MemTable {
@@ -12,7 +31,7 @@ impl MemTable {
         Self::default()
     }
 
-    fn get_or_create_table(&self, name: &str) -> Ref<String, DashMap<String, Value>> {
+    fn get_or_create_table(&self, name: &str) -> Ref<'_, String, Table> {
         match self.tables.get(name) {
             Some(table) => table,
             None => {
@@ -24,39 +43,161 @@ impl MemTable {
 }
 
 impl Storage for MemTable {
-    fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+    fn get(&self, table: &str, key: &[u8]) -> Result<Option<Value>, KvError> {
         let table = self.get_or_create_table(table);
         // get 返回的是一个Option
-        Ok(table.get(key).map(|v| v.value().clone()))
+        Ok(table.data.get(key).map(|v| v.value().clone()))
+    }
+
+    fn set(&self, table: &str, key: Bytes, value: Value) -> Result<Option<Value>, KvError> {
+        let table = self.get_or_create_table(table);
+        let result = match table.data.entry(key) {
+            Entry::Occupied(mut entry) => Some(entry.insert(value)),
+            Entry::Vacant(entry) => {
+                table.keys.write().unwrap().insert(entry.key().clone());
+                entry.insert(value);
+                None
+            }
+        };
+        Ok(result)
     }
 
-    fn set(&self, table: &str, key: String, value: Value) -> Result<Option<Value>, KvError> {
+    fn contains(&self, table: &str, key: &[u8]) -> Result<bool, KvError> {
         let table = self.get_or_create_table(table);
-        Ok(table.insert(key, value))
+        Ok(table.data.contains_key(key))
     }
 
-    fn contains(&self, table: &str, key: &str) -> Result<bool, KvError> {
+    fn del(&self, table: &str, key: &[u8]) -> Result<Option<Value>, KvError> {
         let table = self.get_or_create_table(table);
-        Ok(table.contains_key(key))
+        let result = match table.data.entry(Bytes::copy_from_slice(key)) {
+            Entry::Occupied(entry) => {
+                table.keys.write().unwrap().remove(key);
+                Some(entry.remove())
+            }
+            Entry::Vacant(_) => None,
+        };
+        Ok(result)
     }
 
-    fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+    fn update(
+        &self,
+        table: &str,
+        key: &[u8],
+        f: &mut dyn FnMut(Option<Value>) -> Result<Option<Value>, KvError>,
+    ) -> Result<Option<Value>, KvError> {
         let table = self.get_or_create_table(table);
-        Ok(table.remove(key).map(|(_k, v)| v))
+        // entry 会锁住 key 所在的 shard，保证读和写之间不会有别人插进来
+        let result = match table.data.entry(Bytes::copy_from_slice(key)) {
+            Entry::Occupied(mut entry) => match f(Some(entry.get().clone()))? {
+                Some(v) => {
+                    entry.insert(v.clone());
+                    Ok(Some(v))
+                }
+                None => {
+                    table.keys.write().unwrap().remove(key);
+                    entry.remove();
+                    Ok(None)
+                }
+            },
+            Entry::Vacant(entry) => match f(None)? {
+                Some(v) => {
+                    table.keys.write().unwrap().insert(entry.key().clone());
+                    entry.insert(v.clone());
+                    Ok(Some(v))
+                }
+                None => Ok(None),
+            },
+        };
+        result
     }
 
     fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError> {
+        Ok(self.snapshot(table)?.into_iter().collect())
+    }
+
+    fn get_iter(&self, table: &str) -> Result<Box<dyn Iterator<Item = Kvpair>>, KvError> {
+        Ok(Box::new(self.snapshot(table)?.into_iter()))
+    }
+
+    // Bytes 的 clone 只是增加引用计数，value 完全不会被 clone
+    fn get_keys(&self, table: &str) -> Result<Box<dyn Iterator<Item = Bytes>>, KvError> {
         let table = self.get_or_create_table(table);
-        Ok(table
+        let keys: Vec<_> = table.data.iter().map(|entry| entry.key().clone()).collect();
+        Ok(Box::new(keys.into_iter()))
+    }
+
+    fn snapshot(&self, table: &str) -> Result<Snapshot, KvError> {
+        let table = self.get_or_create_table(table);
+        // 同时持有所有 shard 的读锁，这期间的写操作都会被挡住，
+        // 所以读到的是同一时刻的数据
+        let shards: Vec<_> = table
+            .data
+            .shards()
+            .iter()
+            .map(|shard| shard.read())
+            .collect();
+        Ok(shards
             .iter()
-            .map(|kv| Kvpair::new(kv.key(), kv.value().clone()))
+            .flat_map(|shard| shard.iter())
+            .map(|(k, v)| (k.clone(), v.get().clone()))
             .collect())
     }
 
-    fn get_iter(&self, table: &str) -> Result<Box<dyn Iterator<Item = Kvpair>>, KvError> {
-        let table = self.get_or_create_table(table).clone();
-        let kv_iter = table.into_iter().map(|kv| kv.into());
-        Ok(Box::new(kv_iter))
+    fn snapshot_keys(&self, table: &str, keys: &[Bytes]) -> Result<Snapshot, KvError> {
+        let table = self.get_or_create_table(table);
+        let shards: Vec<_> = table
+            .data
+            .shards()
+            .iter()
+            .map(|shard| shard.read())
+            .collect();
+        Ok(keys
+            .iter()
+            .filter_map(|key| {
+                let shard = &shards[table.data.determine_map(key.as_ref())];
+                shard
+                    .get(key.as_ref())
+                    .map(|v| (key.clone(), v.get().clone()))
+            })
+            .collect())
+    }
+
+    // 先从 keys 里按顺序取出范围内的 key，再逐个读取 value，只会读取和 clone 范围内的数据。
+    // 读取期间被删除的 key 会被跳过
+    fn get_range(
+        &self,
+        table: &str,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+    ) -> Result<Box<dyn Iterator<Item = Kvpair>>, KvError> {
+        // BTreeSet 的 range 在 start 大于 end 时会 panic
+        let valid = match (start, end) {
+            (Bound::Excluded(s), Bound::Excluded(e)) => s < e,
+            (Bound::Included(s) | Bound::Excluded(s), Bound::Included(e) | Bound::Excluded(e)) => {
+                s <= e
+            }
+            _ => true,
+        };
+        if !valid {
+            return Ok(Box::new(std::iter::empty()));
+        }
+
+        let table = self.get_or_create_table(table);
+        let keys: Vec<Bytes> = table
+            .keys
+            .read()
+            .unwrap()
+            .range::<[u8], _>((start, end))
+            .cloned()
+            .collect();
+        let pairs: Vec<_> = keys
+            .into_iter()
+            .filter_map(|key| {
+                let value = table.data.get(&key)?.value().clone();
+                Some(Kvpair::new(key, value))
+            })
+            .collect();
+        Ok(Box::new(pairs.into_iter()))
     }
 }
 
@@ -1,30 +1,230 @@
+mod bitmap;
+mod bloom;
+mod cipher;
+mod compress;
+mod hyperloglog;
+mod index;
+mod json;
+mod list;
 mod memory;
+mod path;
+mod query;
+mod set;
 mod sleddb;
+mod snapshot;
+mod stream;
+mod timeseries;
+mod version;
+mod zset;
 
+pub use bitmap::{BitOp, BitmapStorage};
+pub use bloom::{BloomFilter, BloomStorage};
+pub use cipher::Keyring;
+pub use compress::{CompressionAlgorithm, ValueCompression};
+pub use hyperloglog::{HyperLogLog, HyperLogLogStorage};
+pub use index::IndexedStorage;
+pub use json::JsonStorage;
+pub use list::ListStorage;
 pub use memory::MemTable;
+pub use path::PathStorage;
+pub(crate) use query::glob_match;
+pub use query::QueryStorage;
+pub use set::SetStorage;
 pub use sleddb::SledDb;
+pub use snapshot::Snapshot;
+pub use stream::{StreamId, StreamStorage};
+pub use timeseries::{Aggregation, TimeSeriesStorage};
+pub use version::VersionedStorage;
+pub use zset::SortedSetStorage;
 
-use crate::{KvError, Kvpair, Value};
+use bytes::Bytes;
+use prost::Message;
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    ops::Bound,
+    sync::{Mutex, MutexGuard},
+};
+
+use crate::{value, KvError, Kvpair, Value};
 
 // 对存储的抽象, 我们不关心数据存在哪儿, 但需要定义外界如何和存储打交道
+// table 名是字符串，key 可以是任意的字节
 pub trait Storage {
     // 从一个 HashTable 里获取一个 key 的 value
-    fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError>;
+    fn get(&self, table: &str, key: &[u8]) -> Result<Option<Value>, KvError>;
     // 从一个 HashTable 里设置一个 key 的 value, 返回旧的 value
-    fn set(&self, table: &str, key: String, value: Value) -> Result<Option<Value>, KvError>;
+    fn set(&self, table: &str, key: Bytes, value: Value) -> Result<Option<Value>, KvError>;
     // 查看 HashTable 中是否含有 key
-    fn contains(&self, table: &str, key: &str) -> Result<bool, KvError>;
+    fn contains(&self, table: &str, key: &[u8]) -> Result<bool, KvError>;
     // 从 HashTable 中删除一个 key
-    fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KvError>;
+    fn del(&self, table: &str, key: &[u8]) -> Result<Option<Value>, KvError>;
+    // 原子地读取并修改一个 key 的 value, f 返回 None 表示删除这个 key, 返回修改后的 value
+    // f 可能会被调用多次，并且不能在 f 里再访问同一个 Storage
+    fn update(
+        &self,
+        table: &str,
+        key: &[u8],
+        f: &mut dyn FnMut(Option<Value>) -> Result<Option<Value>, KvError>,
+    ) -> Result<Option<Value>, KvError>;
     // 遍历 HashTable, 返回所有 kv pair
     fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError>;
     // 遍历 HashTable, 返回 kv pair 的 Iterator
     // 目前 Rust 还不支持在 trait 里使用 impl trait 做返回值，所以要这样写
     fn get_iter(&self, table: &str) -> Result<Box<dyn Iterator<Item = Kvpair>>, KvError>;
+    // 只遍历 HashTable 的 key，不读取也不 clone value，用于 Hkeys、随机采样这类只关心 key 的场景
+    fn get_keys(&self, table: &str) -> Result<Box<dyn Iterator<Item = Bytes>>, KvError>;
+    // 随机返回 table 里的 key。count 为正数时返回的 key 互不相同，最多返回 table 里所有的 key；
+    // 为负数时 key 可以重复，table 不为空时正好返回 -count 个
+    // 默认实现基于 get_keys 做蓄水池抽样
+    fn random_keys(&self, table: &str, count: i64) -> Result<Vec<Bytes>, KvError> {
+        sample_keys(self.get_keys(table)?, count)
+    }
+    // 获取 table 在某一时刻的一致性快照，用于 Hgetall、扫描、备份等需要读多个 key 的场景
+    fn snapshot(&self, table: &str) -> Result<Snapshot, KvError>;
+    // 和 snapshot 一样，但只包含 keys 里（存在）的 key
+    fn snapshot_keys(&self, table: &str, keys: &[Bytes]) -> Result<Snapshot, KvError>;
+    // 按 key 的字节序遍历 table 里在 start 和 end 之间的 kv pair，用于流这类需要有序读取的数据
+    // 默认实现基于 snapshot，能按顺序遍历的后端应该覆盖它
+    fn get_range(
+        &self,
+        table: &str,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+    ) -> Result<Box<dyn Iterator<Item = Kvpair>>, KvError> {
+        let pairs: Vec<_> = self.snapshot(table)?.range(start, end).collect();
+        Ok(Box::new(pairs.into_iter()))
+    }
+}
+
+// 列表、多版本历史等复杂的数据以 protobuf 编码后存成 Value::Binary
+pub(crate) fn message_to_value(msg: &impl Message) -> Value {
+    Bytes::from(msg.encode_to_vec()).into()
+}
+
+pub(crate) fn value_to_message<T: Message + Default>(value: &Value) -> Result<T, KvError> {
+    match &value.value {
+        Some(value::Value::Binary(data)) => Ok(T::decode(data.as_ref())?),
+        _ => Err(KvError::ConvertError(value.clone(), "Binary")),
+    }
+}
+
+// 读取一个以 protobuf 编码存放的结构，key 不存在时返回空的结构
+pub(crate) fn get_message<S, M>(store: &S, table: &str, key: &[u8]) -> Result<M, KvError>
+where
+    S: Storage + ?Sized,
+    M: Message + Default,
+{
+    match store.get(table, key)? {
+        Some(v) => value_to_message(&v),
+        None => Ok(M::default()),
+    }
+}
+
+// 原子地修改一个以 protobuf 编码存放的结构，f 的返回值作为结果返回
+// 修改之后结构为空时删除这个 key
+pub(crate) fn update_message<S, M, F, R>(
+    store: &S,
+    table: &str,
+    key: &[u8],
+    mut f: F,
+) -> Result<R, KvError>
+where
+    S: Storage + ?Sized,
+    M: Message + Default + PartialEq,
+    F: FnMut(&mut M) -> R,
+{
+    let mut result = None;
+    store.update(table, key, &mut |v| {
+        let mut msg: M = match v {
+            Some(v) => value_to_message(&v)?,
+            None => M::default(),
+        };
+        result = Some(f(&mut msg));
+        if msg == M::default() {
+            Ok(None)
+        } else {
+            Ok(Some(message_to_value(&msg)))
+        }
+    })?;
+    result.ok_or_else(|| KvError::Internal("Value was not updated".into()))
+}
+
+// 稳定的 64 位哈希（FNV-1a 加上 splitmix64 的混淆），sketch 会持久化，不能用每次启动都不同的哈希
+// seed 不同时可以当作相互独立的哈希函数使用
+pub(crate) fn hash64(data: &[u8], seed: u64) -> u64 {
+    let mut h = 0xcbf2_9ce4_8422_2325 ^ seed.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    for b in data {
+        h ^= *b as u64;
+        h = h.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    h ^= h >> 30;
+    h = h.wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    h ^= h >> 27;
+    h = h.wrapping_mul(0x94d0_49bb_1331_11eb);
+    h ^ (h >> 31)
+}
+
+// 主数据的写入除了 value 本身还要维护历史版本、索引这些别的 key，Storage::update 只能保证单个 key 的原子性，
+// 所以同一个 key 的这类写入用 row lock 串行起来。锁按 table 和 key 的哈希分片，不同的 key 偶尔会共用一把锁
+const ROW_LOCKS: usize = 256;
+static ROW_LOCK: [Mutex<()>; ROW_LOCKS] = [const { Mutex::new(()) }; ROW_LOCKS];
+
+pub(crate) fn lock_row(table: &str, key: &[u8]) -> MutexGuard<'static, ()> {
+    let i = hash64(key, hash64(table.as_bytes(), 0)) % ROW_LOCKS as u64;
+    ROW_LOCK[i as usize].lock().unwrap()
+}
+
+// 允许重复时一次最多返回的 key 的个数，避免一个请求就分配大量内存
+const MAX_RANDOM_KEYS: u64 = 1 << 16;
+
+fn sample_keys(keys: impl Iterator<Item = Bytes>, count: i64) -> Result<Vec<Bytes>, KvError> {
+    // 用随机的 seed 加上计数器做哈希，得到 [0, n) 之间的随机数
+    let seed = RandomState::new().build_hasher().finish();
+    let mut counter = 0u64;
+    let mut below = |n: usize| {
+        counter += 1;
+        (hash64(&counter.to_be_bytes(), seed) % n as u64) as usize
+    };
+
+    let n = count.unsigned_abs();
+    if count < 0 {
+        if n > MAX_RANDOM_KEYS {
+            return Err(KvError::InvalidCommand(format!(
+                "Cannot return more than {} random keys",
+                MAX_RANDOM_KEYS
+            )));
+        }
+        let keys: Vec<_> = keys.collect();
+        if keys.is_empty() {
+            return Ok(vec![]);
+        }
+        return Ok((0..n).map(|_| keys[below(keys.len())].clone()).collect());
+    }
+
+    // 蓄水池抽样：第 i 个 key 以 n / (i + 1) 的概率替换掉一个已经选中的 key
+    let n = n as usize;
+    let mut result = Vec::new();
+    for (i, key) in keys.enumerate() {
+        if i < n {
+            result.push(key);
+        } else {
+            let j = below(i + 1);
+            if j < n {
+                result[j] = key;
+            }
+        }
+    }
+    // 蓄水池里前面的位置更可能保留着先遍历到的 key，打乱一下顺序
+    for i in (1..result.len()).rev() {
+        result.swap(i, below(i + 1));
+    }
+    Ok(result)
 }
 
 #[cfg(test)]
 mod tests {
+    use std::{sync::Arc, thread};
     use tempfile::tempdir;
 
     use super::*;
@@ -47,6 +247,35 @@ mod tests {
         test_get_iter(store);
     }
 
+    #[test]
+    fn memtable_update_should_work() {
+        let store = MemTable::new();
+        test_update(store);
+    }
+
+    #[test]
+    fn memtable_binary_key_should_work() {
+        let store = MemTable::new();
+        test_binary_key(store);
+    }
+
+    #[test]
+    fn memtable_get_range_should_work() {
+        test_get_range(MemTable::new());
+    }
+
+    #[test]
+    fn memtable_get_keys_should_work() {
+        test_get_keys(MemTable::new());
+    }
+
+    #[test]
+    fn memtable_snapshot_should_work() {
+        let store = MemTable::new();
+        test_snapshot(store.clone());
+        test_snapshot_consistency(Arc::new(store));
+    }
+
     fn test_basic_interface(store: impl Storage) {
         // 第一次 set 会创建 table, 插入 key 并返回 None (之前没值)
         let v = store.set("t1", "hello".into(), "world".into());
@@ -56,26 +285,26 @@ mod tests {
         assert_eq!(v1.unwrap(), Some("world".into()));
 
         // get 存在的 key 会得到最新的值
-        let v = store.get("t1", "hello");
+        let v = store.get("t1", b"hello");
         // 此处 编译器会根据上下文自动推断出要 into 成什么类型
         assert_eq!(v.unwrap(), Some("world1".into()));
 
         // get 不存在的 key 或者 table 会得到 None
-        assert_eq!(None, store.get("t1", "not_exist").unwrap());
-        assert_eq!(None, store.get("t2", "hello").unwrap());
+        assert_eq!(None, store.get("t1", b"not_exist").unwrap());
+        assert_eq!(None, store.get("t2", b"hello").unwrap());
 
         // contains 存在的 key 返回 true, 否则返回 false
-        assert!(store.contains("t1", "hello").unwrap());
-        assert!(!store.contains("t1", "hello1").unwrap());
-        assert!(!store.contains("t2", "hello").unwrap());
+        assert!(store.contains("t1", b"hello").unwrap());
+        assert!(!store.contains("t1", b"hello1").unwrap());
+        assert!(!store.contains("t2", b"hello").unwrap());
 
         // del 存在的 key 返回之前的值
-        let v = store.del("t1", "hello");
+        let v = store.del("t1", b"hello");
         assert_eq!(v.unwrap(), Some("world1".into()));
 
         // del 不存在的 key 或 table 返回 None
-        assert_eq!(store.del("t1", "hello1").unwrap(), None);
-        assert_eq!(store.del("t2", "hello").unwrap(), None);
+        assert_eq!(store.del("t1", b"hello1").unwrap(), None);
+        assert_eq!(store.del("t2", b"hello").unwrap(), None);
     }
 
     fn test_get_all(store: impl Storage) {
@@ -92,7 +321,180 @@ mod tests {
         )
     }
 
+    fn test_update(store: impl Storage) {
+        let incr = |v: Option<Value>| match v {
+            Some(Value {
+                value: Some(value::Value::Integer(i)),
+            }) => Ok(Some((i + 1).into())),
+            None => Ok(Some(1.into())),
+            Some(v) => Err(KvError::ConvertError(v, "Integer")),
+        };
+
+        // key 不存在时 f 拿到 None
+        assert_eq!(
+            store.update("t1", b"k1", &mut |v| incr(v)).unwrap(),
+            Some(1.into())
+        );
+        assert_eq!(
+            store.update("t1", b"k1", &mut |v| incr(v)).unwrap(),
+            Some(2.into())
+        );
+        assert_eq!(store.get("t1", b"k1").unwrap(), Some(2.into()));
+
+        // f 出错时不修改数据
+        store.set("t1", "k2".into(), "hello".into()).unwrap();
+        assert!(store.update("t1", b"k2", &mut |v| incr(v)).is_err());
+        assert_eq!(store.get("t1", b"k2").unwrap(), Some("hello".into()));
+
+        // f 返回 None 时删除 key
+        assert_eq!(store.update("t1", b"k1", &mut |_| Ok(None)).unwrap(), None);
+        assert!(!store.contains("t1", b"k1").unwrap());
+    }
+
+    fn test_binary_key(store: impl Storage) {
+        // 不是 utf8，并且包含 sled 里用来分隔 table 和 key 的 `:`
+        let key = Bytes::from_static(&[0xff, b':', 0x00, 0x80]);
+        store.set("t5", key.clone(), "v1".into()).unwrap();
+        assert_eq!(store.get("t5", &key).unwrap(), Some("v1".into()));
+        assert!(store.contains("t5", &key).unwrap());
+        assert_eq!(
+            store.get_all("t5").unwrap(),
+            vec![Kvpair::new(key.clone(), "v1".into())]
+        );
+        assert_eq!(store.del("t5", &key).unwrap(), Some("v1".into()));
+        assert!(!store.contains("t5", &key).unwrap());
+    }
+
+    fn test_snapshot(store: impl Storage) {
+        store.set("t3", "k1".into(), "v1".into()).unwrap();
+        store.set("t3", "k2".into(), "v2".into()).unwrap();
+        let snapshot = store.snapshot("t3").unwrap();
+
+        // 快照之后的修改不影响快照
+        store.set("t3", "k1".into(), "v3".into()).unwrap();
+        store.del("t3", b"k2").unwrap();
+        assert_eq!(snapshot.len(), 2);
+        assert_eq!(snapshot.get(b"k1"), Some(&"v1".into()));
+        assert_eq!(
+            snapshot.into_iter().collect::<Vec<_>>(),
+            vec![
+                Kvpair::new("k1", "v1".into()),
+                Kvpair::new("k2", "v2".into())
+            ]
+        );
+
+        let keys = vec!["k1".into(), "k2".into()];
+        let snapshot = store.snapshot_keys("t3", &keys).unwrap();
+        assert_eq!(snapshot.get(b"k1"), Some(&"v3".into()));
+        assert!(!snapshot.contains(b"k2"));
+    }
+
+    // 写入线程每一轮按顺序把所有 key 都改成当前的轮数，
+    // 所以任意时刻的数据按 key 排序后都是单调不增的，并且最多相差 1
+    fn test_snapshot_consistency(store: Arc<impl Storage + Send + Sync + 'static>) {
+        let keys: Vec<Bytes> = (0..50).map(|i| format!("k{:03}", i).into()).collect();
+        for key in &keys {
+            store.set("t4", key.clone(), 0.into()).unwrap();
+        }
+
+        let writer = {
+            let store = store.clone();
+            let keys = keys.clone();
+            thread::spawn(move || {
+                for round in 1..=200 {
+                    for key in &keys {
+                        store.set("t4", key.clone(), round.into()).unwrap();
+                    }
+                }
+            })
+        };
+
+        while !writer.is_finished() {
+            let values: Vec<_> = store
+                .snapshot("t4")
+                .unwrap()
+                .iter()
+                .map(|(_, v)| match v.value {
+                    Some(value::Value::Integer(i)) => i,
+                    _ => unreachable!(),
+                })
+                .collect();
+            assert_eq!(values.len(), keys.len());
+            assert!(values.windows(2).all(|w| w[0] >= w[1]));
+            assert!(values[0] - values[values.len() - 1] <= 1);
+
+            let ends = [keys[0].clone(), keys[keys.len() - 1].clone()];
+            let values: Vec<_> = store
+                .snapshot_keys("t4", &ends)
+                .unwrap()
+                .iter()
+                .map(|(_, v)| match v.value {
+                    Some(value::Value::Integer(i)) => i,
+                    _ => unreachable!(),
+                })
+                .collect();
+            assert!(values[0] >= values[1] && values[0] - values[1] <= 1);
+        }
+        writer.join().unwrap();
+    }
+
     #[allow(dead_code)]
+    fn test_get_range(store: impl Storage) {
+        for key in ["a", "b", "c", "d"] {
+            store.set("t1", key.into(), key.into()).unwrap();
+        }
+        store.set("t2", "b".into(), "x".into()).unwrap();
+        let keys = |start: Bound<&[u8]>, end: Bound<&[u8]>| {
+            store
+                .get_range("t1", start, end)
+                .unwrap()
+                .map(|pair| pair.key)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            keys(Bound::Included(b"b"), Bound::Excluded(b"d")),
+            vec![Bytes::from("b"), Bytes::from("c")]
+        );
+        assert_eq!(
+            keys(Bound::Excluded(b"b"), Bound::Unbounded),
+            vec![Bytes::from("c"), Bytes::from("d")]
+        );
+        assert_eq!(
+            keys(Bound::Unbounded, Bound::Included(b"a")),
+            vec![Bytes::from("a")]
+        );
+        // start 在 end 之后时返回空
+        assert!(keys(Bound::Included(b"d"), Bound::Included(b"a")).is_empty());
+    }
+
+    fn test_get_keys(store: impl Storage) {
+        for key in ["k1", "k2", "k3"] {
+            store.set("t6", key.into(), key.into()).unwrap();
+        }
+        store.set("t7", "other".into(), "v".into()).unwrap();
+        let mut keys: Vec<_> = store.get_keys("t6").unwrap().collect();
+        keys.sort();
+        assert_eq!(keys, vec!["k1", "k2", "k3"]);
+
+        // count 为正数时 key 不重复，最多返回所有的 key
+        let keys = store.random_keys("t6", 2).unwrap();
+        assert_eq!(keys.len(), 2);
+        assert_ne!(keys[0], keys[1]);
+        let mut keys = store.random_keys("t6", 10).unwrap();
+        keys.sort();
+        assert_eq!(keys, vec!["k1", "k2", "k3"]);
+
+        // count 为负数时 key 可以重复，正好返回 -count 个
+        let keys = store.random_keys("t6", -10).unwrap();
+        assert_eq!(keys.len(), 10);
+        assert!(keys.iter().all(|k| k.starts_with(b"k")));
+
+        assert!(store.random_keys("t6", 0).unwrap().is_empty());
+        assert!(store.random_keys("none", -10).unwrap().is_empty());
+        assert!(store.random_keys("t6", i64::MIN).is_err());
+    }
+
     fn test_get_iter(store: impl Storage) {
         store.set("t2", "k1".into(), "v1".into()).unwrap();
         store.set("t2", "k2".into(), "v2".into()).unwrap();
@@ -127,4 +529,51 @@ mod tests {
         let store = SledDb::new(dir);
         test_get_iter(store);
     }
+
+    #[test]
+    fn sleddb_update_should_work() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir);
+        test_update(store);
+    }
+
+    #[test]
+    fn sleddb_binary_key_should_work() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir);
+        test_binary_key(store);
+    }
+
+    #[test]
+    fn sleddb_get_range_should_work() {
+        let dir = tempdir().unwrap();
+        test_get_range(SledDb::new(dir));
+    }
+
+    #[test]
+    fn sleddb_get_keys_should_work() {
+        let dir = tempdir().unwrap();
+        test_get_keys(SledDb::new(dir));
+    }
+
+    #[test]
+    fn sleddb_snapshot_should_work() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir);
+        test_snapshot(store.clone());
+        test_snapshot_consistency(Arc::new(store));
+    }
+
+    #[test]
+    fn encrypted_sleddb_should_work() {
+        let keys = "1:000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir).with_encryption(Keyring::parse(keys).unwrap(), true);
+        test_basic_interface(store.clone());
+        test_get_all(store.clone());
+        test_get_iter(store.clone());
+        test_update(store.clone());
+        test_get_keys(store.clone());
+        test_binary_key(store);
+    }
 }
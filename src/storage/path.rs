@@ -0,0 +1,114 @@
+use crate::{IndexedStorage, KvError, Storage, Value, ValueMap};
+
+// 在 Storage 的基础上提供对嵌套 value 的局部修改，所有实现了 Storage 的结构都自动获得这些方法
+// path 的含义见 Value::get_path。修改通过 update_indexed 完成，所以是原子的，并且会维护索引和历史版本
+pub trait PathStorage: Storage {
+    // 获取 key 的 value 在 path 处的值
+    fn get_at(&self, table: &str, key: &[u8], path: &[String]) -> Result<Option<Value>, KvError> {
+        Ok(self
+            .get(table, key)?
+            .and_then(|v| v.get_path(path).cloned()))
+    }
+
+    // 设置 key 的 value 在 path 处的值，返回之前的值。key 不存在时从一个空的 map 开始
+    fn set_at(
+        &self,
+        table: &str,
+        key: &[u8],
+        path: &[String],
+        value: Value,
+    ) -> Result<Option<Value>, KvError> {
+        let mut old = None;
+        self.update_indexed(table, key, &mut |v| {
+            let mut v = v.unwrap_or_else(|| ValueMap::default().into());
+            old = v.set_path(path, value.clone())?;
+            Ok(Some(v))
+        })?;
+        Ok(old)
+    }
+
+    // 删除 key 的 value 在 path 处的 map key 或者列表元素，返回被删除的值
+    fn del_at(&self, table: &str, key: &[u8], path: &[String]) -> Result<Option<Value>, KvError> {
+        let mut old = None;
+        self.update_indexed(table, key, &mut |v| match v {
+            Some(mut v) => {
+                old = v.del_path(path)?;
+                Ok(Some(v))
+            }
+            None => {
+                old = None;
+                Ok(None)
+            }
+        })?;
+        Ok(old)
+    }
+}
+
+impl<T: Storage + ?Sized> PathStorage for T {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MemTable, SledDb};
+    use std::collections::BTreeMap;
+    use tempfile::tempdir;
+
+    #[test]
+    fn memtable_nested_value_should_work() {
+        test_nested_value(MemTable::new());
+    }
+
+    #[test]
+    fn sleddb_nested_value_should_work() {
+        let dir = tempdir().unwrap();
+        test_nested_value(SledDb::new(dir));
+    }
+
+    fn test_nested_value(store: impl Storage) {
+        let path = |p: &[&str]| p.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let user: Value = BTreeMap::from([
+            ("name".to_string(), Value::from("alice")),
+            ("scores".to_string(), vec![1.into(), 2.into()].into()),
+        ])
+        .into();
+        store.set("t1", "u1".into(), user.clone()).unwrap();
+        // 嵌套的 value 可以完整地存取
+        assert_eq!(store.get("t1", b"u1").unwrap(), Some(user));
+
+        let old = store
+            .set_at("t1", b"u1", &path(&["scores", "-1"]), 3.into())
+            .unwrap();
+        assert_eq!(old, Some(2.into()));
+        let old = store
+            .set_at("t1", b"u1", &path(&["address", "city"]), "paris".into())
+            .unwrap();
+        assert_eq!(old, None);
+        assert_eq!(
+            store
+                .get_at("t1", b"u1", &path(&["address", "city"]))
+                .unwrap(),
+            Some("paris".into())
+        );
+
+        let removed = store.del_at("t1", b"u1", &path(&["scores", "0"])).unwrap();
+        assert_eq!(removed, Some(1.into()));
+        let scores = store.get_at("t1", b"u1", &path(&["scores"])).unwrap();
+        assert_eq!(scores, Some(vec![3.into()].into()));
+
+        // key 不存在时从空的 map 开始，del_at 不会创建 key
+        store
+            .set_at("t1", b"u2", &path(&["name"]), "bob".into())
+            .unwrap();
+        assert_eq!(
+            store.get_at("t1", b"u2", &path(&["name"])).unwrap(),
+            Some("bob".into())
+        );
+        assert_eq!(store.del_at("t1", b"u3", &path(&["name"])).unwrap(), None);
+        assert!(!store.contains("t1", b"u3").unwrap());
+
+        // 穿过标量的 path 是错误
+        assert!(store
+            .set_at("t1", b"u1", &path(&["name", "first"]), "a".into())
+            .is_err());
+    }
+}
@@ -0,0 +1,485 @@
+use std::{cmp::Ordering, collections::BTreeMap};
+
+use crate::{value, Aggregate, KvError, Kvpair, Query, Storage, Value};
+
+// Redis 风格的 glob：* 匹配任意多个字符，? 匹配一个字符，[abc] / [a-z] / [^a] 匹配字符集合，
+// \ 转义下一个字符。按字节匹配，所以 key 不需要是 utf8
+pub(crate) fn glob_match(pattern: &[u8], s: &[u8]) -> bool {
+    let (mut p, mut i) = (0, 0);
+    // 最近的一个 * 之后的位置和它当前匹配到的位置，失败时回退到这里让 * 多匹配一个字符
+    let mut star = None;
+    while i < s.len() {
+        if pattern.get(p) == Some(&b'*') {
+            p += 1;
+            star = Some((p, i));
+        } else if let Some(next) = match_one(pattern, p, s[i]) {
+            p = next;
+            i += 1;
+        } else if let Some((sp, si)) = star {
+            p = sp;
+            i = si + 1;
+            star = Some((sp, i));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == b'*')
+}
+
+// 用 pattern[p..] 的第一个元素匹配字符 c，成功时返回下一个元素的位置
+fn match_one(pattern: &[u8], p: usize, c: u8) -> Option<usize> {
+    match pattern.get(p)? {
+        b'?' => Some(p + 1),
+        b'\\' if p + 1 < pattern.len() => (pattern[p + 1] == c).then_some(p + 2),
+        b'[' => match pattern[p + 1..].iter().position(|x| *x == b']') {
+            Some(end) => {
+                let (negate, class) = match &pattern[p + 1..p + 1 + end] {
+                    [b'^', class @ ..] => (true, class),
+                    class => (false, class),
+                };
+                (class_match(class, c) != negate).then_some(p + end + 2)
+            }
+            // 没有闭合的 [ 当作普通字符
+            None => (c == b'[').then_some(p + 1),
+        },
+        x => (*x == c).then_some(p + 1),
+    }
+}
+
+fn class_match(class: &[u8], c: u8) -> bool {
+    let mut i = 0;
+    while i < class.len() {
+        if i + 2 < class.len() && class[i + 1] == b'-' {
+            if (class[i]..=class[i + 2]).contains(&c) {
+                return true;
+            }
+            i += 3;
+        } else {
+            if class[i] == c {
+                return true;
+            }
+            i += 1;
+        }
+    }
+    false
+}
+
+// 比较两个标量，整数和浮点数之间按数值比较，不同类型之间不能比较
+fn compare(a: &Value, b: &Value) -> Option<Ordering> {
+    use value::Value::*;
+    match (a.value.as_ref()?, b.value.as_ref()?) {
+        (Integer(a), Integer(b)) => Some(a.cmp(b)),
+        (Integer(a), Float(b)) => (*a as f64).partial_cmp(b),
+        (Float(a), Integer(b)) => a.partial_cmp(&(*b as f64)),
+        (Float(a), Float(b)) => a.partial_cmp(b),
+        (String(a), String(b)) => Some(a.cmp(b)),
+        (Binary(a), Binary(b)) => Some(a.cmp(b)),
+        (Bool(a), Bool(b)) => Some(a.cmp(b)),
+        _ => None,
+    }
+}
+
+fn type_name(v: &Value) -> &'static str {
+    match &v.value {
+        None => "null",
+        Some(value::Value::String(_)) => "string",
+        Some(value::Value::Binary(_)) => "binary",
+        Some(value::Value::Integer(_)) => "integer",
+        Some(value::Value::Float(_)) => "float",
+        Some(value::Value::Bool(_)) => "bool",
+        Some(value::Value::List(_)) => "list",
+        Some(value::Value::Map(_)) => "map",
+    }
+}
+
+fn as_f64(v: &Value) -> Option<f64> {
+    match v.value {
+        Some(value::Value::Integer(i)) => Some(i as f64),
+        Some(value::Value::Float(f)) => Some(f),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Type,
+    Exists,
+}
+
+// 编译好的过滤条件：key 的 glob 和所有的 predicate，predicate 之间是 AND 的关系
+struct Filter<'a> {
+    key_pattern: &'a [u8],
+    predicates: Vec<(&'a [String], Op, Value)>,
+}
+
+impl<'a> Filter<'a> {
+    fn new(query: &'a Query) -> Result<Self, KvError> {
+        let predicates = query
+            .predicates
+            .iter()
+            .map(|p| {
+                let op = match p.op.to_ascii_lowercase().as_str() {
+                    "eq" => Op::Eq,
+                    "ne" => Op::Ne,
+                    "lt" => Op::Lt,
+                    "le" => Op::Le,
+                    "gt" => Op::Gt,
+                    "ge" => Op::Ge,
+                    "type" => Op::Type,
+                    "exists" => Op::Exists,
+                    op => {
+                        return Err(KvError::InvalidCommand(format!(
+                            "Invalid predicate op: {}",
+                            op
+                        )))
+                    }
+                };
+                Ok((p.path.as_slice(), op, p.value.clone().unwrap_or_default()))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            key_pattern: query.key_pattern.as_bytes(),
+            predicates,
+        })
+    }
+
+    fn matches(&self, key: &[u8], value: &Value) -> bool {
+        if !self.key_pattern.is_empty() && !glob_match(self.key_pattern, key) {
+            return false;
+        }
+        self.predicates.iter().all(|(path, op, target)| {
+            // path 不存在时所有的条件都不满足
+            let v = match value.get_path(path) {
+                Some(v) => v,
+                None => return false,
+            };
+            let ord = compare(v, target);
+            match op {
+                Op::Eq => v == target || ord == Some(Ordering::Equal),
+                Op::Ne => v != target && ord != Some(Ordering::Equal),
+                Op::Lt => ord == Some(Ordering::Less),
+                Op::Le => matches!(ord, Some(Ordering::Less | Ordering::Equal)),
+                Op::Gt => ord == Some(Ordering::Greater),
+                Op::Ge => matches!(ord, Some(Ordering::Greater | Ordering::Equal)),
+                Op::Type => *target == type_name(v).into(),
+                Op::Exists => true,
+            }
+        })
+    }
+}
+
+// 在 Storage 的基础上提供服务器端的过滤和聚合，所有实现了 Storage 的结构都自动获得这些方法
+// 通过 get_iter 遍历 table，不需要把整个 table 发给客户端
+pub trait QueryStorage: Storage {
+    // 获取 key 匹配 key_pattern 并且满足所有条件的 kv pair，按 projection 投影，最多返回 limit 个
+    // 有 projection 时返回的 value 是一个 map，key 是用 "." 连起来的 path，不存在的字段不返回
+    fn query(&self, query: &Query) -> Result<Vec<Kvpair>, KvError> {
+        let mut result = vec![];
+        self.query_each(query, &mut |pair| {
+            result.push(pair);
+            true
+        })?;
+        Ok(result)
+    }
+
+    // 和 query 一样，但边遍历边把结果交给 f，不需要把所有结果都放在内存里。f 返回 false 时停止遍历
+    fn query_each(&self, query: &Query, f: &mut dyn FnMut(Kvpair) -> bool) -> Result<(), KvError> {
+        let limit = if query.limit == 0 {
+            usize::MAX
+        } else {
+            query.limit as usize
+        };
+
+        let filter = Filter::new(query)?;
+        let mut count = 0;
+        for pair in self.get_iter(&query.table)? {
+            if count >= limit {
+                break;
+            }
+            let value = pair.value.unwrap_or_default();
+            if !filter.matches(&pair.key, &value) {
+                continue;
+            }
+            let value = if query.projection.is_empty() {
+                value
+            } else {
+                query
+                    .projection
+                    .iter()
+                    .filter_map(|f| Some((f.path.join("."), value.get_path(&f.path)?.clone())))
+                    .collect::<BTreeMap<_, _>>()
+                    .into()
+            };
+            count += 1;
+            if !f(Kvpair::new(pair.key, value)) {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    // 对满足条件的 kv pair 计算所有的聚合，每个聚合返回一个 value
+    // 只有整数和浮点数参与 sum / min / max / avg，没有数据时 min / max / avg 返回空值
+    fn aggregate(&self, query: &Query) -> Result<Vec<Value>, KvError> {
+        let filter = Filter::new(query)?;
+        let mut states: Vec<_> = query
+            .aggregates
+            .iter()
+            .map(AggregateState::new)
+            .collect::<Result<_, _>>()?;
+        for pair in self.get_iter(&query.table)? {
+            let value = pair.value.unwrap_or_default();
+            if !filter.matches(&pair.key, &value) {
+                continue;
+            }
+            for (state, agg) in states.iter_mut().zip(&query.aggregates) {
+                if let Some(v) = value.get_path(&agg.path) {
+                    state.add(v);
+                }
+            }
+        }
+        Ok(states.into_iter().map(AggregateState::finish).collect())
+    }
+}
+
+impl<T: Storage + ?Sized> QueryStorage for T {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Func {
+    Count,
+    Sum,
+    Min,
+    Max,
+    Avg,
+}
+
+struct AggregateState {
+    func: Func,
+    count: u64,
+    // 所有的值都是整数并且没有溢出时，sum 保持整数
+    int_sum: Option<i64>,
+    sum: f64,
+    best: Option<Value>,
+}
+
+impl AggregateState {
+    fn new(agg: &Aggregate) -> Result<Self, KvError> {
+        let func = match agg.func.to_ascii_lowercase().as_str() {
+            "count" => Func::Count,
+            "sum" => Func::Sum,
+            "min" => Func::Min,
+            "max" => Func::Max,
+            "avg" => Func::Avg,
+            f => {
+                return Err(KvError::InvalidCommand(format!(
+                    "Invalid aggregate function: {}",
+                    f
+                )))
+            }
+        };
+        Ok(Self {
+            func,
+            count: 0,
+            int_sum: Some(0),
+            sum: 0.0,
+            best: None,
+        })
+    }
+
+    fn add(&mut self, v: &Value) {
+        if self.func == Func::Count {
+            self.count += 1;
+            return;
+        }
+        let f = match as_f64(v) {
+            Some(f) => f,
+            None => return,
+        };
+        self.count += 1;
+        self.sum += f;
+        self.int_sum = match (self.int_sum, &v.value) {
+            (Some(sum), Some(value::Value::Integer(i))) => sum.checked_add(*i),
+            _ => None,
+        };
+        let better = match (&self.best, self.func) {
+            (None, _) => true,
+            (Some(best), Func::Min) => compare(v, best) == Some(Ordering::Less),
+            (Some(best), Func::Max) => compare(v, best) == Some(Ordering::Greater),
+            _ => false,
+        };
+        if better {
+            self.best = Some(v.clone());
+        }
+    }
+
+    fn finish(self) -> Value {
+        match self.func {
+            Func::Count => (self.count as i64).into(),
+            Func::Sum => match self.int_sum {
+                Some(sum) => sum.into(),
+                None => self.sum.into(),
+            },
+            Func::Min | Func::Max => self.best.unwrap_or_default(),
+            Func::Avg if self.count == 0 => Value::default(),
+            Func::Avg => (self.sum / self.count as f64).into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FieldPath, MemTable, Predicate, SledDb};
+
+    #[test]
+    fn memtable_query_should_work() {
+        test_query(MemTable::new());
+    }
+
+    #[test]
+    fn sleddb_query_should_work() {
+        let dir = tempfile::tempdir().unwrap();
+        test_query(SledDb::new(dir));
+    }
+
+    #[test]
+    fn glob_match_should_work() {
+        let cases = [
+            ("*", "anything", true),
+            ("user:*", "user:1", true),
+            ("user:*", "order:1", false),
+            ("h?llo", "hello", true),
+            ("h?llo", "hllo", false),
+            ("h[ae]llo", "hallo", true),
+            ("h[^e]llo", "hello", false),
+            ("h[a-c]llo", "hbllo", true),
+            ("h\\*llo", "h*llo", true),
+            ("h\\*llo", "hello", false),
+            ("*o*o", "foobar", false),
+            ("*a*b", "xaab", true),
+            ("a[bc", "a[bc", true),
+            ("", "", true),
+            ("a*", "", false),
+        ];
+        for (pattern, s, expected) in cases {
+            assert_eq!(
+                glob_match(pattern.as_bytes(), s.as_bytes()),
+                expected,
+                "{} {}",
+                pattern,
+                s
+            );
+        }
+    }
+
+    fn path(p: &str) -> Vec<String> {
+        p.split('.')
+            .filter(|s| !s.is_empty())
+            .map(|s| s.to_string())
+            .collect()
+    }
+
+    fn predicate(p: &str, op: &str, value: Value) -> Predicate {
+        Predicate {
+            path: path(p),
+            op: op.into(),
+            value: Some(value),
+        }
+    }
+
+    fn aggregate(func: &str, p: &str) -> Aggregate {
+        Aggregate {
+            func: func.into(),
+            path: path(p),
+        }
+    }
+
+    fn test_query(store: impl Storage) {
+        let order = |user: &str, amount: Value| -> Value {
+            BTreeMap::from([
+                ("user".to_string(), Value::from(user)),
+                ("amount".to_string(), amount),
+            ])
+            .into()
+        };
+        store
+            .set("t1", "order:1".into(), order("alice", 10.into()))
+            .unwrap();
+        store
+            .set("t1", "order:2".into(), order("bob", 2.5.into()))
+            .unwrap();
+        store
+            .set("t1", "order:3".into(), order("alice", 5.into()))
+            .unwrap();
+        store.set("t1", "other".into(), 100.into()).unwrap();
+
+        let mut query = Query {
+            table: "t1".into(),
+            key_pattern: "order:*".into(),
+            predicates: vec![predicate("amount", "ge", 5.into())],
+            projection: vec![FieldPath {
+                path: vec!["user".into()],
+            }],
+            ..Default::default()
+        };
+        let mut res = store.query(&query).unwrap();
+        res.sort_by(|a, b| a.key.cmp(&b.key));
+        let projected = |user: &str| -> Value {
+            BTreeMap::from([("user".to_string(), Value::from(user))]).into()
+        };
+        assert_eq!(
+            res,
+            vec![
+                Kvpair::new("order:1", projected("alice")),
+                Kvpair::new("order:3", projected("alice")),
+            ]
+        );
+
+        query.limit = 1;
+        assert_eq!(store.query(&query).unwrap().len(), 1);
+
+        // 整数和浮点数混在一起时 sum 是浮点数
+        query.predicates = vec![];
+        query.aggregates = vec![
+            aggregate("count", "amount"),
+            aggregate("sum", "amount"),
+            aggregate("min", "amount"),
+            aggregate("max", "amount"),
+            aggregate("avg", "amount"),
+        ];
+        let res = store.aggregate(&query).unwrap();
+        assert_eq!(
+            res,
+            vec![
+                3.into(),
+                17.5.into(),
+                2.5.into(),
+                10.into(),
+                (17.5 / 3.0).into()
+            ]
+        );
+
+        query.predicates = vec![predicate("user", "eq", "alice".into())];
+        query.aggregates = vec![aggregate("sum", "amount"), aggregate("avg", "none")];
+        let res = store.aggregate(&query).unwrap();
+        assert_eq!(res, vec![15.into(), Value::default()]);
+
+        query.key_pattern = "".into();
+        query.predicates = vec![predicate("", "type", "integer".into())];
+        query.aggregates = vec![aggregate("sum", "")];
+        assert_eq!(store.aggregate(&query).unwrap(), vec![100.into()]);
+
+        query.predicates = vec![predicate("amount", "like", 1.into())];
+        assert!(store.aggregate(&query).is_err());
+        assert!(store.query(&query).is_err());
+        query.predicates = vec![];
+        query.aggregates = vec![aggregate("median", "amount")];
+        assert!(store.aggregate(&query).is_err());
+    }
+}
@@ -0,0 +1,158 @@
+use bytes::Bytes;
+use prost::Message;
+use std::collections::BTreeMap;
+
+use super::{get_message, update_message, value_to_message};
+use crate::{KvError, Storage, Value, ValueList};
+
+// 集合存放在另外一个 table 里，每个集合编码成一个 ValueList
+// 成员按编码后的字节排序并去重，所以 Smembers 的结果是稳定的
+fn set_table(table: &str) -> String {
+    format!("__set__.{}", table)
+}
+
+// Value 里有 f64，没法直接做 Hash / Ord，用编码后的字节来比较成员
+pub(super) fn member_key(member: &Value) -> Vec<u8> {
+    member.encode_to_vec()
+}
+
+fn to_members(values: Vec<Value>) -> BTreeMap<Vec<u8>, Value> {
+    values.into_iter().map(|v| (member_key(&v), v)).collect()
+}
+
+// 在 Storage 的基础上提供集合的能力，所有实现了 Storage 的结构都自动获得这些方法
+pub trait SetStorage: Storage {
+    // 往集合里加入一组成员，返回新加入的成员个数
+    fn set_add(&self, table: &str, key: &[u8], members: Vec<Value>) -> Result<usize, KvError> {
+        update_message(self, &set_table(table), key, |set: &mut ValueList| {
+            let mut all = to_members(std::mem::take(&mut set.values));
+            let count = all.len();
+            all.extend(to_members(members.clone()));
+            let added = all.len() - count;
+            set.values = all.into_values().collect();
+            added
+        })
+    }
+
+    // 从集合里删除一组成员，返回实际删除的成员个数。集合为空时会删除对应的 key
+    fn set_remove(&self, table: &str, key: &[u8], members: &[Value]) -> Result<usize, KvError> {
+        update_message(self, &set_table(table), key, |set: &mut ValueList| {
+            let mut all = to_members(std::mem::take(&mut set.values));
+            let removed = members
+                .iter()
+                .filter(|m| all.remove(&member_key(m)).is_some())
+                .count();
+            set.values = all.into_values().collect();
+            removed
+        })
+    }
+
+    fn set_members(&self, table: &str, key: &[u8]) -> Result<Vec<Value>, KvError> {
+        let set: ValueList = get_message(self, &set_table(table), key)?;
+        Ok(set.values)
+    }
+
+    fn set_is_member(&self, table: &str, key: &[u8], member: &Value) -> Result<bool, KvError> {
+        let set: ValueList = get_message(self, &set_table(table), key)?;
+        Ok(set.values.contains(member))
+    }
+
+    // 获取一组集合的交集，不存在的集合当作空集
+    fn set_inter(&self, table: &str, keys: &[Bytes]) -> Result<Vec<Value>, KvError> {
+        let mut sets = get_sets(self, table, keys)?.into_iter();
+        let mut result = sets.next().unwrap_or_default();
+        for set in sets {
+            result.retain(|k, _| set.contains_key(k));
+        }
+        Ok(result.into_values().collect())
+    }
+
+    // 获取一组集合的并集
+    fn set_union(&self, table: &str, keys: &[Bytes]) -> Result<Vec<Value>, KvError> {
+        let mut result = BTreeMap::new();
+        for set in get_sets(self, table, keys)? {
+            result.extend(set);
+        }
+        Ok(result.into_values().collect())
+    }
+}
+
+impl<T: Storage + ?Sized> SetStorage for T {}
+
+// 用快照一次读出所有的集合，保证它们是同一时刻的
+fn get_sets<S: Storage + ?Sized>(
+    store: &S,
+    table: &str,
+    keys: &[Bytes],
+) -> Result<Vec<BTreeMap<Vec<u8>, Value>>, KvError> {
+    let snapshot = store.snapshot_keys(&set_table(table), keys)?;
+    keys.iter()
+        .map(|key| match snapshot.get(key) {
+            Some(v) => Ok(to_members(value_to_message::<ValueList>(v)?.values)),
+            None => Ok(BTreeMap::new()),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MemTable, SledDb};
+    use tempfile::tempdir;
+
+    #[test]
+    fn memtable_set_should_work() {
+        test_set(MemTable::new());
+    }
+
+    #[test]
+    fn sleddb_set_should_work() {
+        let dir = tempdir().unwrap();
+        test_set(SledDb::new(dir));
+    }
+
+    fn test_set(store: impl Storage) {
+        let values = |v: &[&str]| v.iter().map(|&s| Value::from(s)).collect::<Vec<_>>();
+
+        assert_eq!(
+            store
+                .set_add("t1", b"s1", values(&["a", "b", "a"]))
+                .unwrap(),
+            2
+        );
+        assert_eq!(store.set_add("t1", b"s1", values(&["b", "c"])).unwrap(), 1);
+        assert_eq!(
+            store
+                .set_add("t1", b"s2", values(&["b", "c", "d"]))
+                .unwrap(),
+            3
+        );
+        assert_eq!(
+            store.set_members("t1", b"s1").unwrap(),
+            values(&["a", "b", "c"])
+        );
+        assert!(store.set_is_member("t1", b"s1", &"a".into()).unwrap());
+        assert!(!store.set_is_member("t1", b"s1", &"d".into()).unwrap());
+
+        let keys = vec![Bytes::from("s1"), Bytes::from("s2")];
+        assert_eq!(store.set_inter("t1", &keys).unwrap(), values(&["b", "c"]));
+        assert_eq!(
+            store.set_union("t1", &keys).unwrap(),
+            values(&["a", "b", "c", "d"])
+        );
+        // 不存在的集合当作空集
+        let keys = vec![Bytes::from("s1"), Bytes::from("none")];
+        assert_eq!(store.set_inter("t1", &keys).unwrap(), vec![]);
+
+        assert_eq!(
+            store.set_remove("t1", b"s1", &values(&["a", "x"])).unwrap(),
+            1
+        );
+        assert_eq!(
+            store.set_remove("t1", b"s1", &values(&["b", "c"])).unwrap(),
+            2
+        );
+        assert_eq!(store.set_members("t1", b"s1").unwrap(), vec![]);
+        assert!(!store.contains("__set__.t1", b"s1").unwrap());
+    }
+}
@@ -1,26 +1,228 @@
-use sled::{Db, IVec};
-use std::{convert::TryInto, path::Path, str};
+use bytes::Bytes;
+use sled::{transaction::TransactionError, Db, IVec};
+use std::{
+    collections::HashMap,
+    convert::{TryFrom, TryInto},
+    ops::Bound,
+    path::Path,
+    str,
+    sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
 
-use crate::{KvError, Kvpair, Storage, Value};
+use crate::{KvError, Kvpair, Snapshot, Storage, Value};
 
-#[derive(Debug)]
-pub struct SledDb(Db);
+use super::{
+    cipher::Keyring,
+    compress::{CompressionAlgorithm, ValueCompression},
+    hash64,
+};
+
+// value 在 sled 里的存储格式:
+// - 普通的 value 直接存 Value 的 protobuf 编码
+// - 加密的 value 存 ENVELOPE_TAG | ENCRYPTED | 密文
+// - 压缩的 value 存 ENVELOPE_TAG | COMPRESSED | 算法 | 压缩后的数据
+// 加密和压缩可以叠加：先压缩，再把压缩后的 envelope 加密
+// protobuf 里 field number 0 是非法的，所以合法的 Value 编码不会以 0 开头，
+// 新旧几种格式的数据可以混在一起读
+const ENVELOPE_TAG: u8 = 0;
+const ENCRYPTED: u8 = 1;
+const COMPRESSED: u8 = 2;
+
+// 快照锁的分片个数
+const SNAPSHOT_LOCKS: usize = 64;
+
+#[derive(Clone, Debug)]
+pub struct SledDb {
+    db: Db,
+    // 配置了 keyring 时，value 会加密后再写入
+    keyring: Option<Arc<Keyring>>,
+    // 是否同时（确定性地）加密 key
+    encrypt_keys: bool,
+    // 每个 table 的 value 压缩配置，没有配置的 table 不压缩
+    compression: Arc<HashMap<String, ValueCompression>>,
+    // sled 的遍历不保证看到的是同一时刻的数据。写操作持有所在 table 的快照锁的读锁（彼此不冲突），
+    // 遍历整个 table 做快照时持有写锁，这样快照期间这个 table 不会有写入，读到的就是一致的数据。
+    // 锁按 table 名的哈希分片，快照只会阻塞同一个分片里的 table
+    snapshot_locks: Arc<Vec<RwLock<()>>>,
+}
 
 impl SledDb {
     pub fn new(path: impl AsRef<Path>) -> Self {
-        Self(sled::open(path).unwrap())
+        Self {
+            db: sled::open(path).unwrap(),
+            keyring: None,
+            encrypt_keys: false,
+            compression: Arc::new(HashMap::new()),
+            snapshot_locks: Arc::new((0..SNAPSHOT_LOCKS).map(|_| RwLock::new(())).collect()),
+        }
+    }
+
+    // 开启静态加密。encrypt_keys 为 true 时 key 也会被加密，
+    // 但 table 名仍然是明文，这样才能按 table 遍历
+    pub fn with_encryption(mut self, keyring: Keyring, encrypt_keys: bool) -> Self {
+        self.keyring = Some(Arc::new(keyring));
+        self.encrypt_keys = encrypt_keys;
+        self
+    }
+
+    // 为某个 table 开启 value 压缩。只影响之后写入的数据，之前的数据照样可以读
+    pub fn with_compression(mut self, table: impl Into<String>, config: ValueCompression) -> Self {
+        Arc::make_mut(&mut self.compression).insert(table.into(), config);
+        self
+    }
+
+    // 用 keyring 里当前的密钥重新加密所有的 value（包括开启加密前写入的明文数据），
+    // 开启 key 加密之前写入的明文 key 也会换成加密后的 key，返回重写的 kv pair 个数。
+    // 密钥轮换时，先在密钥文件里追加新密钥，再调用它
+    pub fn rotate(&self) -> Result<usize, KvError> {
+        let keyring = match &self.keyring {
+            Some(v) => v,
+            None => return Ok(0),
+        };
+
+        let active = keyring.active_id().to_be_bytes();
+        let mut count = 0;
+        for item in self.db.iter() {
+            let (k, v) = item?;
+            let (table, raw_key) = split_full_key(&k)?;
+            // 加密 key 的密钥不会轮换，解不开的 key 只能是开启 key 加密之前写入的明文
+            let (key, plain_key) = match self.decode_key(table, raw_key) {
+                Ok(key) => (key, false),
+                Err(_) => (Bytes::copy_from_slice(raw_key), true),
+            };
+            let up_to_date = !plain_key
+                && v.len() > 6
+                && v[0] == ENVELOPE_TAG
+                && v[1] == ENCRYPTED
+                && v[2..6] == active[..];
+            if up_to_date {
+                continue;
+            }
+
+            let value = self.decode_value(table, &key, &v)?;
+            let data = self.encode_value(table, &key, value)?;
+            let _guard = self.write_guard(table);
+            if !plain_key {
+                // 如果在这期间被并发地改写了，新值已经是用当前密钥加密的，忽略即可
+                if let Ok(Ok(())) = self.db.compare_and_swap(&k, Some(v), Some(data)) {
+                    count += 1;
+                }
+                continue;
+            }
+
+            // 用事务把明文 key 换成加密后的 key。如果加密后的 key 已经存在，
+            // 说明开启 key 加密之后又写入过，以新写入的数据为准
+            let name = self.get_full_key(table, &key)?;
+            let moved: Result<bool, TransactionError<()>> = self.db.transaction(|tx| {
+                if tx.get(&k)?.as_ref() != Some(&v) {
+                    return Ok(false);
+                }
+                tx.remove(&k)?;
+                if tx.get(&name)?.is_none() {
+                    tx.insert(name.as_slice(), data.as_slice())?;
+                }
+                Ok(true)
+            });
+            match moved {
+                Ok(true) => count += 1,
+                Ok(false) | Err(TransactionError::Abort(())) => {}
+                Err(TransactionError::Storage(e)) => return Err(e.into()),
+            }
+        }
+
+        Ok(count)
     }
 
     // 在 sleddb 里， 因为它可以 scan_prefix, 我们用 prefix
     // 来模拟一个 table。当然还可以有其它方案
-    fn get_full_key(table: &str, key: &str) -> String {
-        format!("{}:{}", table, key)
+    fn get_full_key(&self, table: &str, key: &[u8]) -> Result<Vec<u8>, KvError> {
+        match &self.keyring {
+            Some(keyring) if self.encrypt_keys => {
+                let data = keyring.encrypt_deterministic(key, table.as_bytes())?;
+                Ok(join_key(table, hex::encode(data).as_bytes()))
+            }
+            _ => Ok(join_key(table, key)),
+        }
     }
 
     // 遍历 table 的 key 时，我们直接把 preix: 当成 table
     fn get_table_prefix(table: &str) -> String {
         format!("{}:", table)
     }
+
+    fn decode_key(&self, table: &str, key: &[u8]) -> Result<Bytes, KvError> {
+        match &self.keyring {
+            Some(keyring) if self.encrypt_keys => {
+                let data = hex::decode(key)
+                    .map_err(|_| KvError::CryptoError("Invalid encrypted key".into()))?;
+                Ok(keyring
+                    .decrypt_deterministic(&data, table.as_bytes())?
+                    .into())
+            }
+            _ => Ok(Bytes::copy_from_slice(key)),
+        }
+    }
+
+    fn encode_value(&self, table: &str, key: &[u8], value: Value) -> Result<Vec<u8>, KvError> {
+        let mut data: Vec<u8> = value.try_into()?;
+        if let Some(config) = self.compression.get(table) {
+            if let Some(compressed) = config.compress(&data)? {
+                data = vec![ENVELOPE_TAG, COMPRESSED, config.algorithm as u8];
+                data.extend(compressed);
+            }
+        }
+
+        match &self.keyring {
+            Some(keyring) => {
+                let aad = join_key(table, key);
+                let mut buf = vec![ENVELOPE_TAG, ENCRYPTED];
+                buf.extend(keyring.encrypt(&data, &aad)?);
+                Ok(buf)
+            }
+            None => Ok(data),
+        }
+    }
+
+    fn decode_value(&self, table: &str, key: &[u8], data: &[u8]) -> Result<Value, KvError> {
+        match data {
+            [ENVELOPE_TAG, ENCRYPTED, rest @ ..] => {
+                let keyring = self.keyring.as_ref().ok_or_else(|| {
+                    KvError::CryptoError("Value is encrypted but no keyring is configured".into())
+                })?;
+                let aad = join_key(table, key);
+                let data = keyring.decrypt(rest, &aad)?;
+                self.decode_value(table, key, &data)
+            }
+            [ENVELOPE_TAG, COMPRESSED, algorithm, rest @ ..] => {
+                let data = CompressionAlgorithm::try_from(*algorithm)?.decompress(rest)?;
+                self.decode_value(table, key, &data)
+            }
+            [ENVELOPE_TAG, ..] => Err(KvError::Internal("Unknown value envelope".into())),
+            _ => data.try_into(),
+        }
+    }
+
+    fn snapshot_lock(&self, table: &str) -> &RwLock<()> {
+        &self.snapshot_locks[(hash64(table.as_bytes(), 0) % SNAPSHOT_LOCKS as u64) as usize]
+    }
+
+    fn write_guard(&self, table: &str) -> RwLockReadGuard<'_, ()> {
+        self.snapshot_lock(table).read().unwrap()
+    }
+
+    fn snapshot_guard(&self, table: &str) -> RwLockWriteGuard<'_, ()> {
+        self.snapshot_lock(table).write().unwrap()
+    }
+
+    fn decode_pair(&self, table: &str, item: Result<(IVec, IVec), sled::Error>) -> Kvpair {
+        let result = item.map_err(KvError::from).and_then(|(k, v)| {
+            let (_, key) = split_full_key(&k)?;
+            let key = self.decode_key(table, key)?;
+            let value = self.decode_value(table, &key, &v)?;
+            Ok(Kvpair::new(key, value))
+        });
+        result.unwrap_or_default()
+    }
 }
 
 // 把 Option<Result<T,E>> flip 成 Result<Option<T>, E>
@@ -30,62 +232,357 @@ fn flip<T, E>(x: Option<Result<T, E>>) -> Result<Option<T>, E> {
 }
 
 impl Storage for SledDb {
-    fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
-        let name = SledDb::get_full_key(table, key);
-        let result = self.0.get(name.as_bytes())?.map(|v| v.as_ref().try_into());
+    fn get(&self, table: &str, key: &[u8]) -> Result<Option<Value>, KvError> {
+        let name = self.get_full_key(table, key)?;
+        let result = self
+            .db
+            .get(name)?
+            .map(|v| self.decode_value(table, key, &v));
         flip(result)
     }
 
-    fn set(&self, table: &str, key: String, value: Value) -> Result<Option<Value>, KvError> {
-        let name = SledDb::get_full_key(table, &key);
-        let data: Vec<u8> = value.try_into()?;
+    fn set(&self, table: &str, key: Bytes, value: Value) -> Result<Option<Value>, KvError> {
+        let name = self.get_full_key(table, &key)?;
+        let data = self.encode_value(table, &key, value)?;
 
-        let result = self.0.insert(name, data)?.map(|v| v.as_ref().try_into());
+        let _guard = self.write_guard(table);
+        let result = self
+            .db
+            .insert(name, data)?
+            .map(|v| self.decode_value(table, &key, &v));
         flip(result)
     }
 
-    fn contains(&self, table: &str, key: &str) -> Result<bool, KvError> {
-        let name = SledDb::get_full_key(table, key);
+    fn contains(&self, table: &str, key: &[u8]) -> Result<bool, KvError> {
+        let name = self.get_full_key(table, key)?;
 
-        Ok(self.0.contains_key(name)?)
+        Ok(self.db.contains_key(name)?)
     }
 
-    fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
-        let name = SledDb::get_full_key(table, key);
+    fn del(&self, table: &str, key: &[u8]) -> Result<Option<Value>, KvError> {
+        let name = self.get_full_key(table, key)?;
 
-        let result = self.0.remove(name)?.map(|v| v.as_ref().try_into());
+        let _guard = self.write_guard(table);
+        let result = self
+            .db
+            .remove(name)?
+            .map(|v| self.decode_value(table, key, &v));
         flip(result)
     }
 
+    fn update(
+        &self,
+        table: &str,
+        key: &[u8],
+        f: &mut dyn FnMut(Option<Value>) -> Result<Option<Value>, KvError>,
+    ) -> Result<Option<Value>, KvError> {
+        let name = self.get_full_key(table, key)?;
+        // 用 compare_and_swap 实现乐观锁，如果期间被别人改了就重试
+        loop {
+            let old = self.db.get(&name)?;
+            let old_value = flip(old.as_ref().map(|v| self.decode_value(table, key, v)))?;
+            let value = f(old_value)?;
+            let data = flip(value.clone().map(|v| self.encode_value(table, key, v)))?;
+            let _guard = self.write_guard(table);
+            if self.db.compare_and_swap(&name, old, data)?.is_ok() {
+                return Ok(value);
+            }
+        }
+    }
+
     fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError> {
+        Ok(self.snapshot(table)?.into_iter().collect())
+    }
+
+    // get_iter 是惰性的，不持有锁，所以遍历期间的写入可能会被看到。需要一致性时用 snapshot
+    fn get_iter(&self, table: &str) -> Result<Box<dyn Iterator<Item = Kvpair>>, KvError> {
         let prefix = SledDb::get_table_prefix(table);
-        let result = self.0.scan_prefix(prefix).map(|v| v.into()).collect();
+        // iterator 需要是 'static 的，所以 clone 一份 SledDb（内部都是 Arc）
+        let db = self.clone();
+        let table = table.to_owned();
+        let iter = self
+            .db
+            .scan_prefix(prefix)
+            .map(move |v| db.decode_pair(&table, v));
+        Ok(Box::new(iter))
+    }
 
-        Ok(result)
+    // 和 get_iter 一样是惰性的。只解码 key，value 不会被解密和解压
+    fn get_keys(&self, table: &str) -> Result<Box<dyn Iterator<Item = Bytes>>, KvError> {
+        let prefix = SledDb::get_table_prefix(table);
+        let db = self.clone();
+        let table = table.to_owned();
+        let iter = self.db.scan_prefix(prefix).keys().filter_map(move |k| {
+            let k = k.ok()?;
+            let (_, key) = split_full_key(&k).ok()?;
+            db.decode_key(&table, key).ok()
+        });
+        Ok(Box::new(iter))
     }
 
-    fn get_iter(&self, table: &str) -> Result<Box<dyn Iterator<Item = Kvpair>>, KvError> {
+    fn snapshot(&self, table: &str) -> Result<Snapshot, KvError> {
         let prefix = SledDb::get_table_prefix(table);
-        let iter = self.0.scan_prefix(prefix).map(|v| v.into());
+        let _guard = self.snapshot_guard(table);
+        self.db
+            .scan_prefix(prefix)
+            .map(|item| {
+                let (k, v) = item?;
+                let (_, key) = split_full_key(&k)?;
+                let key = self.decode_key(table, key)?;
+                let value = self.decode_value(table, &key, &v)?;
+                Ok((key, value))
+            })
+            .collect()
+    }
+
+    // 加密后的 key 不再保持原来的顺序，这时只能退回到基于快照的实现
+    fn get_range(
+        &self,
+        table: &str,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+    ) -> Result<Box<dyn Iterator<Item = Kvpair>>, KvError> {
+        if self.keyring.is_some() && self.encrypt_keys {
+            let pairs: Vec<_> = self.snapshot(table)?.range(start, end).collect();
+            return Ok(Box::new(pairs.into_iter()));
+        }
+
+        let start = match start {
+            Bound::Included(k) => Bound::Included(join_key(table, k)),
+            Bound::Excluded(k) => Bound::Excluded(join_key(table, k)),
+            Bound::Unbounded => Bound::Included(join_key(table, b"")),
+        };
+        // table 前缀之后的下一个 key，`;` 紧跟在 `:` 之后
+        let end = match end {
+            Bound::Included(k) => Bound::Included(join_key(table, k)),
+            Bound::Excluded(k) => Bound::Excluded(join_key(table, k)),
+            Bound::Unbounded => Bound::Excluded(format!("{};", table).into_bytes()),
+        };
+        // sled 的 range 在 start 大于 end 时会 panic
+        let valid = match (&start, &end) {
+            (Bound::Excluded(s), Bound::Excluded(e)) => s < e,
+            (Bound::Included(s) | Bound::Excluded(s), Bound::Included(e) | Bound::Excluded(e)) => {
+                s <= e
+            }
+            _ => true,
+        };
+        if !valid {
+            return Ok(Box::new(std::iter::empty()));
+        }
+
+        let db = self.clone();
+        let table = table.to_owned();
+        let iter = self
+            .db
+            .range((start, end))
+            .map(move |v| db.decode_pair(&table, v));
         Ok(Box::new(iter))
     }
-}
 
-impl From<Result<(IVec, IVec), sled::Error>> for Kvpair {
-    fn from(v: Result<(IVec, IVec), sled::Error>) -> Self {
-        match v {
-            Ok((k, v)) => match v.as_ref().try_into() {
-                Ok(v) => Kvpair::new(ivec_to_key(k.as_ref()), v),
-                Err(_) => Kvpair::default(),
-            },
-            _ => Kvpair::default(),
+    // 只读取几个 key 时用 sled 的事务，事务里的读取是一致的，也不会阻塞 table 的写入
+    fn snapshot_keys(&self, table: &str, keys: &[Bytes]) -> Result<Snapshot, KvError> {
+        let names = keys
+            .iter()
+            .map(|key| self.get_full_key(table, key))
+            .collect::<Result<Vec<_>, _>>()?;
+        let values: Result<Vec<_>, TransactionError<()>> = self.db.transaction(|tx| {
+            let mut values = Vec::with_capacity(names.len());
+            for name in &names {
+                values.push(tx.get(name)?);
+            }
+            Ok(values)
+        });
+        let values = match values {
+            Ok(values) => values,
+            Err(TransactionError::Storage(e)) => return Err(e.into()),
+            Err(TransactionError::Abort(())) => unreachable!("snapshot never aborts"),
+        };
+
+        let mut result = vec![];
+        for (key, value) in keys.iter().zip(values) {
+            if let Some(v) = value {
+                result.push((key.clone(), self.decode_value(table, key, &v)?));
+            }
         }
+        Ok(result.into_iter().collect())
     }
 }
 
-fn ivec_to_key(ivec: &[u8]) -> &str {
-    let s = str::from_utf8(ivec).unwrap();
-    let mut iter = s.split(':');
-    iter.next();
-    iter.next().unwrap()
+fn join_key(table: &str, key: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(table.len() + 1 + key.len());
+    buf.extend_from_slice(table.as_bytes());
+    buf.push(b':');
+    buf.extend_from_slice(key);
+    buf
+}
+
+// 把 sled 里的 key 拆成 table 和 key 两部分。table 名里不能有 `:`，key 里可以有
+fn split_full_key(ivec: &[u8]) -> Result<(&str, &[u8]), KvError> {
+    let pos = ivec
+        .iter()
+        .position(|b| *b == b':')
+        .ok_or_else(|| KvError::Internal("Invalid key in sled".into()))?;
+    let table = str::from_utf8(&ivec[..pos])
+        .map_err(|_| KvError::Internal("Invalid key in sled".into()))?;
+    Ok((table, &ivec[pos + 1..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    const KEY1: &str = "1:0000000000000000000000000000000000000000000000000000000000000001";
+    const KEY2: &str = "2:0000000000000000000000000000000000000000000000000000000000000002";
+
+    #[test]
+    fn encrypted_value_should_not_be_plaintext_on_disk() {
+        let dir = tempdir().unwrap();
+        let keyring = Keyring::parse(KEY1).unwrap();
+        let store = SledDb::new(dir).with_encryption(keyring, true);
+        store.set("t1", "hello".into(), "world".into()).unwrap();
+
+        let (k, v) = store.db.iter().next().unwrap().unwrap();
+        assert!(k.starts_with(b"t1:"));
+        assert!(!k.ends_with(b"hello"));
+        assert!(!v.windows(5).any(|w| w == b"world"));
+
+        assert_eq!(store.get("t1", b"hello").unwrap(), Some("world".into()));
+        assert_eq!(
+            store.get_all("t1").unwrap(),
+            vec![Kvpair::new("hello", "world".into())]
+        );
+    }
+
+    #[test]
+    fn rotate_should_reencrypt_old_and_plaintext_values() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().to_owned();
+        // 开启加密前写入的明文数据
+        let store = SledDb::new(&path);
+        store.set("t1", "k1".into(), "v1".into()).unwrap();
+        drop(store);
+
+        let store = SledDb::new(&path).with_encryption(Keyring::parse(KEY1).unwrap(), false);
+        store.set("t1", "k2".into(), "v2".into()).unwrap();
+        assert_eq!(store.get("t1", b"k1").unwrap(), Some("v1".into()));
+        drop(store);
+
+        let keys = format!("{}\n{}", KEY1, KEY2);
+        let store = SledDb::new(&path).with_encryption(Keyring::parse(&keys).unwrap(), false);
+        assert_eq!(store.rotate().unwrap(), 2);
+        assert_eq!(store.rotate().unwrap(), 0);
+        drop(store);
+
+        // 轮换完成后，只有新密钥也能读出所有数据
+        let store = SledDb::new(&path).with_encryption(Keyring::parse(KEY2).unwrap(), false);
+        assert_eq!(store.get("t1", b"k1").unwrap(), Some("v1".into()));
+        assert_eq!(store.get("t1", b"k2").unwrap(), Some("v2".into()));
+    }
+
+    #[test]
+    fn snapshot_should_only_block_its_own_table() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir);
+        let _guard = store.snapshot_guard("t1");
+
+        // 其它分片里的 table 可以正常写入
+        let lock = store.snapshot_lock("t1");
+        let other = (2..)
+            .map(|i| format!("t{}", i))
+            .find(|t| !std::ptr::eq(store.snapshot_lock(t), lock))
+            .unwrap();
+        store.set(&other, "k1".into(), "v1".into()).unwrap();
+        // 读几个 key 的快照不需要快照锁
+        let keys = vec![Bytes::from("k1")];
+        assert!(store.snapshot_keys("t1", &keys).unwrap().is_empty());
+        assert_eq!(store.snapshot_keys(&other, &keys).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn rotate_should_encrypt_plaintext_keys() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().to_owned();
+        // 只加密了 value，key 还是明文
+        let store = SledDb::new(&path).with_encryption(Keyring::parse(KEY1).unwrap(), false);
+        store.set("t1", "k1".into(), "v1".into()).unwrap();
+        drop(store);
+
+        // 之后开启 key 加密，明文的 key 在轮换前读不到
+        let keys = format!("{}\n{}", KEY1, KEY2);
+        let store = SledDb::new(&path).with_encryption(Keyring::parse(&keys).unwrap(), true);
+        store.set("t1", "k2".into(), "v2".into()).unwrap();
+        assert_eq!(store.get("t1", b"k1").unwrap(), None);
+
+        assert_eq!(store.rotate().unwrap(), 1);
+        assert_eq!(store.rotate().unwrap(), 0);
+        assert!(store.db.get("t1:k1").unwrap().is_none());
+        assert_eq!(store.get("t1", b"k1").unwrap(), Some("v1".into()));
+        assert_eq!(store.get("t1", b"k2").unwrap(), Some("v2".into()));
+        assert_eq!(store.get_all("t1").unwrap().len(), 2);
+    }
+
+    #[test]
+    fn compressed_value_should_be_tagged_and_read_back() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().to_owned();
+        let big: Value = "hello ".repeat(1000).into();
+
+        // 开启压缩前写入的数据
+        let store = SledDb::new(&path);
+        store.set("t1", "old".into(), big.clone()).unwrap();
+        drop(store);
+
+        let store = SledDb::new(&path).with_compression("t1", ValueCompression::lz4(1024));
+        store.set("t1", "new".into(), big.clone()).unwrap();
+        store.set("t1", "small".into(), "hello".into()).unwrap();
+        store.set("t2", "new".into(), big.clone()).unwrap();
+
+        let raw = store.db.get("t1:new").unwrap().unwrap();
+        assert_eq!(
+            raw[..3],
+            [ENVELOPE_TAG, COMPRESSED, CompressionAlgorithm::Lz4 as u8]
+        );
+        assert!(raw.len() < 1000);
+        // 小于 threshold 的 value 和没有配置压缩的 table 不压缩
+        assert_ne!(store.db.get("t1:small").unwrap().unwrap()[0], ENVELOPE_TAG);
+        assert_ne!(store.db.get("t2:new").unwrap().unwrap()[0], ENVELOPE_TAG);
+
+        assert_eq!(store.get("t1", b"old").unwrap(), Some(big.clone()));
+        assert_eq!(store.get("t1", b"new").unwrap(), Some(big.clone()));
+        assert_eq!(store.get_all("t1").unwrap().len(), 3);
+        drop(store);
+
+        // 关掉压缩后，之前压缩过的数据仍然可以读
+        let store = SledDb::new(&path);
+        assert_eq!(store.get("t1", b"new").unwrap(), Some(big));
+    }
+
+    #[test]
+    fn compressed_and_encrypted_value_should_work() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir)
+            .with_compression("t1", ValueCompression::zstd(128))
+            .with_encryption(Keyring::parse(KEY1).unwrap(), false);
+        let big: Value = "hello ".repeat(1000).into();
+        store.set("t1", "k1".into(), big.clone()).unwrap();
+
+        // 先压缩再加密，所以密文也很小
+        let raw = store.db.get("t1:k1").unwrap().unwrap();
+        assert_eq!(raw[..2], [ENVELOPE_TAG, ENCRYPTED]);
+        assert!(raw.len() < 1000);
+        assert_eq!(store.get("t1", b"k1").unwrap(), Some(big));
+    }
+
+    #[test]
+    fn encrypted_value_without_keyring_should_fail() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().to_owned();
+        let store = SledDb::new(&path).with_encryption(Keyring::parse(KEY1).unwrap(), false);
+        store.set("t1", "k1".into(), "v1".into()).unwrap();
+        drop(store);
+
+        let store = SledDb::new(&path);
+        assert!(store.get("t1", b"k1").is_err());
+    }
 }
@@ -0,0 +1,74 @@
+use bytes::Bytes;
+use std::{
+    collections::{btree_map, BTreeMap},
+    ops::Bound,
+};
+
+use crate::{Kvpair, Value};
+
+// table 在某一时刻的一致性快照，按 key 排序
+// 快照是一份拷贝，拿到之后 table 的修改不会影响它
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Snapshot {
+    data: BTreeMap<Bytes, Value>,
+}
+
+impl Snapshot {
+    pub fn get(&self, key: &[u8]) -> Option<&Value> {
+        self.data.get(key)
+    }
+
+    pub fn contains(&self, key: &[u8]) -> bool {
+        self.data.contains_key(key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Bytes, &Value)> {
+        self.data.iter()
+    }
+
+    // 按 key 的顺序返回 start 和 end 之间的 kv pair
+    pub fn range<'a>(
+        &'a self,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+    ) -> impl Iterator<Item = Kvpair> + 'a {
+        // BTreeMap::range 在 start 大于 end 时会 panic，这种情况直接返回空
+        let valid = match (start, end) {
+            (Bound::Excluded(s), Bound::Excluded(e)) => s < e,
+            (Bound::Included(s) | Bound::Excluded(s), Bound::Included(e) | Bound::Excluded(e)) => {
+                s <= e
+            }
+            _ => true,
+        };
+        valid
+            .then(|| self.data.range::<[u8], _>((start, end)))
+            .into_iter()
+            .flatten()
+            .map(|(k, v)| Kvpair::new(k.clone(), v.clone()))
+    }
+}
+
+impl FromIterator<(Bytes, Value)> for Snapshot {
+    fn from_iter<T: IntoIterator<Item = (Bytes, Value)>>(iter: T) -> Self {
+        Self {
+            data: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl IntoIterator for Snapshot {
+    type Item = Kvpair;
+    type IntoIter = std::iter::Map<btree_map::IntoIter<Bytes, Value>, fn((Bytes, Value)) -> Kvpair>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.data.into_iter().map(Kvpair::from)
+    }
+}
@@ -0,0 +1,579 @@
+use std::{
+    fmt,
+    ops::Bound,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use super::{get_message, lock_row, message_to_value, update_message, value_to_message};
+use crate::{KvError, Kvpair, PendingEntry, Storage, StreamEntry, StreamGroup, StreamMeta};
+
+// 流的消息存放在 __stream__.{table} 里，key 是 "流的 key 的长度 | 流的 key | 消息 id"，
+// 消息 id 用大端编码，所以同一个流的消息在有序的后端（比如 sled）里是按 id 排好序的，
+// 按范围读取时不需要扫描整个 table
+fn entry_table(table: &str) -> String {
+    format!("__stream__.{}", table)
+}
+
+// 流的元数据（最后的 id、长度）存放在 __stream_meta__.{table} 里，key 就是流的 key
+fn meta_table(table: &str) -> String {
+    format!("__stream_meta__.{}", table)
+}
+
+// 消费组存放在 __stream_group__.{table} 里，key 是 "流的 key 的长度 | 流的 key | 组名"
+fn group_table(table: &str) -> String {
+    format!("__stream_group__.{}", table)
+}
+
+// 同一个 key 的数据有相同的前缀，并且一个 key 不会是另一个 key 的前缀
+pub(super) fn prefixed(key: &[u8], suffix: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(4 + key.len() + suffix.len());
+    buf.extend_from_slice(&(key.len() as u32).to_be_bytes());
+    buf.extend_from_slice(key);
+    buf.extend_from_slice(suffix);
+    buf
+}
+
+fn entry_key(key: &[u8], id: StreamId) -> Vec<u8> {
+    let mut suffix = [0u8; 16];
+    suffix[..8].copy_from_slice(&id.ms.to_be_bytes());
+    suffix[8..].copy_from_slice(&id.seq.to_be_bytes());
+    prefixed(key, &suffix)
+}
+
+fn group_key(key: &[u8], group: &str) -> Vec<u8> {
+    prefixed(key, group.as_bytes())
+}
+
+pub(super) fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+// 消息 id，由毫秒时间戳和同一毫秒内的序号组成
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl StreamId {
+    pub const MIN: StreamId = StreamId { ms: 0, seq: 0 };
+    pub const MAX: StreamId = StreamId {
+        ms: u64::MAX,
+        seq: u64::MAX,
+    };
+
+    pub fn new(ms: u64, seq: u64) -> Self {
+        Self { ms, seq }
+    }
+
+    // 范围的起点："-" 表示最小的 id，只有时间戳时序号取 0
+    pub fn parse_start(s: &str) -> Result<Self, KvError> {
+        match s {
+            "-" => Ok(Self::MIN),
+            _ => s.parse(),
+        }
+    }
+
+    // 范围的终点："+" 表示最大的 id，只有时间戳时包含这一毫秒内的所有消息
+    pub fn parse_end(s: &str) -> Result<Self, KvError> {
+        match s {
+            "+" => Ok(Self::MAX),
+            _ if !s.contains('-') => Ok(Self::new(parse_u64(s, s)?, u64::MAX)),
+            _ => s.parse(),
+        }
+    }
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+impl FromStr for StreamId {
+    type Err = KvError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('-') {
+            Some((ms, seq)) => Ok(Self::new(parse_u64(ms, s)?, parse_u64(seq, s)?)),
+            None => Ok(Self::new(parse_u64(s, s)?, 0)),
+        }
+    }
+}
+
+fn parse_u64(s: &str, id: &str) -> Result<u64, KvError> {
+    s.parse()
+        .map_err(|_| KvError::InvalidCommand(format!("Invalid stream id: {}", id)))
+}
+
+// 在 Storage 的基础上提供流的能力，所有实现了 Storage 的结构都自动获得这些方法
+// 同一个流的 stream_add 用 row lock 串行起来，并发的 stream_add 会得到不同的、递增的 id。
+// 消息按 id 的顺序写入，读到一条消息时，id 比它小的消息都已经写入了
+pub trait StreamStorage: Storage {
+    // 追加一条消息，id 为 None 时自动生成，返回消息的 id
+    fn stream_add(
+        &self,
+        table: &str,
+        key: &[u8],
+        id: Option<StreamId>,
+        fields: Vec<Kvpair>,
+    ) -> Result<StreamId, KvError> {
+        let now = now_ms();
+        let _guard = lock_row(&meta_table(table), key);
+        let last = self.stream_last_id(table, key)?;
+        let id = match id {
+            Some(id) if id > last => id,
+            Some(id) => {
+                return Err(KvError::InvalidCommand(format!(
+                    "Stream id {} is not greater than the last id {}",
+                    id, last
+                )))
+            }
+            None if now > last.ms => StreamId::new(now, 0),
+            None => match last.seq.checked_add(1) {
+                Some(seq) => StreamId::new(last.ms, seq),
+                None => {
+                    return Err(KvError::InvalidCommand(format!(
+                        "Cannot generate a stream id after {}",
+                        last
+                    )))
+                }
+            },
+        };
+
+        // 先写消息再更新元数据，这样元数据里最后的 id 对应的消息总是已经存在
+        let entry = StreamEntry {
+            id: id.to_string(),
+            fields,
+        };
+        self.set(
+            &entry_table(table),
+            entry_key(key, id).into(),
+            message_to_value(&entry),
+        )?;
+        update_message(self, &meta_table(table), key, |meta: &mut StreamMeta| {
+            meta.last_ms = id.ms;
+            meta.last_seq = id.seq;
+            meta.length += 1;
+        })?;
+        Ok(id)
+    }
+
+    // 获取 id 在 start 和 end 之间的消息，count 为 0 时不限制个数
+    fn stream_range(
+        &self,
+        table: &str,
+        key: &[u8],
+        start: Bound<StreamId>,
+        end: Bound<StreamId>,
+        count: usize,
+    ) -> Result<Vec<StreamEntry>, KvError> {
+        let count = if count == 0 { usize::MAX } else { count };
+        scan(self, table, key, start, end)?
+            .take(count)
+            .map(|item| item.map(|(_, entry)| entry))
+            .collect()
+    }
+
+    fn stream_len(&self, table: &str, key: &[u8]) -> Result<u64, KvError> {
+        let meta: StreamMeta = get_message(self, &meta_table(table), key)?;
+        Ok(meta.length)
+    }
+
+    // 流里最后一条消息的 id，流不存在时是 0-0
+    fn stream_last_id(&self, table: &str, key: &[u8]) -> Result<StreamId, KvError> {
+        let meta: StreamMeta = get_message(self, &meta_table(table), key)?;
+        Ok(StreamId::new(meta.last_ms, meta.last_seq))
+    }
+
+    // 删除最早的消息，直到长度不超过 max_len 并且没有早于 max_age_ms 毫秒之前的消息
+    // 参数为 0 表示不按这个条件裁剪，返回删除的消息个数
+    fn stream_trim(
+        &self,
+        table: &str,
+        key: &[u8],
+        max_len: u64,
+        max_age_ms: u64,
+    ) -> Result<usize, KvError> {
+        let mut ids = vec![];
+        if max_len > 0 {
+            let excess = self.stream_len(table, key)?.saturating_sub(max_len);
+            for item in
+                scan(self, table, key, Bound::Unbounded, Bound::Unbounded)?.take(excess as usize)
+            {
+                ids.push(item?.0);
+            }
+        }
+        if max_age_ms > 0 {
+            let deadline = StreamId::new(now_ms().saturating_sub(max_age_ms), 0);
+            for item in scan(
+                self,
+                table,
+                key,
+                Bound::Unbounded,
+                Bound::Excluded(deadline),
+            )? {
+                ids.push(item?.0);
+            }
+        }
+        ids.sort();
+        ids.dedup();
+
+        // 并发裁剪时同一条消息只会被一方删除成功，长度只按实际删除的个数减少
+        let mut removed = 0;
+        for id in ids {
+            if self
+                .del(&entry_table(table), &entry_key(key, id))?
+                .is_some()
+            {
+                removed += 1;
+            }
+        }
+        if removed > 0 {
+            update_message(self, &meta_table(table), key, |meta: &mut StreamMeta| {
+                meta.length = meta.length.saturating_sub(removed as u64);
+            })?;
+        }
+        Ok(removed)
+    }
+
+    // 创建消费组，从 id 大于 start 的消息开始投递，start 为 None 时只投递之后新追加的消息
+    fn group_create(
+        &self,
+        table: &str,
+        key: &[u8],
+        group: &str,
+        start: Option<StreamId>,
+    ) -> Result<(), KvError> {
+        let start = match start {
+            Some(id) => id,
+            None => self.stream_last_id(table, key)?,
+        };
+        update_message(
+            self,
+            &group_table(table),
+            &group_key(key, group),
+            |g: &mut StreamGroup| {
+                if *g != StreamGroup::default() {
+                    return Err(KvError::InvalidCommand(format!(
+                        "Consumer group {} already exists",
+                        group
+                    )));
+                }
+                g.last_delivered = start.to_string();
+                Ok(())
+            },
+        )?
+    }
+
+    // 以 consumer 的身份读取最多 count 条还没有投递过的消息，并记为待确认
+    fn group_read(
+        &self,
+        table: &str,
+        key: &[u8],
+        group: &str,
+        consumer: &str,
+        count: usize,
+    ) -> Result<Vec<StreamEntry>, KvError> {
+        let count = if count == 0 { usize::MAX } else { count };
+        let gkey = group_key(key, group);
+        loop {
+            let state: StreamGroup = get_message(self, &group_table(table), &gkey)?;
+            if state == StreamGroup::default() {
+                return Err(KvError::InvalidCommand(format!(
+                    "Consumer group {} does not exist",
+                    group
+                )));
+            }
+
+            let last: StreamId = state.last_delivered.parse()?;
+            let entries = scan(self, table, key, Bound::Excluded(last), Bound::Unbounded)?
+                .take(count)
+                .collect::<Result<Vec<_>, _>>()?;
+            let (new_last, _) = match entries.last() {
+                Some(v) => v,
+                None => return Ok(vec![]),
+            };
+
+            // 只有在这期间没有别的消费者读过这个组时才提交，否则重新读
+            let now = now_ms();
+            let committed =
+                update_message(self, &group_table(table), &gkey, |g: &mut StreamGroup| {
+                    if g.last_delivered != state.last_delivered {
+                        return false;
+                    }
+                    g.last_delivered = new_last.to_string();
+                    g.pending
+                        .extend(entries.iter().map(|(_, entry)| PendingEntry {
+                            id: entry.id.clone(),
+                            consumer: consumer.into(),
+                            delivered_at: now,
+                            delivery_count: 1,
+                        }));
+                    true
+                })?;
+            if committed {
+                return Ok(entries.into_iter().map(|(_, entry)| entry).collect());
+            }
+        }
+    }
+
+    // 确认一组消息已经处理完，返回确认的个数
+    fn group_ack(
+        &self,
+        table: &str,
+        key: &[u8],
+        group: &str,
+        ids: &[StreamId],
+    ) -> Result<usize, KvError> {
+        let ids: Vec<_> = ids.iter().map(|id| id.to_string()).collect();
+        update_message(
+            self,
+            &group_table(table),
+            &group_key(key, group),
+            |g: &mut StreamGroup| {
+                let count = g.pending.len();
+                g.pending.retain(|p| !ids.contains(&p.id));
+                count - g.pending.len()
+            },
+        )
+    }
+
+    // 获取消费组里所有待确认的消息
+    fn group_pending(
+        &self,
+        table: &str,
+        key: &[u8],
+        group: &str,
+    ) -> Result<Vec<PendingEntry>, KvError> {
+        let g: StreamGroup = get_message(self, &group_table(table), &group_key(key, group))?;
+        Ok(g.pending)
+    }
+}
+
+impl<T: Storage + ?Sized> StreamStorage for T {}
+
+// 按 id 的顺序遍历流里在 start 和 end 之间的消息
+fn scan<S: Storage + ?Sized>(
+    store: &S,
+    table: &str,
+    key: &[u8],
+    start: Bound<StreamId>,
+    end: Bound<StreamId>,
+) -> Result<impl Iterator<Item = Result<(StreamId, StreamEntry), KvError>>, KvError> {
+    // 没有边界时也要限制在这个流的范围内，不能读到别的流
+    let start = match start {
+        Bound::Unbounded => Bound::Included(entry_key(key, StreamId::MIN)),
+        bound => bound.map(|id| entry_key(key, id)),
+    };
+    let end = match end {
+        Bound::Unbounded => Bound::Included(entry_key(key, StreamId::MAX)),
+        bound => bound.map(|id| entry_key(key, id)),
+    };
+
+    let iter = store.get_range(
+        &entry_table(table),
+        start.as_ref().map(|k| k.as_slice()),
+        end.as_ref().map(|k| k.as_slice()),
+    )?;
+    Ok(iter.map(|pair| {
+        let entry: StreamEntry = value_to_message(&pair.value.unwrap_or_default())?;
+        Ok((entry.id.parse()?, entry))
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MemTable, SledDb};
+    use std::{sync::Arc, thread};
+    use tempfile::tempdir;
+
+    #[test]
+    fn memtable_stream_should_work() {
+        test_stream(MemTable::new());
+    }
+
+    #[test]
+    fn sleddb_stream_should_work() {
+        let dir = tempdir().unwrap();
+        test_stream(SledDb::new(dir));
+    }
+
+    #[test]
+    fn memtable_consumer_group_should_work() {
+        test_consumer_group(MemTable::new());
+    }
+
+    #[test]
+    fn sleddb_consumer_group_should_work() {
+        let dir = tempdir().unwrap();
+        test_consumer_group(SledDb::new(dir));
+    }
+
+    fn add(store: &impl Storage, key: &[u8], id: u64, n: i64) -> StreamId {
+        let fields = vec![Kvpair::new("n", n.into())];
+        store
+            .stream_add("t1", key, Some(StreamId::new(id, 0)), fields)
+            .unwrap()
+    }
+
+    fn ids(entries: &[StreamEntry]) -> Vec<&str> {
+        entries.iter().map(|e| e.id.as_str()).collect()
+    }
+
+    fn test_stream(store: impl Storage) {
+        for i in 1..=5 {
+            add(&store, b"s1", i, i as i64);
+        }
+        // 另一个流不会影响 s1 的读取
+        add(&store, b"s", 3, 0);
+        add(&store, b"s10", 3, 0);
+
+        // id 必须递增
+        let res = store.stream_add("t1", b"s1", Some(StreamId::new(5, 0)), vec![]);
+        assert!(res.is_err());
+        // 自动生成的 id 比之前的都大
+        let auto = store.stream_add("t1", b"s1", None, vec![]).unwrap();
+        assert!(auto > StreamId::new(5, 0));
+        assert_eq!(store.stream_len("t1", b"s1").unwrap(), 6);
+        assert_eq!(store.stream_last_id("t1", b"s1").unwrap(), auto);
+
+        let all = store
+            .stream_range("t1", b"s1", Bound::Unbounded, Bound::Unbounded, 0)
+            .unwrap();
+        assert_eq!(all.len(), 6);
+        assert_eq!(all[0].fields, vec![Kvpair::new("n", 1.into())]);
+
+        let start = Bound::Included(StreamId::new(2, 0));
+        let end = Bound::Included(StreamId::new(4, 0));
+        let res = store.stream_range("t1", b"s1", start, end, 2).unwrap();
+        assert_eq!(ids(&res), vec!["2-0", "3-0"]);
+
+        let after = Bound::Excluded(StreamId::new(4, 0));
+        let res = store
+            .stream_range("t1", b"s1", after, Bound::Unbounded, 0)
+            .unwrap();
+        assert_eq!(res.len(), 2);
+
+        // 按长度裁剪
+        assert_eq!(store.stream_trim("t1", b"s1", 4, 0).unwrap(), 2);
+        assert_eq!(store.stream_len("t1", b"s1").unwrap(), 4);
+        let res = store
+            .stream_range("t1", b"s1", Bound::Unbounded, Bound::Unbounded, 1)
+            .unwrap();
+        assert_eq!(ids(&res), vec!["3-0"]);
+
+        // 按时间裁剪，手动指定的 id 都是很久以前的
+        assert_eq!(store.stream_trim("t1", b"s1", 0, 60_000).unwrap(), 3);
+        assert_eq!(store.stream_len("t1", b"s1").unwrap(), 1);
+        // 裁剪之后 id 仍然递增
+        assert_eq!(store.stream_last_id("t1", b"s1").unwrap(), auto);
+    }
+
+    #[test]
+    fn concurrent_add_should_not_skip_entries() {
+        let store = Arc::new(MemTable::new());
+        let writers: Vec<_> = (0..4)
+            .map(|_| {
+                let store = store.clone();
+                thread::spawn(move || {
+                    for _ in 0..200 {
+                        store.stream_add("t1", b"s1", None, vec![]).unwrap();
+                    }
+                })
+            })
+            .collect();
+
+        // 像 Xread 一样每次从上次读到的最后一个 id 之后开始读，不能漏掉任何消息
+        let mut last = StreamId::MIN;
+        let mut seen = 0;
+        while seen < 800 {
+            let after = Bound::Excluded(last);
+            let entries = store
+                .stream_range("t1", b"s1", after, Bound::Unbounded, 0)
+                .unwrap();
+            if let Some(entry) = entries.last() {
+                last = entry.id.parse().unwrap();
+            }
+            seen += entries.len();
+        }
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        assert_eq!(seen, 800);
+    }
+
+    #[test]
+    fn stream_id_should_not_overflow() {
+        let store = MemTable::new();
+        store
+            .stream_add("t1", b"s1", Some(StreamId::MAX), vec![])
+            .unwrap();
+        let res = store.stream_add("t1", b"s1", None, vec![]);
+        assert!(matches!(res, Err(KvError::InvalidCommand(_))));
+        assert_eq!(store.stream_len("t1", b"s1").unwrap(), 1);
+    }
+
+    fn test_consumer_group(store: impl Storage) {
+        for i in 1..=3 {
+            add(&store, b"s1", i, i as i64);
+        }
+        store
+            .group_create("t1", b"s1", "g1", Some(StreamId::MIN))
+            .unwrap();
+        assert!(store.group_create("t1", b"s1", "g1", None).is_err());
+        assert!(store.group_read("t1", b"s1", "none", "c1", 1).is_err());
+
+        let res = store.group_read("t1", b"s1", "g1", "c1", 2).unwrap();
+        assert_eq!(ids(&res), vec!["1-0", "2-0"]);
+        let res = store.group_read("t1", b"s1", "g1", "c2", 0).unwrap();
+        assert_eq!(ids(&res), vec!["3-0"]);
+        assert!(store
+            .group_read("t1", b"s1", "g1", "c1", 0)
+            .unwrap()
+            .is_empty());
+
+        let pending = store.group_pending("t1", b"s1", "g1").unwrap();
+        let consumers: Vec<_> = pending.iter().map(|p| p.consumer.as_str()).collect();
+        assert_eq!(consumers, vec!["c1", "c1", "c2"]);
+
+        let acked = [
+            StreamId::new(1, 0),
+            StreamId::new(3, 0),
+            StreamId::new(9, 0),
+        ];
+        assert_eq!(store.group_ack("t1", b"s1", "g1", &acked).unwrap(), 2);
+        let pending = store.group_pending("t1", b"s1", "g1").unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].id, "2-0");
+
+        // "$" 创建的组只会读到之后的消息
+        store.group_create("t1", b"s1", "g2", None).unwrap();
+        assert!(store
+            .group_read("t1", b"s1", "g2", "c1", 0)
+            .unwrap()
+            .is_empty());
+        add(&store, b"s1", 10, 10);
+        let res = store.group_read("t1", b"s1", "g2", "c1", 0).unwrap();
+        assert_eq!(ids(&res), vec!["10-0"]);
+    }
+
+    #[test]
+    fn stream_id_parse_should_work() {
+        assert_eq!("5-1".parse::<StreamId>().unwrap(), StreamId::new(5, 1));
+        assert_eq!("5".parse::<StreamId>().unwrap(), StreamId::new(5, 0));
+        assert_eq!(StreamId::parse_start("-").unwrap(), StreamId::MIN);
+        assert_eq!(StreamId::parse_end("+").unwrap(), StreamId::MAX);
+        assert_eq!(
+            StreamId::parse_end("5").unwrap(),
+            StreamId::new(5, u64::MAX)
+        );
+        assert!("a-1".parse::<StreamId>().is_err());
+        assert_eq!(StreamId::new(5, 1).to_string(), "5-1");
+    }
+}
@@ -0,0 +1,406 @@
+use std::{ops::Bound, str::FromStr};
+
+use super::{
+    get_message,
+    stream::{now_ms, prefixed},
+    update_message,
+};
+use crate::{value, DownsampleRule, KvError, Sample, Storage, TimeSeriesMeta};
+
+// 采样点存放在 __ts__.{table} 里，key 是 "序列的 key 的长度 | 序列的 key | 时间戳"，
+// 时间戳用大端编码，value 是 Float。同一个序列的采样点在有序的后端里按时间排好序
+fn sample_table(table: &str) -> String {
+    format!("__ts__.{}", table)
+}
+
+// 序列的元数据（保留时长、降采样规则）存放在 __ts_meta__.{table} 里，key 就是序列的 key
+fn meta_table(table: &str) -> String {
+    format!("__ts_meta__.{}", table)
+}
+
+fn sample_key(key: &[u8], timestamp: u64) -> Vec<u8> {
+    prefixed(key, &timestamp.to_be_bytes())
+}
+
+// 时间桶的聚合方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aggregation {
+    Avg,
+    Min,
+    Max,
+    Sum,
+    Count,
+}
+
+impl Aggregation {
+    // 聚合一个时间桶里的值，values 不能为空
+    pub fn apply(&self, values: &[f64]) -> f64 {
+        match self {
+            Aggregation::Avg => values.iter().sum::<f64>() / values.len() as f64,
+            Aggregation::Min => values.iter().copied().fold(f64::INFINITY, f64::min),
+            Aggregation::Max => values.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+            Aggregation::Sum => values.iter().sum(),
+            Aggregation::Count => values.len() as f64,
+        }
+    }
+}
+
+impl FromStr for Aggregation {
+    type Err = KvError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "avg" => Ok(Aggregation::Avg),
+            "min" => Ok(Aggregation::Min),
+            "max" => Ok(Aggregation::Max),
+            "sum" => Ok(Aggregation::Sum),
+            "count" => Ok(Aggregation::Count),
+            _ => Err(KvError::InvalidCommand(format!(
+                "Invalid aggregation: {}",
+                s
+            ))),
+        }
+    }
+}
+
+// 在 Storage 的基础上提供时间序列，所有实现了 Storage 的结构都自动获得这些方法
+pub trait TimeSeriesStorage: Storage {
+    // 写入一个采样点，按序列的保留时长删除过期的数据，并更新所有降采样的目标序列
+    // timestamp 为 0 时使用当前时间，返回采样点的时间戳
+    // 降采样写入的目标序列会按它自己的保留时长裁剪，但不会再触发它自己的降采样规则
+    fn ts_add(&self, table: &str, key: &[u8], timestamp: u64, value: f64) -> Result<u64, KvError> {
+        let timestamp = if timestamp == 0 { now_ms() } else { timestamp };
+        let meta = add_sample(self, table, key, timestamp, value)?;
+        for rule in &meta.rules {
+            let aggregation: Aggregation = rule.aggregation.parse()?;
+            let start = timestamp - timestamp % rule.bucket_ms;
+            let end = start.saturating_add(rule.bucket_ms - 1);
+            let values: Vec<f64> = scan(self, table, key, start, end)?
+                .map(|s| s.value)
+                .collect();
+            if !values.is_empty() {
+                let v = aggregation.apply(&values);
+                add_sample(self, &rule.dest_table, &rule.dest_key, start, v)?;
+            }
+        }
+        Ok(timestamp)
+    }
+
+    // 获取时间戳在 [from, to] 之间的采样点
+    // aggregation 不为 None 时按 bucket_ms 毫秒的时间桶聚合，时间戳是时间桶的起点，空的时间桶不返回
+    fn ts_range(
+        &self,
+        table: &str,
+        key: &[u8],
+        from: u64,
+        to: u64,
+        aggregation: Option<(Aggregation, u64)>,
+    ) -> Result<Vec<Sample>, KvError> {
+        let samples = scan(self, table, key, from, to)?;
+        let (aggregation, bucket_ms) = match aggregation {
+            Some((_, 0)) => {
+                return Err(KvError::InvalidCommand(
+                    "Bucket size must be greater than 0".into(),
+                ))
+            }
+            Some(v) => v,
+            None => return Ok(samples.collect()),
+        };
+
+        let mut result = vec![];
+        let mut bucket: Option<(u64, Vec<f64>)> = None;
+        for sample in samples {
+            let start = sample.timestamp - sample.timestamp % bucket_ms;
+            match &mut bucket {
+                Some((ts, values)) if *ts == start => values.push(sample.value),
+                _ => {
+                    if let Some((ts, values)) = bucket.replace((start, vec![sample.value])) {
+                        result.push(Sample::new(ts, aggregation.apply(&values)));
+                    }
+                }
+            }
+        }
+        if let Some((ts, values)) = bucket {
+            result.push(Sample::new(ts, aggregation.apply(&values)));
+        }
+        Ok(result)
+    }
+
+    // 设置序列的保留时长，0 表示一直保留。已经过期的数据在下一次写入时删除
+    fn ts_set_retention(&self, table: &str, key: &[u8], retention_ms: u64) -> Result<(), KvError> {
+        update_message(
+            self,
+            &meta_table(table),
+            key,
+            |meta: &mut TimeSeriesMeta| {
+                meta.retention_ms = retention_ms;
+            },
+        )
+    }
+
+    // 添加降采样规则，目标序列相同的规则会被替换
+    fn ts_create_rule(&self, table: &str, key: &[u8], rule: DownsampleRule) -> Result<(), KvError> {
+        rule.aggregation.parse::<Aggregation>()?;
+        if rule.bucket_ms == 0 {
+            return Err(KvError::InvalidCommand(
+                "Bucket size must be greater than 0".into(),
+            ));
+        }
+        if rule.dest_table == table && rule.dest_key == key {
+            return Err(KvError::InvalidCommand(
+                "Cannot downsample a series into itself".into(),
+            ));
+        }
+
+        update_message(
+            self,
+            &meta_table(table),
+            key,
+            |meta: &mut TimeSeriesMeta| {
+                meta.rules
+                    .retain(|r| r.dest_table != rule.dest_table || r.dest_key != rule.dest_key);
+                meta.rules.push(rule.clone());
+            },
+        )
+    }
+
+    // 删除写入 dest_table 里 dest_key 的降采样规则，返回删除的个数
+    fn ts_delete_rule(
+        &self,
+        table: &str,
+        key: &[u8],
+        dest_table: &str,
+        dest_key: &[u8],
+    ) -> Result<usize, KvError> {
+        update_message(
+            self,
+            &meta_table(table),
+            key,
+            |meta: &mut TimeSeriesMeta| {
+                let count = meta.rules.len();
+                meta.rules
+                    .retain(|r| r.dest_table != dest_table || r.dest_key != dest_key);
+                count - meta.rules.len()
+            },
+        )
+    }
+
+    fn ts_rules(&self, table: &str, key: &[u8]) -> Result<Vec<DownsampleRule>, KvError> {
+        let meta: TimeSeriesMeta = get_message(self, &meta_table(table), key)?;
+        Ok(meta.rules)
+    }
+}
+
+impl<T: Storage + ?Sized> TimeSeriesStorage for T {}
+
+// 保留时长从序列里最大的时间戳开始算，但不会超过当前时间，
+// 这样一个时间戳在未来的采样点不会让整个序列的数据都过期
+fn retention_start(meta: &TimeSeriesMeta) -> u64 {
+    if meta.retention_ms == 0 {
+        return 0;
+    }
+    meta.max_timestamp
+        .min(now_ms())
+        .saturating_sub(meta.retention_ms)
+}
+
+// 写入一个采样点并按保留时长裁剪，返回序列的元数据。已经过期的采样点不会写入
+fn add_sample<S: Storage + ?Sized>(
+    store: &S,
+    table: &str,
+    key: &[u8],
+    timestamp: u64,
+    value: f64,
+) -> Result<TimeSeriesMeta, KvError> {
+    let meta = update_message(
+        store,
+        &meta_table(table),
+        key,
+        |meta: &mut TimeSeriesMeta| {
+            if timestamp < retention_start(meta) {
+                return Err(KvError::InvalidCommand(format!(
+                    "Timestamp {} is older than the retention of the series",
+                    timestamp
+                )));
+            }
+            meta.max_timestamp = meta.max_timestamp.max(timestamp);
+            Ok(meta.clone())
+        },
+    )??;
+    store.set(
+        &sample_table(table),
+        sample_key(key, timestamp).into(),
+        value.into(),
+    )?;
+
+    // 每次写入都会裁剪，所以这个范围里通常只有刚刚过期的几个采样点
+    let end = retention_start(&meta);
+    if end > 0 {
+        let start = sample_key(key, 0);
+        let end = sample_key(key, end);
+        let expired: Vec<_> = store
+            .get_range(
+                &sample_table(table),
+                Bound::Included(start.as_slice()),
+                Bound::Excluded(end.as_slice()),
+            )?
+            .map(|pair| pair.key)
+            .collect();
+        for k in expired {
+            store.del(&sample_table(table), &k)?;
+        }
+    }
+    Ok(meta)
+}
+
+// 按时间顺序遍历序列里时间戳在 [from, to] 之间的采样点
+fn scan<S: Storage + ?Sized>(
+    store: &S,
+    table: &str,
+    key: &[u8],
+    from: u64,
+    to: u64,
+) -> Result<impl Iterator<Item = Sample>, KvError> {
+    let start = sample_key(key, from);
+    let end = sample_key(key, to);
+    let iter = store.get_range(
+        &sample_table(table),
+        Bound::Included(start.as_slice()),
+        Bound::Included(end.as_slice()),
+    )?;
+    Ok(iter.filter_map(|pair| {
+        let ts = pair.key.len().checked_sub(8).map(|i| &pair.key[i..])?;
+        let timestamp = u64::from_be_bytes(ts.try_into().ok()?);
+        let value = match pair.value?.value {
+            Some(value::Value::Float(f)) => f,
+            Some(value::Value::Integer(i)) => i as f64,
+            _ => return None,
+        };
+        Some(Sample::new(timestamp, value))
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MemTable, SledDb};
+    use tempfile::tempdir;
+
+    #[test]
+    fn memtable_timeseries_should_work() {
+        test_timeseries(MemTable::new());
+    }
+
+    #[test]
+    fn sleddb_timeseries_should_work() {
+        let dir = tempdir().unwrap();
+        test_timeseries(SledDb::new(dir));
+    }
+
+    #[test]
+    fn memtable_downsample_and_retention_should_work() {
+        test_downsample_and_retention(MemTable::new());
+    }
+
+    #[test]
+    fn sleddb_downsample_and_retention_should_work() {
+        let dir = tempdir().unwrap();
+        test_downsample_and_retention(SledDb::new(dir));
+    }
+
+    #[test]
+    fn future_sample_should_not_expire_series() {
+        let store = MemTable::new();
+        let now = now_ms();
+        store.ts_set_retention("t1", b"cpu", 60_000).unwrap();
+        store.ts_add("t1", b"cpu", now - 1000, 1.0).unwrap();
+        // 时间戳在很远的未来的采样点不会让现在的数据过期
+        store.ts_add("t1", b"cpu", u64::MAX / 2, 2.0).unwrap();
+        let res = store.ts_range("t1", b"cpu", 0, u64::MAX, None).unwrap();
+        assert_eq!(res.len(), 2);
+        // 回填保留时长之内的数据也可以
+        store.ts_add("t1", b"cpu", now - 2000, 3.0).unwrap();
+        assert!(store.ts_add("t1", b"cpu", now - 120_000, 4.0).is_err());
+        let res = store.ts_range("t1", b"cpu", 0, u64::MAX, None).unwrap();
+        assert_eq!(res.len(), 3);
+    }
+
+    #[test]
+    fn aggregation_should_work() {
+        let values = [1.0, 4.0, 2.0, 5.0];
+        assert_eq!(Aggregation::Avg.apply(&values), 3.0);
+        assert_eq!(Aggregation::Min.apply(&values), 1.0);
+        assert_eq!(Aggregation::Max.apply(&values), 5.0);
+        assert_eq!(Aggregation::Sum.apply(&values), 12.0);
+        assert_eq!(Aggregation::Count.apply(&values), 4.0);
+        assert_eq!("AVG".parse::<Aggregation>().unwrap(), Aggregation::Avg);
+        assert!("median".parse::<Aggregation>().is_err());
+    }
+
+    fn test_timeseries(store: impl Storage) {
+        for (ts, v) in [(1000, 1.0), (1500, 3.0), (2000, 5.0), (2999, 7.0)] {
+            store.ts_add("t1", b"cpu", ts, v).unwrap();
+        }
+        // 别的序列的数据不会混进来
+        store.ts_add("t1", b"cpu2", 1200, 100.0).unwrap();
+
+        let res = store.ts_range("t1", b"cpu", 1200, 2000, None).unwrap();
+        assert_eq!(res, vec![Sample::new(1500, 3.0), Sample::new(2000, 5.0)]);
+
+        let agg = Some((Aggregation::Avg, 1000));
+        let res = store.ts_range("t1", b"cpu", 0, u64::MAX, agg).unwrap();
+        assert_eq!(res, vec![Sample::new(1000, 2.0), Sample::new(2000, 6.0)]);
+
+        let agg = Some((Aggregation::Count, 1000));
+        let res = store.ts_range("t1", b"cpu", 0, u64::MAX, agg).unwrap();
+        assert_eq!(res, vec![Sample::new(1000, 2.0), Sample::new(2000, 2.0)]);
+
+        let agg = Some((Aggregation::Max, 0));
+        assert!(store.ts_range("t1", b"cpu", 0, u64::MAX, agg).is_err());
+    }
+
+    fn test_downsample_and_retention(store: impl Storage) {
+        let rule = DownsampleRule {
+            dest_table: "t1_1s".into(),
+            dest_key: "cpu".into(),
+            aggregation: "max".into(),
+            bucket_ms: 1000,
+        };
+        store.ts_create_rule("t1", b"cpu", rule.clone()).unwrap();
+        // 重复添加同一个目标序列的规则会替换之前的规则
+        store.ts_create_rule("t1", b"cpu", rule).unwrap();
+        assert_eq!(store.ts_rules("t1", b"cpu").unwrap().len(), 1);
+
+        let bad = DownsampleRule {
+            dest_table: "t1".into(),
+            dest_key: "cpu".into(),
+            aggregation: "max".into(),
+            bucket_ms: 1000,
+        };
+        assert!(store.ts_create_rule("t1", b"cpu", bad).is_err());
+
+        for (ts, v) in [(1000, 1.0), (1500, 3.0), (2100, 2.0)] {
+            store.ts_add("t1", b"cpu", ts, v).unwrap();
+        }
+        let res = store.ts_range("t1_1s", b"cpu", 0, u64::MAX, None).unwrap();
+        assert_eq!(res, vec![Sample::new(1000, 3.0), Sample::new(2000, 2.0)]);
+
+        // 保留 1000 毫秒，下一次写入时删除过期的数据
+        store.ts_set_retention("t1", b"cpu", 1000).unwrap();
+        store.ts_add("t1", b"cpu", 2600, 4.0).unwrap();
+        let res = store.ts_range("t1", b"cpu", 0, u64::MAX, None).unwrap();
+        assert_eq!(res, vec![Sample::new(2100, 2.0), Sample::new(2600, 4.0)]);
+        // 降采样的结果不受源序列保留时长的影响
+        let res = store.ts_range("t1_1s", b"cpu", 0, u64::MAX, None).unwrap();
+        assert_eq!(res, vec![Sample::new(1000, 3.0), Sample::new(2000, 4.0)]);
+
+        // 早于保留时长的采样点不会写入
+        assert!(store.ts_add("t1", b"cpu", 1200, 1.0).is_err());
+
+        assert_eq!(
+            store.ts_delete_rule("t1", b"cpu", "t1_1s", b"cpu").unwrap(),
+            1
+        );
+        assert!(store.ts_rules("t1", b"cpu").unwrap().is_empty());
+    }
+}
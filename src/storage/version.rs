@@ -0,0 +1,515 @@
+use bytes::{BufMut, Bytes, BytesMut};
+use std::{
+    collections::HashSet,
+    ops::Bound,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use super::{lock_row, message_to_value, value_to_message};
+use crate::{value, KvError, Kvpair, Storage, Value, VersionPolicy, VersionedValue};
+
+// 存放全局版本号和每个 table 多版本配置的 table
+pub(super) const META_TABLE: &str = "__meta__";
+// 全局版本号的 key
+const VERSION_KEY: &[u8] = b"version";
+
+// table 的历史版本存放在另外一个 table 里，每个版本一行，key 是 "key 的长度（u32 大端）| key | 版本号（u64 大端）"，
+// value 是 VersionedValue。另外 "key 的长度 | key" 这一行记录这个 key 最新的版本号
+// 同一个 key 的所有版本在有序后端里是连续的一段，并且按版本号排列
+fn history_table(table: &str) -> String {
+    format!("__history__.{}", table)
+}
+
+fn history_prefix(key: &[u8]) -> Bytes {
+    let mut buf = BytesMut::with_capacity(4 + key.len());
+    buf.put_u32(key.len() as u32);
+    buf.put_slice(key);
+    buf.freeze()
+}
+
+fn history_key(key: &[u8], version: u64) -> Bytes {
+    let mut buf = BytesMut::with_capacity(4 + key.len() + 8);
+    buf.put_slice(&history_prefix(key));
+    buf.put_u64(version);
+    buf.freeze()
+}
+
+// 从历史 table 的 key 里解析出原来的 key，以及版本号（最新版本号那一行没有版本号）
+fn parse_history_key(k: &[u8]) -> Option<(&[u8], Option<u64>)> {
+    let len = u32::from_be_bytes(k.get(..4)?.try_into().ok()?) as usize;
+    let key = k.get(4..4 + len)?;
+    match &k[4 + len..] {
+        [] => Some((key, None)),
+        version => Some((key, Some(u64::from_be_bytes(version.try_into().ok()?)))),
+    }
+}
+
+fn policy_key(table: &str) -> Bytes {
+    format!("versioning.{}", table).into()
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+// 在 Storage 的基础上提供多版本的能力，所有实现了 Storage 的结构都自动获得这些方法
+// 只有开启了多版本的 table，set_versioned / del_versioned 才会记录历史版本
+pub trait VersionedStorage: Storage {
+    // 开启（Some）或关闭（None）table 的多版本
+    // 关闭之后的写入不会记录历史，已有的历史版本会过期，所以关闭时删除 table 所有的历史版本
+    fn set_versioning(&self, table: &str, policy: Option<VersionPolicy>) -> Result<(), KvError> {
+        match policy {
+            Some(policy) => {
+                self.set(META_TABLE, policy_key(table), message_to_value(&policy))?;
+            }
+            None => {
+                self.del(META_TABLE, &policy_key(table))?;
+                let history = history_table(table);
+                for key in self.get_keys(&history)? {
+                    self.del(&history, &key)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn get_versioning(&self, table: &str) -> Result<Option<VersionPolicy>, KvError> {
+        self.get(META_TABLE, &policy_key(table))?
+            .map(|v| value_to_message(&v))
+            .transpose()
+    }
+
+    // 和 Storage::update 一样，但开启了多版本的 table 会记录下这次修改
+    // 写入和记录历史在同一个 row lock 里完成，这样历史里最新的版本总是和当前的值一致
+    fn update_versioned(
+        &self,
+        table: &str,
+        key: &[u8],
+        f: &mut dyn FnMut(Option<Value>) -> Result<Option<Value>, KvError>,
+    ) -> Result<Option<Value>, KvError> {
+        let _guard = lock_row(table, key);
+        let (_, value) = write_versioned(self, table, key, f)?;
+        Ok(value)
+    }
+
+    // 和 set 一样，但开启了多版本的 table 会记录下这次写入
+    fn set_versioned(
+        &self,
+        table: &str,
+        key: Bytes,
+        value: Value,
+    ) -> Result<Option<Value>, KvError> {
+        let mut old = None;
+        self.update_versioned(table, &key, &mut |v| {
+            old = v;
+            Ok(Some(value.clone()))
+        })?;
+        Ok(old)
+    }
+
+    // 和 del 一样，但开启了多版本的 table 会记录下这次删除
+    fn del_versioned(&self, table: &str, key: &[u8]) -> Result<Option<Value>, KvError> {
+        let mut old = None;
+        self.update_versioned(table, key, &mut |v| {
+            old = v;
+            Ok(None)
+        })?;
+        Ok(old)
+    }
+
+    // 获取 key 在版本 version 时的值。从来没有记录过历史的 key 返回当前值
+    fn get_version(&self, table: &str, key: &[u8], version: u64) -> Result<Option<Value>, KvError> {
+        if self
+            .get(&history_table(table), &history_prefix(key))?
+            .is_none()
+        {
+            return self.get(table, key);
+        }
+        match versions(self, table, key, version)?.last() {
+            Some(pair) => Ok(value_of(pair)?.and_then(|pair| pair.value)),
+            None => Ok(None),
+        }
+    }
+
+    // 获取 key 的所有历史版本，按版本号从小到大排列
+    fn history(&self, table: &str, key: &[u8]) -> Result<Vec<VersionedValue>, KvError> {
+        versions(self, table, key, u64::MAX)?
+            .map(|pair| value_to_message(&pair.value.unwrap_or_default()))
+            .collect()
+    }
+
+    // 获取 table 在版本 version 时的所有 kv pair
+    fn get_all_as_of(&self, table: &str, version: u64) -> Result<Vec<Kvpair>, KvError> {
+        let mut seen = HashSet::new();
+        // 历史 table 按 key 和版本号排列，每个 key 取版本号不大于 version 的最后一个版本
+        let mut latest: Option<Kvpair> = None;
+        let mut result = vec![];
+        let history = history_table(table);
+        for pair in self.get_range(&history, Bound::Unbounded, Bound::Unbounded)? {
+            let (key, v) = match parse_history_key(&pair.key) {
+                Some((key, v)) => (Bytes::copy_from_slice(key), v),
+                None => continue,
+            };
+            if latest.as_ref().is_some_and(|l| l.key != key) {
+                result.extend(latest.take().and_then(|l| value_of(l).transpose()));
+            }
+            match v {
+                None => {
+                    seen.insert(key);
+                }
+                Some(v) if v <= version => {
+                    latest = Some(Kvpair::new(key, pair.value.unwrap_or_default()))
+                }
+                Some(_) => {}
+            }
+        }
+        result.extend(latest.and_then(|l| value_of(l).transpose()));
+        let mut result = result.into_iter().collect::<Result<Vec<_>, _>>()?;
+
+        // 没有历史版本的 key 是开启多版本之前写入的，直接用当前值
+        result.extend(
+            self.get_iter(table)?
+                .filter(|pair| !seen.contains(&pair.key)),
+        );
+        Ok(result)
+    }
+}
+
+impl<T: Storage + ?Sized> VersionedStorage for T {}
+
+// 修改 key 并在需要时记录历史，返回修改前后的值。调用者需要持有这个 key 的 row lock
+pub(super) fn write_versioned<S: Storage + ?Sized>(
+    store: &S,
+    table: &str,
+    key: &[u8],
+    f: &mut dyn FnMut(Option<Value>) -> Result<Option<Value>, KvError>,
+) -> Result<(Option<Value>, Option<Value>), KvError> {
+    let mut old = None;
+    let value = store.update(table, key, &mut |v| {
+        old = v.clone();
+        f(v)
+    })?;
+
+    // 没有变化的删除（key 本来就不存在）不算一个版本
+    if old.is_some() || value.is_some() {
+        if let Some(policy) = store.get_versioning(table)? {
+            record_version(store, table, key, old.clone(), value.clone(), &policy)?;
+        }
+    }
+    Ok((old, value))
+}
+
+// key 的版本号不大于 version 的所有版本，按版本号从小到大排列
+fn versions<S: Storage + ?Sized>(
+    store: &S,
+    table: &str,
+    key: &[u8],
+    version: u64,
+) -> Result<Box<dyn Iterator<Item = Kvpair>>, KvError> {
+    store.get_range(
+        &history_table(table),
+        Bound::Included(&history_key(key, 0)),
+        Bound::Included(&history_key(key, version)),
+    )
+}
+
+// 把历史 table 里的一个版本转换成这个版本时 key 的值，被删除时返回 None
+fn value_of(pair: Kvpair) -> Result<Option<Kvpair>, KvError> {
+    let v: VersionedValue = value_to_message(&pair.value.unwrap_or_default())?;
+    Ok(match v.value {
+        Some(value) if !v.deleted => Some(Kvpair::new(pair.key, value)),
+        _ => None,
+    })
+}
+
+// 记录一次写入。如果历史里最新的版本和写入之前的值不一致（这个 key 第一次记录历史，
+// 或者关闭多版本期间被修改过），先把之前的值记为一个版本，这样查询更早的版本时也能得到正确的结果
+// 第一次记录时之前的值记为版本 0
+fn record_version<S: Storage + ?Sized>(
+    store: &S,
+    table: &str,
+    key: &[u8],
+    old: Option<Value>,
+    value: Option<Value>,
+    policy: &VersionPolicy,
+) -> Result<(), KvError> {
+    let history = history_table(table);
+    let prefix = history_prefix(key);
+    let timestamp = now_ms();
+
+    let last = match store.get(&history, &prefix)? {
+        Some(Value {
+            value: Some(value::Value::Integer(v)),
+        }) => Some(v as u64),
+        Some(v) => return Err(KvError::ConvertError(v, "Integer")),
+        None => None,
+    };
+    let last_value = match last {
+        Some(v) => match store.get(&history, &history_key(key, v))? {
+            Some(record) => {
+                let record: VersionedValue = value_to_message(&record)?;
+                record.value.filter(|_| !record.deleted)
+            }
+            None => None,
+        },
+        None => None,
+    };
+    if last_value != old {
+        let version = match last {
+            Some(_) => next_version(store)?,
+            None => 0,
+        };
+        let record = VersionedValue {
+            version,
+            timestamp,
+            deleted: old.is_none(),
+            value: old,
+        };
+        store.set(
+            &history,
+            history_key(key, version),
+            message_to_value(&record),
+        )?;
+    }
+
+    let version = next_version(store)?;
+    let record = VersionedValue {
+        version,
+        timestamp,
+        deleted: value.is_none(),
+        value,
+    };
+    store.set(
+        &history,
+        history_key(key, version),
+        message_to_value(&record),
+    )?;
+    store.set(&history, prefix, (version as i64).into())?;
+    prune(store, table, key, policy, timestamp)
+}
+
+// 生成一个新的全局版本号
+fn next_version<S: Storage + ?Sized>(store: &S) -> Result<u64, KvError> {
+    let value = store.update(META_TABLE, VERSION_KEY, &mut |v| match v {
+        Some(Value {
+            value: Some(value::Value::Integer(i)),
+        }) => Ok(Some((i + 1).into())),
+        None => Ok(Some(1.into())),
+        Some(v) => Err(KvError::ConvertError(v, "Integer")),
+    })?;
+
+    match value {
+        Some(Value {
+            value: Some(value::Value::Integer(i)),
+        }) => Ok(i as u64),
+        _ => Err(KvError::Internal("Failed to generate version".into())),
+    }
+}
+
+// 按照保留策略删除 key 旧的版本，最新的版本总是会保留
+// 只按时间清理时从最旧的版本开始，遇到第一个没有过期的版本就停止，不需要读取所有的版本
+fn prune<S: Storage + ?Sized>(
+    store: &S,
+    table: &str,
+    key: &[u8],
+    policy: &VersionPolicy,
+    now: u64,
+) -> Result<(), KvError> {
+    let max = policy.max_versions as usize;
+    let deadline = (policy.max_age_secs > 0)
+        .then(|| now.saturating_sub(policy.max_age_secs.saturating_mul(1000)));
+    if max == 0 && deadline.is_none() {
+        return Ok(());
+    }
+
+    let mut rows = vec![];
+    for pair in versions(store, table, key, u64::MAX)? {
+        let v: VersionedValue = value_to_message(&pair.value.unwrap_or_default())?;
+        let expired = deadline.is_some_and(|d| v.timestamp < d);
+        if !expired && max == 0 {
+            break;
+        }
+        rows.push((pair.key, expired));
+    }
+
+    let excess = if max == 0 {
+        0
+    } else {
+        rows.len().saturating_sub(max)
+    };
+    // 最新的版本刚刚写入，不会过期，也不会超过个数限制，所以不会被删除
+    let history = history_table(table);
+    for (i, (k, expired)) in rows.iter().enumerate() {
+        if i < excess || *expired {
+            store.del(&history, k)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MemTable, SledDb};
+    use std::{sync::Arc, thread};
+    use tempfile::tempdir;
+
+    #[test]
+    fn memtable_version_retention_should_work() {
+        test_version_retention(MemTable::new());
+    }
+
+    #[test]
+    fn sleddb_version_retention_should_work() {
+        let dir = tempdir().unwrap();
+        test_version_retention(SledDb::new(dir));
+    }
+
+    fn test_version_retention(store: impl Storage) {
+        store
+            .set_versioning("t1", Some(VersionPolicy::new(2, 0)))
+            .unwrap();
+        for i in 0..5 {
+            store.set_versioned("t1", "k1".into(), i.into()).unwrap();
+        }
+        let history = store.history("t1", b"k1").unwrap();
+        let versions: Vec<_> = history.iter().map(|v| v.version).collect();
+        assert_eq!(versions, vec![4, 5]);
+        assert_eq!(store.get_version("t1", b"k1", 4).unwrap(), Some(3.into()));
+        // 被清理掉的版本已经读不到了
+        assert_eq!(store.get_version("t1", b"k1", 1).unwrap(), None);
+
+        // 关闭后不再记录新的版本，已有的历史也被删除，查询返回当前值
+        store.set_versioning("t1", None).unwrap();
+        store.set_versioned("t1", "k1".into(), 10.into()).unwrap();
+        assert!(store.history("t1", b"k1").unwrap().is_empty());
+        assert_eq!(store.get_version("t1", b"k1", 5).unwrap(), Some(10.into()));
+        let all = store.get_all_as_of("t1", 5).unwrap();
+        assert_eq!(all, vec![Kvpair::new("k1", 10.into())]);
+
+        // 再次开启时，关闭期间写入的值会先记为一个版本
+        store
+            .set_versioning("t1", Some(VersionPolicy::new(0, 0)))
+            .unwrap();
+        store.set_versioned("t1", "k1".into(), 11.into()).unwrap();
+        let history = store.history("t1", b"k1").unwrap();
+        let values: Vec<_> = history.iter().map(|v| v.value.clone()).collect();
+        assert_eq!(values, vec![Some(10.into()), Some(11.into())]);
+        assert_eq!(store.get_version("t1", b"k1", 5).unwrap(), Some(10.into()));
+
+        // 绕过多版本写入的值也会在下一次写入时补记
+        store.set("t1", "k1".into(), 12.into()).unwrap();
+        store.del_versioned("t1", b"k1").unwrap();
+        let history = store.history("t1", b"k1").unwrap();
+        assert_eq!(history.len(), 4);
+        let before = history[3].version - 1;
+        assert_eq!(history[2].version, before);
+        assert_eq!(
+            store.get_version("t1", b"k1", before).unwrap(),
+            Some(12.into())
+        );
+        assert_eq!(store.get_version("t1", b"k1", u64::MAX).unwrap(), None);
+        assert!(store.get_all_as_of("t1", u64::MAX).unwrap().is_empty());
+        let all = store.get_all_as_of("t1", before).unwrap();
+        assert_eq!(all, vec![Kvpair::new("k1", 12.into())]);
+    }
+
+    #[test]
+    fn concurrent_versioned_writes_should_agree_with_value() {
+        let store = Arc::new(MemTable::new());
+        store
+            .set_versioning("t1", Some(VersionPolicy::new(0, 0)))
+            .unwrap();
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let store = store.clone();
+                thread::spawn(move || {
+                    for j in 0..50 {
+                        store
+                            .set_versioned("t1", "k1".into(), (i * 100 + j).into())
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        // 历史按版本号排列，最新的版本就是当前的值
+        let history = store.history("t1", b"k1").unwrap();
+        assert_eq!(history.len(), 400);
+        assert!(history.windows(2).all(|w| w[0].version < w[1].version));
+        let latest = history.last().unwrap().value.clone();
+        assert_eq!(latest, store.get("t1", b"k1").unwrap());
+    }
+
+    #[test]
+    fn prune_by_age_should_keep_latest_version() {
+        let store = MemTable::new();
+        for i in 1..=3 {
+            let record = VersionedValue {
+                version: i,
+                timestamp: i * 1000,
+                ..Default::default()
+            };
+            let key = history_key(b"k1", i);
+            store
+                .set(&history_table("t1"), key, message_to_value(&record))
+                .unwrap();
+        }
+        let versions = |store: &MemTable| -> Vec<u64> {
+            let history = store.history("t1", b"k1").unwrap();
+            history.iter().map(|v| v.version).collect()
+        };
+
+        let policy = VersionPolicy::new(0, 1);
+        prune(&store, "t1", b"k1", &policy, 2500).unwrap();
+        assert_eq!(versions(&store), vec![2, 3]);
+        prune(&store, "t1", b"k1", &VersionPolicy::new(1, 0), 2500).unwrap();
+        assert_eq!(versions(&store), vec![3]);
+
+        // 很大的保留时间不会溢出
+        prune(
+            &store,
+            "t1",
+            b"k1",
+            &VersionPolicy::new(0, u64::MAX),
+            100_000,
+        )
+        .unwrap();
+        assert_eq!(versions(&store), vec![3]);
+    }
+
+    #[test]
+    fn history_keys_should_not_overlap() {
+        // 一个 key 是另一个 key 的前缀时，各自的版本不会混在一起
+        let store = MemTable::new();
+        store
+            .set_versioning("t1", Some(VersionPolicy::new(0, 0)))
+            .unwrap();
+        store.set_versioned("t1", "k".into(), 1.into()).unwrap();
+        store.set_versioned("t1", "k\0".into(), 2.into()).unwrap();
+        store.set_versioned("t1", "k".into(), 3.into()).unwrap();
+        assert_eq!(store.history("t1", b"k").unwrap().len(), 2);
+        assert_eq!(store.history("t1", b"k\0").unwrap().len(), 1);
+        assert_eq!(
+            parse_history_key(&history_key(b"k", 7)),
+            Some((&b"k"[..], Some(7)))
+        );
+        assert_eq!(
+            parse_history_key(&history_prefix(b"k")),
+            Some((&b"k"[..], None))
+        );
+
+        let mut all = store.get_all_as_of("t1", u64::MAX).unwrap();
+        all.sort_by(|a, b| a.key.cmp(&b.key));
+        assert_eq!(
+            all,
+            vec![Kvpair::new("k", 3.into()), Kvpair::new("k\0", 2.into())]
+        );
+    }
+}
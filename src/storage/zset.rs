@@ -0,0 +1,480 @@
+use bytes::{BufMut, Bytes, BytesMut};
+use std::{collections::HashMap, convert::TryInto, ops::Bound};
+
+use super::{
+    index::{ordered_f64, prefix_end},
+    list::index_range,
+    lock_row,
+    set::member_key,
+};
+use crate::{KvError, ScoredValue, Storage, Value};
+
+// 有序集合的每个成员在另外一个 table 里占一行，key 是 "key 的长度（u32 大端）| key | 分数 | 成员编码后的字节"，
+// value 是成员。分数按保持顺序的方式编码，所以一个有序集合的成员在有序后端里按 (score, member) 排列，
+// 修改一个成员和按分数查询都只需要访问相关的几行
+fn zset_table(table: &str) -> String {
+    format!("__zset__.{}", table)
+}
+
+// 每个成员的分数另外存一份，key 是 "key 的长度 | key | 成员编码后的字节"，这样知道成员就能找到它的那一行
+// "key 的长度 | key" 这一行记录有序集合的成员个数
+fn score_table(table: &str) -> String {
+    format!("__zset_score__.{}", table)
+}
+
+// key 的长度 | key，不同有序集合的数据不会冲突
+fn zset_prefix(key: &[u8]) -> Vec<u8> {
+    let mut buf = (key.len() as u32).to_be_bytes().to_vec();
+    buf.extend_from_slice(key);
+    buf
+}
+
+fn score_key(key: &[u8], member: &Value) -> Bytes {
+    let mut buf = zset_prefix(key);
+    buf.extend(member_key(member));
+    buf.into()
+}
+
+fn member_row(key: &[u8], score: f64, member: &Value) -> Bytes {
+    let mut buf = BytesMut::from(&zset_prefix(key)[..]);
+    buf.put_slice(&ordered_f64(score));
+    buf.put_slice(&member_key(member));
+    buf.freeze()
+}
+
+// NaN 没法和其它分数比较，无穷大加减之后也可能变成 NaN，所以分数必须是有限的数
+// -0.0 和 0.0 编码之后不一样，统一成 0.0
+fn check_score(score: f64) -> Result<f64, KvError> {
+    if score.is_finite() {
+        Ok(score + 0.0)
+    } else {
+        Err(KvError::InvalidCommand(format!(
+            "Score {} is not a finite number",
+            score
+        )))
+    }
+}
+
+fn get_score<S: Storage + ?Sized>(
+    store: &S,
+    table: &str,
+    key: &[u8],
+    member: &Value,
+) -> Result<Option<f64>, KvError> {
+    store
+        .get(&score_table(table), &score_key(key, member))?
+        .map(|v| v.try_into())
+        .transpose()
+}
+
+fn zset_len<S: Storage + ?Sized>(store: &S, table: &str, key: &[u8]) -> Result<usize, KvError> {
+    let len: Option<i64> = store
+        .get(&score_table(table), &zset_prefix(key))?
+        .map(|v| v.try_into())
+        .transpose()?;
+    Ok(len.unwrap_or_default().max(0) as usize)
+}
+
+// 修改一个成员的分数，old 是它原来的分数，返回是不是新加入的成员。调用者需要持有 row lock
+// 先写新的成员行，再写分数，最后删掉旧的成员行。读取成员行时会用分数校验，所以中途出错也不会读到错误的结果
+fn set_score<S: Storage + ?Sized>(
+    store: &S,
+    table: &str,
+    key: &[u8],
+    member: &Value,
+    old: Option<f64>,
+    score: f64,
+) -> Result<bool, KvError> {
+    store.set(
+        &zset_table(table),
+        member_row(key, score, member),
+        member.clone(),
+    )?;
+    store.set(&score_table(table), score_key(key, member), score.into())?;
+    match old {
+        Some(old) if old.to_bits() != score.to_bits() => {
+            store.del(&zset_table(table), &member_row(key, old, member))?;
+            Ok(false)
+        }
+        Some(_) => Ok(false),
+        None => {
+            let len = zset_len(store, table, key)? as i64 + 1;
+            store.set(&score_table(table), zset_prefix(key).into(), len.into())?;
+            Ok(true)
+        }
+    }
+}
+
+// 按 (score, member) 的顺序遍历 key 在 [start, end) 之间的成员，f 返回 false 时停止
+// 分数和成员行不一致的行（写入中途出错留下的）会被跳过
+fn scan<S: Storage + ?Sized>(
+    store: &S,
+    table: &str,
+    key: &[u8],
+    start: Bound<&[u8]>,
+    end: Bound<&[u8]>,
+    f: &mut dyn FnMut(ScoredValue) -> bool,
+) -> Result<(), KvError> {
+    let offset = zset_prefix(key).len();
+    for pair in store.get_range(&zset_table(table), start, end)? {
+        let score = match pair.key.get(offset..offset + 8) {
+            Some(bytes) => bytes,
+            None => continue,
+        };
+        let member = pair.value.unwrap_or_default();
+        let current = get_score(store, table, key, &member)?;
+        match current {
+            Some(current) if ordered_f64(current) == score => {
+                if !f(ScoredValue::new(member, current)) {
+                    break;
+                }
+            }
+            _ => continue,
+        }
+    }
+    Ok(())
+}
+
+// 在 Storage 的基础上提供有序集合的能力，所有实现了 Storage 的结构都自动获得这些方法
+// 成员和分数分开存放，修改同一个有序集合时用 row lock 串行起来
+pub trait SortedSetStorage: Storage {
+    // 加入一组成员，已经存在的成员会更新分数，返回新加入的成员个数
+    // 重复的成员以最后一个分数为准
+    fn zset_add(
+        &self,
+        table: &str,
+        key: &[u8],
+        members: Vec<ScoredValue>,
+    ) -> Result<usize, KvError> {
+        let mut scores: Vec<(Value, f64)> = vec![];
+        let mut positions = HashMap::new();
+        for v in members {
+            let score = check_score(v.score)?;
+            let member = v.member.unwrap_or_default();
+            match positions.get(&member_key(&member)) {
+                Some(&i) => scores[i] = (member, score),
+                None => {
+                    positions.insert(member_key(&member), scores.len());
+                    scores.push((member, score));
+                }
+            }
+        }
+
+        let _guard = lock_row(&zset_table(table), key);
+        // 先读出所有成员原来的分数，这一步出错时还没有写入任何数据
+        let olds = scores
+            .iter()
+            .map(|(member, _)| get_score(self, table, key, member))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut added = 0;
+        for ((member, score), old) in scores.iter().zip(olds) {
+            if set_score(self, table, key, member, old, *score)? {
+                added += 1;
+            }
+        }
+        Ok(added)
+    }
+
+    // 按排名获取 [start, stop] 之间的成员，rev 为 true 时按分数从大到小排名
+    fn zset_range(
+        &self,
+        table: &str,
+        key: &[u8],
+        start: i64,
+        stop: i64,
+        rev: bool,
+    ) -> Result<Vec<ScoredValue>, KvError> {
+        let len = zset_len(self, table, key)?;
+        let range = index_range(start, stop, len);
+        // 从大到小的排名换算成从小到大的排名
+        let skip = if rev { len - range.end } else { range.start };
+        let take = range.len();
+
+        let mut result = vec![];
+        let mut i = 0;
+        if take > 0 {
+            let prefix = zset_prefix(key);
+            let end = prefix_end(&prefix);
+            scan(
+                self,
+                table,
+                key,
+                Bound::Included(&prefix),
+                end.as_deref().map_or(Bound::Unbounded, Bound::Excluded),
+                &mut |v| {
+                    if i >= skip {
+                        result.push(v);
+                    }
+                    i += 1;
+                    result.len() < take
+                },
+            )?;
+        }
+        if rev {
+            result.reverse();
+        }
+        Ok(result)
+    }
+
+    // 获取分数在 [min, max] 之间的成员
+    fn zset_range_by_score(
+        &self,
+        table: &str,
+        key: &[u8],
+        min: f64,
+        max: f64,
+    ) -> Result<Vec<ScoredValue>, KvError> {
+        if min.is_nan() || max.is_nan() {
+            return Err(KvError::InvalidCommand(
+                "Score range is not a number".into(),
+            ));
+        }
+        let mut start = zset_prefix(key);
+        start.extend_from_slice(&ordered_f64(min + 0.0));
+        let mut end = zset_prefix(key);
+        end.extend_from_slice(&ordered_f64(max + 0.0));
+        let end = prefix_end(&end);
+
+        let mut result = vec![];
+        scan(
+            self,
+            table,
+            key,
+            Bound::Included(&start),
+            end.as_deref().map_or(Bound::Unbounded, Bound::Excluded),
+            &mut |v| {
+                result.push(v);
+                true
+            },
+        )?;
+        Ok(result)
+    }
+
+    // 获取成员的排名，从 0 开始
+    fn zset_rank(
+        &self,
+        table: &str,
+        key: &[u8],
+        member: &Value,
+        rev: bool,
+    ) -> Result<Option<usize>, KvError> {
+        // 分数和成员行要在同一个 row lock 里读取，否则并发修改分数时可能找不到成员
+        let _guard = lock_row(&zset_table(table), key);
+        let score = match get_score(self, table, key, member)? {
+            Some(score) => score,
+            None => return Ok(None),
+        };
+        let mut rank = 0;
+        scan(
+            self,
+            table,
+            key,
+            Bound::Included(&zset_prefix(key)),
+            Bound::Excluded(&member_row(key, score, member)),
+            &mut |_| {
+                rank += 1;
+                true
+            },
+        )?;
+        let len = zset_len(self, table, key)?;
+        Ok(Some(if rev {
+            len.saturating_sub(rank + 1)
+        } else {
+            rank
+        }))
+    }
+
+    // 给成员的分数加上 increment，成员不存在时先以 0 分加入，返回新的分数
+    fn zset_incr_by(
+        &self,
+        table: &str,
+        key: &[u8],
+        member: Value,
+        increment: f64,
+    ) -> Result<f64, KvError> {
+        check_score(increment)?;
+
+        let _guard = lock_row(&zset_table(table), key);
+        let old = get_score(self, table, key, &member)?;
+        let score = check_score(old.unwrap_or_default() + increment)?;
+        set_score(self, table, key, &member, old, score)?;
+        Ok(score)
+    }
+}
+
+impl<T: Storage + ?Sized> SortedSetStorage for T {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MemTable, SledDb};
+    use std::{sync::Arc, thread};
+    use tempfile::tempdir;
+
+    #[test]
+    fn memtable_sorted_set_should_work() {
+        test_sorted_set(MemTable::new());
+    }
+
+    #[test]
+    fn sleddb_sorted_set_should_work() {
+        let dir = tempdir().unwrap();
+        test_sorted_set(SledDb::new(dir));
+    }
+
+    #[test]
+    fn zset_should_store_one_row_per_member() {
+        let store = MemTable::new();
+        let members = vec![
+            ScoredValue::new("a".into(), 1.0),
+            ScoredValue::new("b".into(), -0.0),
+            // 同一次写入里重复的成员以最后一个分数为准，只算一次
+            ScoredValue::new("a".into(), 2.0),
+        ];
+        assert_eq!(store.zset_add("t1", b"z", members).unwrap(), 2);
+        store.zset_incr_by("t1", b"z", "b".into(), 5.0).unwrap();
+        store.zset_incr_by("t1", b"z", "b".into(), -5.0).unwrap();
+        // 另一个 key 是 z 的前缀的有序集合不会混在一起
+        store
+            .zset_add("t1", b"z\0", vec![ScoredValue::new("c".into(), 1.0)])
+            .unwrap();
+
+        assert_eq!(store.get_iter("__zset__.t1").unwrap().count(), 3);
+        let all = store.zset_range("t1", b"z", 0, -1, false).unwrap();
+        assert_eq!(
+            all,
+            vec![
+                ScoredValue::new("b".into(), 0.0),
+                ScoredValue::new("a".into(), 2.0)
+            ]
+        );
+        let res = store.zset_range_by_score("t1", b"z", -0.0, 0.0).unwrap();
+        assert_eq!(res, vec![ScoredValue::new("b".into(), 0.0)]);
+        let res = store.zset_range("t1", b"z", -1, -1, true).unwrap();
+        assert_eq!(res, vec![ScoredValue::new("b".into(), 0.0)]);
+        assert!(store
+            .zset_range("t1", b"none", 0, -1, false)
+            .unwrap()
+            .is_empty());
+
+        // 没有分数对应的成员行（写入中途出错留下的）不会被读到
+        store
+            .set(
+                "__zset__.t1",
+                member_row(b"z", 1.5, &"a".into()),
+                "a".into(),
+            )
+            .unwrap();
+        assert_eq!(store.zset_range("t1", b"z", 0, -1, false).unwrap().len(), 2);
+        assert_eq!(
+            store.zset_rank("t1", b"z", &"a".into(), false).unwrap(),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn zset_rank_should_see_concurrent_incr() {
+        let store = Arc::new(MemTable::new());
+        let members = (0..10)
+            .map(|i| ScoredValue::new(i.into(), i as f64))
+            .collect();
+        store.zset_add("t1", b"z", members).unwrap();
+
+        let writer = {
+            let store = store.clone();
+            thread::spawn(move || {
+                for i in 0..500 {
+                    store
+                        .zset_incr_by("t1", b"z", 0.into(), (i % 3) as f64 - 1.0)
+                        .unwrap();
+                }
+            })
+        };
+        for _ in 0..500 {
+            let rank = store.zset_rank("t1", b"z", &0.into(), false).unwrap();
+            assert!(rank.is_some());
+        }
+        writer.join().unwrap();
+    }
+
+    fn test_sorted_set(store: impl Storage) {
+        let members = |v: &[(&str, f64)]| {
+            v.iter()
+                .map(|&(m, s)| ScoredValue::new(m.into(), s))
+                .collect::<Vec<_>>()
+        };
+
+        let added = store
+            .zset_add(
+                "t1",
+                b"z",
+                members(&[("alice", 3.0), ("bob", 1.0), ("carol", 2.0)]),
+            )
+            .unwrap();
+        assert_eq!(added, 3);
+        // 已经存在的成员只更新分数
+        let added = store
+            .zset_add("t1", b"z", members(&[("bob", 5.0), ("dave", 0.5)]))
+            .unwrap();
+        assert_eq!(added, 1);
+
+        let all = store.zset_range("t1", b"z", 0, -1, false).unwrap();
+        assert_eq!(
+            all,
+            members(&[("dave", 0.5), ("carol", 2.0), ("alice", 3.0), ("bob", 5.0)])
+        );
+        let top2 = store.zset_range("t1", b"z", 0, 1, true).unwrap();
+        assert_eq!(top2, members(&[("bob", 5.0), ("alice", 3.0)]));
+
+        let mid = store.zset_range_by_score("t1", b"z", 1.0, 3.0).unwrap();
+        assert_eq!(mid, members(&[("carol", 2.0), ("alice", 3.0)]));
+
+        assert_eq!(
+            store.zset_rank("t1", b"z", &"alice".into(), false).unwrap(),
+            Some(2)
+        );
+        assert_eq!(
+            store.zset_rank("t1", b"z", &"alice".into(), true).unwrap(),
+            Some(1)
+        );
+        assert_eq!(
+            store.zset_rank("t1", b"z", &"eve".into(), false).unwrap(),
+            None
+        );
+
+        assert_eq!(
+            store.zset_incr_by("t1", b"z", "dave".into(), 10.0).unwrap(),
+            10.5
+        );
+        assert_eq!(
+            store.zset_incr_by("t1", b"z", "eve".into(), 1.5).unwrap(),
+            1.5
+        );
+        assert_eq!(
+            store.zset_rank("t1", b"z", &"dave".into(), true).unwrap(),
+            Some(0)
+        );
+        assert_eq!(store.zset_range("t1", b"z", 0, -1, false).unwrap().len(), 5);
+
+        // 不是有限的数的分数会被拒绝，不会写入任何成员
+        let res = store.zset_add("t1", b"z", members(&[("frank", 1.0), ("gina", f64::NAN)]));
+        assert!(matches!(res, Err(KvError::InvalidCommand(_))));
+        assert!(store
+            .zset_incr_by("t1", b"z", "alice".into(), f64::NAN)
+            .is_err());
+        assert!(store
+            .zset_incr_by("t1", b"z", "alice".into(), f64::INFINITY)
+            .is_err());
+        assert!(store
+            .zset_range_by_score("t1", b"z", f64::NAN, 1.0)
+            .is_err());
+        let all = store
+            .zset_range_by_score("t1", b"z", f64::NEG_INFINITY, f64::INFINITY)
+            .unwrap();
+        assert_eq!(all.len(), 5);
+        assert_eq!(
+            store.zset_rank("t1", b"z", &"frank".into(), false).unwrap(),
+            None
+        );
+    }
+}